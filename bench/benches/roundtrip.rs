@@ -0,0 +1,128 @@
+//! Serialize, deserialize and round-trip times for each shape in `capnez-bench`, through the
+//! generated capnez conversions and through serde_json and bincode on the same values.
+//!
+//! `cargo bench -p capnez-bench` for numbers, `cargo bench -p capnez-bench -- --quick` for a smoke run.
+
+use capnez::pool::MessagePool;
+use capnez_bench::{article, people, person, sparse_matrix, Article, ArticleView, Person, SparseMatrixData};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// The capnez, serde_json and bincode measurements shared by every shape; `to_capnp_bytes`,
+/// `to_capnp_bytes_in` and `from_capnp_bytes` are generated per type, so they come in as closures.
+fn compare<T: Serialize + DeserializeOwned>(
+    c: &mut Criterion,
+    shape: &str,
+    value: &T,
+    to_capnp: impl Fn(&T) -> Vec<u8>,
+    to_capnp_in: impl Fn(&T, &MessagePool) -> Vec<u8>,
+    from_capnp: impl Fn(&[u8]) -> T,
+) {
+    let capnp_bytes = to_capnp(value);
+    let json_bytes = serde_json::to_vec(value).unwrap();
+    let bincode_bytes = bincode::serialize(value).unwrap();
+    let pool = MessagePool::new();
+
+    let mut group = c.benchmark_group(format!("{}/serialize", shape));
+    group.throughput(Throughput::Bytes(capnp_bytes.len() as u64));
+    group.bench_function("capnez", |b| b.iter(|| to_capnp(black_box(value))));
+    group.bench_function("capnez_pool", |b| b.iter(|| to_capnp_in(black_box(value), &pool)));
+    group.bench_function("serde_json", |b| b.iter(|| serde_json::to_vec(black_box(value)).unwrap()));
+    group.bench_function("bincode", |b| b.iter(|| bincode::serialize(black_box(value)).unwrap()));
+    group.finish();
+
+    let mut group = c.benchmark_group(format!("{}/deserialize", shape));
+    group.throughput(Throughput::Bytes(capnp_bytes.len() as u64));
+    group.bench_function("capnez", |b| b.iter(|| from_capnp(black_box(&capnp_bytes))));
+    group.bench_function("serde_json", |b| b.iter(|| serde_json::from_slice::<T>(black_box(&json_bytes)).unwrap()));
+    group.bench_function("bincode", |b| b.iter(|| bincode::deserialize::<T>(black_box(&bincode_bytes)).unwrap()));
+    group.finish();
+
+    let mut group = c.benchmark_group(format!("{}/roundtrip", shape));
+    group.bench_function("capnez", |b| b.iter(|| from_capnp(&to_capnp(black_box(value)))));
+    group.bench_function("capnez_pool", |b| b.iter(|| from_capnp(&to_capnp_in(black_box(value), &pool))));
+    group.bench_function("serde_json", |b| {
+        b.iter(|| serde_json::from_slice::<T>(&serde_json::to_vec(black_box(value)).unwrap()).unwrap())
+    });
+    group.bench_function("bincode", |b| {
+        b.iter(|| bincode::deserialize::<T>(&bincode::serialize(black_box(value)).unwrap()).unwrap())
+    });
+    group.finish();
+}
+
+fn person_bench(c: &mut Criterion) {
+    compare(
+        c,
+        "person",
+        &person(),
+        Person::to_capnp_bytes,
+        Person::to_capnp_bytes_in,
+        |bytes| Person::from_capnp_bytes(bytes).unwrap(),
+    );
+}
+
+/// Serializing a batch of 10k people one message each, where a pool saves an allocation per message.
+fn person_pool_bench(c: &mut Criterion) {
+    let people = people(10_000);
+    let pool = MessagePool::new();
+    let mut group = c.benchmark_group("person_10k/serialize");
+    group.throughput(Throughput::Elements(people.len() as u64));
+    group.bench_function("capnez", |b| {
+        b.iter(|| black_box(&people).iter().map(|person| person.to_capnp_bytes().len()).sum::<usize>())
+    });
+    group.bench_function("capnez_pool", |b| {
+        b.iter(|| black_box(&people).iter().map(|person| person.to_capnp_bytes_in(&pool).len()).sum::<usize>())
+    });
+    group.finish();
+}
+
+fn sparse_matrix_bench(c: &mut Criterion) {
+    compare(
+        c,
+        "sparse_matrix_10k",
+        &sparse_matrix(10_000),
+        SparseMatrixData::to_capnp_bytes,
+        SparseMatrixData::to_capnp_bytes_in,
+        |bytes| SparseMatrixData::from_capnp_bytes(bytes).unwrap(),
+    );
+
+    // Decoding cost as the list grows, for capnez only
+    let mut group = c.benchmark_group("sparse_matrix/deserialize_by_entries");
+    for entries in [100, 1_000, 10_000] {
+        let bytes = sparse_matrix(entries).to_capnp_bytes();
+        group.throughput(Throughput::Elements(u64::from(entries)));
+        group.bench_with_input(BenchmarkId::new("capnez", entries), &bytes, |b, bytes| {
+            b.iter(|| SparseMatrixData::from_capnp_bytes(black_box(bytes)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn article_bench(c: &mut Criterion) {
+    let value = article();
+    compare(
+        c,
+        "article",
+        &value,
+        Article::to_capnp_bytes,
+        Article::to_capnp_bytes_in,
+        |bytes| Article::from_capnp_bytes(bytes).unwrap(),
+    );
+
+    // The same message read into `ArticleView`, which borrows its text instead of copying it. The
+    // message has to outlive the view, so reading it is part of the measurement.
+    let bytes = value.to_capnp_bytes();
+    let mut group = c.benchmark_group("article/deserialize_borrowed");
+    group.throughput(Throughput::Bytes(bytes.len() as u64));
+    group.bench_function("capnez", |b| {
+        b.iter(|| {
+            let message = capnp::serialize::read_message_from_flat_slice(&mut black_box(&bytes[..]), Default::default()).unwrap();
+            let reader = message.get_root::<capnez_bench::schema_capnp::article_view::Reader>().unwrap();
+            black_box(ArticleView::from_capnp(reader).unwrap());
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, person_bench, person_pool_bench, sparse_matrix_bench, article_bench);
+criterion_main!(benches);
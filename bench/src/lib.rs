@@ -0,0 +1,97 @@
+//! The shapes `benches/roundtrip.rs` measures, each a `#[capnp]` struct that also derives serde so
+//! every format encodes the same Rust value: a small flat struct, a matrix holding a list of 10k
+//! structs, and a struct that is mostly text.
+
+use capnez_codegen::capnp_include;
+use capnez_macros::capnp;
+use serde::{Deserialize, Serialize};
+
+capnp_include!();
+
+#[capnp]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Person {
+    pub name: String,
+    pub age: u32,
+    pub email: String,
+}
+
+#[capnp]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MatrixEntry {
+    pub row: u32,
+    pub col: u32,
+    pub value: f64,
+}
+
+#[capnp]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SparseMatrixData {
+    pub rows: u32,
+    pub cols: u32,
+    pub values: Vec<MatrixEntry>,
+}
+
+#[capnp]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Article {
+    pub title: String,
+    pub author: String,
+    pub body: String,
+    pub tags: Vec<String>,
+    pub comments: Vec<String>,
+    pub thumbnail: Vec<u8>,
+    pub digest: [u8; 32],
+}
+
+/// `Article` read without copying: `from_capnp` points every field into the message.
+#[capnp]
+#[derive(Debug, PartialEq)]
+pub struct ArticleView<'a> {
+    pub title: &'a str,
+    pub author: &'a str,
+    pub body: &'a str,
+    pub tags: Vec<&'a str>,
+    pub comments: Vec<&'a str>,
+    pub thumbnail: &'a [u8],
+    pub digest: [u8; 32],
+}
+
+pub fn person() -> Person {
+    Person {
+        name: "John Doe".to_string(),
+        age: 30,
+        email: "john@example.com".to_string(),
+    }
+}
+
+/// `count` people, each with its own name and email.
+pub fn people(count: u32) -> Vec<Person> {
+    (0..count)
+        .map(|i| Person { name: format!("Person {}", i), age: 20 + i % 60, email: format!("person{}@example.com", i) })
+        .collect()
+}
+
+/// A 1000x1000 matrix with `entries` values spread over it.
+pub fn sparse_matrix(entries: u32) -> SparseMatrixData {
+    SparseMatrixData {
+        rows: 1000,
+        cols: 1000,
+        values: (0..entries)
+            .map(|i| MatrixEntry { row: i / 1000 % 1000, col: i * 7 % 1000, value: f64::from(i) * 0.5 })
+            .collect(),
+    }
+}
+
+pub fn article() -> Article {
+    let paragraph = "Cap'n Proto messages are laid out in memory the way they go on the wire, so reading one is a matter of following pointers. ";
+    Article {
+        title: "Zero-copy serialization in practice".to_string(),
+        author: "A. N. Author".to_string(),
+        body: paragraph.repeat(200),
+        tags: ["rust", "serialization", "capnp", "performance"].iter().map(|tag| tag.to_string()).collect(),
+        comments: (0..100).map(|i| format!("Comment {}: {}", i, &paragraph[..80])).collect(),
+        thumbnail: (0..16 * 1024).map(|i| (i % 251) as u8).collect(),
+        digest: core::array::from_fn(|i| i as u8),
+    }
+}
@@ -0,0 +1,235 @@
+//! An integrity envelope for serialized messages that travel between machines or sit in files.
+//!
+//! A sealed message is the standard stream framing behind a 24-byte header, all little-endian:
+//!
+//! | Bytes  | Field                                   |
+//! |--------|-----------------------------------------|
+//! | 0..4   | magic `CPNZ`                            |
+//! | 4..6   | format version, currently 1             |
+//! | 6..8   | zero                                    |
+//! | 8..16  | payload length in bytes                 |
+//! | 16..20 | CRC-32C of the payload                  |
+//! | 20..24 | zero                                    |
+//!
+//! The header is a whole number of words, so the payload keeps the alignment of the buffer.
+//! [`verify`] checks all of it before capnp sees a byte, so a truncated or corrupted file fails with
+//! an [`EnvelopeError`] naming the problem rather than a pointer-out-of-bounds deep in a read. Peers
+//! that do not use capnez keep using the raw framing; nothing here changes it.
+
+use std::fmt;
+
+const MAGIC: &[u8; 4] = b"CPNZ";
+const VERSION: u16 = 1;
+/// Bytes in front of the payload.
+pub const HEADER_LEN: usize = 24;
+
+/// Why sealed bytes were rejected.
+#[derive(Debug)]
+pub enum EnvelopeError {
+    /// The bytes do not start with the envelope's magic; they may be a raw message.
+    BadMagic,
+    /// Sealed by a newer format version than this build reads.
+    UnsupportedVersion(u16),
+    /// The bytes are not as long as the header says, header included: truncated, or with trailing data.
+    LengthMismatch { expected: u64, actual: u64 },
+    /// The payload is intact in length but not in content.
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// The payload passed every check but is not a valid message.
+    Capnp(capnp::Error),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for EnvelopeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "not a sealed capnez message (bad magic)"),
+            Self::UnsupportedVersion(version) => write!(f, "unsupported envelope version {} (this build reads {})", version, VERSION),
+            Self::LengthMismatch { expected, actual } => write!(f, "expected {} bytes, found {}: the message is truncated or has trailing data", expected, actual),
+            Self::ChecksumMismatch { expected, actual } => write!(f, "checksum mismatch (expected {:#010x}, computed {:#010x}): the message is corrupt", expected, actual),
+            Self::Capnp(e) => write!(f, "{}", e),
+            Self::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for EnvelopeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Capnp(e) => Some(e),
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<capnp::Error> for EnvelopeError {
+    fn from(e: capnp::Error) -> Self {
+        Self::Capnp(e)
+    }
+}
+
+impl From<std::io::Error> for EnvelopeError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// Puts `payload`, a serialized message, behind the envelope header.
+pub fn seal(payload: &[u8]) -> Vec<u8> {
+    let mut sealed = Vec::with_capacity(HEADER_LEN + payload.len());
+    sealed.extend_from_slice(MAGIC);
+    sealed.extend_from_slice(&VERSION.to_le_bytes());
+    sealed.extend_from_slice(&[0; 2]);
+    sealed.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    sealed.extend_from_slice(&crc32c::crc32c(payload).to_le_bytes());
+    sealed.extend_from_slice(&[0; 4]);
+    sealed.extend_from_slice(payload);
+    sealed
+}
+
+/// Checks the envelope of `sealed`, returning the payload it holds.
+pub fn verify(sealed: &[u8]) -> Result<&[u8], EnvelopeError> {
+    if sealed.len() < MAGIC.len() || &sealed[..MAGIC.len()] != MAGIC {
+        return Err(EnvelopeError::BadMagic);
+    }
+    if sealed.len() < HEADER_LEN {
+        return Err(EnvelopeError::LengthMismatch { expected: HEADER_LEN as u64, actual: sealed.len() as u64 });
+    }
+    let (header, payload) = sealed.split_at(HEADER_LEN);
+    let version = u16::from_le_bytes([header[4], header[5]]);
+    if version != VERSION {
+        return Err(EnvelopeError::UnsupportedVersion(version));
+    }
+    let len = u64::from_le_bytes(header[8..16].try_into().unwrap());
+    if len != payload.len() as u64 {
+        return Err(EnvelopeError::LengthMismatch { expected: (HEADER_LEN as u64).saturating_add(len), actual: sealed.len() as u64 });
+    }
+    let expected = u32::from_le_bytes(header[16..20].try_into().unwrap());
+    let actual = crc32c::crc32c(payload);
+    if expected != actual {
+        return Err(EnvelopeError::ChecksumMismatch { expected, actual });
+    }
+    Ok(payload)
+}
+
+/// Writes `message` to the file at `path`, sealed.
+#[cfg(feature = "io")]
+pub fn write_file_checked<A: capnp::message::Allocator>(
+    path: impl AsRef<std::path::Path>,
+    message: &capnp::message::Builder<A>,
+) -> Result<(), EnvelopeError> {
+    std::fs::write(path, seal(&capnp::serialize::write_message_to_words(message)))?;
+    Ok(())
+}
+
+/// Reads a message written by [`write_file_checked`], verifying it first.
+#[cfg(feature = "io")]
+pub fn read_file_checked(
+    path: impl AsRef<std::path::Path>,
+    options: capnp::message::ReaderOptions,
+) -> Result<capnp::message::Reader<capnp::serialize::OwnedSegments>, EnvelopeError> {
+    let sealed = std::fs::read(path)?;
+    Ok(capnp::serialize::read_message(&mut verify(&sealed)?, options)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload() -> Vec<u8> {
+        let mut message = capnp::message::Builder::new_default();
+        message.set_root(capnp::text::Reader::from("sealed contents")).unwrap();
+        capnp::serialize::write_message_to_words(&message)
+    }
+
+    #[test]
+    fn verify_returns_the_sealed_payload() {
+        let payload = payload();
+        let sealed = seal(&payload);
+        assert_eq!(sealed.len(), HEADER_LEN + payload.len());
+        assert_eq!(verify(&sealed).unwrap(), payload.as_slice());
+    }
+
+    #[test]
+    fn a_flipped_payload_byte_fails_the_checksum() {
+        let payload = payload();
+        let mut sealed = seal(&payload);
+        sealed[HEADER_LEN + 9] ^= 0x40;
+        let expected = crc32c::crc32c(&payload);
+        let actual = crc32c::crc32c(&sealed[HEADER_LEN..]);
+        assert!(
+            matches!(verify(&sealed), Err(EnvelopeError::ChecksumMismatch { expected: e, actual: a }) if e == expected && a == actual),
+            "{:?}", verify(&sealed)
+        );
+    }
+
+    #[test]
+    fn a_flipped_checksum_byte_fails_the_checksum() {
+        let mut sealed = seal(&payload());
+        sealed[17] ^= 0x01;
+        assert!(matches!(verify(&sealed), Err(EnvelopeError::ChecksumMismatch { .. })), "{:?}", verify(&sealed));
+    }
+
+    #[test]
+    fn truncation_is_a_length_mismatch() {
+        let sealed = seal(&payload());
+        let full = sealed.len() as u64;
+        for cut in [1, 8, sealed.len() - HEADER_LEN] {
+            let truncated = &sealed[..sealed.len() - cut];
+            assert!(
+                matches!(verify(truncated), Err(EnvelopeError::LengthMismatch { expected, actual }) if expected == full && actual == full - cut as u64),
+                "cut {}: {:?}", cut, verify(truncated)
+            );
+        }
+        // Cut inside the header, past the magic
+        assert!(matches!(verify(&sealed[..10]), Err(EnvelopeError::LengthMismatch { expected: 24, actual: 10 })), "{:?}", verify(&sealed[..10]));
+    }
+
+    #[test]
+    fn trailing_data_is_a_length_mismatch() {
+        let mut sealed = seal(&payload());
+        let full = sealed.len() as u64;
+        sealed.extend_from_slice(&[0; 8]);
+        assert!(
+            matches!(verify(&sealed), Err(EnvelopeError::LengthMismatch { expected, actual }) if expected == full && actual == full + 8),
+            "{:?}", verify(&sealed)
+        );
+    }
+
+    #[test]
+    fn a_newer_version_is_unsupported() {
+        let mut sealed = seal(&payload());
+        sealed[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(matches!(verify(&sealed), Err(EnvelopeError::UnsupportedVersion(v)) if v == VERSION + 1), "{:?}", verify(&sealed));
+    }
+
+    #[test]
+    fn unsealed_bytes_have_bad_magic() {
+        let payload = payload();
+        assert!(matches!(verify(&payload), Err(EnvelopeError::BadMagic)));
+        assert!(matches!(verify(&[]), Err(EnvelopeError::BadMagic)));
+        assert!(matches!(verify(b"CPN"), Err(EnvelopeError::BadMagic)));
+
+        let mut sealed = seal(&payload);
+        sealed[0] = b'X';
+        assert!(matches!(verify(&sealed), Err(EnvelopeError::BadMagic)));
+    }
+
+    #[cfg(feature = "io")]
+    #[test]
+    fn files_are_checked_on_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("message.bin");
+        let mut message = capnp::message::Builder::new_default();
+        message.set_root(capnp::text::Reader::from("sealed contents")).unwrap();
+        write_file_checked(&path, &message).unwrap();
+
+        let read = read_file_checked(&path, capnp::message::ReaderOptions::new()).unwrap();
+        assert_eq!(read.get_root::<capnp::text::Reader>().unwrap().to_str().unwrap(), "sealed contents");
+
+        let len = std::fs::metadata(&path).unwrap().len();
+        std::fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(len - 4).unwrap();
+        let truncated = read_file_checked(&path, capnp::message::ReaderOptions::new());
+        assert!(matches!(truncated, Err(EnvelopeError::LengthMismatch { expected, actual }) if expected == len && actual == len - 4));
+    }
+}
@@ -0,0 +1,63 @@
+//! Serde codecs for types stored as opaque bytes.
+//!
+//! A field whose type derives `Serialize` but is not `#[capnp]` becomes `List(UInt8)` in the schema,
+//! and `capnez-codegen` generates `set_<field>_serde`/`get_<field>_serde` accessors that go through
+//! one of these codecs: [`Json`] unless the field says `#[capnp(serde_with = "bincode")]` or
+//! `#[capnp(serde_with = "postcard")]`.
+
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Encodes values to bytes and back, reporting failures as `capnp::Error`s.
+pub trait SerdeCodec {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> capnp::Result<Vec<u8>>;
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> capnp::Result<T>;
+}
+
+fn failed(codec: &str, action: &str, e: impl std::fmt::Display) -> capnp::Error {
+    capnp::Error::failed(format!("{} {} failed: {}", codec, action, e))
+}
+
+/// `serde_json`, the default; readable on the wire but the largest.
+#[cfg(feature = "json")]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl SerdeCodec for Json {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> capnp::Result<Vec<u8>> {
+        serde_json::to_vec(value).map_err(|e| failed("json", "encoding", e))
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> capnp::Result<T> {
+        serde_json::from_slice(bytes).map_err(|e| failed("json", "decoding", e))
+    }
+}
+
+/// `bincode` 1.x with its default configuration.
+#[cfg(feature = "bincode")]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl SerdeCodec for Bincode {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> capnp::Result<Vec<u8>> {
+        bincode::serialize(value).map_err(|e| failed("bincode", "encoding", e))
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> capnp::Result<T> {
+        bincode::deserialize(bytes).map_err(|e| failed("bincode", "decoding", e))
+    }
+}
+
+/// `postcard`, the most compact of the three.
+#[cfg(feature = "postcard")]
+pub struct Postcard;
+
+#[cfg(feature = "postcard")]
+impl SerdeCodec for Postcard {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> capnp::Result<Vec<u8>> {
+        postcard::to_allocvec(value).map_err(|e| failed("postcard", "encoding", e))
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> capnp::Result<T> {
+        postcard::from_bytes(bytes).map_err(|e| failed("postcard", "decoding", e))
+    }
+}
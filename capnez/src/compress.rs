@@ -0,0 +1,261 @@
+//! Compressed framing for serialized messages, for payloads that compress well beyond what packing
+//! achieves, such as large numeric matrices.
+//!
+//! Every frame starts with a 16-byte header: the magic `CPZC`, the codec, a flag for packed payloads,
+//! two zero bytes and the compressed length as a little-endian `u64`. [`read_message_compressed`]
+//! picks the decompressor from the header, so readers need not know how a message was written, only
+//! have the matching feature enabled. Frames can follow each other on one stream.
+//!
+//! ```ignore
+//! write_packed_message_compressed(&mut file, &message, Codec::Zstd { level: 3 })?;
+//! let message = read_message_compressed(BufReader::new(file), ReaderOptions::new())?;
+//! ```
+
+use capnp::message::{Allocator, Builder, Reader, ReaderOptions};
+use capnp::serialize::OwnedSegments;
+use std::io::{Read, Write};
+
+const MAGIC: &[u8; 4] = b"CPZC";
+const HEADER_LEN: usize = 16;
+/// Flag for a payload in the packed encoding.
+const PACKED: u8 = 1;
+
+/// How a frame's payload is compressed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    /// Stored as is, still framed.
+    None,
+    /// zstd at `level`, from 1 (fastest) to 22; 3 is zstd's own default.
+    #[cfg(feature = "compress-zstd")]
+    Zstd { level: i32 },
+    /// LZ4 block compression: less thorough than zstd, but much faster.
+    #[cfg(feature = "compress-lz4")]
+    Lz4,
+}
+
+impl Codec {
+    fn id(self) -> u8 {
+        match self {
+            Codec::None => 0,
+            #[cfg(feature = "compress-zstd")]
+            Codec::Zstd { .. } => 1,
+            #[cfg(feature = "compress-lz4")]
+            Codec::Lz4 => 2,
+        }
+    }
+
+    fn compress(self, bytes: Vec<u8>) -> capnp::Result<Vec<u8>> {
+        match self {
+            Codec::None => Ok(bytes),
+            #[cfg(feature = "compress-zstd")]
+            Codec::Zstd { level } => Ok(zstd::bulk::compress(&bytes, level)?),
+            #[cfg(feature = "compress-lz4")]
+            Codec::Lz4 => Ok(lz4_flex::compress_prepend_size(&bytes)),
+        }
+    }
+}
+
+fn decompress(id: u8, bytes: Vec<u8>) -> capnp::Result<Vec<u8>> {
+    match id {
+        0 => Ok(bytes),
+        #[cfg(feature = "compress-zstd")]
+        1 => Ok(zstd::stream::decode_all(bytes.as_slice())?),
+        #[cfg(feature = "compress-lz4")]
+        2 => lz4_flex::decompress_size_prepended(&bytes).map_err(|e| capnp::Error::failed(format!("lz4 decompression failed: {}", e))),
+        #[cfg(not(feature = "compress-zstd"))]
+        1 => Err(not_compiled_in("zstd", "compress-zstd")),
+        #[cfg(not(feature = "compress-lz4"))]
+        2 => Err(not_compiled_in("lz4", "compress-lz4")),
+        other => Err(capnp::Error::failed(format!("unknown compression codec {}", other))),
+    }
+}
+
+#[cfg(not(all(feature = "compress-zstd", feature = "compress-lz4")))]
+fn not_compiled_in(codec: &str, feature: &str) -> capnp::Error {
+    capnp::Error::failed(format!("the message is {}-compressed; enable capnez's `{}` feature to read it", codec, feature))
+}
+
+/// Writes `message` as one frame, compressed with `codec`.
+pub fn write_message_compressed<W: Write, A: Allocator>(writer: W, message: &Builder<A>, codec: Codec) -> capnp::Result<()> {
+    write_frame(writer, capnp::serialize::write_message_to_words(message), codec, 0)
+}
+
+/// Like [`write_message_compressed`], compressing the packed encoding of `message`. Packing first
+/// drops the zero bytes cheaply, which leaves less for the codec to do.
+pub fn write_packed_message_compressed<W: Write, A: Allocator>(writer: W, message: &Builder<A>, codec: Codec) -> capnp::Result<()> {
+    let mut packed = Vec::new();
+    capnp::serialize_packed::write_message(&mut packed, message)?;
+    write_frame(writer, packed, codec, PACKED)
+}
+
+fn write_frame<W: Write>(mut writer: W, bytes: Vec<u8>, codec: Codec, flags: u8) -> capnp::Result<()> {
+    let body = codec.compress(bytes)?;
+    let mut header = [0; HEADER_LEN];
+    header[..4].copy_from_slice(MAGIC);
+    header[4] = codec.id();
+    header[5] = flags;
+    header[8..].copy_from_slice(&(body.len() as u64).to_le_bytes());
+    writer.write_all(&header)?;
+    writer.write_all(&body)?;
+    Ok(())
+}
+
+/// Reads one frame written by [`write_message_compressed`] or [`write_packed_message_compressed`],
+/// with whichever codec it names.
+pub fn read_message_compressed<R: Read>(mut reader: R, options: ReaderOptions) -> capnp::Result<Reader<OwnedSegments>> {
+    let mut header = [0; HEADER_LEN];
+    reader.read_exact(&mut header)?;
+    if &header[..4] != MAGIC {
+        return Err(capnp::Error::failed("not a compressed capnez message (bad magic)".to_string()));
+    }
+    let len = u64::from_le_bytes(header[8..].try_into().unwrap());
+    let mut body = Vec::new();
+    reader.take(len).read_to_end(&mut body)?;
+    if body.len() as u64 != len {
+        return Err(capnp::Error::failed(format!("compressed message truncated: expected {} bytes, found {}", len, body.len())));
+    }
+    let bytes = decompress(header[4], body)?;
+    if header[5] & PACKED != 0 {
+        capnp::serialize_packed::read_message(bytes.as_slice(), options)
+    } else {
+        capnp::serialize::read_message(&mut bytes.as_slice(), options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use capnp::message::HeapAllocator;
+
+    /// A message of `len` readings that repeat every 16, like a sensor stuck in a loop.
+    fn readings(len: u32) -> Builder<HeapAllocator> {
+        let mut message = Builder::new_default();
+        let mut list = message.initn_root::<capnp::primitive_list::Builder<u64>>(len);
+        for i in 0..len {
+            list.set(i, 1_000_000 + u64::from(i % 16));
+        }
+        message
+    }
+
+    fn read_readings(message: &Reader<OwnedSegments>) -> Vec<u64> {
+        message.get_root::<capnp::primitive_list::Reader<u64>>().unwrap().iter().collect()
+    }
+
+    fn unpacked_len(message: &Builder<HeapAllocator>) -> usize {
+        capnp::serialize::write_message_to_words(message).len()
+    }
+
+    /// The compressed length recorded in the frame at the start of `bytes`.
+    fn body_len(bytes: &[u8]) -> usize {
+        u64::from_le_bytes(bytes[8..HEADER_LEN].try_into().unwrap()) as usize
+    }
+
+    fn round_trip(codec: Codec) {
+        let message = readings(10_000);
+        let expected = (0..10_000).map(|i| 1_000_000 + u64::from(i % 16)).collect::<Vec<_>>();
+        for packed in [false, true] {
+            let mut bytes = Vec::new();
+            if packed {
+                write_packed_message_compressed(&mut bytes, &message, codec).unwrap();
+            } else {
+                write_message_compressed(&mut bytes, &message, codec).unwrap();
+            }
+            assert_eq!(bytes[4], codec.id());
+            assert_eq!(bytes[5], if packed { PACKED } else { 0 });
+            assert_eq!(bytes.len(), HEADER_LEN + body_len(&bytes));
+            let read = read_message_compressed(bytes.as_slice(), ReaderOptions::new()).unwrap();
+            assert_eq!(read_readings(&read), expected, "{:?}, packed: {}", codec, packed);
+        }
+    }
+
+    #[test]
+    fn uncompressed_round_trip() {
+        round_trip(Codec::None);
+    }
+
+    #[cfg(feature = "compress-zstd")]
+    #[test]
+    fn zstd_round_trip() {
+        round_trip(Codec::Zstd { level: 3 });
+    }
+
+    #[cfg(feature = "compress-lz4")]
+    #[test]
+    fn lz4_round_trip() {
+        round_trip(Codec::Lz4);
+    }
+
+    #[test]
+    fn packing_runs_before_the_codec() {
+        let message = readings(10_000);
+        let mut plain = Vec::new();
+        write_message_compressed(&mut plain, &message, Codec::None).unwrap();
+        let mut packed = Vec::new();
+        write_packed_message_compressed(&mut packed, &message, Codec::None).unwrap();
+        assert_eq!(body_len(&plain), unpacked_len(&message));
+        assert!(body_len(&packed) < body_len(&plain), "{} vs {}", body_len(&packed), body_len(&plain));
+    }
+
+    #[cfg(any(feature = "compress-zstd", feature = "compress-lz4"))]
+    #[test]
+    fn a_repetitive_message_shrinks() {
+        let message = readings(100_000);
+        let unpacked = unpacked_len(&message);
+        let codecs = [
+            #[cfg(feature = "compress-zstd")]
+            Codec::Zstd { level: 3 },
+            #[cfg(feature = "compress-lz4")]
+            Codec::Lz4,
+        ];
+        for codec in codecs {
+            let mut bytes = Vec::new();
+            write_packed_message_compressed(&mut bytes, &message, codec).unwrap();
+            assert!(body_len(&bytes) * 10 < unpacked, "{:?} left {} of {} bytes", codec, body_len(&bytes), unpacked);
+        }
+    }
+
+    #[cfg(all(feature = "compress-zstd", feature = "compress-lz4"))]
+    #[test]
+    fn frames_of_different_codecs_read_back_from_one_stream() {
+        let codecs = [Codec::Zstd { level: 19 }, Codec::Lz4, Codec::None, Codec::Zstd { level: 1 }];
+        let mut stream = Vec::new();
+        for (i, codec) in codecs.iter().enumerate() {
+            let message = readings(100 * (i as u32 + 1));
+            if i % 2 == 0 {
+                write_packed_message_compressed(&mut stream, &message, *codec).unwrap();
+            } else {
+                write_message_compressed(&mut stream, &message, *codec).unwrap();
+            }
+        }
+
+        let mut reader = stream.as_slice();
+        for i in 0..codecs.len() {
+            let message = read_message_compressed(&mut reader, ReaderOptions::new()).unwrap();
+            assert_eq!(read_readings(&message).len(), 100 * (i + 1));
+        }
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn bad_magic_and_truncation_are_errors() {
+        let mut bytes = Vec::new();
+        write_message_compressed(&mut bytes, &readings(100), Codec::None).unwrap();
+
+        let mut wrong = bytes.clone();
+        wrong[0] = b'X';
+        let err = read_message_compressed(wrong.as_slice(), ReaderOptions::new()).err().unwrap();
+        assert!(err.to_string().contains("bad magic"), "{}", err);
+
+        let err = read_message_compressed(&bytes[..bytes.len() - 8], ReaderOptions::new()).err().unwrap();
+        assert!(err.to_string().contains("truncated"), "{}", err);
+    }
+
+    #[test]
+    fn an_unknown_codec_is_an_error() {
+        let mut bytes = Vec::new();
+        write_message_compressed(&mut bytes, &readings(100), Codec::None).unwrap();
+        bytes[4] = 9;
+        let err = read_message_compressed(bytes.as_slice(), ReaderOptions::new()).err().unwrap();
+        assert!(err.to_string().contains("unknown compression codec 9"), "{}", err);
+    }
+}
@@ -0,0 +1,102 @@
+//! Schema-driven rendering of messages through capnp's dynamic reflection.
+//!
+//! Backs the `to_capnp_json` helpers that `capnez-codegen` generates, but works on any reader:
+//! `capnez::dynamic::to_json(reader.into())`.
+
+use capnp::dynamic_value;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+/// Renders a value as JSON, following the conventions of capnp's own JSON codec: 64-bit integers
+/// and non-finite floats are strings, `Data` is an array of bytes, enums are their enumerant names,
+/// and a struct lists its non-union fields plus the active union member.
+pub fn to_json(value: dynamic_value::Reader<'_>) -> capnp::Result<String> {
+    let mut out = String::new();
+    write_value(&mut out, value)?;
+    Ok(out)
+}
+
+fn write_value(out: &mut String, value: dynamic_value::Reader<'_>) -> capnp::Result<()> {
+    match value {
+        dynamic_value::Reader::Void => out.push_str("null"),
+        dynamic_value::Reader::Bool(b) => out.push_str(if b { "true" } else { "false" }),
+        dynamic_value::Reader::Int8(n) => push(out, n),
+        dynamic_value::Reader::Int16(n) => push(out, n),
+        dynamic_value::Reader::Int32(n) => push(out, n),
+        dynamic_value::Reader::Int64(n) => write_string(out, &n.to_string()),
+        dynamic_value::Reader::UInt8(n) => push(out, n),
+        dynamic_value::Reader::UInt16(n) => push(out, n),
+        dynamic_value::Reader::UInt32(n) => push(out, n),
+        dynamic_value::Reader::UInt64(n) => write_string(out, &n.to_string()),
+        dynamic_value::Reader::Float32(n) => write_float(out, n as f64),
+        dynamic_value::Reader::Float64(n) => write_float(out, n),
+        dynamic_value::Reader::Enum(e) => match e.get_enumerant()? {
+            Some(enumerant) => write_string(out, enumerant.get_proto().get_name()?.to_str()?),
+            None => push(out, e.get_value()),
+        },
+        dynamic_value::Reader::Text(t) => write_string(out, t.to_str()?),
+        dynamic_value::Reader::Data(d) => {
+            out.push('[');
+            for (i, byte) in d.iter().enumerate() {
+                if i > 0 { out.push(','); }
+                push(out, byte);
+            }
+            out.push(']');
+        }
+        dynamic_value::Reader::List(list) => {
+            out.push('[');
+            for i in 0..list.len() {
+                if i > 0 { out.push(','); }
+                write_value(out, list.get(i)?)?;
+            }
+            out.push(']');
+        }
+        dynamic_value::Reader::Struct(s) => {
+            let mut fields = s.get_schema().get_non_union_fields()?.iter().collect::<Vec<_>>();
+            if let Some(active) = s.which()? {
+                fields.push(active);
+            }
+            out.push('{');
+            for (i, field) in fields.into_iter().enumerate() {
+                if i > 0 { out.push(','); }
+                write_string(out, field.get_proto().get_name()?.to_str()?);
+                out.push(':');
+                write_value(out, s.get(field)?)?;
+            }
+            out.push('}');
+        }
+        dynamic_value::Reader::AnyPointer(_) | dynamic_value::Reader::Capability(_) => out.push_str("null"),
+    }
+    Ok(())
+}
+
+fn push(out: &mut String, n: impl core::fmt::Display) {
+    let _ = write!(out, "{}", n);
+}
+
+fn write_float(out: &mut String, n: f64) {
+    if n.is_nan() {
+        out.push_str("\"NaN\"");
+    } else if n.is_infinite() {
+        out.push_str(if n > 0.0 { "\"Infinity\"" } else { "\"-Infinity\"" });
+    } else {
+        push(out, n);
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => { let _ = write!(out, "\\u{:04x}", c as u32); }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
@@ -0,0 +1,543 @@
+//! An append-only file of messages with random access by index, e.g. an event log of batches.
+//!
+//! Layout: the magic `CAPNZLG1`, then one record per message: a 16-byte header holding the message's
+//! length in bytes as a little-endian `u64`, the CRC-32C of that length and the message as a
+//! little-endian `u32` and four zero bytes, followed by the message in the standard stream framing.
+//! Messages are a whole, nonzero number of words, so every record starts 8-byte aligned.
+//!
+//! Next to the log, `<path>.idx` holds the offset of every record as a little-endian `u64`, so opening
+//! a log reads the index and checks only the records appended after its last entry, and reading the
+//! N-th message is a single seek. A log without an index, or with one that does not fit it, is opened
+//! by walking every record header instead, and the next writer rewrites the index. A crash mid-append
+//! leaves a final record that is cut short or fails its checksum. Readers ignore it, and the next
+//! writer to open the log cuts it off, in both cases reporting it through
+//! [`MessageLogReader::torn_tail`] and, with the `tracing` feature, a warning. Damage to an earlier
+//! record fails only the reads of that record, unless it hits a header the walk needs: the bytes from
+//! there count as a torn tail only if no intact record follows them, and otherwise the log fails to
+//! open rather than have the writer cut off the records after the damage.
+
+use capnp::message::{Allocator, Builder, Reader, ReaderOptions};
+use capnp::serialize::OwnedSegments;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const MAGIC: &[u8; 8] = b"CAPNZLG1";
+const HEADER_LEN: u64 = 16;
+
+/// The end of a log that was cut off mid-append and is left out of it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TornTail {
+    /// Where the damaged record starts, which is where the intact log ends.
+    pub offset: u64,
+    /// Bytes from there to the end of the file.
+    pub len: u64,
+}
+
+/// Appends messages to a log file.
+///
+/// ```no_run
+/// # fn demo(batch: &capnp::message::Builder<capnp::message::HeapAllocator>) -> capnp::Result<()> {
+/// let mut log = capnez::io::MessageLogWriter::open("events.log")?;
+/// let index = log.append(batch)?;
+/// log.sync()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct MessageLogWriter {
+    file: File,
+    index: File,
+    len: u64,
+    end: u64,
+}
+
+impl MessageLogWriter {
+    /// Creates an empty log at `path`, replacing any file there.
+    pub fn create(path: impl AsRef<Path>) -> capnp::Result<Self> {
+        let path = path.as_ref();
+        let mut file = File::create(path)?;
+        file.write_all(MAGIC)?;
+        file.sync_all()?;
+        let index = File::create(index_path(path))?;
+        index.sync_all()?;
+        Ok(Self { file, index, len: 0, end: MAGIC.len() as u64 })
+    }
+
+    /// Opens the log at `path` for appending, creating it if there is none. A torn final record is
+    /// cut off first, and the index rewritten if it does not match the log.
+    pub fn open(path: impl AsRef<Path>) -> capnp::Result<Self> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Self::create(path);
+        }
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        let scan = scan(&mut file, path)?;
+        if let Some(torn) = &scan.torn {
+            warn_torn(path, torn);
+            file.set_len(torn.offset)?;
+            file.sync_all()?;
+        }
+        file.seek(SeekFrom::Start(scan.end))?;
+
+        let mut index = OpenOptions::new().create(true).read(true).write(true).truncate(false).open(index_path(path))?;
+        // A trailing partial entry is not among the records read, so compare the size as well
+        if !scan.indexed || index.metadata()?.len() != scan.records.len() as u64 * 8 {
+            index.set_len(0)?;
+            index.seek(SeekFrom::Start(0))?;
+            index.write_all(&scan.records.iter().flat_map(|offset| offset.to_le_bytes()).collect::<Vec<_>>())?;
+            index.sync_all()?;
+        }
+        index.seek(SeekFrom::End(0))?;
+        Ok(Self { file, index, len: scan.records.len() as u64, end: scan.end })
+    }
+
+    /// Appends `message`, returning its index in the log. It is durable once [`sync`](Self::sync) returns.
+    pub fn append<A: Allocator>(&mut self, message: &Builder<A>) -> capnp::Result<u64> {
+        let mut record = vec![0; HEADER_LEN as usize];
+        capnp::serialize::write_message(&mut record, message)?;
+        let (header, payload) = record.split_at_mut(HEADER_LEN as usize);
+        let len = (payload.len() as u64).to_le_bytes();
+        header[..8].copy_from_slice(&len);
+        header[8..12].copy_from_slice(&checksum(&len, payload).to_le_bytes());
+
+        let written = self.file.write_all(&record).and_then(|()| self.index.write_all(&self.end.to_le_bytes()));
+        if let Err(e) = written {
+            // Drop whatever part of the record and its index entry made it, so later appends do not
+            // land behind them
+            let _ = self.file.set_len(self.end).and_then(|()| self.file.seek(SeekFrom::Start(self.end)));
+            let _ = self.index.set_len(self.len * 8).and_then(|()| self.index.seek(SeekFrom::End(0)));
+            return Err(e.into());
+        }
+        self.end += record.len() as u64;
+        self.len += 1;
+        Ok(self.len - 1)
+    }
+
+    /// Number of messages in the log.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Flushes the appended messages to disk, then their index entries.
+    pub fn sync(&mut self) -> capnp::Result<()> {
+        self.file.sync_data()?;
+        self.index.sync_data()?;
+        Ok(())
+    }
+}
+
+/// Reads the messages of a log file by index.
+///
+/// The records are located when the log is opened; messages appended later are not seen until it is
+/// opened again.
+pub struct MessageLogReader {
+    file: Mutex<File>,
+    path: PathBuf,
+    /// Offset of each record.
+    records: Vec<u64>,
+    /// End of the last record.
+    end: u64,
+    torn: Option<TornTail>,
+}
+
+impl MessageLogReader {
+    pub fn open(path: impl AsRef<Path>) -> capnp::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = File::open(&path)?;
+        let scan = scan(&mut file, &path)?;
+        if let Some(torn) = &scan.torn {
+            warn_torn(&path, torn);
+        }
+        Ok(Self { file: Mutex::new(file), path, records: scan.records, end: scan.end, torn: scan.torn })
+    }
+
+    /// Number of intact messages in the log.
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// The damaged final record left out of the log, if there was one.
+    pub fn torn_tail(&self) -> Option<&TornTail> {
+        self.torn.as_ref()
+    }
+
+    /// Reads the message at `index`, checking it against its checksum.
+    pub fn get(&self, index: usize, options: ReaderOptions) -> capnp::Result<Reader<OwnedSegments>> {
+        let &offset = self.records.get(index).ok_or_else(|| {
+            capnp::Error::failed(format!("{} holds {} messages, there is no message {}", self.path.display(), self.records.len(), index))
+        })?;
+        let next = self.records.get(index + 1).copied().unwrap_or(self.end);
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        match read_record(&mut file, offset, next)? {
+            Some(bytes) => capnp::serialize::read_message(&mut bytes.as_slice(), options),
+            None => Err(capnp::Error::failed(format!(
+                "message {} of {} (at byte {}) does not match its length or checksum",
+                index, self.path.display(), offset
+            ))),
+        }
+    }
+
+    /// Reads every message in append order.
+    pub fn iter(&self, options: ReaderOptions) -> impl Iterator<Item = capnp::Result<Reader<OwnedSegments>>> + '_ {
+        (0..self.len()).map(move |index| self.get(index, options))
+    }
+}
+
+struct Scan {
+    records: Vec<u64>,
+    /// End of the last intact record.
+    end: u64,
+    torn: Option<TornTail>,
+    /// Whether the index file holds exactly `records`.
+    indexed: bool,
+}
+
+fn scan(file: &mut File, path: &Path) -> capnp::Result<Scan> {
+    let file_len = file.metadata()?.len();
+    let mut magic = [0; MAGIC.len()];
+    file.seek(SeekFrom::Start(0))?;
+    if file_len < MAGIC.len() as u64 || file.read_exact(&mut magic).is_err() || &magic != MAGIC {
+        return Err(capnp::Error::failed(format!("{} is not a message log", path.display())));
+    }
+
+    let index = read_index(path)?;
+    let mut records = index.clone();
+    let mut end = MAGIC.len() as u64;
+    // Trust the index only if its offsets climb from the first record and its last entry is a record
+    // that fits the file; otherwise walk the log from the start
+    let climbs = records.first() == Some(&end) && records.windows(2).all(|pair| pair[0].checked_add(HEADER_LEN).is_some_and(|min| min < pair[1]));
+    match records.last() {
+        Some(&last) if climbs => match record_len(file, last, file_len)? {
+            Some(len) => end = last + HEADER_LEN + len,
+            None => records.clear(),
+        },
+        _ => records.clear(),
+    }
+    // Records appended after the last index entry made it to disk
+    while let Some(len) = record_len(file, end, file_len)? {
+        records.push(end);
+        end += HEADER_LEN + len;
+    }
+    // A crash can leave the file extended before the record's bytes reach it, so the length alone does
+    // not prove the last record whole
+    if let Some(&offset) = records.last() {
+        if read_record(file, offset, end)?.is_none() {
+            records.pop();
+            end = offset;
+        }
+    }
+    if let Some(next) = next_intact_record(file, end, file_len)? {
+        return Err(capnp::Error::failed(format!(
+            "{} has a damaged record at byte {} followed by intact records from byte {}; it needs repair before it can be opened",
+            path.display(), end, next
+        )));
+    }
+    let torn = (end < file_len).then(|| TornTail { offset: end, len: file_len - end });
+    let indexed = index == records;
+    Ok(Scan { records, end, torn, indexed })
+}
+
+fn index_path(path: &Path) -> PathBuf {
+    let mut index = path.as_os_str().to_owned();
+    index.push(".idx");
+    PathBuf::from(index)
+}
+
+/// The record offsets in the index next to the log at `path`; none if there is no index, and a
+/// trailing partial entry is left out.
+fn read_index(path: &Path) -> io::Result<Vec<u64>> {
+    match std::fs::read(index_path(path)) {
+        Ok(bytes) => Ok(bytes.chunks_exact(8).map(|entry| u64::from_le_bytes(entry.try_into().unwrap())).collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// The message length in the record header at `offset`, if there is a header and it describes a
+/// nonzero, word-aligned message that ends within `file_len`.
+fn record_len(file: &mut File, offset: u64, file_len: u64) -> io::Result<Option<u64>> {
+    if file_len.saturating_sub(offset) < HEADER_LEN {
+        return Ok(None);
+    }
+    let mut header = [0; HEADER_LEN as usize];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut header)?;
+    let len = u64::from_le_bytes(header[..8].try_into().unwrap());
+    let fits = len != 0 && len.is_multiple_of(8) && len <= file_len - offset - HEADER_LEN;
+    Ok(fits.then_some(len))
+}
+
+/// The message of the record at `offset`, which must end exactly at `end`, or `None` if its length
+/// does not or it fails its checksum.
+fn read_record(file: &mut File, offset: u64, end: u64) -> io::Result<Option<Vec<u8>>> {
+    let mut header = [0; HEADER_LEN as usize];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut header)?;
+    let len = u64::from_le_bytes(header[..8].try_into().unwrap());
+    if len == 0 || !len.is_multiple_of(8) || len.checked_add(HEADER_LEN).and_then(|n| offset.checked_add(n)) != Some(end) {
+        return Ok(None);
+    }
+    let mut bytes = vec![0; len as usize];
+    file.read_exact(&mut bytes)?;
+    let crc = u32::from_le_bytes(header[8..12].try_into().unwrap());
+    Ok((checksum(&header[..8], &bytes) == crc).then_some(bytes))
+}
+
+/// Offset of the first record past `from` that passes its checksum, trying every word boundary. Only
+/// called on the bytes after the last record found, which are normally a single torn append.
+fn next_intact_record(file: &mut File, from: u64, file_len: u64) -> io::Result<Option<u64>> {
+    let mut offset = from + 8;
+    while offset + HEADER_LEN < file_len {
+        if let Some(len) = record_len(file, offset, file_len)? {
+            if read_record(file, offset, offset + HEADER_LEN + len)?.is_some() {
+                return Ok(Some(offset));
+            }
+        }
+        offset += 8;
+    }
+    Ok(None)
+}
+
+/// CRC-32C over a record's length field and message, so a damaged length fails the check too.
+fn checksum(len: &[u8], message: &[u8]) -> u32 {
+    crc32c::crc32c_append(crc32c::crc32c(len), message)
+}
+
+fn warn_torn(path: &Path, torn: &TornTail) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(path = %path.display(), offset = torn.offset, bytes = torn.len, "ignoring a torn record at the end of a message log");
+    #[cfg(not(feature = "tracing"))]
+    let _ = (path, torn);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use capnp::message::HeapAllocator;
+
+    fn text(value: &str) -> Builder<HeapAllocator> {
+        let mut message = Builder::new_default();
+        message.set_root(capnp::text::Reader::from(value)).unwrap();
+        message
+    }
+
+    fn append(path: &Path, values: &[&str]) {
+        let mut log = MessageLogWriter::open(path).unwrap();
+        for value in values {
+            log.append(&text(value)).unwrap();
+        }
+        log.sync().unwrap();
+    }
+
+    fn read(reader: &MessageLogReader, index: usize) -> capnp::Result<String> {
+        let message = reader.get(index, ReaderOptions::new())?;
+        Ok(message.get_root::<capnp::text::Reader>()?.to_str()?.to_string())
+    }
+
+    fn read_all(path: &Path) -> Vec<String> {
+        let reader = MessageLogReader::open(path).unwrap();
+        (0..reader.len()).map(|index| read(&reader, index).unwrap()).collect()
+    }
+
+    fn grow(path: &Path, bytes: &[u8]) {
+        OpenOptions::new().append(true).open(path).unwrap().write_all(bytes).unwrap();
+    }
+
+    #[test]
+    fn appends_after_reopening_continue_the_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.log");
+        append(&path, &["a", "b"]);
+        append(&path, &["c"]);
+
+        let mut log = MessageLogWriter::open(&path).unwrap();
+        assert_eq!(log.len(), 3);
+        assert_eq!(log.append(&text("d")).unwrap(), 3);
+        log.sync().unwrap();
+        drop(log);
+
+        assert_eq!(read_all(&path), ["a", "b", "c", "d"]);
+        assert_eq!(read_index(&path).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn opening_trusts_the_index_instead_of_walking_the_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.log");
+        let values = (0..100).map(|i| i.to_string()).collect::<Vec<_>>();
+        append(&path, &values.iter().map(String::as_str).collect::<Vec<_>>());
+
+        // Zero the header of one record in the middle; a walk would stop there, the index skips it
+        let offsets = read_index(&path).unwrap();
+        let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::Start(offsets[40])).unwrap();
+        file.write_all(&[0; HEADER_LEN as usize]).unwrap();
+        drop(file);
+
+        let reader = MessageLogReader::open(&path).unwrap();
+        assert_eq!(reader.len(), 100);
+        assert_eq!(read(&reader, 73).unwrap(), "73");
+        assert_eq!(read(&reader, 99).unwrap(), "99");
+        assert!(read(&reader, 40).is_err());
+        assert!(reader.torn_tail().is_none());
+    }
+
+    #[test]
+    fn a_missing_index_is_rebuilt_by_the_next_writer() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.log");
+        append(&path, &["a", "b", "c"]);
+        let offsets = read_index(&path).unwrap();
+        std::fs::remove_file(index_path(&path)).unwrap();
+
+        assert_eq!(read_all(&path), ["a", "b", "c"]);
+        append(&path, &["d"]);
+        let rebuilt = read_index(&path).unwrap();
+        assert_eq!(rebuilt.len(), 4);
+        assert_eq!(rebuilt[..3], offsets);
+        assert_eq!(read_all(&path), ["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn records_missing_from_the_index_are_found() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.log");
+        append(&path, &["a", "b", "c"]);
+        // As if the process died after appending the records but before their index entries
+        let offsets = read_index(&path).unwrap();
+        OpenOptions::new().write(true).open(index_path(&path)).unwrap().set_len(8).unwrap();
+
+        assert_eq!(read_all(&path), ["a", "b", "c"]);
+        append(&path, &[]);
+        assert_eq!(read_index(&path).unwrap(), offsets);
+    }
+
+    #[test]
+    fn a_truncated_tail_is_left_out_and_then_cut_off() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.log");
+        append(&path, &["a", "b", "c"]);
+        let offsets = read_index(&path).unwrap();
+        let len = std::fs::metadata(&path).unwrap().len();
+        OpenOptions::new().write(true).open(&path).unwrap().set_len(len - 5).unwrap();
+
+        let reader = MessageLogReader::open(&path).unwrap();
+        assert_eq!(reader.len(), 2);
+        assert_eq!(reader.torn_tail(), Some(&TornTail { offset: offsets[2], len: len - 5 - offsets[2] }));
+        drop(reader);
+
+        append(&path, &["d"]);
+        assert_eq!(read_all(&path), ["a", "b", "d"]);
+        assert!(MessageLogReader::open(&path).unwrap().torn_tail().is_none());
+    }
+
+    #[test]
+    fn a_zero_filled_tail_is_not_a_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.log");
+        append(&path, &["a", "b"]);
+        let len = std::fs::metadata(&path).unwrap().len();
+        // The file grew, but none of the appended bytes reached it
+        grow(&path, &[0; 64]);
+
+        let reader = MessageLogReader::open(&path).unwrap();
+        assert_eq!(reader.len(), 2);
+        assert_eq!(reader.torn_tail(), Some(&TornTail { offset: len, len: 64 }));
+    }
+
+    #[test]
+    fn a_length_that_is_not_whole_words_is_not_a_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.log");
+        append(&path, &["a"]);
+        let len = std::fs::metadata(&path).unwrap().len();
+        let mut header = [0; HEADER_LEN as usize];
+        header[..8].copy_from_slice(&12u64.to_le_bytes());
+        grow(&path, &header);
+        grow(&path, &[1; 16]);
+
+        let reader = MessageLogReader::open(&path).unwrap();
+        assert_eq!(reader.len(), 1);
+        assert_eq!(reader.torn_tail(), Some(&TornTail { offset: len, len: 32 }));
+    }
+
+    #[test]
+    fn the_checksum_covers_the_length() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.log");
+        append(&path, &["a", "b"]);
+
+        // Shorten the final record's length by a word, keeping its checksum
+        let offsets = read_index(&path).unwrap();
+        let mut file = OpenOptions::new().read(true).write(true).open(&path).unwrap();
+        let mut len = [0; 8];
+        file.seek(SeekFrom::Start(offsets[1])).unwrap();
+        file.read_exact(&mut len).unwrap();
+        let shorter = u64::from_le_bytes(len) - 8;
+        file.seek(SeekFrom::Start(offsets[1])).unwrap();
+        file.write_all(&shorter.to_le_bytes()).unwrap();
+        drop(file);
+
+        let reader = MessageLogReader::open(&path).unwrap();
+        assert_eq!(reader.len(), 1);
+        assert_eq!(reader.torn_tail().map(|torn| torn.offset), Some(offsets[1]));
+    }
+
+    #[test]
+    fn a_damaged_record_followed_by_intact_ones_is_not_cut_off() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.log");
+        append(&path, &["a", "b", "c", "d", "e"]);
+        let offsets = read_index(&path).unwrap();
+        std::fs::remove_file(index_path(&path)).unwrap();
+
+        // Flip the top bit of the middle record's length, so a walk of the log stops there
+        let flip = |path: &Path| {
+            let mut file = OpenOptions::new().read(true).write(true).open(path).unwrap();
+            let mut byte = [0; 1];
+            file.seek(SeekFrom::Start(offsets[2] + 7)).unwrap();
+            file.read_exact(&mut byte).unwrap();
+            file.seek(SeekFrom::Start(offsets[2] + 7)).unwrap();
+            file.write_all(&[byte[0] ^ 0x80]).unwrap();
+        };
+        flip(&path);
+        let damaged = std::fs::read(&path).unwrap();
+
+        let err = MessageLogWriter::open(&path).err().expect("opened a log with a damaged middle record");
+        let message = err.to_string();
+        assert!(message.contains(&format!("damaged record at byte {}", offsets[2])), "{}", message);
+        assert!(message.contains(&format!("intact records from byte {}", offsets[3])), "{}", message);
+        assert!(MessageLogReader::open(&path).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), damaged, "the log was changed");
+
+        // Nothing was lost: once repaired, every record is there
+        flip(&path);
+        append(&path, &["f"]);
+        assert_eq!(read_all(&path), ["a", "b", "c", "d", "e", "f"]);
+    }
+
+    #[test]
+    fn a_damaged_final_record_is_still_a_torn_tail() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.log");
+        append(&path, &["a", "b", "c"]);
+        let offsets = read_index(&path).unwrap();
+        std::fs::remove_file(index_path(&path)).unwrap();
+        let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::Start(offsets[2] + 8)).unwrap();
+        file.write_all(&[0; 4]).unwrap();
+        drop(file);
+
+        append(&path, &["d"]);
+        assert_eq!(read_all(&path), ["a", "b", "d"]);
+    }
+}
@@ -0,0 +1,101 @@
+//! Zero-copy reads of serialized messages through a memory map.
+//!
+//! [`read_message_mmap`] maps the file and parses only its segment table; readers obtained from the
+//! returned message point into the mapping, so pages are loaded as they are touched and a spot check
+//! of a multi-gigabyte message reads a few pages of it. The mapping starts on a page boundary and
+//! segments are laid out at 8-byte offsets, so segment data is always word-aligned as capnp requires.
+//!
+//! A mapping reflects later changes to the file. Truncating the file while it is mapped turns reads
+//! of the lost pages into a `SIGBUS`, and writing to it changes what readers already handed out
+//! return, which breaks the bounds capnp checked when it parsed the message. Only map files nothing
+//! else writes to; to update one, write a new file and rename it over the old one, which leaves
+//! existing mappings on the old contents.
+
+use capnp::message::{Reader, ReaderOptions};
+use capnp::serialize::BufferSegments;
+use memmap2::Mmap;
+use std::fs::File;
+use std::path::Path;
+
+/// Segments of a memory-mapped message, owning the mapping.
+pub type MmapSegments = BufferSegments<Mmap>;
+
+/// Maps the single serialized message (as written by `capnp::serialize::write_message`) in the file
+/// at `path`.
+///
+/// # Safety
+///
+/// The file must not be modified or truncated, by this or any other process, while the message is
+/// alive: its readers point straight into the mapping, and a changed file changes them underneath,
+/// which is undefined behavior. Replacing the file by renaming another over it is fine.
+pub unsafe fn read_message_mmap(path: impl AsRef<Path>, options: ReaderOptions) -> capnp::Result<Reader<MmapSegments>> {
+    let path = path.as_ref();
+    let file = File::open(path)?;
+    if file.metadata()?.len() == 0 {
+        return Err(capnp::Error::failed(format!("{} is empty, not a serialized message", path.display())));
+    }
+    let mmap = Mmap::map(&file)?;
+    Ok(Reader::new(BufferSegments::new(mmap, options)?, options))
+}
+
+/// Reader options for a message of `len` bytes: the default traversal limit of 64 MiB, raised for
+/// larger messages to cover reading every word of the message four times.
+///
+/// capnp counts a list's whole size against the limit on every access to the list, so keep list
+/// readers around rather than fetching the list again for each element.
+pub fn sized_options(len: u64) -> ReaderOptions {
+    let default = ReaderOptions::new().traversal_limit_in_words.unwrap_or(usize::MAX);
+    let words = usize::try_from(len / 8).unwrap_or(usize::MAX);
+    let mut options = ReaderOptions::new();
+    options.traversal_limit_in_words(Some(default.max(words.saturating_mul(4))));
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use capnp::message::ReaderSegments;
+
+    #[test]
+    fn every_segment_is_word_aligned() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("message.bin");
+        // Small first segments, so the message spans several
+        let mut message = capnp::message::Builder::new(capnp::message::HeapAllocator::new().first_segment_words(16));
+        let mut list = message.init_root::<capnp::primitive_list::Builder<u64>>(10_000);
+        for i in 0..10_000 {
+            list.set(i, u64::from(i) * 7);
+        }
+        let mut file = File::create(&path).unwrap();
+        capnp::serialize::write_message(&mut file, &message).unwrap();
+        drop(file);
+
+        // SAFETY: the file is private to this test and not written again while mapped
+        let reader = unsafe { read_message_mmap(&path, ReaderOptions::new()) }.unwrap();
+        let list = reader.get_root::<capnp::primitive_list::Reader<u64>>().unwrap();
+        assert_eq!(list.get(9_999), 9_999 * 7);
+        let segments = reader.into_segments();
+        assert!(segments.len() > 1);
+        for i in 0..segments.len() as u32 {
+            assert_eq!(segments.get_segment(i).unwrap().as_ptr() as usize % 8, 0, "segment {}", i);
+        }
+    }
+
+    #[test]
+    fn an_empty_file_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("empty.bin");
+        File::create(&path).unwrap();
+        // SAFETY: as above
+        let err = unsafe { read_message_mmap(&path, ReaderOptions::new()) }.err().expect("an empty file was read");
+        assert!(err.to_string().contains("is empty"), "{}", err);
+    }
+
+    #[test]
+    fn sized_options_only_ever_raise_the_limit() {
+        let default = ReaderOptions::new().traversal_limit_in_words;
+        assert_eq!(sized_options(0).traversal_limit_in_words, default);
+        assert_eq!(sized_options(1 << 20).traversal_limit_in_words, default);
+        assert_eq!(sized_options(1 << 30).traversal_limit_in_words, Some((1 << 30) / 8 * 4));
+    }
+}
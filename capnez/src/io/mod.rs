@@ -0,0 +1,11 @@
+//! File helpers for persisting Cap'n Proto messages.
+
+mod log;
+#[cfg(feature = "mmap")]
+mod mmap;
+mod transaction;
+
+pub use log::{MessageLogReader, MessageLogWriter, TornTail};
+#[cfg(feature = "mmap")]
+pub use mmap::{read_message_mmap, sized_options, MmapSegments};
+pub use transaction::{gc, read_consistent, recover, Snapshot, Transaction};
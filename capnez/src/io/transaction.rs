@@ -0,0 +1,437 @@
+//! All-or-nothing writes of several related messages to one directory.
+//!
+//! Layout of a store directory:
+//!
+//! - `blobs/<sha256>.bin`: one serialized message per file, named by content hash
+//! - `MANIFEST`: the committed set, one `<sha256> <name>` line per message
+//! - `MANIFEST.prev`: the set `MANIFEST` replaced, whose blobs [`gc`] keeps for readers still using it
+//! - `LOCK`: held by the single writer for the lifetime of a [`Transaction`], and by [`gc`]/[`recover`]
+//! - `staging/` and `*.tmp`: leftovers of a transaction that has not committed yet
+//!
+//! A transaction stages and fsyncs its blobs, moves them into `blobs/`, then commits by atomically
+//! renaming `MANIFEST.tmp` over `MANIFEST`. Readers only ever follow `MANIFEST`, so they see either the
+//! previous set or the new one, never a mix. Writers serialize on `LOCK`; readers take no lock.
+
+use capnp::message::{Allocator, Builder, Reader, ReaderOptions};
+use capnp::serialize::OwnedSegments;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const MANIFEST: &str = "MANIFEST";
+const PREVIOUS: &str = "MANIFEST.prev";
+const LOCK: &str = "LOCK";
+const BLOBS: &str = "blobs";
+const STAGING: &str = "staging";
+
+/// A set of named messages that is committed atomically.
+///
+/// ```no_run
+/// # fn demo(manifest: &capnp::message::Builder<capnp::message::HeapAllocator>,
+/// #         index: &capnp::message::Builder<capnp::message::HeapAllocator>) -> capnp::Result<()> {
+/// let mut tx = capnez::io::Transaction::new("state")?;
+/// tx.put("manifest", manifest)?;
+/// tx.put("index", index)?;
+/// tx.commit()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Transaction {
+    dir: PathBuf,
+    puts: BTreeMap<String, Vec<u8>>,
+    /// The writer lock, released when the transaction is committed or dropped.
+    _lock: File,
+    /// Set by tests to stop the commit after a phase, as if the process had been killed there.
+    #[cfg(test)]
+    crash_after: Option<Phase>,
+}
+
+/// The durable steps of [`Transaction::commit`], in order.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Phase {
+    Staged,
+    Moved,
+    PreviousWritten,
+    ManifestTmpWritten,
+}
+
+impl Transaction {
+    /// Opens (creating if needed) the store at `dir`, rolling back any incomplete transaction first.
+    ///
+    /// Blocks while another transaction on the same store is open, including one in this process.
+    pub fn new(dir: impl Into<PathBuf>) -> capnp::Result<Self> {
+        let dir = dir.into();
+        let lock = lock(&dir)?;
+        recover_locked(&dir)?;
+        Ok(Self {
+            dir,
+            puts: BTreeMap::new(),
+            _lock: lock,
+            #[cfg(test)]
+            crash_after: None,
+        })
+    }
+
+    /// Stages `message` under `name`, replacing any earlier put of the same name in this transaction.
+    pub fn put<A: Allocator>(&mut self, name: &str, message: &Builder<A>) -> capnp::Result<()> {
+        if name.is_empty() || name.contains('\n') {
+            return Err(capnp::Error::failed(format!("invalid message name {:?}", name)));
+        }
+        let mut bytes = Vec::new();
+        capnp::serialize::write_message(&mut bytes, message)?;
+        self.puts.insert(name.to_string(), bytes);
+        Ok(())
+    }
+
+    /// Writes every staged message and publishes them together. Names not put in this transaction
+    /// keep their committed contents.
+    pub fn commit(self) -> capnp::Result<()> {
+        let staging = self.dir.join(STAGING);
+        let blobs = self.dir.join(BLOBS);
+        let previous = read_manifest(&self.dir, MANIFEST)?;
+        let mut manifest = previous.clone();
+
+        // Phase 1: write and fsync every new blob in the staging area. Names with identical contents
+        // share one blob, and blobs already in place are immutable, so neither is written twice.
+        let mut staged = BTreeMap::new();
+        for (name, bytes) in &self.puts {
+            let hash = hex_digest(bytes);
+            manifest.insert(name.clone(), hash.clone());
+            if staged.contains_key(&hash) || blobs.join(format!("{}.bin", hash)).exists() {
+                continue;
+            }
+            let path = staging.join(format!("{}.bin", hash));
+            let mut file = File::create(&path)?;
+            file.write_all(bytes)?;
+            file.sync_all()?;
+            staged.insert(hash, path);
+        }
+        sync_dir(&staging)?;
+        if self.crashed_after(Phase::Staged) {
+            return Ok(());
+        }
+
+        // Phase 2: move blobs into place; unreferenced until the manifest swap
+        for (hash, path) in staged {
+            fs::rename(path, blobs.join(format!("{}.bin", hash)))?;
+        }
+        sync_dir(&blobs)?;
+        if self.crashed_after(Phase::Moved) {
+            return Ok(());
+        }
+
+        // Phase 3: record the manifest being replaced, so gc keeps its blobs for readers still on it
+        write_manifest(&self.dir, PREVIOUS, &previous)?;
+        if self.crashed_after(Phase::PreviousWritten) {
+            return Ok(());
+        }
+
+        // Phase 4: the commit point is the rename of the new manifest over the old one
+        let tmp = write_manifest_tmp(&self.dir, MANIFEST, &manifest)?;
+        if self.crashed_after(Phase::ManifestTmpWritten) {
+            return Ok(());
+        }
+        fs::rename(&tmp, self.dir.join(MANIFEST))?;
+        sync_dir(&self.dir)?;
+        Ok(())
+    }
+
+    #[cfg(test)]
+    fn crashed_after(&self, phase: Phase) -> bool {
+        self.crash_after == Some(phase)
+    }
+
+    /// Outside tests every commit runs to the end.
+    #[cfg(not(test))]
+    fn crashed_after(&self, _phase: Phase) -> bool {
+        false
+    }
+}
+
+/// The committed messages of a store as of one manifest read.
+pub struct Snapshot {
+    messages: BTreeMap<String, Vec<u8>>,
+}
+
+impl Snapshot {
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.messages.keys().map(String::as_str)
+    }
+
+    pub fn get(&self, name: &str, options: ReaderOptions) -> capnp::Result<Option<Reader<OwnedSegments>>> {
+        match self.messages.get(name) {
+            Some(bytes) => Ok(Some(capnp::serialize::read_message(&mut bytes.as_slice(), options)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Reads the committed set. Blobs are immutable, so reading them after the manifest stays consistent.
+///
+/// [`gc`] keeps the blobs of the current and the previous manifest. A reader that falls further behind
+/// finds a blob gone; it then rereads the manifest and starts over, failing only if the manifest is
+/// unchanged.
+pub fn read_consistent(dir: impl AsRef<Path>) -> capnp::Result<Snapshot> {
+    let dir = dir.as_ref();
+    let mut manifest = read_manifest(dir, MANIFEST)?;
+    loop {
+        match read_blobs(dir, &manifest) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let current = read_manifest(dir, MANIFEST)?;
+                if current == manifest {
+                    return Err(e.into());
+                }
+                manifest = current;
+            }
+            Err(e) => return Err(e.into()),
+            Ok(messages) => return Ok(Snapshot { messages }),
+        }
+    }
+}
+
+fn read_blobs(dir: &Path, manifest: &BTreeMap<String, String>) -> std::io::Result<BTreeMap<String, Vec<u8>>> {
+    let mut messages = BTreeMap::new();
+    for (name, hash) in manifest {
+        let bytes = fs::read(dir.join(BLOBS).join(format!("{}.bin", hash)))?;
+        if hex_digest(&bytes) != *hash {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("blob for {:?} does not match its hash {}", name, hash),
+            ));
+        }
+        messages.insert(name.clone(), bytes);
+    }
+    Ok(messages)
+}
+
+/// Creates the store layout if needed and rolls back any transaction that did not reach its commit point.
+///
+/// Takes the writer lock, so it blocks while a [`Transaction`] on the same store is open.
+pub fn recover(dir: impl AsRef<Path>) -> capnp::Result<()> {
+    let dir = dir.as_ref();
+    let _lock = lock(dir)?;
+    recover_locked(dir)
+}
+
+fn recover_locked(dir: &Path) -> capnp::Result<()> {
+    fs::create_dir_all(dir.join(BLOBS))?;
+    let staging = dir.join(STAGING);
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+    fs::create_dir_all(&staging)?;
+    for name in [MANIFEST, PREVIOUS] {
+        let tmp = dir.join(format!("{}.tmp", name));
+        if tmp.exists() {
+            fs::remove_file(tmp)?;
+        }
+    }
+    gc_locked(dir)?;
+    Ok(())
+}
+
+/// Deletes blobs referenced by neither the committed manifest nor the one it replaced, returning how
+/// many were removed.
+///
+/// Takes the writer lock, so it blocks while a [`Transaction`] on the same store is open.
+pub fn gc(dir: impl AsRef<Path>) -> capnp::Result<usize> {
+    let dir = dir.as_ref();
+    let _lock = lock(dir)?;
+    gc_locked(dir)
+}
+
+fn gc_locked(dir: &Path) -> capnp::Result<usize> {
+    let mut live = HashSet::new();
+    for manifest in [MANIFEST, PREVIOUS] {
+        live.extend(read_manifest(dir, manifest)?.into_values().map(|hash| format!("{}.bin", hash)));
+    }
+    let mut removed = 0;
+    for entry in fs::read_dir(dir.join(BLOBS))? {
+        let entry = entry?;
+        if !live.contains(entry.file_name().to_string_lossy().as_ref()) {
+            fs::remove_file(entry.path())?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Opens the store's lock file and takes the writer lock on it, blocking until it is free.
+fn lock(dir: &Path) -> capnp::Result<File> {
+    fs::create_dir_all(dir)?;
+    let file = fs::OpenOptions::new().create(true).truncate(false).write(true).open(dir.join(LOCK))?;
+    file.lock()?;
+    Ok(file)
+}
+
+fn read_manifest(dir: &Path, name: &str) -> capnp::Result<BTreeMap<String, String>> {
+    let content = match fs::read_to_string(dir.join(name)) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e.into()),
+    };
+    content.lines()
+        .map(|line| match line.split_once(' ') {
+            Some((hash, name)) => Ok((name.to_string(), hash.to_string())),
+            None => Err(capnp::Error::failed(format!("malformed manifest line {:?}", line))),
+        })
+        .collect()
+}
+
+/// Atomically replaces the manifest file `name` with `manifest`.
+fn write_manifest(dir: &Path, name: &str, manifest: &BTreeMap<String, String>) -> capnp::Result<()> {
+    let tmp = write_manifest_tmp(dir, name, manifest)?;
+    fs::rename(&tmp, dir.join(name))?;
+    sync_dir(dir)?;
+    Ok(())
+}
+
+/// Writes and fsyncs `manifest` to `<name>.tmp`, returning its path.
+fn write_manifest_tmp(dir: &Path, name: &str, manifest: &BTreeMap<String, String>) -> capnp::Result<PathBuf> {
+    let tmp = dir.join(format!("{}.tmp", name));
+    let mut file = File::create(&tmp)?;
+    for (name, hash) in manifest {
+        writeln!(file, "{} {}", hash, name)?;
+    }
+    file.sync_all()?;
+    Ok(tmp)
+}
+
+fn hex_digest(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use capnp::message::HeapAllocator;
+
+    fn text(value: &str) -> Builder<HeapAllocator> {
+        let mut message = Builder::new_default();
+        message.set_root(capnp::text::Reader::from(value)).unwrap();
+        message
+    }
+
+    fn committed(dir: &Path) -> BTreeMap<String, String> {
+        let snapshot = read_consistent(dir).unwrap();
+        snapshot.names()
+            .map(|name| {
+                let message = snapshot.get(name, ReaderOptions::new()).unwrap().unwrap();
+                let value = message.get_root::<capnp::text::Reader>().unwrap().to_str().unwrap().to_string();
+                (name.to_string(), value)
+            })
+            .collect()
+    }
+
+    fn blob_count(dir: &Path) -> usize {
+        fs::read_dir(dir.join(BLOBS)).unwrap().count()
+    }
+
+    fn commit(dir: &Path, puts: &[(&str, &str)]) {
+        let mut tx = Transaction::new(dir).unwrap();
+        for (name, value) in puts {
+            tx.put(name, &text(value)).unwrap();
+        }
+        tx.commit().unwrap();
+    }
+
+    fn expected(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn names_with_identical_contents_share_a_blob() {
+        let dir = tempfile::tempdir().unwrap();
+        commit(dir.path(), &[("a", "same"), ("b", "same")]);
+        assert_eq!(committed(dir.path()), expected(&[("a", "same"), ("b", "same")]));
+        assert_eq!(blob_count(dir.path()), 1);
+
+        // Putting contents that already have a blob reuses it
+        commit(dir.path(), &[("c", "same")]);
+        assert_eq!(committed(dir.path()), expected(&[("a", "same"), ("b", "same"), ("c", "same")]));
+        assert_eq!(blob_count(dir.path()), 1);
+    }
+
+    #[test]
+    fn crash_before_the_commit_point_rolls_back() {
+        for phase in [Phase::Staged, Phase::Moved, Phase::PreviousWritten, Phase::ManifestTmpWritten] {
+            let dir = tempfile::tempdir().unwrap();
+            commit(dir.path(), &[("a", "one"), ("b", "two")]);
+
+            let mut tx = Transaction::new(dir.path()).unwrap();
+            tx.put("a", &text("changed")).unwrap();
+            tx.put("c", &text("new")).unwrap();
+            tx.crash_after = Some(phase);
+            tx.commit().unwrap();
+
+            // Readers never see the half-done transaction, before or after the store is reopened
+            let before = expected(&[("a", "one"), ("b", "two")]);
+            assert_eq!(committed(dir.path()), before, "{:?}", phase);
+            drop(Transaction::new(dir.path()).unwrap());
+            assert_eq!(committed(dir.path()), before, "{:?}", phase);
+            assert_eq!(fs::read_dir(dir.path().join(STAGING)).unwrap().count(), 0, "{:?}", phase);
+            assert!(!dir.path().join("MANIFEST.tmp").exists(), "{:?}", phase);
+            assert_eq!(blob_count(dir.path()), 2, "{:?}", phase);
+
+            // And the store still takes the next transaction
+            commit(dir.path(), &[("a", "changed")]);
+            assert_eq!(committed(dir.path()), expected(&[("a", "changed"), ("b", "two")]), "{:?}", phase);
+        }
+    }
+
+    #[test]
+    fn gc_keeps_the_blobs_of_the_previous_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        commit(dir.path(), &[("a", "v1")]);
+        let stale = read_manifest(dir.path(), MANIFEST).unwrap();
+
+        // A reader that read the v1 manifest can still load its blobs after v2 commits and gc runs
+        commit(dir.path(), &[("a", "v2")]);
+        assert_eq!(gc(dir.path()).unwrap(), 0);
+        assert_eq!(read_blobs(dir.path(), &stale).unwrap().len(), 1);
+
+        // Once v1 is two commits behind it is collected
+        commit(dir.path(), &[("a", "v3")]);
+        assert_eq!(gc(dir.path()).unwrap(), 1);
+        assert_eq!(blob_count(dir.path()), 2);
+        assert_eq!(read_blobs(dir.path(), &stale).unwrap_err().kind(), std::io::ErrorKind::NotFound);
+        assert_eq!(committed(dir.path()), expected(&[("a", "v3")]));
+    }
+
+    #[test]
+    fn writers_wait_for_the_open_transaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let tx = Transaction::new(dir.path()).unwrap();
+
+        let path = dir.path().to_path_buf();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let waiter = std::thread::spawn(move || {
+            let count = gc(&path).unwrap();
+            sender.send(count).unwrap();
+        });
+        assert!(receiver.recv_timeout(std::time::Duration::from_millis(100)).is_err());
+        drop(tx);
+        assert_eq!(receiver.recv().unwrap(), 0);
+        waiter.join().unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut tx = Transaction::new(dir.path()).unwrap();
+        assert!(tx.put("", &text("x")).is_err());
+        assert!(tx.put("two\nlines", &text("x")).is_err());
+    }
+}
@@ -0,0 +1,53 @@
+//! Runtime helpers for messages whose schema was generated by `capnez-codegen`.
+//!
+//! Everything that needs a filesystem or sockets sits behind a cargo feature, so the crate builds
+//! for `wasm32-unknown-unknown` with `default-features = false`.
+//!
+//! Without the default `std` feature the crate is `#![no_std]` and needs only `alloc`, for firmware
+//! sharing message definitions with a backend. `limits`, `pool` and `dynamic` work there; every other
+//! feature turns `std` back on.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(all(feature = "io", target_arch = "wasm32", target_os = "unknown"))]
+compile_error!(
+    "capnez feature `io` needs a filesystem, which wasm32-unknown-unknown does not have; \
+     depend on capnez with `default-features = false` (WASI targets may keep `io`)"
+);
+
+#[cfg(all(feature = "rpc", target_arch = "wasm32", target_os = "unknown"))]
+compile_error!(
+    "capnez feature `rpc` needs tokio sockets, which wasm32-unknown-unknown does not have; \
+     use the `wasm` feature and run capnp-rpc over a `capnez::wasm::MessagePortStream` instead"
+);
+
+#[cfg(all(feature = "tls", target_arch = "wasm32", target_os = "unknown"))]
+compile_error!(
+    "capnez feature `tls` needs tokio sockets, which wasm32-unknown-unknown does not have; \
+     browsers secure the transport themselves, e.g. with a `wss://` WebSocket"
+);
+
+#[cfg(feature = "checked")]
+pub mod checked;
+#[cfg(any(feature = "json", feature = "bincode", feature = "postcard"))]
+pub mod codec;
+#[cfg(any(feature = "compress-zstd", feature = "compress-lz4"))]
+pub mod compress;
+#[cfg(feature = "dynamic")]
+pub mod dynamic;
+#[cfg(feature = "io")]
+pub mod io;
+#[cfg(feature = "limits")]
+pub mod limits;
+#[cfg(feature = "tracing")]
+pub mod observe;
+#[cfg(feature = "pool")]
+pub mod pool;
+#[cfg(feature = "rpc")]
+pub mod rpc;
+#[cfg(any(feature = "rpc", feature = "wasm"))]
+pub mod twoparty;
+#[cfg(feature = "wasm")]
+pub mod wasm;
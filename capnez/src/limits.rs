@@ -0,0 +1,122 @@
+//! Caps on decoding untrusted bytes.
+//!
+//! `ReaderOptions::new()` accepts any input length and lets capnp traverse 64 MiB of it, which is
+//! generous for bytes from a client. [`DecodeLimits`] bounds the input length before capnp reads
+//! anything, and the traversal and nesting limits while it reads, and reports whichever was hit as
+//! [`DecodeError::Limit`] rather than a generic capnp error:
+//!
+//! ```ignore
+//! let limits = DecodeLimits { max_message_bytes: Some(1 << 20), ..DecodeLimits::default() };
+//! match Order::from_capnp_bytes_limited(&bytes, &limits) {
+//!     Err(DecodeError::Limit(limit)) => reject(limit),
+//!     ...
+//! }
+//! ```
+//!
+//! Readers that take `ReaderOptions`, e.g. `capnez::io::MessageLogReader::get`, get the traversal
+//! and nesting limits through [`DecodeLimits::reader_options`].
+
+use capnp::message::{Reader, ReaderOptions};
+use capnp::serialize::SliceSegments;
+use core::fmt;
+
+/// How much of a message decoding may read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// Longest input accepted, in bytes, checked before capnp reads anything. `None` for no limit.
+    pub max_message_bytes: Option<usize>,
+    /// Words capnp may read while traversing the message, counting data it reads more than once, so
+    /// a small message whose pointers alias each other cannot amplify into unbounded work. `None`
+    /// for no limit.
+    pub traversal_limit_words: Option<usize>,
+    /// Deepest nesting of structs and lists.
+    pub nesting_limit: i32,
+}
+
+impl Default for DecodeLimits {
+    /// capnp's own defaults: 64 MiB of traversal and 64 levels of nesting, with the input length
+    /// capped at the same 64 MiB.
+    fn default() -> Self {
+        Self { max_message_bytes: Some(64 << 20), traversal_limit_words: Some(8 << 20), nesting_limit: 64 }
+    }
+}
+
+impl DecodeLimits {
+    /// The traversal and nesting limits, as options for capnp's readers.
+    pub fn reader_options(&self) -> ReaderOptions {
+        let mut options = ReaderOptions::new();
+        options.traversal_limit_in_words(self.traversal_limit_words).nesting_limit(self.nesting_limit);
+        options
+    }
+
+    /// Fails if an input of `len` bytes is longer than `max_message_bytes`.
+    pub fn check_len(&self, len: usize) -> Result<(), DecodeError> {
+        match self.max_message_bytes {
+            Some(limit) if len > limit => Err(DecodeError::Limit(Limit::MessageBytes { limit, actual: len })),
+            _ => Ok(()),
+        }
+    }
+
+    /// Reads the message in `bytes` and passes it to `read`, e.g. a generated `from_capnp`. Errors
+    /// from either step that come from one of the limits are reported as [`DecodeError::Limit`].
+    pub fn decode<T>(&self, bytes: &[u8], read: impl FnOnce(Reader<SliceSegments<'_>>) -> capnp::Result<T>) -> Result<T, DecodeError> {
+        self.check_len(bytes.len())?;
+        capnp::serialize::read_message_from_flat_slice(&mut &bytes[..], self.reader_options())
+            .and_then(read)
+            .map_err(|e| self.classify(e))
+    }
+
+    /// Tells the errors capnp raises on reaching one of the limits from any other.
+    pub fn classify(&self, e: capnp::Error) -> DecodeError {
+        match e.kind {
+            capnp::ErrorKind::ReadLimitExceeded | capnp::ErrorKind::MessageTooLarge(_) => {
+                DecodeError::Limit(Limit::TraversalWords { limit: self.traversal_limit_words.unwrap_or(usize::MAX) })
+            }
+            capnp::ErrorKind::MessageIsTooDeeplyNested | capnp::ErrorKind::MessageIsTooDeeplyNestedOrContainsCycles => {
+                DecodeError::Limit(Limit::Nesting { limit: self.nesting_limit })
+            }
+            _ => DecodeError::Capnp(e),
+        }
+    }
+}
+
+/// The limit a decode ran into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    /// The input was longer than `max_message_bytes`.
+    MessageBytes { limit: usize, actual: usize },
+    /// Reading the message took more than `traversal_limit_words`.
+    TraversalWords { limit: usize },
+    /// The message nests deeper than `nesting_limit`.
+    Nesting { limit: i32 },
+}
+
+/// Why bytes could not be decoded within [`DecodeLimits`].
+#[derive(Debug)]
+pub enum DecodeError {
+    /// The message may be valid, but is larger or deeper than allowed.
+    Limit(Limit),
+    /// The message is not valid.
+    Capnp(capnp::Error),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Limit(Limit::MessageBytes { limit, actual }) => write!(f, "message of {} bytes exceeds the limit of {}", actual, limit),
+            Self::Limit(Limit::TraversalWords { limit }) => write!(f, "reading the message exceeded the traversal limit of {} words", limit),
+            Self::Limit(Limit::Nesting { limit }) => write!(f, "message nests deeper than the limit of {}", limit),
+            Self::Capnp(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Capnp(e) => Some(e),
+            Self::Limit(_) => None,
+        }
+    }
+}
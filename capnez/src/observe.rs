@@ -0,0 +1,242 @@
+//! Per-call tracing and metrics for RPC servers.
+//!
+//! Wrap a server implementation in [`Instrumented`] before turning it into a client. capnez-codegen
+//! implements every generated `Server` trait for `Instrumented<S>`, forwarding each call to `S`:
+//!
+//! ```ignore
+//! let client: hello_world::Client = capnp_rpc::new_client(
+//!     Instrumented::new(HelloWorldImpl).observer(metrics),
+//! );
+//! ```
+//!
+//! Every call runs inside an `rpc` span at `INFO` level with `interface`, `method` and `params_bytes`
+//! fields, and records `latency_us` and `outcome` (`ok` or `error`) on it once the call completes;
+//! failures also emit a `WARN` event carrying the error. tracing needs span names to be static, so
+//! filter on the fields rather than the name.
+
+use capnp::capability::Promise;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use tracing::Instrument;
+
+/// One incoming call, as seen by an [`RpcObserver`].
+#[derive(Clone, Debug)]
+pub struct Call {
+    /// Capnp name of the interface, e.g. `HelloWorld`.
+    pub interface: &'static str,
+    /// Capnp name of the method, e.g. `sayHello`.
+    pub method: &'static str,
+    /// Size of the parameters as received, if they could be read.
+    pub params_bytes: Option<u64>,
+}
+
+/// Hooks around every call to an [`Instrumented`] server, e.g. to feed a metrics backend.
+pub trait RpcObserver {
+    fn on_call_start(&self, call: &Call) {
+        let _ = call;
+    }
+
+    /// Called once the call's promise resolves, with how long that took since the call arrived.
+    fn on_call_end(&self, call: &Call, outcome: &Result<(), capnp::Error>, elapsed: Duration) {
+        let _ = (call, outcome, elapsed);
+    }
+}
+
+/// A server implementation whose calls are traced and reported to observers.
+pub struct Instrumented<S> {
+    inner: S,
+    observers: Vec<Rc<dyn RpcObserver>>,
+}
+
+impl<S> Instrumented<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner, observers: Vec::new() }
+    }
+
+    /// Adds an observer; observers are called in the order they were added.
+    pub fn observer(mut self, observer: impl RpcObserver + 'static) -> Self {
+        self.observers.push(Rc::new(observer));
+        self
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Runs `dispatch` on the wrapped server inside a span for the call; used by the generated impls.
+    #[doc(hidden)]
+    pub fn observe(
+        &mut self,
+        interface: &'static str,
+        method: &'static str,
+        params_bytes: Option<u64>,
+        dispatch: impl FnOnce(&mut S) -> Promise<(), capnp::Error>,
+    ) -> Promise<(), capnp::Error> {
+        let call = Call { interface, method, params_bytes };
+        let span = tracing::info_span!(
+            "rpc",
+            interface,
+            method,
+            params_bytes,
+            latency_us = tracing::field::Empty,
+            outcome = tracing::field::Empty,
+        );
+        for observer in &self.observers {
+            observer.on_call_start(&call);
+        }
+
+        let started = Instant::now();
+        let promise = span.in_scope(|| dispatch(&mut self.inner));
+        let observers = self.observers.clone();
+        Promise::from_future(async move {
+            let outcome = promise.instrument(span.clone()).await;
+            let elapsed = started.elapsed();
+            span.record("latency_us", elapsed.as_micros() as u64);
+            span.record("outcome", if outcome.is_ok() { "ok" } else { "error" });
+            if let Err(e) = &outcome {
+                tracing::warn!(parent: &span, error = %e, "rpc call failed");
+            }
+            for observer in &observers {
+                observer.on_call_end(&call, &outcome, elapsed);
+            }
+            outcome
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::BTreeMap;
+    use std::future::Future;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll, Waker};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    /// A span or event as captured: its name and each field's value, strings unquoted.
+    #[derive(Debug, Default)]
+    struct Captured {
+        name: &'static str,
+        fields: BTreeMap<&'static str, String>,
+    }
+
+    impl Visit for Captured {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.fields.insert(field.name(), format!("{:?}", value));
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.fields.insert(field.name(), value.to_string());
+        }
+    }
+
+    /// Records every span, with the values recorded on it later, and every event.
+    #[derive(Clone, Default)]
+    struct Capture {
+        spans: Arc<Mutex<Vec<Captured>>>,
+        events: Arc<Mutex<Vec<Captured>>>,
+    }
+
+    impl Subscriber for Capture {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, attributes: &Attributes<'_>) -> Id {
+            let mut span = Captured { name: attributes.metadata().name(), ..Captured::default() };
+            attributes.record(&mut span);
+            let mut spans = self.spans.lock().unwrap();
+            spans.push(span);
+            Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, span: &Id, values: &Record<'_>) {
+            values.record(&mut self.spans.lock().unwrap()[span.into_u64() as usize - 1]);
+        }
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut captured = Captured { name: event.metadata().name(), ..Captured::default() };
+            event.record(&mut captured);
+            captured.fields.insert("level", event.metadata().level().to_string());
+            self.events.lock().unwrap().push(captured);
+        }
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    /// Logs every hook call as `start` or `end <outcome>` with the method name.
+    #[derive(Clone, Default)]
+    struct Hooks(Rc<RefCell<Vec<String>>>);
+
+    impl RpcObserver for Hooks {
+        fn on_call_start(&self, call: &Call) {
+            self.0.borrow_mut().push(format!("start {}.{}", call.interface, call.method));
+        }
+
+        fn on_call_end(&self, call: &Call, outcome: &Result<(), capnp::Error>, _: Duration) {
+            let outcome = if outcome.is_ok() { "ok" } else { "error" };
+            self.0.borrow_mut().push(format!("end {}.{} {}", call.interface, call.method, outcome));
+        }
+    }
+
+    /// Polls a promise that is already resolved.
+    fn resolved(promise: Promise<(), capnp::Error>) -> Result<(), capnp::Error> {
+        match std::pin::pin!(promise).poll(&mut Context::from_waker(Waker::noop())) {
+            Poll::Ready(outcome) => outcome,
+            Poll::Pending => panic!("the promise was expected to be resolved"),
+        }
+    }
+
+    #[test]
+    fn a_call_runs_in_an_rpc_span_with_its_fields() {
+        let capture = Capture::default();
+        let hooks = Hooks::default();
+        let mut server = Instrumented::new(()).observer(hooks.clone());
+        tracing::subscriber::with_default(capture.clone(), || {
+            resolved(server.observe("HelloWorld", "sayHello", Some(48), |_| Promise::ok(()))).unwrap();
+        });
+
+        let spans = capture.spans.lock().unwrap();
+        assert_eq!(spans.len(), 1, "{:?}", spans);
+        let span = &spans[0];
+        assert_eq!(span.name, "rpc");
+        assert_eq!(span.fields["interface"], "HelloWorld");
+        assert_eq!(span.fields["method"], "sayHello");
+        assert_eq!(span.fields["params_bytes"], "48");
+        assert_eq!(span.fields["outcome"], "ok");
+        assert!(span.fields["latency_us"].parse::<u64>().is_ok(), "{:?}", span.fields);
+        assert!(capture.events.lock().unwrap().is_empty());
+        assert_eq!(*hooks.0.borrow(), ["start HelloWorld.sayHello", "end HelloWorld.sayHello ok"]);
+    }
+
+    #[test]
+    fn a_failed_call_is_recorded_and_warned_about() {
+        let capture = Capture::default();
+        let hooks = Hooks::default();
+        let mut server = Instrumented::new(()).observer(hooks.clone());
+        let outcome = tracing::subscriber::with_default(capture.clone(), || {
+            resolved(server.observe("HelloWorld", "sayHello", None, |_| Promise::err(capnp::Error::failed("no greeting".to_string()))))
+        });
+        assert!(outcome.is_err());
+
+        let spans = capture.spans.lock().unwrap();
+        assert_eq!(spans[0].fields["outcome"], "error");
+        assert!(!spans[0].fields.contains_key("params_bytes"), "{:?}", spans[0].fields);
+        let events = capture.events.lock().unwrap();
+        assert_eq!(events.len(), 1, "{:?}", events);
+        assert_eq!(events[0].fields["level"], "WARN");
+        assert!(events[0].fields["error"].contains("no greeting"), "{:?}", events[0].fields);
+        assert_eq!(*hooks.0.borrow(), ["start HelloWorld.sayHello", "end HelloWorld.sayHello error"]);
+    }
+}
@@ -0,0 +1,180 @@
+//! Reusable message buffers, for serializing many messages without allocating for each one.
+//!
+//! `Builder::new_default()` allocates its first segment on every message and frees it afterwards.
+//! A [`MessagePool`] keeps one zeroed buffer and hands it to each builder as its first segment, so
+//! a steady stream of messages that fit in it allocates nothing:
+//!
+//! ```ignore
+//! let pool = MessagePool::new();
+//! for person in &people {
+//!     let bytes = pool.with_builder(|message| {
+//!         person.to_capnp(message.init_root());
+//!         capnp::serialize::write_message_to_words(message)
+//!     });
+//! }
+//! ```
+//!
+//! Messages larger than the buffer spill into heap segments as usual, and the buffer grows to the
+//! largest message seen, up to a cap, so the next one fits. The words a message wrote are zeroed
+//! before the buffer is reused, so nothing of one message shows through in the next.
+//!
+//! A pool is `Send` but not `Sync`: keep one per thread, e.g. in a `thread_local!`.
+
+use capnp::message::{Allocator, Builder, HeapAllocator};
+use capnp::Word;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+/// Bytes in a new pool's buffer; the same as the first segment of `Builder::new_default()`.
+const INITIAL_BYTES: usize = 8 * 1024;
+/// Default cap on the buffer, so one huge message does not pin its size for the pool's lifetime.
+const MAX_BYTES: usize = 16 * 1024 * 1024;
+
+/// A reusable first segment for message builders; see the [module docs](self).
+pub struct MessagePool {
+    scratch: RefCell<Scratch>,
+    max_words: usize,
+}
+
+impl Default for MessagePool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MessagePool {
+    /// A pool with an 8 KiB buffer that may grow to 16 MiB.
+    pub fn new() -> Self {
+        Self::with_max_capacity(MAX_BYTES)
+    }
+
+    /// A pool whose buffer grows to at most `max_bytes`; larger messages still work, but allocate.
+    pub fn with_max_capacity(max_bytes: usize) -> Self {
+        let max_words = max_bytes / 8;
+        Self { scratch: RefCell::new(Scratch::new(max_words.min(INITIAL_BYTES / 8))), max_words }
+    }
+
+    /// Current size of the buffer in bytes.
+    pub fn capacity(&self) -> usize {
+        self.scratch.try_borrow().map_or(0, |scratch| scratch.words.len() * 8)
+    }
+
+    /// Runs `f` with an empty builder backed by the pool's buffer, and readies the buffer for the
+    /// next message once `f` returns. Calling this again from inside `f` works, but the inner
+    /// builder allocates like `Builder::new_default()`.
+    pub fn with_builder<R>(&self, f: impl FnOnce(&mut Builder<PooledAllocator<'_>>) -> R) -> R {
+        let Ok(mut scratch) = self.scratch.try_borrow_mut() else {
+            return f(&mut Builder::new(PooledAllocator { scratch: &mut Scratch::new(0) }));
+        };
+        let result = f(&mut Builder::new(PooledAllocator { scratch: &mut scratch }));
+        // The builder is gone, so all of its segments were handed back
+        let used = core::mem::take(&mut scratch.used);
+        if used > scratch.words.len() && scratch.words.len() < self.max_words {
+            scratch.words = Word::allocate_zeroed_vec(used.next_power_of_two().min(self.max_words));
+        }
+        result
+    }
+}
+
+/// The allocator of a builder from [`MessagePool::with_builder`].
+pub struct PooledAllocator<'a> {
+    scratch: &'a mut Scratch,
+}
+
+struct Scratch {
+    /// All zero whenever no builder holds it, as capnp expects of a new segment.
+    words: Vec<Word>,
+    in_use: bool,
+    heap: HeapAllocator,
+    /// Words the current message has used, over all segments handed back so far.
+    used: usize,
+}
+
+impl Scratch {
+    fn new(words: usize) -> Self {
+        Self { words: Word::allocate_zeroed_vec(words), in_use: false, heap: HeapAllocator::new(), used: 0 }
+    }
+}
+
+unsafe impl Allocator for PooledAllocator<'_> {
+    fn allocate_segment(&mut self, minimum_size: u32) -> (*mut u8, u32) {
+        let scratch = &mut *self.scratch;
+        if scratch.in_use || scratch.words.is_empty() || scratch.words.len() < minimum_size as usize {
+            return scratch.heap.allocate_segment(minimum_size);
+        }
+        scratch.in_use = true;
+        let len = scratch.words.len() as u32;
+        (Word::words_to_bytes_mut(&mut scratch.words).as_mut_ptr(), len)
+    }
+
+    unsafe fn deallocate_segment(&mut self, ptr: *mut u8, word_size: u32, words_used: u32) {
+        let scratch = &mut *self.scratch;
+        scratch.used += words_used as usize;
+        let bytes = Word::words_to_bytes_mut(&mut scratch.words);
+        if ptr == bytes.as_mut_ptr() {
+            bytes[..words_used as usize * 8].fill(0);
+            scratch.in_use = false;
+        } else {
+            scratch.heap.deallocate_segment(ptr, word_size, words_used);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a message holding `len` bytes of `fill`, returning it serialized.
+    fn data(pool: &MessagePool, len: u32, fill: u8) -> Vec<u8> {
+        pool.with_builder(|message| {
+            message.initn_root::<capnp::data::Builder>(len).fill(fill);
+            capnp::serialize::write_message_to_words(message)
+        })
+    }
+
+    #[test]
+    fn the_buffer_stays_within_its_cap() {
+        let max = 64 * 1024;
+        let pool = MessagePool::with_max_capacity(max);
+        for i in 0..1_000u32 {
+            // Mostly small messages, with one well past the cap every so often
+            let len = if i % 100 == 99 { 1024 * 1024 } else { (i * 37) % 20_000 + 1 };
+            let bytes = data(&pool, len, 0xAB);
+            assert!(bytes.len() > len as usize);
+            assert!(pool.capacity() <= max, "message {}: capacity {}", i, pool.capacity());
+        }
+        assert_eq!(pool.capacity(), max);
+    }
+
+    #[test]
+    fn nothing_of_one_message_shows_through_in_the_next() {
+        let pool = MessagePool::new();
+        for round in 0..100u32 {
+            let len = 1 + (round * 53) % 6_000;
+            data(&pool, len, 0xFF);
+            assert!(Word::words_to_bytes(&pool.scratch.borrow().words).iter().all(|&b| b == 0));
+
+            // A fresh blob is all zero, and the message matches one built without the pool
+            let pooled = pool.with_builder(|message| {
+                assert!(message.initn_root::<capnp::data::Builder>(len / 2 + 1).iter().all(|&b| b == 0));
+                capnp::serialize::write_message_to_words(message)
+            });
+            let mut fresh = Builder::new_default();
+            fresh.initn_root::<capnp::data::Builder>(len / 2 + 1);
+            assert_eq!(pooled, capnp::serialize::write_message_to_words(&fresh));
+        }
+    }
+
+    #[test]
+    fn a_nested_builder_allocates_on_its_own() {
+        let pool = MessagePool::new();
+        let (outer, inner) = pool.with_builder(|message| {
+            message.initn_root::<capnp::data::Builder>(16).fill(1);
+            let inner = data(&pool, 16, 2);
+            (capnp::serialize::write_message_to_words(message), inner)
+        });
+        assert_ne!(outer, inner);
+        assert_eq!(outer[outer.len() - 16..], [1; 16]);
+        assert_eq!(inner[inner.len() - 16..], [2; 16]);
+    }
+}
@@ -0,0 +1,105 @@
+//! Deadlines and cancellation for outgoing calls.
+
+use capnp::capability::{FromTypelessPipeline, Request, Response};
+use capnp::traits::{Owned, Pipelined};
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// When to give up on a call. Giving up drops the call's promise, which capnp-rpc turns into a
+/// cancellation sent to the server.
+#[derive(Clone, Debug, Default)]
+pub struct CallOptions {
+    /// How long to wait for the response.
+    pub timeout: Option<Duration>,
+    /// Abandons the call once cancelled.
+    pub cancel: Option<CancellationToken>,
+}
+
+/// Why a call made with [`RequestExt`] or [`ReconnectingClient`](super::ReconnectingClient) failed.
+#[derive(Debug)]
+pub enum CallError {
+    /// No response arrived within the timeout; the call may be retried.
+    Timeout(Duration),
+    /// The call's cancellation token was cancelled first.
+    Canceled,
+    /// The connection was lost before the response arrived; the call may be retried once reconnected.
+    Retryable(capnp::Error),
+    /// No connection could be established within the retry policy's attempts.
+    Connect(std::io::Error),
+    /// The call itself failed.
+    Rpc(capnp::Error),
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout(timeout) => write!(f, "no response within {:?}", timeout),
+            Self::Canceled => write!(f, "call canceled"),
+            Self::Retryable(e) => write!(f, "connection lost: {}", e),
+            Self::Connect(e) => write!(f, "could not connect: {}", e),
+            Self::Rpc(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for CallError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Retryable(e) | Self::Rpc(e) => Some(e),
+            Self::Connect(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<capnp::Error> for CallError {
+    fn from(e: capnp::Error) -> Self {
+        match e.kind {
+            capnp::ErrorKind::Disconnected => Self::Retryable(e),
+            _ => Self::Rpc(e),
+        }
+    }
+}
+
+/// Sending a request with a deadline or cancellation token, e.g.
+/// `request.send_timeout(Duration::from_secs(5)).await`.
+pub trait RequestExt<Results: Owned> {
+    fn send_with(self, options: CallOptions) -> impl Future<Output = Result<Response<Results>, CallError>>;
+
+    fn send_timeout(self, timeout: Duration) -> impl Future<Output = Result<Response<Results>, CallError>>
+    where
+        Self: Sized,
+    {
+        self.send_with(CallOptions { timeout: Some(timeout), ..Default::default() })
+    }
+}
+
+impl<Params, Results> RequestExt<Results> for Request<Params, Results>
+where
+    Params: Owned,
+    Results: Pipelined + Owned + 'static + Unpin,
+    <Results as Pipelined>::Pipeline: FromTypelessPipeline,
+{
+    async fn send_with(self, options: CallOptions) -> Result<Response<Results>, CallError> {
+        let response = self.send().promise;
+        let deadline = async {
+            match options.timeout {
+                Some(timeout) => tokio::time::sleep(timeout).await,
+                None => futures::future::pending().await,
+            }
+        };
+        let canceled = async {
+            match &options.cancel {
+                Some(token) => token.cancelled().await,
+                None => futures::future::pending().await,
+            }
+        };
+        tokio::select! {
+            response = response => Ok(response?),
+            _ = deadline => Err(CallError::Timeout(options.timeout.unwrap_or_default())),
+            _ = canceled => Err(CallError::Canceled),
+        }
+    }
+}
@@ -0,0 +1,257 @@
+//! Connection and call limits for the `serve_*` functions.
+
+use capnp::capability::{Client, Promise, Request};
+use capnp::message::ReaderOptions;
+use capnp::private::capability::{ClientHook, ParamsHook, ResultsHook};
+use futures::channel::oneshot;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// Limits and buffer sizes for [`serve_tcp`](super::serve_tcp), [`serve_unix`](super::serve_unix) and
+/// `serve_tls`.
+#[derive(Clone)]
+pub struct ServerOptions {
+    /// Connections served at once.
+    pub max_connections: usize,
+    /// What happens to connections beyond `max_connections`.
+    pub when_full: WhenFull,
+    /// Calls on the served capability one connection may have running at once. Beyond that, new calls
+    /// wait until one completes; the connection keeps being read, so returns and cancellations still
+    /// arrive, including for calls the server makes back to the client. Calls on capabilities
+    /// returned by those calls are not counted.
+    pub max_inflight_calls_per_connection: usize,
+    /// Calls one connection may have waiting for a slot under `max_inflight_calls_per_connection`.
+    /// Beyond that, new calls fail right away with an `Overloaded` error, so a client cannot queue
+    /// unbounded work.
+    pub max_queued_calls_per_connection: usize,
+    /// Capacity of each connection's read buffer, in bytes.
+    pub read_buffer_size: usize,
+    /// Capacity of each connection's write buffer, in bytes.
+    pub write_buffer_size: usize,
+    /// Limits on every incoming message, e.g. its size in words and nesting depth.
+    pub reader_options: ReaderOptions,
+    /// Called with the current counts whenever one of them changes, e.g. to export them as metrics.
+    pub on_stats: Option<Arc<dyn Fn(ServerStats) + Send + Sync>>,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        Self {
+            max_connections: 1024,
+            when_full: WhenFull::Queue,
+            max_inflight_calls_per_connection: 128,
+            max_queued_calls_per_connection: 1024,
+            read_buffer_size: 8 * 1024,
+            write_buffer_size: 8 * 1024,
+            reader_options: ReaderOptions::new(),
+            on_stats: None,
+        }
+    }
+}
+
+/// Handling of new connections while `max_connections` are open.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WhenFull {
+    /// Stop accepting until a connection closes; new ones wait in the listener's backlog.
+    Queue,
+    /// Accept and immediately close them.
+    Reject,
+}
+
+/// Counts reported to [`ServerOptions::on_stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ServerStats {
+    /// Connections currently open, including ones still in their TLS handshake.
+    pub connections: usize,
+    /// Calls currently running, over all connections.
+    pub inflight_calls: usize,
+    /// Connections closed right away under [`WhenFull::Reject`], since the server started.
+    pub rejected: u64,
+    /// Connections that failed before they were served, e.g. in `accept` or while setting socket
+    /// options, since the server started. The server keeps accepting after each.
+    pub accept_errors: u64,
+}
+
+/// The counts of one server, shared by its connections.
+pub(super) struct Tracker {
+    stats: Cell<ServerStats>,
+    on_stats: Option<Arc<dyn Fn(ServerStats) + Send + Sync>>,
+    /// Signalled whenever a connection closes, for the accept loop waiting under [`WhenFull::Queue`].
+    pub(super) slot_freed: tokio::sync::Notify,
+}
+
+impl Tracker {
+    pub(super) fn new(options: &ServerOptions) -> Self {
+        Self { stats: Cell::new(ServerStats::default()), on_stats: options.on_stats.clone(), slot_freed: tokio::sync::Notify::new() }
+    }
+
+    pub(super) fn connections(&self) -> usize {
+        self.stats.get().connections
+    }
+
+    pub(super) fn update(&self, change: impl FnOnce(&mut ServerStats)) {
+        let mut stats = self.stats.get();
+        change(&mut stats);
+        self.stats.set(stats);
+        if let Some(on_stats) = &self.on_stats {
+            on_stats(stats);
+        }
+    }
+
+    /// Counts a new connection until the returned guard is dropped.
+    pub(super) fn open(self: &Rc<Self>) -> OpenConnection {
+        self.update(|stats| stats.connections += 1);
+        OpenConnection(self.clone())
+    }
+}
+
+pub(super) struct OpenConnection(Rc<Tracker>);
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        self.0.update(|stats| stats.connections -= 1);
+        self.0.slot_freed.notify_one();
+    }
+}
+
+/// The calls running on one connection, and the ones waiting to start.
+pub(super) struct Gate {
+    inflight: Cell<usize>,
+    max: usize,
+    max_queued: usize,
+    /// Waiting calls, oldest first. A freed slot is handed over as an [`Entered`] through the channel.
+    queue: RefCell<VecDeque<oneshot::Sender<Entered>>>,
+    tracker: Rc<Tracker>,
+}
+
+/// Where a new call stands under its connection's limits.
+enum Slot {
+    Ready(Entered),
+    Queued(oneshot::Receiver<Entered>),
+    Full,
+}
+
+impl Gate {
+    pub(super) fn new(options: &ServerOptions, tracker: Rc<Tracker>) -> Rc<Self> {
+        Rc::new(Self {
+            inflight: Cell::new(0),
+            max: options.max_inflight_calls_per_connection,
+            max_queued: options.max_queued_calls_per_connection,
+            queue: RefCell::new(VecDeque::new()),
+            tracker,
+        })
+    }
+
+    /// Wraps `bootstrap` so calls on it count against this gate.
+    pub(super) fn throttle(self: &Rc<Self>, bootstrap: &Client) -> Client {
+        Client::new(Box::new(Throttled { inner: bootstrap.hook.add_ref(), gate: self.clone() }))
+    }
+
+    fn enter(self: &Rc<Self>) -> Slot {
+        if self.inflight.get() < self.max {
+            self.inflight.set(self.inflight.get() + 1);
+            self.tracker.update(|stats| stats.inflight_calls += 1);
+            return Slot::Ready(Entered(self.clone()));
+        }
+        let mut queue = self.queue.borrow_mut();
+        queue.retain(|waiter| !waiter.is_canceled());
+        if queue.len() >= self.max_queued {
+            return Slot::Full;
+        }
+        let (sender, receiver) = oneshot::channel();
+        queue.push_back(sender);
+        Slot::Queued(receiver)
+    }
+}
+
+/// One running call; dropped when the call completes or is canceled.
+struct Entered(Rc<Gate>);
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        let gate = &self.0;
+        // Hand the slot straight to the oldest waiting call, if any is still waiting
+        loop {
+            let Some(waiter) = gate.queue.borrow_mut().pop_front() else { break };
+            if !waiter.is_canceled() {
+                let _ = waiter.send(Entered(gate.clone()));
+                return;
+            }
+        }
+        gate.inflight.set(gate.inflight.get() - 1);
+        gate.tracker.update(|stats| stats.inflight_calls -= 1);
+    }
+}
+
+/// Forwards everything to the served capability, counting each incoming call against a gate.
+struct Throttled {
+    inner: Box<dyn ClientHook>,
+    gate: Rc<Gate>,
+}
+
+impl ClientHook for Throttled {
+    fn add_ref(&self) -> Box<dyn ClientHook> {
+        Box::new(Throttled { inner: self.inner.add_ref(), gate: self.gate.clone() })
+    }
+
+    fn new_call(
+        &self,
+        interface_id: u64,
+        method_id: u16,
+        size_hint: Option<capnp::MessageSize>,
+    ) -> Request<capnp::any_pointer::Owned, capnp::any_pointer::Owned> {
+        self.inner.new_call(interface_id, method_id, size_hint)
+    }
+
+    fn call(
+        &self,
+        interface_id: u64,
+        method_id: u16,
+        params: Box<dyn ParamsHook>,
+        results: Box<dyn ResultsHook>,
+    ) -> Promise<(), capnp::Error> {
+        match self.gate.enter() {
+            Slot::Ready(entered) => {
+                let promise = self.inner.call(interface_id, method_id, params, results);
+                Promise::from_future(async move {
+                    let _entered = entered;
+                    promise.await
+                })
+            }
+            Slot::Queued(slot) => {
+                let inner = self.inner.add_ref();
+                Promise::from_future(async move {
+                    let _entered = slot.await.map_err(|_| capnp::Error::disconnected("connection closed".to_string()))?;
+                    inner.call(interface_id, method_id, params, results).await
+                })
+            }
+            Slot::Full => Promise::err(capnp::Error::overloaded(format!(
+                "too many calls on this connection: {} running and {} waiting",
+                self.gate.max, self.gate.max_queued
+            ))),
+        }
+    }
+
+    // Not an RPC import, whatever the wrapped capability is
+    fn get_brand(&self) -> usize {
+        0
+    }
+
+    fn get_ptr(&self) -> usize {
+        self.inner.get_ptr()
+    }
+
+    fn get_resolved(&self) -> Option<Box<dyn ClientHook>> {
+        self.inner.get_resolved()
+    }
+
+    fn when_more_resolved(&self) -> Option<Promise<Box<dyn ClientHook>, capnp::Error>> {
+        self.inner.when_more_resolved()
+    }
+
+    fn when_resolved(&self) -> Promise<(), capnp::Error> {
+        self.inner.when_resolved()
+    }
+}
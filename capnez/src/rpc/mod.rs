@@ -0,0 +1,280 @@
+//! Transports for `#[capnp]` interfaces over TCP, TLS, Unix domain sockets or an in-process pipe: the tokio,
+//! `twoparty::VatNetwork` and `RpcSystem` setup every server and client otherwise repeats.
+//!
+//! capnp-rpc is single-threaded, so both sides need a `tokio::task::LocalSet`. [`serve_tcp`] brings its
+//! own; the future returned by [`connect_tcp`] must be spawned on one by the caller:
+//!
+//! ```ignore
+//! // server
+//! let client: hello_world::Client = capnp_rpc::new_client(HelloWorldImpl);
+//! let shutdown = async { tokio::signal::ctrl_c().await.ok(); };
+//! capnez::rpc::serve_tcp(addr, client, ServerOptions::default(), shutdown).await?;
+//!
+//! // client
+//! let (hello_world, rpc_system) = capnez::rpc::connect_tcp::<hello_world::Client>(addr).await?;
+//! let local = tokio::task::LocalSet::new();
+//! local.spawn_local(rpc_system);
+//! let reply = local.run_until(hello_world.say_hello_request().send_timeout(Duration::from_secs(5))).await?;
+//! ```
+//!
+//! Long-running clients can use a [`ReconnectingClient`] instead, which reconnects with backoff once the
+//! connection is lost.
+//!
+//! [`stream`] holds the runtime side of streaming methods, which capnez-codegen generates glue for.
+//!
+//! Servers bound their resource use per [`ServerOptions`]: connections beyond the limit are queued or
+//! rejected, and calls beyond a connection's limit wait for one to complete, or fail as overloaded once
+//! too many are waiting.
+
+mod call;
+mod limits;
+mod reconnect;
+pub mod stream;
+
+pub use call::{CallError, CallOptions, RequestExt};
+pub use limits::{ServerOptions, ServerStats, WhenFull};
+pub use reconnect::{ReconnectingClient, RetryPolicy};
+
+use crate::twoparty::{connect_stream, network, serve_stream};
+use capnp::capability::{Client, FromClientHook};
+use capnp_rpc::{rpc_twoparty_capnp::Side, RpcSystem};
+use futures::io::{BufReader, BufWriter};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, Stream, StreamExt};
+use limits::{Gate, Tracker};
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::time::Duration;
+#[cfg(feature = "tls")]
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::compat::TokioAsyncReadCompatExt;
+
+/// The rustls version `serve_tls` and `connect_tls` take configurations from.
+#[cfg(feature = "tls")]
+pub use tokio_rustls::rustls;
+
+/// Buffer size of each direction of a [`local_pair`] pipe.
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+/// Serves `client` as the bootstrap capability of every connection accepted on `addr`, until
+/// `shutdown` completes.
+///
+/// Each connection runs its own `RpcSystem` on a `LocalSet` owned by this function. Once `shutdown`
+/// completes no further connections are accepted, and connections still open are closed on return.
+pub async fn serve_tcp<C>(addr: SocketAddr, client: C, options: ServerOptions, shutdown: impl Future<Output = ()>) -> io::Result<()>
+where
+    C: FromClientHook,
+{
+    serve_tcp_listener(TcpListener::bind(addr).await?, client, options, shutdown).await
+}
+
+/// Like [`serve_tcp`], on a listener that is already bound, e.g. to port 0 to let the OS pick a free
+/// port, which `listener.local_addr()` then reports.
+pub async fn serve_tcp_listener<C>(listener: TcpListener, client: C, options: ServerOptions, shutdown: impl Future<Output = ()>) -> io::Result<()>
+where
+    C: FromClientHook,
+{
+    let connections = futures::stream::unfold(listener, |listener| async move {
+        // RPC messages are small and latency-bound
+        let accepted = listener.accept().await.and_then(|(stream, _)| stream.set_nodelay(true).map(|()| stream.compat()));
+        Some((accepted.map(futures::future::ok), listener))
+    });
+    serve(connections, client, options, shutdown).await
+}
+
+/// Connects to a server started with [`serve_tcp`] (or any twoparty server) at `addr`.
+///
+/// Returns the server's bootstrap capability and the connection's `RpcSystem`, which must be polled,
+/// typically with `LocalSet::spawn_local`, for any call on the capability to make progress.
+pub async fn connect_tcp<C>(addr: SocketAddr) -> io::Result<(C, impl Future<Output = Result<(), capnp::Error>>)>
+where
+    C: FromClientHook,
+{
+    let stream = TcpStream::connect(addr).await?;
+    stream.set_nodelay(true)?;
+    Ok(connect_stream(stream.compat()))
+}
+
+/// Like [`serve_tcp`], with every connection wrapped in TLS using `config`, including its ALPN protocols.
+///
+/// Handshakes run alongside the accept loop, and a connection whose handshake fails is dropped
+/// without affecting the others.
+#[cfg(feature = "tls")]
+pub async fn serve_tls<C>(
+    addr: SocketAddr,
+    config: Arc<rustls::ServerConfig>,
+    client: C,
+    options: ServerOptions,
+    shutdown: impl Future<Output = ()>,
+) -> io::Result<()>
+where
+    C: FromClientHook,
+{
+    let listener = TcpListener::bind(addr).await?;
+    let acceptor = tokio_rustls::TlsAcceptor::from(config);
+    let connections = futures::stream::unfold((listener, acceptor), |(listener, acceptor)| async move {
+        let accepted = listener.accept().await.and_then(|(stream, _)| stream.set_nodelay(true).map(|()| stream));
+        let handshake = accepted.map(|stream| {
+            let acceptor = acceptor.clone();
+            async move { acceptor.accept(stream).await.map(|stream| stream.compat()) }
+        });
+        Some((handshake, (listener, acceptor)))
+    });
+    serve(connections, client, options, shutdown).await
+}
+
+/// Like [`connect_tcp`], over TLS using `config`, including its ALPN protocols. The server's certificate
+/// must be valid for `server_name`; if it is not, the handshake fails and so does this.
+#[cfg(feature = "tls")]
+pub async fn connect_tls<C>(
+    addr: SocketAddr,
+    config: Arc<rustls::ClientConfig>,
+    server_name: rustls::pki_types::ServerName<'static>,
+) -> io::Result<(C, impl Future<Output = Result<(), capnp::Error>>)>
+where
+    C: FromClientHook,
+{
+    let stream = TcpStream::connect(addr).await?;
+    stream.set_nodelay(true)?;
+    let stream = tokio_rustls::TlsConnector::from(config).connect(server_name, stream).await?;
+    Ok(connect_stream(stream.compat()))
+}
+
+/// Like [`serve_tcp`], on a Unix domain socket at `path`.
+///
+/// A socket file left behind by a server that exited without cleaning up is replaced; if another
+/// server is still listening on it, this fails with `AddrInUse`. The socket file is removed on return.
+#[cfg(unix)]
+pub async fn serve_unix<C>(
+    path: impl AsRef<std::path::Path>,
+    client: C,
+    options: ServerOptions,
+    shutdown: impl Future<Output = ()>,
+) -> io::Result<()>
+where
+    C: FromClientHook,
+{
+    let path = path.as_ref();
+    if path.exists() {
+        match tokio::net::UnixStream::connect(path).await {
+            Ok(_) => return Err(io::Error::new(io::ErrorKind::AddrInUse, format!("a server is already listening on {}", path.display()))),
+            Err(_) => std::fs::remove_file(path)?,
+        }
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+    let _socket_file = SocketFile(path.to_path_buf());
+    let connections = futures::stream::unfold(listener, |listener| async move {
+        let accepted = listener.accept().await.map(|(stream, _)| stream.compat());
+        Some((accepted.map(futures::future::ok), listener))
+    });
+    serve(connections, client, options, shutdown).await
+}
+
+/// Like [`connect_tcp`], to a server listening on the Unix domain socket at `path`.
+#[cfg(unix)]
+pub async fn connect_unix<C>(path: impl AsRef<std::path::Path>) -> io::Result<(C, impl Future<Output = Result<(), capnp::Error>>)>
+where
+    C: FromClientHook,
+{
+    let stream = tokio::net::UnixStream::connect(path).await?;
+    Ok(connect_stream(stream.compat()))
+}
+
+/// Connects a client to `server` through an in-memory pipe, with no sockets involved, e.g. to exercise
+/// a server implementation in a test.
+///
+/// The returned future drives both ends and must be polled like the one from [`connect_tcp`]; it
+/// completes once the client side is dropped.
+pub fn local_pair<C>(server: C) -> (C, impl Future<Output = Result<(), capnp::Error>>)
+where
+    C: FromClientHook,
+{
+    let (client_end, server_end) = tokio::io::duplex(PIPE_CAPACITY);
+    let server_system = serve_stream(server_end.compat(), server);
+    let (client, client_system) = connect_stream(client_end.compat());
+    (client, async move { futures::future::try_join(client_system, server_system).await.map(|_| ()) })
+}
+
+/// How long the accept loop pauses after a failed accept, so a persistent error such as running out of
+/// file descriptors does not turn it into a busy loop.
+const ACCEPT_ERROR_PAUSE: Duration = Duration::from_millis(10);
+
+/// Accepts `connections` until `shutdown` completes or the stream ends, serving `client` on each. Every
+/// connection is a future of the stream to talk over, so a handshake does not hold up the next accept.
+///
+/// A failed accept only costs that connection: it is counted in [`ServerStats::accept_errors`] and
+/// logged with the `tracing` feature, and the loop goes on accepting.
+async fn serve<F, S, C>(
+    connections: impl Stream<Item = io::Result<F>>,
+    client: C,
+    options: ServerOptions,
+    shutdown: impl Future<Output = ()>,
+) -> io::Result<()>
+where
+    F: Future<Output = io::Result<S>> + 'static,
+    S: AsyncRead + AsyncWrite + 'static,
+    C: FromClientHook,
+{
+    let bootstrap = Client::new(client.into_client_hook());
+    let tracker = Rc::new(Tracker::new(&options));
+    let options = Rc::new(options);
+    let local = tokio::task::LocalSet::new();
+    local.run_until(async move {
+        tokio::pin!(connections, shutdown);
+        loop {
+            let queued = options.when_full == WhenFull::Queue && tracker.connections() >= options.max_connections;
+            tokio::select! {
+                _ = &mut shutdown => return Ok(()),
+                _ = tracker.slot_freed.notified(), if queued => {}
+                accepted = connections.next(), if !queued => match accepted {
+                    Some(Err(e)) => {
+                        warn_accept(&e);
+                        tracker.update(|stats| stats.accept_errors += 1);
+                        tokio::time::sleep(ACCEPT_ERROR_PAUSE).await;
+                    }
+                    Some(Ok(connection)) if tracker.connections() >= options.max_connections => {
+                        // Under `WhenFull::Reject`; dropping the connection closes it
+                        drop(connection);
+                        tracker.update(|stats| stats.rejected += 1);
+                    }
+                    Some(Ok(connection)) => {
+                        let open = tracker.open();
+                        let (bootstrap, tracker, options) = (bootstrap.clone(), tracker.clone(), options.clone());
+                        tokio::task::spawn_local(async move {
+                            let _open = open;
+                            if let Ok(stream) = connection.await {
+                                let gate = Gate::new(&options, tracker);
+                                let (reader, writer) = stream.split();
+                                let reader = BufReader::with_capacity(options.read_buffer_size, reader);
+                                let writer = BufWriter::with_capacity(options.write_buffer_size, writer);
+                                let network = network(reader, writer, Side::Server, options.reader_options);
+                                let _ = RpcSystem::new(network, Some(gate.throttle(&bootstrap))).await;
+                            }
+                        });
+                    }
+                    None => return Ok(()),
+                },
+            }
+        }
+    }).await
+}
+
+fn warn_accept(error: &io::Error) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(error = %error, "failed to accept a connection");
+    #[cfg(not(feature = "tracing"))]
+    let _ = error;
+}
+
+/// Removes a Unix socket file when the server listening on it stops.
+#[cfg(unix)]
+struct SocketFile(std::path::PathBuf);
+
+#[cfg(unix)]
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
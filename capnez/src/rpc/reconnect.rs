@@ -0,0 +1,176 @@
+//! Clients that outlive the connection they were created on.
+
+use super::{connect_tcp, CallError};
+use capnp::capability::FromClientHook;
+use futures::future::LocalBoxFuture;
+use futures::FutureExt;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+type Connection<C> = LocalBoxFuture<'static, io::Result<(C, LocalBoxFuture<'static, Result<(), capnp::Error>>)>>;
+
+/// How [`ReconnectingClient`] retries a failed connection attempt.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Wait before the second attempt; the first is made right away.
+    pub initial_delay: Duration,
+    /// Factor the wait grows by after each further failed attempt.
+    pub multiplier: f64,
+    pub max_delay: Duration,
+    /// Attempts before giving up with [`CallError::Connect`]; `None` retries forever.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self { initial_delay: Duration::from_millis(100), multiplier: 2.0, max_delay: Duration::from_secs(30), max_attempts: Some(10) }
+    }
+}
+
+impl RetryPolicy {
+    /// Wait before attempt number `attempt`, counting from zero.
+    fn delay(&self, attempt: u32) -> Duration {
+        if attempt == 0 {
+            return Duration::ZERO;
+        }
+        let secs = self.initial_delay.as_secs_f64() * self.multiplier.powi(attempt.saturating_sub(1).min(i32::MAX as u32) as i32);
+        Duration::try_from_secs_f64(secs).map_or(self.max_delay, |delay| delay.min(self.max_delay))
+    }
+}
+
+/// A bootstrap capability that is reconnected, per its [`RetryPolicy`], once its connection is lost.
+///
+/// Connecting is lazy: the first call connects, and so does the first call after the connection's
+/// `RpcSystem` stops or a call fails with a `Disconnected` error. Calls in flight when the connection
+/// drops fail right away with [`CallError::Retryable`]. Like the clients it wraps, this must be used
+/// from within a `LocalSet`, on which it spawns each connection's `RpcSystem`.
+///
+/// ```ignore
+/// let hello_world = ReconnectingClient::<hello_world::Client>::tcp(addr, RetryPolicy::default());
+/// let reply = local.run_until(hello_world.call(|client| {
+///     let mut request = client.say_hello_request();
+///     request.get().set_name("world");
+///     request.send_timeout(Duration::from_secs(5))
+/// })).await?;
+/// ```
+pub struct ReconnectingClient<C> {
+    inner: Rc<Inner<C>>,
+}
+
+impl<C> Clone for ReconnectingClient<C> {
+    fn clone(&self) -> Self {
+        Self { inner: self.inner.clone() }
+    }
+}
+
+struct Inner<C> {
+    policy: RetryPolicy,
+    state: Mutex<State<C>>,
+}
+
+struct State<C> {
+    connect: Box<dyn FnMut() -> Connection<C>>,
+    /// Counts connections made, so a failed call only drops the connection it was made on.
+    generation: u64,
+    live: Option<Live<C>>,
+}
+
+struct Live<C> {
+    client: C,
+    system: JoinHandle<()>,
+}
+
+impl<C> Drop for Inner<C> {
+    fn drop(&mut self) {
+        if let Some(live) = self.state.get_mut().live.take() {
+            live.system.abort();
+        }
+    }
+}
+
+impl<C> ReconnectingClient<C>
+where
+    C: FromClientHook + Clone + 'static,
+{
+    /// Connects with `connect`, which returns a capability and the `RpcSystem` driving it like
+    /// [`connect_tcp`] and its siblings do.
+    pub fn new<F, Fut, S>(mut connect: F, policy: RetryPolicy) -> Self
+    where
+        F: FnMut() -> Fut + 'static,
+        Fut: Future<Output = io::Result<(C, S)>> + 'static,
+        S: Future<Output = Result<(), capnp::Error>> + 'static,
+    {
+        let connect: Box<dyn FnMut() -> Connection<C>> =
+            Box::new(move || connect().map(|connected| connected.map(|(client, system)| (client, system.boxed_local()))).boxed_local());
+        let state = State { connect, generation: 0, live: None };
+        Self { inner: Rc::new(Inner { policy, state: Mutex::new(state) }) }
+    }
+
+    /// Connects to a server started with `serve_tcp` at `addr`.
+    pub fn tcp(addr: SocketAddr, policy: RetryPolicy) -> Self {
+        Self::new(move || connect_tcp::<C>(addr), policy)
+    }
+
+    /// The capability on the current connection, connecting first if there is none.
+    pub async fn client(&self) -> Result<C, CallError> {
+        self.connected().await.map(|(client, _)| client)
+    }
+
+    /// Runs `call` on the current connection's capability. If it fails with [`CallError::Retryable`], the
+    /// connection is dropped, failing its other calls likewise, and the next call reconnects.
+    pub async fn call<T, F, Fut>(&self, call: F) -> Result<T, CallError>
+    where
+        F: FnOnce(C) -> Fut,
+        Fut: Future<Output = Result<T, CallError>>,
+    {
+        let (client, generation) = self.connected().await?;
+        let result = call(client).await;
+        if let Err(CallError::Retryable(_)) = &result {
+            self.disconnect(generation).await;
+        }
+        result
+    }
+
+    async fn connected(&self) -> Result<(C, u64), CallError> {
+        let mut state = self.inner.state.lock().await;
+        if let Some(live) = &state.live {
+            if !live.system.is_finished() {
+                return Ok((live.client.clone(), state.generation));
+            }
+        }
+        state.live = None;
+
+        let policy = &self.inner.policy;
+        let mut attempt = 0;
+        loop {
+            tokio::time::sleep(policy.delay(attempt)).await;
+            attempt += 1;
+            match (state.connect)().await {
+                Ok((client, system)) => {
+                    let system = tokio::task::spawn_local(async move {
+                        let _ = system.await;
+                    });
+                    state.generation += 1;
+                    state.live = Some(Live { client: client.clone(), system });
+                    return Ok((client, state.generation));
+                }
+                Err(e) if policy.max_attempts.is_some_and(|max| attempt >= max) => return Err(CallError::Connect(e)),
+                Err(_) => {}
+            }
+        }
+    }
+
+    async fn disconnect(&self, generation: u64) {
+        let mut state = self.inner.state.lock().await;
+        if state.generation == generation {
+            if let Some(live) = state.live.take() {
+                live.system.abort();
+            }
+        }
+    }
+}
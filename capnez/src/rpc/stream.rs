@@ -0,0 +1,131 @@
+//! Runtime side of streaming methods, used by the glue capnez-codegen generates for them.
+//!
+//! The caller passes the server a receiver capability backed by an [`ItemSender`]; each chunk the server
+//! pushes to it lands in the matching [`ItemStream`], which yields the items one at a time. Only a few
+//! chunks are buffered, and a push is not acknowledged until its chunk is, so a slow consumer holds back
+//! the server rather than piling up items in memory.
+
+use capnp::capability::FromServer;
+use futures::channel::mpsc;
+use futures::future::LocalBoxFuture;
+use futures::{FutureExt, SinkExt, StreamExt};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// The trait `send_stream` takes items from.
+pub use futures::Stream;
+
+/// Chunks buffered between a receiver and its stream.
+const BUFFERED_CHUNKS: usize = 4;
+
+enum Chunk<T> {
+    Items(Vec<T>),
+    Done,
+}
+
+/// Where a receiver capability forwards the chunks pushed to it.
+pub struct ItemSender<T> {
+    tx: mpsc::Sender<Chunk<T>>,
+}
+
+impl<T: 'static> ItemSender<T> {
+    /// Hands `items` to the stream, resolving once it has room for them.
+    pub fn send(&self, items: Vec<T>) -> impl Future<Output = Result<(), capnp::Error>> + 'static {
+        let mut tx = self.tx.clone();
+        async move { tx.send(Chunk::Items(items)).await.map_err(|_| dropped()) }
+    }
+
+    /// Ends the stream once the items sent so far have been taken.
+    pub fn finish(&self) -> impl Future<Output = Result<(), capnp::Error>> + 'static {
+        let mut tx = self.tx.clone();
+        async move { tx.send(Chunk::Done).await.map_err(|_| dropped()) }
+    }
+}
+
+fn dropped() -> capnp::Error {
+    capnp::Error::failed("the stream was dropped by the caller".to_string())
+}
+
+/// The items of a streaming call, in the order the server sent them.
+///
+/// Fails if the call fails, or if the receiver is released before the server ends the stream, e.g.
+/// because the connection dropped. Dropping the stream fails the server's next push, which stops it.
+pub struct ItemStream<T> {
+    rx: mpsc::Receiver<Chunk<T>>,
+    chunk: std::vec::IntoIter<T>,
+    call: Option<LocalBoxFuture<'static, Result<(), capnp::Error>>>,
+    finished: bool,
+}
+
+// Nothing is pinned in place: the call is boxed, and the chunk is only ever moved out of
+impl<T> Unpin for ItemStream<T> {}
+
+impl<T> ItemStream<T> {
+    /// Ties the stream to the call feeding it, so that the call failing ends the stream with its error.
+    pub fn with_call<R: 'static>(mut self, call: impl Future<Output = Result<R, capnp::Error>> + 'static) -> Self {
+        self.call = Some(call.map(|result| result.map(drop)).boxed_local());
+        self
+    }
+}
+
+impl<T> Stream for ItemStream<T> {
+    type Item = Result<T, capnp::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            if let Some(item) = this.chunk.next() {
+                return Poll::Ready(Some(Ok(item)));
+            }
+            if this.finished {
+                return Poll::Ready(None);
+            }
+            if let Some(Poll::Ready(result)) = this.call.as_mut().map(|call| call.poll_unpin(cx)) {
+                this.call = None;
+                if let Err(e) = result {
+                    this.finished = true;
+                    return Poll::Ready(Some(Err(e)));
+                }
+            }
+            match futures::ready!(this.rx.poll_next_unpin(cx)) {
+                Some(Chunk::Items(items)) => this.chunk = items.into_iter(),
+                Some(Chunk::Done) => this.finished = true,
+                None => {
+                    this.finished = true;
+                    return Poll::Ready(Some(Err(capnp::Error::disconnected("the stream's receiver was released before it ended".to_string()))));
+                }
+            }
+        }
+    }
+}
+
+/// A receiver capability of type `C` and the stream of the items pushed to it.
+pub fn channel<C, T>() -> (C, ItemStream<T>)
+where
+    C: FromServer<ItemSender<T>>,
+    T: 'static,
+{
+    let (tx, rx) = mpsc::channel(BUFFERED_CHUNKS);
+    let stream = ItemStream { rx, chunk: Vec::new().into_iter(), call: None, finished: false };
+    (capnp_rpc::new_client(ItemSender { tx }), stream)
+}
+
+/// Pushes `items` in chunks of at most `chunk` with `push`, each once the previous one was taken, then
+/// ends the stream with `done`.
+pub async fn send_chunks<T, P, D>(
+    items: impl Stream<Item = T>,
+    chunk: usize,
+    mut push: impl FnMut(Vec<T>) -> P,
+    done: impl FnOnce() -> D,
+) -> Result<(), capnp::Error>
+where
+    P: Future<Output = Result<(), capnp::Error>>,
+    D: Future<Output = Result<(), capnp::Error>>,
+{
+    let mut chunks = std::pin::pin!(items.chunks(chunk));
+    while let Some(items) = chunks.next().await {
+        push(items).await?;
+    }
+    done().await
+}
@@ -0,0 +1,48 @@
+//! capnp-rpc over a byte stream the caller already has, with no tokio involved: the
+//! `twoparty::VatNetwork` and `RpcSystem` setup for, e.g., a `wasm::MessagePortStream` in the browser.
+//!
+//! The returned futures drive the connection and must be polled, on one thread, by whatever executor
+//! the platform has. [`crate::rpc`] builds its TCP, TLS and Unix transports on the same setup.
+
+use capnp::capability::{Client, FromClientHook};
+use capnp::message::ReaderOptions;
+use capnp_rpc::{rpc_twoparty_capnp::Side, twoparty, RpcSystem, VatNetwork};
+use futures::io::{BufReader, BufWriter};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite};
+use std::future::Future;
+
+/// Connects to the twoparty server at the other end of `stream`.
+///
+/// Returns the server's bootstrap capability and the connection's `RpcSystem`, which must be polled
+/// for any call on the capability to make progress.
+pub fn connect_stream<S, C>(stream: S) -> (C, impl Future<Output = Result<(), capnp::Error>>)
+where
+    S: AsyncRead + AsyncWrite + 'static,
+    C: FromClientHook,
+{
+    let (reader, writer) = stream.split();
+    let network = network(BufReader::new(reader), BufWriter::new(writer), Side::Client, ReaderOptions::new());
+    let mut rpc_system = RpcSystem::new(network, None);
+    let client = rpc_system.bootstrap(Side::Server);
+    (client, rpc_system)
+}
+
+/// Serves `client` as the bootstrap capability of the connection over `stream`. The returned future
+/// completes once the other end disconnects.
+pub fn serve_stream<S, C>(stream: S, client: C) -> impl Future<Output = Result<(), capnp::Error>>
+where
+    S: AsyncRead + AsyncWrite + 'static,
+    C: FromClientHook,
+{
+    let (reader, writer) = stream.split();
+    let network = network(BufReader::new(reader), BufWriter::new(writer), Side::Server, ReaderOptions::new());
+    RpcSystem::new(network, Some(Client::new(client.into_client_hook())))
+}
+
+pub(crate) fn network<R, W>(reader: R, writer: W, side: Side, options: ReaderOptions) -> Box<dyn VatNetwork<Side>>
+where
+    R: AsyncRead + Unpin + 'static,
+    W: AsyncWrite + Unpin + 'static,
+{
+    Box::new(twoparty::VatNetwork::new(reader, writer, side, options))
+}
@@ -0,0 +1,199 @@
+//! Byte-stream adapter over message ports, for running capnp-rpc in the browser.
+//!
+//! capnp-rpc's `twoparty::VatNetwork` needs an `AsyncRead + AsyncWrite` byte stream, but browser
+//! channels (`postMessage`, `MessagePort`, WebSocket) deliver whole messages. [`MessagePortStream`]
+//! bridges the two over any `Sink`/`Stream` of byte vectors, typically a pair of
+//! `futures::channel::mpsc` channels fed from and draining into the JavaScript side:
+//!
+//! ```ignore
+//! let (to_js, from_rust) = futures::channel::mpsc::unbounded::<Vec<u8>>();
+//! let (to_rust, from_js) = futures::channel::mpsc::unbounded::<Vec<u8>>();
+//! // forward `from_rust` to port.postMessage(...) and port.onmessage into `to_rust`
+//! let (greeter, rpc_system) = capnez::wasm::connect_port::<greeter::Client, _, _>(to_js, from_js);
+//! wasm_bindgen_futures::spawn_local(async move { let _ = rpc_system.await; });
+//! ```
+//!
+//! The port carries the raw twoparty stream, so the far end may relay it to a native server.
+
+use crate::twoparty::{connect_stream, serve_stream};
+use capnp::capability::FromClientHook;
+use futures::{ready, AsyncRead, AsyncWrite, Sink, SinkExt, Stream, StreamExt};
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Presents a message-oriented port as a byte stream. Every flush sends the bytes written since the
+/// previous flush as one message; received messages are read back to back.
+pub struct MessagePortStream<Tx, Rx> {
+    tx: Tx,
+    rx: Rx,
+    incoming: Vec<u8>,
+    offset: usize,
+    outgoing: Vec<u8>,
+}
+
+impl<Tx, Rx> MessagePortStream<Tx, Rx> {
+    pub fn new(tx: Tx, rx: Rx) -> Self {
+        Self { tx, rx, incoming: Vec::new(), offset: 0, outgoing: Vec::new() }
+    }
+}
+
+impl<Tx: Unpin, Rx> AsyncRead for MessagePortStream<Tx, Rx>
+where
+    Rx: Stream<Item = Vec<u8>> + Unpin,
+{
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        while this.offset >= this.incoming.len() {
+            match ready!(this.rx.poll_next_unpin(cx)) {
+                Some(message) => {
+                    this.incoming = message;
+                    this.offset = 0;
+                }
+                None => return Poll::Ready(Ok(0)),
+            }
+        }
+        let n = buf.len().min(this.incoming.len() - this.offset);
+        buf[..n].copy_from_slice(&this.incoming[this.offset..this.offset + n]);
+        this.offset += n;
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<Tx, Rx: Unpin> AsyncWrite for MessagePortStream<Tx, Rx>
+where
+    Tx: Sink<Vec<u8>> + Unpin,
+    Tx::Error: std::fmt::Display,
+{
+    fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.get_mut().outgoing.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.outgoing.is_empty() {
+            ready!(this.tx.poll_ready_unpin(cx)).map_err(port_error)?;
+            this.tx.start_send_unpin(std::mem::take(&mut this.outgoing)).map_err(port_error)?;
+        }
+        this.tx.poll_flush_unpin(cx).map_err(port_error)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        self.get_mut().tx.poll_close_unpin(cx).map_err(port_error)
+    }
+}
+
+/// Connects to the server at the other end of a port, as [`connect_stream`] does for a byte stream.
+pub fn connect_port<C, Tx, Rx>(tx: Tx, rx: Rx) -> (C, impl Future<Output = Result<(), capnp::Error>>)
+where
+    C: FromClientHook,
+    Tx: Sink<Vec<u8>> + Unpin + 'static,
+    Tx::Error: std::fmt::Display,
+    Rx: Stream<Item = Vec<u8>> + Unpin + 'static,
+{
+    connect_stream(MessagePortStream::new(tx, rx))
+}
+
+/// Serves `client` to the other end of a port, as [`serve_stream`] does for a byte stream.
+pub fn serve_port<C, Tx, Rx>(tx: Tx, rx: Rx, client: C) -> impl Future<Output = Result<(), capnp::Error>>
+where
+    C: FromClientHook,
+    Tx: Sink<Vec<u8>> + Unpin + 'static,
+    Tx::Error: std::fmt::Display,
+    Rx: Stream<Item = Vec<u8>> + Unpin + 'static,
+{
+    serve_stream(MessagePortStream::new(tx, rx), client)
+}
+
+fn port_error(e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, format!("message port closed: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::channel::mpsc;
+    use futures::executor::block_on;
+    use futures::{AsyncReadExt, AsyncWriteExt};
+
+    type Port = MessagePortStream<mpsc::UnboundedSender<Vec<u8>>, mpsc::UnboundedReceiver<Vec<u8>>>;
+
+    /// A stream and the two ends of its port the other side would hold.
+    fn port() -> (Port, mpsc::UnboundedSender<Vec<u8>>, mpsc::UnboundedReceiver<Vec<u8>>) {
+        let (tx, sent) = mpsc::unbounded();
+        let (incoming, rx) = mpsc::unbounded();
+        (MessagePortStream::new(tx, rx), incoming, sent)
+    }
+
+    #[test]
+    fn each_flush_sends_one_message() {
+        let (mut stream, _incoming, sent) = port();
+        block_on(async {
+            stream.write_all(b"ab").await.unwrap();
+            stream.write_all(b"cd").await.unwrap();
+            stream.flush().await.unwrap();
+            // Nothing was written since, so this flush sends nothing
+            stream.flush().await.unwrap();
+            stream.write_all(b"e").await.unwrap();
+            stream.flush().await.unwrap();
+            // Unflushed bytes are never sent
+            stream.write_all(b"f").await.unwrap();
+        });
+        drop(stream);
+        assert_eq!(block_on(sent.collect::<Vec<_>>()), [b"abcd".to_vec(), b"e".to_vec()]);
+    }
+
+    #[test]
+    fn reads_run_across_message_boundaries() {
+        let (mut stream, incoming, _sent) = port();
+        incoming.unbounded_send(vec![1, 2, 3]).unwrap();
+        incoming.unbounded_send(vec![]).unwrap();
+        incoming.unbounded_send(vec![4, 5]).unwrap();
+        block_on(async {
+            let mut buf = [0; 2];
+            assert_eq!(stream.read(&mut buf).await.unwrap(), 2);
+            assert_eq!(buf, [1, 2]);
+            // A read never spans two messages, and empty messages are skipped
+            assert_eq!(stream.read(&mut buf).await.unwrap(), 1);
+            assert_eq!(buf[0], 3);
+            assert_eq!(stream.read(&mut buf).await.unwrap(), 2);
+            assert_eq!(buf, [4, 5]);
+        });
+    }
+
+    #[test]
+    fn a_closed_port_reads_as_end_of_stream() {
+        let (mut stream, incoming, _sent) = port();
+        incoming.unbounded_send(vec![7]).unwrap();
+        drop(incoming);
+        let mut bytes = Vec::new();
+        block_on(stream.read_to_end(&mut bytes)).unwrap();
+        assert_eq!(bytes, [7]);
+    }
+
+    #[test]
+    fn flushing_into_a_closed_port_is_a_broken_pipe() {
+        let (mut stream, _incoming, sent) = port();
+        drop(sent);
+        let error = block_on(async {
+            stream.write_all(b"lost").await.unwrap();
+            stream.flush().await.unwrap_err()
+        });
+        assert_eq!(error.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn close_flushes_and_ends_the_port() {
+        let (mut stream, _incoming, sent) = port();
+        block_on(async {
+            stream.write_all(b"bye").await.unwrap();
+            stream.close().await.unwrap();
+        });
+        // The port ends while the stream is still alive
+        assert_eq!(block_on(sent.collect::<Vec<_>>()), [b"bye".to_vec()]);
+        drop(stream);
+    }
+}
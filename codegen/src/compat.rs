@@ -0,0 +1,494 @@
+//! Wire-compatibility checks between two schema texts, e.g. a committed snapshot and the schema the
+//! current code generates.
+//!
+//! Unlike `capnez.lock`, which only remembers numbering, this compares full schemas, so a directory
+//! of historical snapshots (`schemas/v1.capnp`, `schemas/v2.capnp`, ...) can each be checked in CI.
+//! The parser covers what capnez emits: enums, structs (with `Optional*` wrapper unions and
+//! defaults), and interfaces. Import, annotation and comment lines are skipped.
+//!
+//! | Change                                   | Severity   |
+//! |------------------------------------------|------------|
+//! | field, enumerant, method or type added    | compatible |
+//! | field or enumerant renamed, same number   | warning    |
+//! | method renamed, same number               | warning    |
+//! | field default changed                     | warning    |
+//! | struct or enum removed                    | warning    |
+//! | field, enumerant or method removed        | breaking   |
+//! | field, parameter or result type changed   | breaking   |
+//! | method renumbered                         | breaking   |
+//! | interface removed                         | breaking   |
+//!
+//! Types are compared by name, so renaming a struct shows up as a type change of every field that
+//! holds it, even though struct names never reach the wire.
+
+use super::{CapnpEnum, CapnpInterface, CapnpStruct, CapnpType, StructField};
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// How a change affects messages and calls between the two schema versions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Old and new peers understand each other.
+    Compatible,
+    /// The wire format is unaffected, but code or readers may notice, e.g. a renamed field.
+    Warning,
+    /// Old and new peers misread each other's messages or fail each other's calls.
+    Breaking,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Compatible => "ok",
+            Self::Warning => "warning",
+            Self::Breaking => "breaking",
+        })
+    }
+}
+
+/// What changed about a type or one of its members.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    /// Same number, different name.
+    Renamed,
+    TypeChanged,
+    DefaultChanged,
+    /// Same name, different number.
+    Renumbered,
+}
+
+/// One difference between the two schemas.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change {
+    pub kind: ChangeKind,
+    pub severity: Severity,
+    /// Where the change is, e.g. `Person.age` or `Person`.
+    pub path: String,
+    /// Human-readable account of the change.
+    pub message: String,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
+
+/// Every difference [`compare_schemas`] found, in schema order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CompatReport {
+    pub changes: Vec<Change>,
+}
+
+impl CompatReport {
+    /// Whether any change breaks wire compatibility.
+    pub fn is_breaking(&self) -> bool {
+        self.changes.iter().any(|c| c.severity == Severity::Breaking)
+    }
+
+    /// The changes of exactly `severity`.
+    pub fn with_severity(&self, severity: Severity) -> impl Iterator<Item = &Change> {
+        self.changes.iter().filter(move |c| c.severity == severity)
+    }
+
+    fn push(&mut self, kind: ChangeKind, severity: Severity, path: String, message: String) {
+        self.changes.push(Change { kind, severity, path, message });
+    }
+}
+
+impl fmt::Display for CompatReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.changes.is_empty() {
+            return writeln!(f, "no changes");
+        }
+        writeln!(
+            f,
+            "{} breaking, {} warnings, {} compatible",
+            self.with_severity(Severity::Breaking).count(),
+            self.with_severity(Severity::Warning).count(),
+            self.with_severity(Severity::Compatible).count(),
+        )?;
+        for change in &self.changes {
+            writeln!(f, "  {}", change)?;
+        }
+        Ok(())
+    }
+}
+
+/// Classifies the changes from the schema text `old` to `new`; fails if either is not a schema
+/// capnez could have generated.
+pub fn compare_schemas(old: &str, new: &str) -> Result<CompatReport> {
+    let old = Model::parse(old).context("Failed to parse the old schema")?;
+    let new = Model::parse(new).context("Failed to parse the new schema")?;
+    let mut report = CompatReport::default();
+    compare_enums(&old, &new, &mut report);
+    compare_structs(&old, &new, &mut report);
+    compare_interfaces(&old, &new, &mut report);
+    Ok(report)
+}
+
+fn compare_enums(old: &Model, new: &Model, report: &mut CompatReport) {
+    for e in &old.enums {
+        let Some(current) = new.enums.iter().find(|n| n.name == e.name) else {
+            report.push(ChangeKind::Removed, Severity::Warning, e.name.clone(), format!("enum `{}` was removed", e.name));
+            continue;
+        };
+        for (id, name) in e.variants.iter().enumerate() {
+            let path = format!("{}.{}", e.name, name);
+            match current.variants.get(id) {
+                None => report.push(ChangeKind::Removed, Severity::Breaking, path.clone(), format!("enumerant `{}` (@{}) was removed", path, id)),
+                Some(renamed) if renamed != name => report.push(
+                    ChangeKind::Renamed, Severity::Warning, format!("{}.{}", e.name, renamed),
+                    format!("enumerant `{}` (@{}) was renamed to `{}`", path, id, renamed),
+                ),
+                Some(_) => {}
+            }
+        }
+        for (id, name) in current.variants.iter().enumerate().skip(e.variants.len()) {
+            let path = format!("{}.{}", e.name, name);
+            report.push(ChangeKind::Added, Severity::Compatible, path.clone(), format!("enumerant `{}` (@{}) was added", path, id));
+        }
+    }
+    for e in new.enums.iter().filter(|n| !old.enums.iter().any(|e| e.name == n.name)) {
+        report.push(ChangeKind::Added, Severity::Compatible, e.name.clone(), format!("enum `{}` was added", e.name));
+    }
+}
+
+fn compare_structs(old: &Model, new: &Model, report: &mut CompatReport) {
+    // Wrapper structs are named after their payload, so their changes show up as field type changes
+    let user = |model: &Model| model.structs.iter().filter(|s| !s.is_optional).cloned().collect::<Vec<_>>();
+    let (old_structs, new_structs) = (user(old), user(new));
+    for s in &old_structs {
+        let Some(current) = new_structs.iter().find(|n| n.name == s.name) else {
+            report.push(ChangeKind::Removed, Severity::Warning, s.name.clone(), format!("struct `{}` was removed", s.name));
+            continue;
+        };
+        let by_id = |s: &CapnpStruct| s.fields.iter().map(|f| (f.1, f.clone())).collect::<BTreeMap<_, _>>();
+        let (before, after) = (by_id(s), by_id(current));
+        for (id, (name, _, ty, default)) in &before {
+            let path = format!("{}.{}", s.name, name);
+            let Some((new_name, _, new_ty, new_default)) = after.get(id) else {
+                report.push(ChangeKind::Removed, Severity::Breaking, path.clone(), format!("field `{}` (@{}) was removed", path, id));
+                continue;
+            };
+            let new_path = format!("{}.{}", s.name, new_name);
+            if ty.to_string() != new_ty.to_string() {
+                report.push(
+                    ChangeKind::TypeChanged, Severity::Breaking, new_path.clone(),
+                    format!("field `{}` (@{}) changed type from {} to {}", new_path, id, ty, new_ty),
+                );
+            } else if name != new_name {
+                report.push(
+                    ChangeKind::Renamed, Severity::Warning, new_path.clone(),
+                    format!("field `{}` (@{}) was renamed to `{}`", path, id, new_name),
+                );
+            }
+            if default != new_default {
+                let shown = |d: &Option<String>| d.clone().unwrap_or_else(|| "none".to_string());
+                report.push(
+                    ChangeKind::DefaultChanged, Severity::Warning, new_path.clone(),
+                    format!("field `{}` (@{}) changed default from {} to {}", new_path, id, shown(default), shown(new_default)),
+                );
+            }
+        }
+        for (id, (name, ..)) in after.iter().filter(|(id, _)| !before.contains_key(id)) {
+            let path = format!("{}.{}", s.name, name);
+            report.push(ChangeKind::Added, Severity::Compatible, path.clone(), format!("field `{}` (@{}) was added", path, id));
+        }
+    }
+    for s in new_structs.iter().filter(|n| !old_structs.iter().any(|s| s.name == n.name)) {
+        report.push(ChangeKind::Added, Severity::Compatible, s.name.clone(), format!("struct `{}` was added", s.name));
+    }
+}
+
+fn compare_interfaces(old: &Model, new: &Model, report: &mut CompatReport) {
+    for i in &old.interfaces {
+        let Some(current) = new.interfaces.iter().find(|n| n.name == i.name) else {
+            report.push(ChangeKind::Removed, Severity::Breaking, i.name.clone(), format!("interface `{}` was removed", i.name));
+            continue;
+        };
+        let position = |methods: &[Method], name: &str| methods.iter().position(|(n, _, _)| n == name);
+        for (id, (name, params, result)) in i.methods.iter().enumerate() {
+            let path = format!("{}.{}", i.name, name);
+            match position(&current.methods, name) {
+                Some(new_id) if new_id != id => report.push(
+                    ChangeKind::Renumbered, Severity::Breaking, path.clone(),
+                    format!("method `{}` moved from @{} to @{}", path, id, new_id),
+                ),
+                Some(_) => {
+                    let (_, new_params, new_result) = &current.methods[id];
+                    compare_signature(&path, (params, result), (new_params, new_result), report);
+                }
+                None => match current.methods.get(id).filter(|(n, _, _)| position(&i.methods, n).is_none()) {
+                    Some((new_name, new_params, new_result)) => {
+                        report.push(
+                            ChangeKind::Renamed, Severity::Warning, format!("{}.{}", i.name, new_name),
+                            format!("method `{}` (@{}) was renamed to `{}`", path, id, new_name),
+                        );
+                        compare_signature(&format!("{}.{}", i.name, new_name), (params, result), (new_params, new_result), report);
+                    }
+                    None => report.push(ChangeKind::Removed, Severity::Breaking, path.clone(), format!("method `{}` (@{}) was removed", path, id)),
+                },
+            }
+        }
+        for (id, (name, _, _)) in current.methods.iter().enumerate() {
+            // Methods in a slot an old method was renamed out of were reported as renames above
+            let renamed = i.methods.get(id).is_some_and(|(old_name, _, _)| position(&current.methods, old_name).is_none());
+            if position(&i.methods, name).is_none() && !renamed {
+                let path = format!("{}.{}", i.name, name);
+                report.push(ChangeKind::Added, Severity::Compatible, path.clone(), format!("method `{}` (@{}) was added", path, id));
+            }
+        }
+    }
+    for i in new.interfaces.iter().filter(|n| !old.interfaces.iter().any(|i| i.name == n.name)) {
+        report.push(ChangeKind::Added, Severity::Compatible, i.name.clone(), format!("interface `{}` was added", i.name));
+    }
+}
+
+type Method = (String, Vec<(String, CapnpType)>, Option<(String, CapnpType)>);
+type Signature<'a> = (&'a Vec<(String, CapnpType)>, &'a Option<(String, CapnpType)>);
+
+/// Parameters are the fields of an implicit struct, numbered by position, and so are compared like fields.
+fn compare_signature(path: &str, (params, result): Signature, (new_params, new_result): Signature, report: &mut CompatReport) {
+    for (id, (name, ty)) in params.iter().enumerate() {
+        let param = format!("{}({})", path, name);
+        match new_params.get(id) {
+            None => report.push(ChangeKind::Removed, Severity::Breaking, param.clone(), format!("parameter `{}` of `{}` was removed", name, path)),
+            Some((_, new_ty)) if new_ty.to_string() != ty.to_string() => report.push(
+                ChangeKind::TypeChanged, Severity::Breaking, param.clone(),
+                format!("parameter `{}` of `{}` changed type from {} to {}", name, path, ty, new_ty),
+            ),
+            Some((new_name, _)) if new_name != name => report.push(
+                ChangeKind::Renamed, Severity::Warning, format!("{}({})", path, new_name),
+                format!("parameter `{}` of `{}` was renamed to `{}`", name, path, new_name),
+            ),
+            Some(_) => {}
+        }
+    }
+    for (name, _) in new_params.iter().skip(params.len()) {
+        report.push(ChangeKind::Added, Severity::Compatible, format!("{}({})", path, name), format!("parameter `{}` of `{}` was added", name, path));
+    }
+    let shown = |result: &Option<(String, CapnpType)>| match result {
+        None => "nothing".to_string(),
+        Some((name, ty)) if name.is_empty() => ty.to_string(),
+        Some((name, ty)) => format!("({} :{})", name, ty),
+    };
+    if shown(result) != shown(new_result) {
+        let same_type = matches!((result, new_result), (Some((a, x)), Some((b, y))) if !a.is_empty() && !b.is_empty() && x.to_string() == y.to_string());
+        let (kind, severity) = if same_type { (ChangeKind::Renamed, Severity::Warning) } else { (ChangeKind::TypeChanged, Severity::Breaking) };
+        report.push(kind, severity, path.to_string(), format!("result of `{}` changed from {} to {}", path, shown(result), shown(new_result)));
+    }
+}
+
+/// The types of one schema text, in the model the generator builds from Rust sources.
+#[derive(Default)]
+struct Model {
+    structs: Vec<CapnpStruct>,
+    enums: Vec<CapnpEnum>,
+    interfaces: Vec<CapnpInterface>,
+}
+
+/// The declaration a line is inside of.
+enum Block {
+    Top,
+    Enum(CapnpEnum, Vec<(usize, String)>),
+    Struct(CapnpStruct, bool),
+    Interface(CapnpInterface, Vec<(usize, Method)>),
+}
+
+impl Model {
+    fn parse(text: &str) -> Result<Self> {
+        let mut model = Self::default();
+        let mut block = Block::Top;
+        for (n, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let at = || format!("line {}: `{}`", n + 1, line);
+            block = match block {
+                Block::Top => Self::parse_top(line).with_context(at)?,
+                Block::Enum(mut e, mut variants) => {
+                    if line == "}" {
+                        variants.sort_by_key(|(id, _)| *id);
+                        e.variants = variants.into_iter().map(|(_, name)| name).collect();
+                        model.enums.push(e);
+                        Block::Top
+                    } else {
+                        let (name, id) = line.strip_suffix(';').and_then(|l| l.split_once(" @")).with_context(at)?;
+                        variants.push((id.parse().with_context(at)?, name.to_string()));
+                        Block::Enum(e, variants)
+                    }
+                }
+                Block::Struct(mut s, in_union) => match line {
+                    "union {" => Block::Struct(s, true),
+                    "}" if in_union => Block::Struct(s, false),
+                    "}" => {
+                        // capnez only emits unions for `Option` wrappers: a `value` and a `none` arm
+                        if let [value, none] = s.fields.as_slice() {
+                            if value.0 == "value" && none.0 == "none" && s.name.starts_with("Optional") {
+                                s.is_optional = true;
+                                s.fields.truncate(1);
+                            }
+                        }
+                        s.fields.sort_by_key(|f| f.1);
+                        model.structs.push(s);
+                        Block::Top
+                    }
+                    _ => {
+                        s.fields.push(parse_field(line).with_context(at)?);
+                        Block::Struct(s, in_union)
+                    }
+                },
+                Block::Interface(mut i, mut methods) => {
+                    if line == "}" {
+                        methods.sort_by_key(|(id, _)| *id);
+                        i.methods = methods.into_iter().map(|(_, m)| m).collect();
+                        model.interfaces.push(i);
+                        Block::Top
+                    } else {
+                        methods.push(parse_method(line).with_context(at)?);
+                        Block::Interface(i, methods)
+                    }
+                }
+            };
+        }
+        if !matches!(block, Block::Top) {
+            bail!("Unexpected end of schema inside a declaration");
+        }
+        Ok(model)
+    }
+
+    fn parse_top(line: &str) -> Result<Block> {
+        if line.starts_with('@') || line.starts_with("using ") || line.starts_with('$') {
+            return Ok(Block::Top);
+        }
+        if let Some(name) = line.strip_prefix("enum ").and_then(|l| l.strip_suffix(" {")) {
+            let e = CapnpEnum { name: name.to_string(), variants: Vec::new(), rust_path: None, rust_variants: Vec::new() };
+            return Ok(Block::Enum(e, Vec::new()));
+        }
+        if let Some(name) = line.strip_prefix("struct ").and_then(|l| l.strip_suffix(" {")) {
+            let s = CapnpStruct {
+                name: name.to_string(),
+                fields: Vec::new(),
+                has_serde: false,
+                is_optional: false,
+                rust: None,
+                serde_with: BTreeMap::new(),
+                validate: BTreeMap::new(),
+                flatten: BTreeMap::new(),
+            };
+            return Ok(Block::Struct(s, false));
+        }
+        if let Some(header) = line.strip_prefix("interface ").and_then(|l| l.strip_suffix(" {")) {
+            let (name, extends) = match header.split_once(" extends(") {
+                Some((name, parents)) => {
+                    let parents = parents.strip_suffix(')').context("Unclosed `extends(`")?;
+                    (name, parents.split(", ").map(str::to_string).collect())
+                }
+                None => (header, Vec::new()),
+            };
+            let i = CapnpInterface { name: name.to_string(), methods: Vec::new(), extends, streams: Vec::new() };
+            return Ok(Block::Interface(i, Vec::new()));
+        }
+        bail!("Expected an enum, struct or interface")
+    }
+}
+
+/// `name @1 :Type;` or `name @1 :Type = default;`.
+fn parse_field(line: &str) -> Result<StructField> {
+    let (name, rest) = line.strip_suffix(';').and_then(|l| l.split_once(" @")).context("Expected a field")?;
+    let (id, rest) = rest.split_once(" :").context("Expected a field type")?;
+    let (ty, default) = match rest.split_once(" = ") {
+        Some((ty, default)) => (ty, Some(default.to_string())),
+        None => (rest, None),
+    };
+    Ok((name.to_string(), id.parse()?, parse_type(ty)?, default))
+}
+
+/// `name @1 (a :T, b :U) -> Result;`, with the result part optional.
+fn parse_method(line: &str) -> Result<(usize, Method)> {
+    let (name, rest) = line.strip_suffix(';').and_then(|l| l.split_once(" @")).context("Expected a method")?;
+    let (id, rest) = rest.split_once(" (").context("Expected method parameters")?;
+    let close = closing_paren(rest).context("Unclosed parameter list")?;
+    let params = match &rest[..close] {
+        "" => Vec::new(),
+        params => params.split(", ").map(|p| {
+            let (name, ty) = p.split_once(" :").context("Expected `name :Type`")?;
+            Ok((name.to_string(), parse_type(ty)?))
+        }).collect::<Result<_>>()?,
+    };
+    let result = match rest[close + 1..].trim() {
+        "" => None,
+        arrow => {
+            let result = arrow.strip_prefix("-> ").context("Expected `->`")?;
+            match result.strip_prefix('(').and_then(|r| r.strip_suffix(')')) {
+                Some(field) => {
+                    let (name, ty) = field.split_once(" :").context("Expected `(name :Type)`")?;
+                    Some((name.to_string(), parse_type(ty)?))
+                }
+                None => Some((String::new(), parse_type(result)?)),
+            }
+        }
+    };
+    Ok((id.parse()?, (name.to_string(), params, result)))
+}
+
+/// Index of the `)` closing a list whose `(` was just consumed.
+fn closing_paren(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return Some(i),
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+fn parse_type(text: &str) -> Result<CapnpType> {
+    Ok(match text {
+        "Text" => CapnpType::Text,
+        "Data" => CapnpType::Data,
+        "Bool" => CapnpType::Bool,
+        "Int8" => CapnpType::Int8,
+        "Int16" => CapnpType::Int16,
+        "Int32" => CapnpType::Int32,
+        "Int64" => CapnpType::Int64,
+        "UInt8" => CapnpType::UInt8,
+        "UInt16" => CapnpType::UInt16,
+        "UInt32" => CapnpType::UInt32,
+        "UInt64" => CapnpType::UInt64,
+        "Float32" => CapnpType::Float32,
+        "Float64" => CapnpType::Float64,
+        _ => match text.strip_prefix("List(").and_then(|t| t.strip_suffix(')')) {
+            Some(inner) => CapnpType::List(Box::new(parse_type(inner)?), None),
+            // Structs, enums, interfaces and `Optional*` wrappers all print as their name
+            None if !text.is_empty() && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.') => {
+                CapnpType::Struct(text.to_string())
+            }
+            None => bail!("Unsupported type `{}`", text),
+        },
+    })
+}
+
+/// `line` without a trailing `# comment`, leaving `#` inside text literals alone.
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
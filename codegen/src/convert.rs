@@ -0,0 +1,1086 @@
+//! Conversion impls appended to `schema_capnp.rs` for every collected struct and enum.
+//!
+//! For a struct `Person` this generates, on the Rust type itself:
+//!
+//! - `to_capnp(&self, person::Builder)` / `from_capnp(person::Reader) -> capnp::Result<Self>`
+//! - `to_capnp_bytes(&self) -> Vec<u8>` / `from_capnp_bytes(&[u8]) -> capnp::Result<Self>`
+//! - `capnp_size_hint(&self) -> usize`, an estimate of the length of `to_capnp_bytes`
+//! - `capnp_builder()`, a `PersonCapnpBuilder` with a setter per field and `build`/`build_message`/`build_bytes`,
+//!   which fail with `MissingFields` if a field without a default was not set
+//! - `capnp_validate(&self)`, which checks the `#[capnp(validate(...))]` constraints that `from_capnp`
+//!   enforces, and `from_capnp_unchecked`/`from_capnp_bytes_unchecked`, which skip them
+//! - behind the consuming crate's `dynamic` feature, `to_capnp_text` and `to_capnp_json`
+//! - behind the consuming crate's `mmap` feature, `open_mmap(path)`, a typed reader over the memory-mapped file
+//! - behind the consuming crate's `checked` feature, `to_capnp_bytes_checked`/`from_capnp_bytes_checked`,
+//!   which wrap the bytes in `capnez::checked`'s integrity envelope
+//! - behind the consuming crate's `io` feature, `append_to_log` and `iter_log` for `capnez::io` message logs
+//! - behind the consuming crate's `compress-zstd` or `compress-lz4` feature, `to_capnp_compressed`/`from_capnp_compressed`
+//! - behind the consuming crate's `limits` feature, `from_capnp_bytes_limited`, which decodes within `capnez::limits::DecodeLimits`
+//! - behind the consuming crate's `pool` feature, `to_capnp_bytes_in(&pool)`, which builds in a `capnez::pool::MessagePool`
+//!
+//! and `From` impls between each Rust enum and its generated counterpart. The impls live in the
+//! `schema_capnp` module, so the types and their fields must be visible from there: anything at the
+//! crate root is, items in submodules need to be `pub` or `pub(crate)` (fields included). Structs whose
+//! fields have no conversion yet (e.g. serde-bytes fallbacks) are skipped.
+//!
+//! A struct with one lifetime parameter may borrow `&'a str` and `&'a [u8]` fields. Its `from_capnp`
+//! takes a `Reader<'a>` and points those fields into the message instead of copying; there is no
+//! `from_capnp_bytes`, since the message would not outlive the call.
+//!
+//! Everything not behind a feature needs only `core` and `alloc`, so it builds in `#![no_std]`
+//! crates, except `write_streamed`/`read_streamed`, which `SchemaGenerator::no_std` leaves out.
+
+use super::{CapnpEnum, CapnpStruct, CapnpType};
+use crate::naming::{rust_accessor, rust_module, rust_variant};
+use crate::wellknown::WellKnown;
+use std::collections::{BTreeMap, BTreeSet};
+use syn::{GenericArgument, PathArguments, Type};
+
+/// Rust-side identity of a collected struct, when conversions can be generated for it.
+#[derive(Clone)]
+pub(crate) struct RustItem {
+    path: String,
+    lifetime: Option<String>,
+    /// Field names, each with whether its text and data leaves are borrowed from the message. Fields
+    /// spliced in by `#[capnp(flatten)]` are paths through the flattened field, e.g. `meta.created_at`.
+    fields: Vec<(String, bool)>,
+    /// Path of each flattened field, with the type it is rebuilt as.
+    flattened: Vec<(String, String)>,
+}
+
+impl RustItem {
+    /// Returns `None` for anything the generated code cannot name or construct: items hidden from
+    /// `schema_capnp`, type or const generics, arrays without a literal length, and references other than
+    /// `&str`/`&[u8]`.
+    pub(crate) fn new(module: &str, item: &syn::ItemStruct) -> Option<Self> {
+        let generics = &item.generics;
+        if !reachable(module, &item.vis) || generics.type_params().next().is_some() || generics.const_params().next().is_some() {
+            return None;
+        }
+        let lifetime = match generics.lifetimes().map(|l| l.lifetime.to_string()).collect::<Vec<_>>().as_slice() {
+            [] => None,
+            [lifetime] => Some(lifetime.clone()),
+            _ => return None,
+        };
+        let fields = item.fields.iter().map(|f| {
+            if !reachable(module, &f.vis) {
+                return None;
+            }
+            let borrowed = borrowed_leaves(&f.ty)?;
+            if borrowed && lifetime.is_none() {
+                return None;
+            }
+            Some((f.ident.as_ref()?.to_string(), borrowed))
+        }).collect::<Option<Vec<_>>>()?;
+        Some(Self { path: format!("{}::{}", module, item.ident), lifetime, fields, flattened: Vec::new() })
+    }
+
+    /// Replaces the field at `index` with the fields of `child`, the struct it holds. Returns `false`
+    /// for a child borrowing from the message, which its parent would have to share the lifetime of.
+    pub(crate) fn flatten(&mut self, index: usize, child: &RustItem) -> bool {
+        if child.lifetime.is_some() {
+            return false;
+        }
+        let (field, _) = self.fields.remove(index);
+        let spliced = child.fields.iter().map(|(inner, borrowed)| (format!("{}.{}", field, inner), *borrowed));
+        self.fields.splice(index..index, spliced);
+        let nested = child.flattened.iter().map(|(inner, path)| (format!("{}.{}", field, inner), path.clone())).collect::<Vec<_>>();
+        self.flattened.push((field, child.path.clone()));
+        self.flattened.extend(nested);
+        true
+    }
+
+    /// The fields of a struct literal for this type, from the value read for each of `fields`; the
+    /// fields of a flattened field are gathered into a literal of the type it holds.
+    fn literal(&self, values: &[(&str, String)], prefix: &str, indent: usize) -> String {
+        let pad = " ".repeat(indent);
+        let mut out = String::new();
+        let mut nested = Vec::new();
+        for (field, value) in values {
+            let Some(rest) = field.strip_prefix(prefix) else { continue };
+            match rest.split_once('.') {
+                None => out.push_str(&format!("{}{}: {},\n", pad, rest, value)),
+                Some((head, _)) if !nested.contains(&head) => {
+                    nested.push(head);
+                    let flattened = format!("{}{}", prefix, head);
+                    let path = self.flattened.iter().find(|(f, _)| *f == flattened).map_or("", |(_, path)| path);
+                    let fields = self.literal(values, &format!("{}.", flattened), indent + 4);
+                    out.push_str(&format!("{}{}: {} {{\n{}{}}},\n", pad, head, path, fields, pad));
+                }
+                Some(_) => {}
+            }
+        }
+        out
+    }
+}
+
+/// Path of a non-generic type, if the generated code can name it.
+pub(crate) fn type_path(module: &str, ident: &syn::Ident, vis: &syn::Visibility, generics: &syn::Generics) -> Option<String> {
+    (generics.params.is_empty() && reachable(module, vis)).then(|| format!("{}::{}", module, ident))
+}
+
+/// Whether the generated `schema_capnp` module can name an item declared in `module`:
+/// everything at the crate root is visible to it, deeper items need to be at least `pub(crate)`.
+fn reachable(module: &str, vis: &syn::Visibility) -> bool {
+    module == "crate" || !matches!(vis, syn::Visibility::Inherited)
+}
+
+/// `Some(true)` if the type holds `&str` or `&[u8]` (possibly inside `Option`/`Vec`/arrays), `Some(false)` if
+/// it holds no references, and `None` for anything that cannot be read back from a message.
+fn borrowed_leaves(ty: &Type) -> Option<bool> {
+    match ty {
+        Type::Reference(r) => match &*r.elem {
+            Type::Path(p) if p.path.is_ident("str") => Some(true),
+            Type::Slice(s) if matches!(&*s.elem, Type::Path(p) if p.path.is_ident("u8")) => Some(true),
+            _ => None,
+        },
+        Type::Path(p) => match &p.path.segments.last()?.arguments {
+            PathArguments::AngleBracketed(args) => args.args.iter().try_fold(false, |acc, arg| match arg {
+                GenericArgument::Type(inner) => Some(acc | borrowed_leaves(inner)?),
+                _ => Some(acc),
+            }),
+            _ => Some(false),
+        },
+        // Arrays are rebuilt from a checked list, which needs a length known here
+        Type::Array(a) if matches!(a.len, syn::Expr::Lit(_)) => borrowed_leaves(&a.elem),
+        _ => None,
+    }
+}
+
+/// Names of the structs and enums conversions can be generated for: every field type must be
+/// convertible, which for nested structs is only known once all of them are, hence the fixpoint.
+fn convertible(structs: &[CapnpStruct], enums: &[CapnpEnum]) -> BTreeSet<String> {
+    let mut names: BTreeSet<String> = structs.iter().filter(|s| s.rust.is_some()).map(|s| s.name.clone())
+        .chain(enums.iter().filter(|e| e.rust_path.is_some()).map(|e| e.name.clone()))
+        .collect();
+    loop {
+        let failing = structs.iter()
+            .filter(|s| names.contains(&s.name))
+            .filter(|s| !s.fields.iter().all(|(_, _, ty, _)| supported(ty, &names)))
+            .map(|s| s.name.clone())
+            .collect::<Vec<_>>();
+        if failing.is_empty() {
+            return names;
+        }
+        for name in failing {
+            names.remove(&name);
+        }
+    }
+}
+
+fn supported(ty: &CapnpType, names: &BTreeSet<String>) -> bool {
+    match ty {
+        // Capabilities only make sense over RPC
+        CapnpType::Bytes(_) | CapnpType::Interface(_) => false,
+        CapnpType::Data => true,
+        CapnpType::Struct(name) => names.contains(name),
+        CapnpType::List(inner, _) | CapnpType::Optional(inner) => supported(inner, names),
+        _ => true,
+    }
+}
+
+/// Where a value is written to.
+enum Place<'a> {
+    /// A named field of a struct builder, written with `set_x` / `init_x`.
+    Field { builder: &'a str, accessor: &'a str },
+    /// An element of a list builder.
+    Elem { list: &'a str, index: &'a str },
+}
+
+struct Writer<'a> {
+    enums: &'a [CapnpEnum],
+    structs: &'a [CapnpStruct],
+    /// Read nested structs with `from_capnp_unchecked`, skipping their `#[capnp(validate(...))]` checks.
+    unchecked: bool,
+}
+
+impl Writer<'_> {
+    fn is_enum(&self, name: &str) -> bool {
+        self.enums.iter().any(|e| e.name == name)
+    }
+
+    fn rust_path(&self, name: &str) -> String {
+        self.structs.iter().find(|s| s.name == name).and_then(|s| s.rust.as_ref()).map(|r| r.path.clone())
+            .or_else(|| self.enums.iter().find(|e| e.name == name).and_then(|e| e.rust_path.clone()))
+            .unwrap_or_else(|| name.to_string())
+    }
+
+    /// Whether the generated getter (or list element, or union payload) is wrapped in a `Result`.
+    fn is_fallible(&self, ty: &CapnpType) -> bool {
+        match ty {
+            CapnpType::WellKnown(known) => known.is_pointer(),
+            _ => ty.scalar().is_none(),
+        }
+    }
+
+    /// Struct-like list elements come back as plain readers rather than `Result`s.
+    fn is_struct_like(&self, ty: &CapnpType) -> bool {
+        match ty {
+            CapnpType::Struct(name) => !self.is_enum(name),
+            CapnpType::Optional(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Rust type of a list element that can be streamed, i.e. one that is written from and read into a plain value.
+    fn element_type(&self, ty: &CapnpType) -> Option<String> {
+        Some(match ty {
+            CapnpType::Text => "String".to_string(),
+            CapnpType::Data => "Vec<u8>".to_string(),
+            _ if ty.scalar().is_some() => ty.scalar()?.to_string(),
+            CapnpType::WellKnown(known) => known.rust_type().to_string(),
+            CapnpType::Struct(name) => {
+                let rust = self.structs.iter().find(|s| &s.name == name).map(|s| s.rust.as_ref().filter(|r| r.lifetime.is_none()));
+                match rust {
+                    Some(rust) => rust?.path.clone(),
+                    None => self.enums.iter().find(|e| &e.name == name)?.rust_path.clone()?,
+                }
+            }
+            _ => return None,
+        })
+    }
+
+    /// Rust type of a whole field of type `ty`, as `from_capnp` builds it, or `None` if it has none
+    /// that outlives the message.
+    fn rust_type(&self, ty: &CapnpType) -> Option<String> {
+        Some(match ty {
+            CapnpType::List(inner, None) => format!("Vec<{}>", self.rust_type(inner)?),
+            CapnpType::List(inner, Some(n)) => format!("[{}; {}]", self.rust_type(inner)?, n),
+            CapnpType::Optional(inner) => format!("Option<{}>", self.rust_type(inner)?),
+            _ => self.element_type(ty)?,
+        })
+    }
+
+    /// Bits `ty` takes in a struct's data section, or `None` if it is stored behind a pointer.
+    fn data_bits(&self, ty: &CapnpType) -> Option<usize> {
+        Some(match ty {
+            CapnpType::Bool => 1,
+            CapnpType::Int8 | CapnpType::UInt8 => 8,
+            CapnpType::Int16 | CapnpType::UInt16 => 16,
+            CapnpType::Int32 | CapnpType::UInt32 | CapnpType::Float32 => 32,
+            CapnpType::Int64 | CapnpType::UInt64 | CapnpType::Float64 => 64,
+            CapnpType::WellKnown(known) if !known.is_pointer() => 64,
+            CapnpType::Struct(name) if self.is_enum(name) => 16,
+            _ => return None,
+        })
+    }
+
+    /// Words of a struct section holding one value of each of `types`, packed as tightly as their sizes allow.
+    fn section_words<'t>(&self, types: impl IntoIterator<Item = &'t CapnpType>) -> usize {
+        let (mut bits, mut pointers) = (0, 0);
+        for ty in types {
+            match self.data_bits(ty) {
+                Some(n) => bits += n,
+                None => pointers += 1,
+            }
+        }
+        bits.div_ceil(64) + pointers
+    }
+
+    /// Expression estimating the words the value behind the reference expression `value` takes outside
+    /// of its own slot: text, data and list contents, and struct sections with everything below them.
+    fn words(&self, ty: &CapnpType, value: &str, depth: usize) -> String {
+        match ty {
+            // Text carries a NUL terminator
+            CapnpType::Text => format!("({}.len() + 8) / 8", value),
+            CapnpType::Data => format!("({}.len() + 7) / 8", value),
+            CapnpType::WellKnown(WellKnown::Uuid) => "2".to_string(),
+            CapnpType::WellKnown(WellKnown::UuidText) => "5".to_string(),
+            CapnpType::Struct(name) if !self.is_enum(name) => format!("{}.capnp_struct_words()", value),
+            CapnpType::List(inner, _) => {
+                if let Some(bits) = self.data_bits(inner) {
+                    return format!("({}.len() * {}).div_ceil(64)", value, bits);
+                }
+                let item = format!("item{}", depth);
+                // Struct elements sit inline behind a tag word, anything else behind a pointer each
+                let slots = if self.is_struct_like(inner) { "1".to_string() } else { format!("{}.len()", value) };
+                format!(
+                    "({} + {}.iter().map(|{}| {}).sum::<usize>())",
+                    slots, value, item, self.words(inner, &item, depth + 1)
+                )
+            }
+            CapnpType::Optional(inner) => {
+                let section = self.section_words([&CapnpType::UInt16, &**inner]);
+                let some = format!("some{}", depth);
+                match self.words(inner, &some, depth + 1) {
+                    payload if payload == "0" => section.to_string(),
+                    payload => format!("(match {} {{ Some({}) => {} + {}, None => {} }})", value, some, section, payload, section),
+                }
+            }
+            _ => "0".to_string(),
+        }
+    }
+
+    /// Statements running `capnp_validate` on every struct inside the value behind the reference
+    /// expression `value`, or `None` if there are none.
+    fn validate_nested(&self, ty: &CapnpType, value: &str, depth: usize) -> Option<String> {
+        match ty {
+            CapnpType::Struct(name) if !self.is_enum(name) => Some(format!("{}.capnp_validate()?;", value)),
+            CapnpType::List(inner, _) => {
+                let item = format!("item{}", depth);
+                let each = self.validate_nested(inner, &item, depth + 1)?;
+                Some(format!("for {} in {}.iter() {{ {} }}", item, value, each))
+            }
+            CapnpType::Optional(inner) => {
+                let some = format!("some{}", depth);
+                let payload = self.validate_nested(inner, &some, depth + 1)?;
+                Some(format!("if let Some({}) = {} {{ {} }}", some, value, payload))
+            }
+            _ => None,
+        }
+    }
+
+    /// Statements writing the value behind the reference expression `value` to `place`.
+    fn write(&self, ty: &CapnpType, value: &str, place: Place, depth: usize) -> String {
+        let (set, init) = match &place {
+            Place::Field { builder, accessor } => (
+                format!("{}.reborrow().set_{}", builder, accessor),
+                format!("{}.reborrow().init_{}", builder, accessor),
+            ),
+            Place::Elem { list, .. } => (format!("{}.set", list), format!("{}.reborrow().init", list)),
+        };
+        let index = match &place {
+            Place::Field { .. } => String::new(),
+            Place::Elem { index, .. } => format!("{}, ", index),
+        };
+        match ty {
+            CapnpType::Text => format!("{}({}AsRef::<str>::as_ref({}));", set, index, value),
+            CapnpType::Data => format!("{}({}AsRef::<[u8]>::as_ref({}));", set, index, value),
+            CapnpType::Int8 | CapnpType::Int16 | CapnpType::Int32 | CapnpType::Int64 | CapnpType::UInt8 | CapnpType::UInt16
+            | CapnpType::UInt32 | CapnpType::UInt64 | CapnpType::Float32 | CapnpType::Float64 | CapnpType::Bool => format!("{}({}*{});", set, index, value),
+            CapnpType::WellKnown(known) => format!("{}({}{});", set, index, known.encode(value)),
+            CapnpType::Struct(name) if self.is_enum(name) => format!("{}({}{}.into());", set, index, value),
+            CapnpType::Struct(_) => match &place {
+                Place::Field { .. } => format!("{}.to_capnp({}());", value, init),
+                Place::Elem { list, index } => format!("{}.to_capnp({}.reborrow().get({}));", value, list, index),
+            },
+            CapnpType::List(inner, _) if matches!(**inner, CapnpType::UInt8) => {
+                format!("copy_bytes_in({}({}{}.len() as u32), &{}[..]);", init, index, value, value)
+            }
+            CapnpType::List(inner, _) => {
+                let (list, item, i) = (format!("list{}", depth), format!("item{}", depth), format!("i{}", depth));
+                let element = self.write(inner, &item, Place::Elem { list: &list, index: &format!("{} as u32", i) }, depth + 1);
+                format!(
+                    "{{ let mut {list} = {init}({index}{value}.len() as u32); \
+                     for ({i}, {item}) in {value}.iter().enumerate() {{ {element} }} }}",
+                    list = list, init = init, index = index, value = value, i = i, item = item, element = element
+                )
+            }
+            CapnpType::Optional(inner) => {
+                let (opt, some) = (format!("opt{}", depth), format!("some{}", depth));
+                let opener = match &place {
+                    Place::Field { .. } => format!("{}()", init),
+                    Place::Elem { list, index } => format!("{}.reborrow().get({})", list, index),
+                };
+                let payload = self.write(inner, some.as_str(), Place::Field { builder: &opt, accessor: "value" }, depth + 1);
+                format!(
+                    "{{ let mut {opt} = {opener}; match {value} {{ Some({some}) => {{ {payload} }} None => {opt}.set_none(()), }} }}",
+                    opt = opt, opener = opener, value = value, some = some, payload = payload
+                )
+            }
+            CapnpType::Bytes(_) | CapnpType::Interface(_) => unreachable!("serde-bytes and capability fields are filtered out before generation"),
+        }
+    }
+
+    /// Expression reading a value of type `ty` from the already unwrapped reader expression `reader`.
+    /// With `borrowed`, text and data are returned as slices of the message rather than copied.
+    /// `label` names the value in errors, e.g. `Point.coords`.
+    fn read(&self, ty: &CapnpType, reader: &str, borrowed: bool, label: &str, depth: usize) -> String {
+        match ty {
+            CapnpType::Text if borrowed => format!("{}.to_str()?", reader),
+            CapnpType::Text => format!("{}.to_string()?", reader),
+            CapnpType::Data if borrowed => reader.to_string(),
+            CapnpType::Data => format!("{}.to_vec()", reader),
+            CapnpType::Int8 | CapnpType::Int16 | CapnpType::Int32 | CapnpType::Int64 | CapnpType::UInt8 | CapnpType::UInt16
+            | CapnpType::UInt32 | CapnpType::UInt64 | CapnpType::Float32 | CapnpType::Float64 | CapnpType::Bool => reader.to_string(),
+            CapnpType::WellKnown(known) => known.decode(reader),
+            CapnpType::Struct(name) if self.is_enum(name) => format!("{}.into()", reader),
+            CapnpType::Struct(name) => {
+                format!("{}::from_capnp{}({})?", self.rust_path(name), if self.unchecked { "_unchecked" } else { "" }, reader)
+            }
+            CapnpType::List(inner, len) if matches!(**inner, CapnpType::UInt8) => match len {
+                None => format!("copy_bytes_out({})", reader),
+                Some(n) => format!(
+                    "{{ let list = {reader}; if list.len() != {n} {{ \
+                     return Err(::capnp::Error::failed(format!(\"expected {n} elements for {label}, got {{}}\", list.len()))); }} \
+                     <[u8; {n}]>::try_from(copy_bytes_out(list)).unwrap_or_else(|_| unreachable!()) }}",
+                    reader = reader, n = n, label = label
+                ),
+            },
+            CapnpType::List(inner, len) => {
+                let (values, item) = (format!("values{}", depth), format!("item{}", depth));
+                let item_reader = if self.is_fallible(inner) && !self.is_struct_like(inner) { format!("{}?", item) } else { item.clone() };
+                let element = self.read(inner, &item_reader, borrowed, &format!("{}[]", label), depth + 1);
+                match len {
+                    None => format!(
+                        "{{ let list = {reader}; let mut {values} = Vec::with_capacity((list.len() as usize).min(READ_RESERVE)); \
+                         for {item} in list.iter() {{ {values}.push({element}); }} {values} }}",
+                        reader = reader, values = values, item = item, element = element
+                    ),
+                    Some(n) => format!(
+                        "{{ let list = {reader}; if list.len() != {n} {{ \
+                         return Err(::capnp::Error::failed(format!(\"expected {n} elements for {label}, got {{}}\", list.len()))); }} \
+                         let mut {values} = Vec::with_capacity({n}); for {item} in list.iter() {{ {values}.push({element}); }} \
+                         <[_; {n}]>::try_from({values}).unwrap_or_else(|_| unreachable!()) }}",
+                        reader = reader, n = n, label = label, values = values, item = item, element = element
+                    ),
+                }
+            }
+            CapnpType::Optional(inner) => {
+                let module = rust_module(&ty.ident());
+                let value = format!("value{}", depth);
+                let payload = if self.is_fallible(inner) { format!("{}?", value) } else { value.clone() };
+                format!(
+                    "match {reader}.which()? {{ {module}::Which::Value({value}) => Some({element}), {module}::Which::None(()) => None, }}",
+                    reader = reader, module = module, value = value, element = self.read(inner, &payload, borrowed, label, depth + 1)
+                )
+            }
+            CapnpType::Bytes(_) | CapnpType::Interface(_) => unreachable!("serde-bytes and capability fields are filtered out before generation"),
+        }
+    }
+}
+
+/// Rust type of `item`, with code moving a list of them through an RPC call: a block writing the `Vec`
+/// named `chunk` to the `items` list of the params builder `params`, and an expression reading that
+/// list back from the received `params`. `None` if `item` has no conversion.
+pub(crate) fn items_codec(structs: &[CapnpStruct], enums: &[CapnpEnum], item: &CapnpType) -> Option<(String, String, String)> {
+    if !supported(item, &convertible(structs, enums)) {
+        return None;
+    }
+    let writer = Writer { enums, structs, unchecked: false };
+    let element = writer.element_type(item)?;
+    let list = CapnpType::List(Box::new(item.clone()), None);
+    let write = writer.write(&list, "chunk", Place::Field { builder: "params", accessor: "items" }, 0);
+    let read = writer.read(&list, "params.get()?.get_items()?", false, "items", 0);
+    Some((element, write, read))
+}
+
+/// `write_streamed`/`read_streamed` for a struct with exactly one list field, which is split into chunks
+/// of at most `STREAM_CHUNK` elements so neither side ever holds the whole list.
+///
+/// The stream is a sequence of framed messages of the struct itself: a header carrying every other
+/// field, then one message per chunk with only the list set, then one with an empty list as terminator.
+fn streamed(writer: &Writer, s: &CapnpStruct, rust: &RustItem, module: &str) -> Option<String> {
+    if rust.lifetime.is_some() {
+        return None;
+    }
+    let mut lists = s.fields.iter().zip(&rust.fields).filter(|((_, _, ty, _), _)| matches!(ty, CapnpType::List(_, None)));
+    let ((list_name, _, list_ty, _), (list_field, _)) = lists.next()?;
+    if lists.next().is_some() {
+        return None;
+    }
+    let CapnpType::List(inner, _) = list_ty else { unreachable!() };
+    let element = writer.element_type(inner)?;
+    let list_accessor = rust_accessor(list_name);
+
+    let header_fields = s.fields.iter().zip(&rust.fields)
+        .filter(|(_, (field, _))| field != list_field)
+        .map(|((name, _, ty, _), (field, _))| {
+            let accessor = rust_accessor(name);
+            format!("            {}\n", writer.write(ty, &format!("(&self.{})", field), Place::Field { builder: "builder", accessor: &accessor }, 0))
+        })
+        .collect::<String>();
+    let write_element = writer.write(inner, "item0", Place::Elem { list: "list0", index: "i0 as u32" }, 1);
+    let read_chunk = writer.read(list_ty, &format!("reader.get_{}()?", list_accessor), false, &format!("{}.{}", s.name, list_name), 0);
+
+    Some(format!(
+        r#"
+#[allow(dead_code, unused_mut, unused_variables, unused_parens, clippy::all)]
+impl {path} {{
+    /// Writes `self` with `{field}` taken from `values`, as framed messages of at most
+    /// `STREAM_CHUNK` elements each. The `{field}` already in `self` are not written.
+    pub fn write_streamed<W, I>(&self, mut writer: W, values: I) -> ::capnp::Result<()>
+    where
+        W: ::std::io::Write,
+        I: IntoIterator,
+        I::Item: ::core::borrow::Borrow<{element}>,
+    {{
+        let mut message = ::capnp::message::Builder::new_default();
+        {{
+            let mut builder = message.init_root::<{module}::Builder<'_>>();
+{header_fields}        }}
+        ::capnp::serialize::write_message(&mut writer, &message)?;
+
+        let mut values = values.into_iter();
+        let mut chunk = Vec::with_capacity(STREAM_CHUNK);
+        loop {{
+            chunk.clear();
+            chunk.extend(values.by_ref().take(STREAM_CHUNK));
+            let mut message = ::capnp::message::Builder::new_default();
+            let mut list0 = message.init_root::<{module}::Builder<'_>>().init_{accessor}(chunk.len() as u32);
+            for (i0, item0) in chunk.iter().map(|v| ::core::borrow::Borrow::<{element}>::borrow(v)).enumerate() {{
+                {write_element}
+            }}
+            ::capnp::serialize::write_message(&mut writer, &message)?;
+            if chunk.is_empty() {{
+                writer.flush()?;
+                return Ok(());
+            }}
+        }}
+    }}
+
+    /// Reads a stream written by `write_streamed`. The returned header has an empty `{field}`;
+    /// the elements are decoded lazily, one chunk at a time.
+    pub fn read_streamed<R: ::std::io::Read>(mut reader: R) -> ::capnp::Result<(Self, impl Iterator<Item = ::capnp::Result<{element}>>)> {{
+        let options = ::capnp::message::ReaderOptions::new();
+        let message = ::capnp::serialize::read_message(&mut reader, options)?;
+        let header = Self::from_capnp(message.get_root()?)?;
+
+        let mut chunk = Vec::new().into_iter();
+        let mut done = false;
+        let values = ::std::iter::from_fn(move || loop {{
+            if let Some(value) = chunk.next() {{
+                return Some(Ok(value));
+            }}
+            if done {{
+                return None;
+            }}
+            let next = (|| -> ::capnp::Result<Vec<{element}>> {{
+                let message = ::capnp::serialize::read_message(&mut reader, options)?;
+                let reader = message.get_root::<{module}::Reader<'_>>()?;
+                Ok({read_chunk})
+            }})();
+            match next {{
+                Ok(values) if values.is_empty() => done = true,
+                Ok(values) => chunk = values.into_iter(),
+                Err(e) => {{
+                    done = true;
+                    return Some(Err(e));
+                }}
+            }}
+        }});
+        Ok((header, values))
+    }}
+}}
+"#,
+        path = rust.path,
+        field = list_field,
+        element = element,
+        module = module,
+        accessor = list_accessor,
+        header_fields = header_fields,
+        write_element = write_element,
+        read_chunk = read_chunk,
+    ))
+}
+
+/// `capnp_builder()` and its `<Name>CapnpBuilder`, which sets fields one call at a time and fails
+/// with `MissingFields` if any field that is neither an `Option` nor has a `#[capnp(default = ...)]`
+/// was left unset.
+fn builder(writer: &Writer, s: &CapnpStruct, rust: &RustItem) -> Option<String> {
+    // A setter per schema field would not line up with the fields of a struct that flattens another,
+    // and a struct without fields has nothing to build
+    if rust.lifetime.is_some() || !rust.flattened.is_empty() || s.fields.is_empty() {
+        return None;
+    }
+    let builder = format!("{}CapnpBuilder", s.name);
+    let type_name = rust.path.rsplit("::").next().unwrap_or(&s.name);
+    let (mut slots, mut empty, mut setters, mut checks, mut values) = (String::new(), String::new(), String::new(), String::new(), String::new());
+    for ((name, _, ty, default), (field, _)) in s.fields.iter().zip(&rust.fields) {
+        let rust_ty = writer.rust_type(ty)?;
+        let (slot, param, value) = match (ty, default) {
+            (CapnpType::Optional(inner), _) => (
+                rust_ty.clone(),
+                writer.rust_type(inner)?,
+                format!("self.{}", field),
+            ),
+            (_, Some(default)) => (
+                format!("Option<{}>", rust_ty),
+                rust_ty.clone(),
+                format!("self.{}.unwrap_or_else(|| {})", field, default_value(ty, default)?),
+            ),
+            (_, None) => {
+                checks.push_str(&format!(
+                    "        if self.{field}.is_none() {{ missing.push(\"{name}\"); }}\n",
+                    field = field, name = field.trim_start_matches("r#"),
+                ));
+                (
+                    format!("Option<{}>", rust_ty),
+                    rust_ty.clone(),
+                    format!("self.{}.unwrap()", field),
+                )
+            }
+        };
+        slots.push_str(&format!("    {}: {},\n", field, slot));
+        empty.push_str(&format!("            {}: None,\n", field));
+        setters.push_str(&format!(
+            r#"
+    /// Sets `{name}`.
+    pub fn {field}(mut self, value: impl Into<{param}>) -> Self {{
+        self.{field} = Some(value.into());
+        self
+    }}
+"#,
+            name = name, field = field, param = param,
+        ));
+        values.push_str(&format!("            {}: {},\n", field, value));
+    }
+
+    Some(format!(
+        r#"
+/// Builds a `{type_name}` or its message one field at a time; see `{type_name}::capnp_builder`.
+#[allow(dead_code, private_interfaces, private_bounds, clippy::all)]
+pub struct {builder} {{
+{slots}}}
+
+#[allow(dead_code, private_interfaces, private_bounds, clippy::all)]
+impl {path} {{
+    /// A builder that names each field as it is set, and checks on `build` that none without a
+    /// default was forgotten.
+    pub fn capnp_builder() -> {builder} {{
+        {builder} {{
+{empty}        }}
+    }}
+}}
+
+#[allow(dead_code, private_interfaces, private_bounds, clippy::all)]
+impl {builder} {{{setters}
+    /// The value, or `MissingFields` naming every required field that was not set.
+    pub fn build(self) -> Result<{path}, MissingFields> {{
+        let mut missing = Vec::new();
+{checks}        if !missing.is_empty() {{
+            return Err(MissingFields {{ type_name: "{type_name}", fields: missing }});
+        }}
+        Ok({path} {{
+{values}        }})
+    }}
+
+    /// The message `to_capnp` writes for the built value.
+    pub fn build_message(self) -> Result<::capnp::message::Builder<::capnp::message::HeapAllocator>, MissingFields> {{
+        let value = self.build()?;
+        let mut message = ::capnp::message::Builder::new_default();
+        value.to_capnp(message.init_root());
+        Ok(message)
+    }}
+
+    /// The bytes `to_capnp_bytes` returns for the built value.
+    pub fn build_bytes(self) -> Result<Vec<u8>, MissingFields> {{
+        Ok(self.build()?.to_capnp_bytes())
+    }}
+}}
+"#,
+        type_name = type_name, builder = builder, path = rust.path,
+        slots = slots, empty = empty, setters = setters, checks = checks, values = values,
+    ))
+}
+
+/// Rust expression for the schema literal `default` of a field of type `ty`.
+fn default_value(ty: &CapnpType, default: &str) -> Option<String> {
+    Some(match ty {
+        CapnpType::Text => format!("String::from({})", default),
+        CapnpType::Bool => default.to_string(),
+        _ => format!("({}) as {}", default, ty.scalar()?),
+    })
+}
+
+/// `set_<field>_serde`/`get_<field>_serde` on the builder and reader of a struct, for every field holding
+/// a serde-only type (or a list of them) as bytes. Needs the `capnez` codec feature the field uses.
+fn serde_glue(s: &CapnpStruct, serde_paths: &BTreeMap<String, String>) -> Option<String> {
+    let module = rust_module(&s.name);
+    let mut setters = String::new();
+    let mut getters = String::new();
+    for (name, _, ty, _) in &s.fields {
+        let (ty_name, is_list) = match ty {
+            CapnpType::Bytes(ty_name) => (ty_name, false),
+            CapnpType::List(inner, _) => match &**inner {
+                CapnpType::Bytes(ty_name) => (ty_name, true),
+                _ => continue,
+            },
+            _ => continue,
+        };
+        let Some(path) = serde_paths.get(ty_name) else { continue };
+        let codec = s.serde_with.get(name).map_or("json", String::as_str);
+        let codec_ty = format!("<::capnez::codec::{} as ::capnez::codec::SerdeCodec>", crate::naming::pascal_case(codec));
+        let accessor = rust_accessor(name);
+
+        if is_list {
+            setters.push_str(&format!(
+                r#"
+    /// Encodes each of `values` with the {codec} codec into `{name}`.
+    pub fn set_{accessor}_serde(&mut self, values: &[{path}]) -> ::capnp::Result<()> {{
+        let mut list = self.reborrow().init_{accessor}(values.len() as u32);
+        for (i, value) in values.iter().enumerate() {{
+            let bytes = {codec_ty}::encode(value)?;
+            copy_bytes_in(list.reborrow().init(i as u32, bytes.len() as u32), &bytes);
+        }}
+        Ok(())
+    }}
+"#,
+                codec = codec, name = name, accessor = accessor, path = path, codec_ty = codec_ty,
+            ));
+            getters.push_str(&format!(
+                r#"
+    /// Decodes every element of `{name}` with the {codec} codec.
+    pub fn get_{accessor}_serde(&self) -> ::capnp::Result<Vec<{path}>> {{
+        self.get_{accessor}()?.iter().map(|item| {codec_ty}::decode(&copy_bytes_out(item?))).collect()
+    }}
+"#,
+                codec = codec, name = name, accessor = accessor, path = path, codec_ty = codec_ty,
+            ));
+        } else {
+            setters.push_str(&format!(
+                r#"
+    /// Encodes `value` with the {codec} codec into `{name}`.
+    pub fn set_{accessor}_serde(&mut self, value: &{path}) -> ::capnp::Result<()> {{
+        let bytes = {codec_ty}::encode(value)?;
+        copy_bytes_in(self.reborrow().init_{accessor}(bytes.len() as u32), &bytes);
+        Ok(())
+    }}
+"#,
+                codec = codec, name = name, accessor = accessor, path = path, codec_ty = codec_ty,
+            ));
+            getters.push_str(&format!(
+                r#"
+    /// Decodes `{name}` with the {codec} codec.
+    pub fn get_{accessor}_serde(&self) -> ::capnp::Result<{path}> {{
+        {codec_ty}::decode(&copy_bytes_out(self.get_{accessor}()?))
+    }}
+"#,
+                codec = codec, name = name, accessor = accessor, path = path, codec_ty = codec_ty,
+            ));
+        }
+    }
+    if setters.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "\n#[allow(dead_code, private_interfaces)]\nimpl<'a> {module}::Builder<'a> {{{setters}}}\n\n#[allow(dead_code, private_interfaces)]\nimpl<'a> {module}::Reader<'a> {{{getters}}}\n",
+        module = module, setters = setters, getters = getters,
+    ))
+}
+
+/// Bulk copies in and out of `List(UInt8)`, which holds fixed-size byte arrays and serde-encoded
+/// values. capnp exposes a list's bytes as a slice on little-endian targets; elsewhere they go one at a time.
+const COPY_BYTES: &str = r#"
+#[allow(dead_code)]
+fn copy_bytes_in(mut list: ::capnp::primitive_list::Builder<'_, u8>, bytes: &[u8]) {
+    #[cfg(target_endian = "little")]
+    if let Some(slice) = list.as_slice() {
+        slice.copy_from_slice(bytes);
+        return;
+    }
+    for (i, byte) in bytes.iter().enumerate() {
+        list.set(i as u32, *byte);
+    }
+}
+
+#[allow(dead_code)]
+fn copy_bytes_out(list: ::capnp::primitive_list::Reader<'_, u8>) -> Vec<u8> {
+    #[cfg(target_endian = "little")]
+    if let Some(slice) = list.as_slice() {
+        return slice.to_vec();
+    }
+    list.iter().collect()
+}
+"#;
+
+/// The error of every generated `<Name>CapnpBuilder`, emitted once.
+const MISSING_FIELDS: &str = r#"
+/// Required fields a `...CapnpBuilder` was built without.
+#[allow(dead_code)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MissingFields {
+    pub type_name: &'static str,
+    pub fields: Vec<&'static str>,
+}
+
+impl ::core::fmt::Display for MissingFields {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+        write!(f, "{} is missing required fields: {}", self.type_name, self.fields.join(", "))
+    }
+}
+
+impl ::core::error::Error for MissingFields {}
+
+impl ::core::convert::From<MissingFields> for ::capnp::Error {
+    fn from(e: MissingFields) -> Self {
+        ::capnp::Error::failed(e.to_string())
+    }
+}
+"#;
+
+/// The allocating types the generated code uses, taken from `alloc` so it also builds in `#![no_std]`
+/// crates. Under std they are the same items the prelude has.
+const ALLOC: &str = r#"
+extern crate alloc;
+#[allow(unused_imports)]
+use alloc::{format, string::{String, ToString}, vec::Vec};
+"#;
+
+/// Conversion impls for every convertible struct and enum. `std` adds `write_streamed`/`read_streamed`,
+/// which go through `std::io`.
+pub(crate) fn generate(structs: &[CapnpStruct], enums: &[CapnpEnum], serde_paths: &BTreeMap<String, String>, std: bool) -> String {
+    let names = convertible(structs, enums);
+    let writer = Writer { enums, structs, unchecked: false };
+    let unchecked = Writer { enums, structs, unchecked: true };
+    let mut code = String::from("\n// Conversions between the annotated Rust types and the generated readers/builders.\n");
+    code.push_str(ALLOC);
+    code.push_str("\n/// Largest number of list elements `write_streamed` puts in one message.\n#[allow(dead_code)]\npub const STREAM_CHUNK: usize = 4096;\n");
+    code.push_str(
+        "\n/// Most list elements `from_capnp` reserves room for before reading them. A list of empty structs\n\
+         /// claims a length it takes no bytes to back, so the length alone is not trusted with more.\n\
+         #[allow(dead_code)]\npub const READ_RESERVE: usize = 4096;\n",
+    );
+    code.push_str(MISSING_FIELDS);
+    code.push_str(COPY_BYTES);
+
+    for e in enums {
+        let Some(path) = &e.rust_path else { continue };
+        let pairs = e.rust_variants.iter().zip(e.variants.iter().map(|v| rust_variant(v))).collect::<Vec<_>>();
+        let to_capnp = pairs.iter().map(|(r, c)| format!("{}::{} => {}::{},", path, r, e.name, c)).collect::<Vec<_>>().join(" ");
+        let from_capnp = pairs.iter().map(|(r, c)| format!("{}::{} => {}::{},", e.name, c, path, r)).collect::<Vec<_>>().join(" ");
+        code.push_str(&format!(
+            "\nimpl ::core::convert::From<&{path}> for {name} {{\n    fn from(value: &{path}) -> Self {{ match value {{ {to_capnp} }} }}\n}}\n\
+             \nimpl ::core::convert::From<{path}> for {name} {{\n    fn from(value: {path}) -> Self {{ Self::from(&value) }}\n}}\n\
+             \nimpl ::core::convert::From<{name}> for {path} {{\n    fn from(value: {name}) -> Self {{ match value {{ {from_capnp} }} }}\n}}\n",
+            path = path,
+            name = e.name,
+            to_capnp = to_capnp,
+            from_capnp = from_capnp,
+        ));
+    }
+
+    for s in structs.iter().filter(|s| names.contains(&s.name)) {
+        let Some(rust) = &s.rust else { continue };
+        let module = rust_module(&s.name);
+
+        let mut write_fields = String::new();
+        let mut read_values = Vec::new();
+        let mut words = writer.section_words(s.fields.iter().map(|(_, _, ty, _)| ty)).to_string();
+        let mut checks = String::new();
+        for ((name, _, ty, _), (field, borrowed)) in s.fields.iter().zip(&rust.fields) {
+            let accessor = rust_accessor(name);
+            let value = format!("(&self.{})", field);
+            write_fields.push_str("        ");
+            write_fields.push_str(&writer.write(ty, &value, Place::Field { builder: "builder", accessor: &accessor }, 0));
+            write_fields.push('\n');
+
+            let getter = format!("reader.get_{}(){}", accessor, if writer.is_fallible(ty) { "?" } else { "" });
+            let label = format!("{}.{}", s.name, name);
+            read_values.push((field.as_str(), unchecked.read(ty, &getter, *borrowed, &label, 0)));
+
+            for validation in s.validate.get(name).into_iter().flatten() {
+                checks.push_str(&format!("        {}\n", validation.check(&value, &label)));
+            }
+            if let Some(nested) = writer.validate_nested(ty, &value, 0) {
+                checks.push_str(&format!("        {}\n", nested));
+            }
+
+            let content = writer.words(ty, &value, 0);
+            if content != "0" {
+                words.push_str(&format!("\n            + {}", content));
+            }
+        }
+
+        let read_fields = rust.literal(&read_values, "", 12);
+        let generics = rust.lifetime.as_ref().map_or(String::new(), |l| format!("<{}>", l));
+        let lifetime = rust.lifetime.as_deref().unwrap_or("'_");
+        // Borrowed fields cannot outlive a message decoded inside the call
+        let from_bytes = match rust.lifetime {
+            Some(_) => "",
+            None => r#"
+    pub fn from_capnp_bytes(bytes: &[u8]) -> ::capnp::Result<Self> {
+        let message = ::capnp::serialize::read_message_from_flat_slice(&mut &bytes[..], ::capnp::message::ReaderOptions::new())?;
+        Self::from_capnp(message.get_root()?)
+    }
+
+    /// `from_capnp_bytes` without the `#[capnp(validate(...))]` checks.
+    pub fn from_capnp_bytes_unchecked(bytes: &[u8]) -> ::capnp::Result<Self> {
+        let message = ::capnp::serialize::read_message_from_flat_slice(&mut &bytes[..], ::capnp::message::ReaderOptions::new())?;
+        Self::from_capnp_unchecked(message.get_root()?)
+    }
+"#,
+        };
+
+        // Reading back from a log, an envelope, a compressed frame or within limits has the same constraint
+        let log = match rust.lifetime {
+            Some(_) => String::new(),
+            None => format!(
+                r#"
+#[cfg(feature = "checked")]
+#[allow(dead_code)]
+impl {path} {{
+    /// `to_capnp_bytes` behind an integrity envelope, see `capnez::checked`.
+    pub fn to_capnp_bytes_checked(&self) -> Vec<u8> {{
+        ::capnez::checked::seal(&self.to_capnp_bytes())
+    }}
+
+    /// Reads bytes from `to_capnp_bytes_checked`, failing with the envelope check that did not pass
+    /// before decoding anything.
+    pub fn from_capnp_bytes_checked(bytes: &[u8]) -> Result<Self, ::capnez::checked::EnvelopeError> {{
+        Ok(Self::from_capnp_bytes(::capnez::checked::verify(bytes)?)?)
+    }}
+}}
+#[cfg(feature = "io")]
+#[allow(dead_code)]
+impl {path} {{
+    /// Appends `self` to `log` as one message, returning its index there.
+    pub fn append_to_log(&self, log: &mut ::capnez::io::MessageLogWriter) -> ::capnp::Result<u64> {{
+        let mut message = ::capnp::message::Builder::new_default();
+        self.to_capnp(message.init_root());
+        log.append(&message)
+    }}
+
+    /// Reads every message of `log` as `{name}`, in append order.
+    pub fn iter_log(log: &::capnez::io::MessageLogReader) -> impl Iterator<Item = ::capnp::Result<Self>> + '_ {{
+        log.iter(::capnp::message::ReaderOptions::new()).map(|message| Self::from_capnp(message?.get_root()?))
+    }}
+}}
+#[cfg(any(feature = "compress-zstd", feature = "compress-lz4"))]
+#[allow(dead_code)]
+impl {path} {{
+    /// Packed and compressed with `codec`, framed as described in `capnez::compress`.
+    pub fn to_capnp_compressed(&self, codec: ::capnez::compress::Codec) -> ::capnp::Result<Vec<u8>> {{
+        let mut message = ::capnp::message::Builder::new_default();
+        self.to_capnp(message.init_root());
+        let mut bytes = Vec::new();
+        ::capnez::compress::write_packed_message_compressed(&mut bytes, &message, codec)?;
+        Ok(bytes)
+    }}
+
+    /// Reads bytes from `to_capnp_compressed`, whichever codec they were written with.
+    pub fn from_capnp_compressed(bytes: &[u8]) -> ::capnp::Result<Self> {{
+        let message = ::capnez::compress::read_message_compressed(bytes, ::capnp::message::ReaderOptions::new())?;
+        Self::from_capnp(message.get_root()?)
+    }}
+}}
+#[cfg(feature = "limits")]
+#[allow(dead_code)]
+impl {path} {{
+    /// `from_capnp_bytes` within `limits`: rejects `bytes` outright when they are too long, and stops
+    /// reading when the message turns out too large or too deeply nested, either way with
+    /// `DecodeError::Limit`.
+    pub fn from_capnp_bytes_limited(bytes: &[u8], limits: &::capnez::limits::DecodeLimits) -> Result<Self, ::capnez::limits::DecodeError> {{
+        limits.decode(bytes, |message| Self::from_capnp(message.get_root()?))
+    }}
+}}
+"#,
+                path = rust.path,
+                name = s.name,
+            ),
+        };
+
+        code.push_str(&format!(
+            r#"
+#[allow(dead_code, unused_mut, unused_variables, unused_parens, clippy::all)]
+impl{generics} {path}{generics} {{
+    pub fn to_capnp(&self, mut builder: {module}::Builder<'_>) {{
+{write_fields}    }}
+
+    /// Reads `reader` and checks the `#[capnp(validate(...))]` constraints of the result.
+    pub fn from_capnp(reader: {module}::Reader<{lifetime}>) -> ::capnp::Result<Self> {{
+        let value = Self::from_capnp_unchecked(reader)?;
+        value.capnp_validate()?;
+        Ok(value)
+    }}
+
+    /// `from_capnp` without the `#[capnp(validate(...))]` checks, here or in nested structs.
+    pub fn from_capnp_unchecked(reader: {module}::Reader<{lifetime}>) -> ::capnp::Result<Self> {{
+        Ok(Self {{
+{read_fields}        }})
+    }}
+
+    /// Checks the `#[capnp(validate(...))]` constraints of `self` and of every struct inside it,
+    /// failing with the first one broken.
+    pub fn capnp_validate(&self) -> ::capnp::Result<()> {{
+{checks}        Ok(())
+    }}
+
+    pub fn to_capnp_bytes(&self) -> Vec<u8> {{
+        // Sized from the hint, the first segment holds all of a large message instead of it growing
+        // segment by segment and being stitched together on output
+        let words = (self.capnp_size_hint() / 8).clamp(1024, 1 << 26) as u32;
+        let mut message = ::capnp::message::Builder::new(::capnp::message::HeapAllocator::new().first_segment_words(words));
+        self.to_capnp(message.init_root());
+        ::capnp::serialize::write_message_to_words(&message)
+    }}
+{from_bytes}
+    /// Estimated length of `to_capnp_bytes()`, from the field types and the lengths of text, data and
+    /// lists, without building the message. It assumes fields pack as tightly as their sizes allow,
+    /// and leaves out the few bytes of framing each segment beyond the first adds to messages over
+    /// 8 KiB, so it can fall slightly short of the real length.
+    pub fn capnp_size_hint(&self) -> usize {{
+        // Segment table and root pointer
+        8 * (2 + self.capnp_struct_words())
+    }}
+
+    #[doc(hidden)]
+    pub fn capnp_struct_words(&self) -> usize {{
+        {words}
+    }}
+}}
+
+#[cfg(feature = "dynamic")]
+#[allow(dead_code)]
+impl{generics} {path}{generics} {{
+    /// Cap'n Proto text format, as printed by `capnp convert binary:text`.
+    pub fn to_capnp_text(&self) -> ::capnp::Result<String> {{
+        let mut message = ::capnp::message::Builder::new_default();
+        self.to_capnp(message.init_root());
+        let reader = message.get_root_as_reader::<{module}::Reader<'_>>()?;
+        Ok(format!("{{:?}}", reader))
+    }}
+
+    /// JSON rendering of the message, produced by walking it with capnp's dynamic reflection.
+    pub fn to_capnp_json(&self) -> ::capnp::Result<String> {{
+        let mut message = ::capnp::message::Builder::new_default();
+        self.to_capnp(message.init_root());
+        let reader = message.get_root_as_reader::<{module}::Reader<'_>>()?;
+        ::capnez::dynamic::to_json(reader.into())
+    }}
+}}
+
+#[cfg(feature = "pool")]
+#[allow(dead_code)]
+impl{generics} {path}{generics} {{
+    /// `to_capnp_bytes`, building the message in `pool`'s reused buffer rather than a fresh allocation.
+    pub fn to_capnp_bytes_in(&self, pool: &::capnez::pool::MessagePool) -> Vec<u8> {{
+        pool.with_builder(|message| {{
+            self.to_capnp(message.init_root());
+            ::capnp::serialize::write_message_to_words(message)
+        }})
+    }}
+}}
+
+#[cfg(feature = "mmap")]
+#[allow(dead_code)]
+impl{generics} {path}{generics} {{
+    /// Maps the message in the file at `path` into memory, with a traversal limit sized to the file.
+    /// The returned reader points straight into the mapping rather than copying the message.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified or truncated, by this or any other process, while the reader is
+    /// alive; see `capnez::io::read_message_mmap`.
+    pub unsafe fn open_mmap(path: impl AsRef<::std::path::Path>) -> ::capnp::Result<::capnp::message::TypedReader<::capnez::io::MmapSegments, {module}::Owned>> {{
+        let path = path.as_ref();
+        let options = ::capnez::io::sized_options(::std::fs::metadata(path)?.len());
+        Ok(::capnp::message::TypedReader::new(::capnez::io::read_message_mmap(path, options)?))
+    }}
+}}
+{log}"#,
+            path = rust.path,
+            generics = generics,
+            lifetime = lifetime,
+            from_bytes = from_bytes,
+            words = words,
+            checks = checks,
+            log = log,
+            module = module,
+            write_fields = write_fields,
+            read_fields = read_fields,
+        ));
+        if let Some(streamed) = streamed(&writer, s, rust, &module).filter(|_| std) {
+            code.push_str(&streamed);
+        }
+        if let Some(builder) = builder(&writer, s, rust) {
+            code.push_str(&builder);
+        }
+    }
+    for s in structs {
+        if let Some(glue) = serde_glue(s, serde_paths) {
+            code.push_str(&glue);
+        }
+    }
+    code
+}
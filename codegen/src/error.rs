@@ -0,0 +1,116 @@
+//! Structured errors for schema generation.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Something that keeps annotated items from becoming a schema.
+///
+/// Collection carries on past a bad item, so a failed run reports every problem it found at once, as
+/// [`CapnezError::Multiple`]. The generator's methods return these inside `anyhow::Error`; match on
+/// them with `err.downcast_ref::<CapnezError>()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CapnezError {
+    /// A field, parameter or return type with no capnp mapping. For interfaces, `struct_name` is the
+    /// interface and `field` is `method(param)`, `method() return value` or `extends`.
+    UnsupportedType { file: PathBuf, struct_name: String, field: String, ty: String, reason: String },
+    /// A `#[capnp]` struct with tuple or unit fields.
+    UnnamedFields { file: PathBuf, struct_name: String },
+    /// Structs containing each other; the first name is repeated at the end.
+    CircularDependency { cycle: Vec<String> },
+    /// Two items, or two members of one item, mapping to the same capnp name.
+    DuplicateName { name: String, first: String, second: String },
+    /// A malformed or misapplied `#[capnp(...)]` attribute.
+    InvalidAttribute { file: PathBuf, item: String, message: String },
+    /// capnpc rejected the generated schema.
+    CapnpcFailed { schema: PathBuf, message: String },
+    /// Several of the above, in the order they were found.
+    Multiple(Vec<CapnezError>),
+}
+
+impl CapnezError {
+    /// An unsupported type, before the item and member it appears in are known.
+    pub(crate) fn unsupported(ty: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self::UnsupportedType { file: PathBuf::new(), struct_name: String::new(), field: String::new(), ty: ty.into(), reason: reason.into() }
+    }
+
+    /// An invalid attribute, before the item it appears on is known.
+    pub(crate) fn attribute(message: impl Into<String>) -> Self {
+        Self::InvalidAttribute { file: PathBuf::new(), item: String::new(), message: message.into() }
+    }
+
+    /// Fills in where an error from [`unsupported`](Self::unsupported) or [`attribute`](Self::attribute)
+    /// occurred; context that is already set is kept.
+    pub(crate) fn at(mut self, path: &Path, owner: &str, member: &str) -> Self {
+        match &mut self {
+            Self::UnsupportedType { file, struct_name, field, .. } => {
+                if file.as_os_str().is_empty() { *file = path.to_path_buf(); }
+                if struct_name.is_empty() { *struct_name = owner.to_string(); }
+                if field.is_empty() { *field = member.to_string(); }
+            }
+            Self::InvalidAttribute { file, item, .. } => {
+                if file.as_os_str().is_empty() { *file = path.to_path_buf(); }
+                if item.is_empty() {
+                    *item = if member.is_empty() { owner.to_string() } else { format!("{}::{}", owner, member) };
+                }
+            }
+            Self::UnnamedFields { file, .. } if file.as_os_str().is_empty() => *file = path.to_path_buf(),
+            Self::Multiple(errors) => {
+                for error in errors.iter_mut() {
+                    *error = std::mem::replace(error, Self::Multiple(Vec::new())).at(path, owner, member);
+                }
+            }
+            _ => {}
+        }
+        self
+    }
+
+    /// `Ok` for no errors, the error itself for one, and [`Multiple`](Self::Multiple) otherwise.
+    pub(crate) fn all(errors: Vec<CapnezError>) -> Result<(), CapnezError> {
+        let mut flat = Vec::new();
+        for error in errors {
+            match error {
+                Self::Multiple(inner) => flat.extend(inner),
+                error => flat.push(error),
+            }
+        }
+        match flat.len() {
+            0 => Ok(()),
+            1 => Err(flat.remove(0)),
+            _ => Err(Self::Multiple(flat)),
+        }
+    }
+}
+
+fn location(file: &Path) -> String {
+    if file.as_os_str().is_empty() { String::new() } else { format!(" (in {})", file.display()) }
+}
+
+impl fmt::Display for CapnezError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedType { file, struct_name, field, ty, reason } => {
+                write!(f, "`{}` of `{}` has unsupported type `{}`: {}{}", field, struct_name, ty, reason, location(file))
+            }
+            Self::UnnamedFields { file, struct_name } => {
+                write!(f, "`{}` is a tuple struct; #[capnp] structs need named fields, or none{}", struct_name, location(file))
+            }
+            Self::CircularDependency { cycle } => {
+                write!(f, "structs contain each other: {}", cycle.join(" -> "))
+            }
+            Self::DuplicateName { name, first, second } => {
+                write!(f, "{} and {} both map to the capnp name `{}`; disambiguate one with #[capnp(rename = \"...\")]", first, second, name)
+            }
+            Self::InvalidAttribute { file, item, message } => write!(f, "invalid #[capnp] attribute on `{}`: {}{}", item, message, location(file)),
+            Self::CapnpcFailed { schema, message } => write!(f, "capnpc failed to compile {}: {}", schema.display(), message),
+            Self::Multiple(errors) => {
+                write!(f, "{} problems found:", errors.len())?;
+                for error in errors {
+                    write!(f, "\n  - {}", error)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for CapnezError {}
@@ -0,0 +1,116 @@
+//! A copy of the schema outside of `OUT_DIR`, for committing and for other languages' toolchains.
+//!
+//! Configured under `[export]` in `capnez.toml` or with [`SchemaGenerator::export`](crate::SchemaGenerator::export).
+//! The copy carries annotations for C++ and Go code generators, which the schema compiled for Rust
+//! leaves out: their import files (`/capnp/c++.capnp`, `/go.capnp`) are not on every machine that
+//! builds the crate.
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where the exported schema goes and which annotations it gets. Only configured annotations are emitted.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Export {
+    /// Destination of the copy; relative paths are taken from the crate root, i.e. next to `Cargo.toml`.
+    pub path: PathBuf,
+    /// `$Cxx.namespace`, e.g. `myco::proto`.
+    pub cxx_namespace: Option<String>,
+    /// `$Go.package`, e.g. `proto`.
+    pub go_package: Option<String>,
+    /// `$Go.import`, e.g. `github.com/myco/myservice/proto`.
+    pub go_import: Option<String>,
+    /// Lines added after the others as written, e.g. `using Java = import "/capnp/java.capnp";`.
+    pub header: Vec<String>,
+}
+
+impl Export {
+    /// Exports to `path` without annotations.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into(), ..Self::default() }
+    }
+
+    /// Reads the `[export]` table of a `capnez.toml`, if it has one.
+    pub fn from_config(path: &Path) -> Result<Option<Self>> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let config: toml::Table = content.parse()
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+        let Some(table) = config.get("export") else { return Ok(None) };
+        let table = table.as_table()
+            .with_context(|| format!("`export` in {} must be a table", path.display()))?;
+        let mut export = Self::default();
+        let mut has_path = false;
+        for (key, value) in table {
+            let string = || value.as_str().map(str::to_string)
+                .with_context(|| format!("`export.{}` in {} must be a string", key, path.display()));
+            match key.as_str() {
+                "path" => {
+                    export.path = PathBuf::from(string()?);
+                    has_path = true;
+                }
+                "cxx_namespace" => export.cxx_namespace = Some(string()?),
+                "go_package" => export.go_package = Some(string()?),
+                "go_import" => export.go_import = Some(string()?),
+                "header" => {
+                    export.header = value.as_array()
+                        .and_then(|lines| lines.iter().map(|line| line.as_str().map(str::to_string)).collect())
+                        .with_context(|| format!("`export.header` in {} must be an array of strings", path.display()))?;
+                }
+                other => bail!("Unknown export setting `{}` in {}", other, path.display()),
+            }
+        }
+        if !has_path {
+            bail!("`export` in {} needs a `path`", path.display());
+        }
+        Ok(Some(export))
+    }
+
+    /// The lines inserted after the file ID.
+    pub(crate) fn header_lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some(namespace) = &self.cxx_namespace {
+            lines.push("using Cxx = import \"/capnp/c++.capnp\";".to_string());
+            lines.push(format!("$Cxx.namespace({});", crate::text_literal(namespace)));
+        }
+        if self.go_package.is_some() || self.go_import.is_some() {
+            lines.push("using Go = import \"/go.capnp\";".to_string());
+        }
+        if let Some(package) = &self.go_package {
+            lines.push(format!("$Go.package({});", crate::text_literal(package)));
+        }
+        if let Some(import) = &self.go_import {
+            lines.push(format!("$Go.import({});", crate::text_literal(import)));
+        }
+        lines.extend(self.header.iter().cloned());
+        lines
+    }
+
+    /// `schema` with the header lines after its file ID.
+    pub(crate) fn render(&self, schema: &str) -> String {
+        let lines = self.header_lines();
+        if lines.is_empty() {
+            return schema.to_string();
+        }
+        let (id, rest) = schema.split_once('\n').unwrap_or((schema, ""));
+        format!("{}\n{}\n\n{}", id, lines.join("\n"), rest.trim_start_matches('\n'))
+    }
+
+    /// Writes the rendered `schema` to the export path, resolved against `root`. The file is replaced
+    /// atomically, and left alone when its content would not change.
+    pub(crate) fn write(&self, schema: &str, root: &Path) -> Result<()> {
+        let path = root.join(&self.path);
+        let content = self.render(schema);
+        if fs::read_to_string(&path).ok().as_deref() == Some(content.as_str()) {
+            return Ok(());
+        }
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let file_name = path.file_name().and_then(|name| name.to_str()).context("Export path has no file name")?;
+        let staged = path.with_file_name(format!(".{}.tmp", file_name));
+        fs::write(&staged, content).with_context(|| format!("Failed to write {}", staged.display()))?;
+        fs::rename(&staged, &path).with_context(|| format!("Failed to replace {}", path.display()))
+    }
+}
@@ -0,0 +1,118 @@
+//! `#[capnp(flatten)]`: a field holding another `#[capnp]` struct is replaced in the schema by that
+//! struct's fields, so
+//!
+//! ```ignore
+//! #[capnp]
+//! struct Metadata { created_at: i64, created_by: String }
+//!
+//! #[capnp]
+//! struct Document { title: String, #[capnp(flatten, prefix = "meta")] meta: Metadata, body: String }
+//! ```
+//!
+//! gives `Document` the fields `title @0`, `metaCreatedAt @1`, `metaCreatedBy @2` and `body @3`.
+//! Without a `prefix` the child's names are kept as they are. Ordinals follow the parent's field
+//! order, with the child's fields in the flattened field's place. A child may flatten fields of its
+//! own; those are spliced into it first.
+//!
+//! The child struct stays in the schema as well. The conversion impls read `Document::meta` back as a
+//! `Metadata` built from the spliced fields and write it out field by field.
+
+use super::{CapnpStruct, CapnpType};
+use crate::error::CapnezError;
+use crate::naming;
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+
+/// Replaces every flattened field with the fields of the struct it holds, failing if that is not a
+/// collected struct, if structs flatten each other, or if a spliced name is already taken.
+pub(crate) fn splice(structs: &mut [CapnpStruct], origins: &HashMap<String, PathBuf>) -> Result<(), CapnezError> {
+    let mut errors = Vec::new();
+    let mut cycles = Vec::new();
+    for index in 0..structs.len() {
+        match resolve(structs, index, &mut Vec::new(), origins) {
+            // Every struct on a cycle runs into it; report it once
+            Err(CapnezError::CircularDependency { cycle }) => {
+                let members = cycle.iter().cloned().collect::<BTreeSet<_>>();
+                if !cycles.contains(&members) {
+                    cycles.push(members);
+                    errors.push(CapnezError::CircularDependency { cycle });
+                }
+            }
+            Err(e) => errors.push(e),
+            Ok(()) => {}
+        }
+    }
+    CapnezError::all(errors)
+}
+
+fn resolve(structs: &mut [CapnpStruct], index: usize, stack: &mut Vec<String>, origins: &HashMap<String, PathBuf>) -> Result<(), CapnezError> {
+    let parent = structs[index].clone();
+    if parent.flatten.is_empty() {
+        return Ok(());
+    }
+    if let Some(start) = stack.iter().position(|name| *name == parent.name) {
+        let mut cycle = stack[start..].to_vec();
+        cycle.push(parent.name);
+        return Err(CapnezError::CircularDependency { cycle });
+    }
+    stack.push(parent.name.clone());
+    let file = origins.get(&parent.name).cloned().unwrap_or_default();
+
+    // Each field, with the parent field it came from
+    let mut fields = Vec::new();
+    let mut serde_with = parent.serde_with.clone();
+    let mut validate = parent.validate.clone();
+    let mut rust = parent.rust.clone();
+    for field in &parent.fields {
+        let Some(prefix) = parent.flatten.get(&field.0) else {
+            fields.push((field.clone(), &field.0));
+            continue;
+        };
+        let CapnpType::Struct(child) = &field.2 else { unreachable!("`flatten` is only accepted on struct fields") };
+        let Some(child_index) = structs.iter().position(|s| s.name == *child) else {
+            return Err(CapnezError::UnsupportedType {
+                file,
+                struct_name: parent.name.clone(),
+                field: field.0.clone(),
+                ty: child.clone(),
+                reason: "only #[capnp] structs can be flattened".to_string(),
+            });
+        };
+        resolve(structs, child_index, stack, origins)?;
+        let child = &structs[child_index];
+
+        rust = match (rust, &child.rust) {
+            (Some(mut rust), Some(inner)) => rust.flatten(fields.len(), inner).then_some(rust),
+            _ => None,
+        };
+        for (name, _, ty, default) in &child.fields {
+            let spliced = prefix.as_deref().map_or_else(|| name.clone(), |prefix| naming::prefixed(prefix, name));
+            if let Some(codec) = child.serde_with.get(name) {
+                serde_with.insert(spliced.clone(), codec.clone());
+            }
+            if let Some(validations) = child.validate.get(name) {
+                validate.insert(spliced.clone(), validations.clone());
+            }
+            fields.push(((spliced, 0, ty.clone(), default.clone()), &field.0));
+        }
+    }
+
+    for (i, ((name, ..), from)) in fields.iter().enumerate() {
+        if let Some((_, other)) = fields[..i].iter().find(|((other, ..), _)| other == name) {
+            let flattened = if parent.flatten.contains_key(*from) { from } else { other };
+            return Err(CapnezError::attribute(format!(
+                "flattening it gives `{}` two fields named `{}`; give it a `prefix`, or a different one", parent.name, name
+            )).at(&file, &parent.name, flattened));
+        }
+    }
+    let fields = fields.into_iter().enumerate().map(|(i, ((name, _, ty, default), _))| (name, i, ty, default)).collect();
+
+    let s = &mut structs[index];
+    s.fields = fields;
+    s.serde_with = serde_with;
+    s.validate = validate;
+    s.rust = rust;
+    s.flatten.clear();
+    stack.pop();
+    Ok(())
+}
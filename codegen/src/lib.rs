@@ -1,79 +1,266 @@
-use anyhow::{Context, Result};
-use std::{fs, path::PathBuf, env, collections::{HashMap, HashSet}};
+use anyhow::{anyhow, bail, Context, Result};
+use std::{fs, path::{Path, PathBuf}, env, collections::{BTreeMap, BTreeSet, HashMap, HashSet}};
 use walkdir::WalkDir;
-use syn::{parse_file, Item, DeriveInput, Data, Fields, Type, PathArguments, GenericArgument, Attribute, ItemTrait, Meta};
+use lock::SchemaLock;
+use convert::RustItem;
+use syn::{parse_file, Item, DeriveInput, Data, Fields, Type, PathArguments, GenericArgument, Attribute, ItemTrait};
+
+mod compat;
+mod convert;
+mod error;
+mod export;
+mod flatten;
+mod lock;
+pub mod model;
+mod naming;
+mod optional;
+mod server;
+mod stream;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod validate;
+mod wellknown;
+
+pub use compat::{compare_schemas, Change, ChangeKind, CompatReport, Severity};
+pub use error::CapnezError;
+pub use export::Export;
+pub use model::SchemaModel;
 
 #[derive(Clone)]
 enum CapnpType {
-    Text, UInt32, UInt64, Float32, Float64, Bool, Bytes,
-    List(Box<CapnpType>),
+    Text, Int8, Int16, Int32, Int64, UInt8, UInt16, UInt32, UInt64, Float32, Float64, Bool, Data,
+    /// A serde-only type stored as opaque bytes; holds the capnp name the type would otherwise have.
+    Bytes(String),
+    /// A library type mapped behind a cargo feature, see [`wellknown`].
+    WellKnown(wellknown::WellKnown),
+    /// Element type, and the length of a fixed-size array, which `from_capnp` checks.
+    List(Box<CapnpType>, Option<usize>),
     Optional(Box<CapnpType>),
     Struct(String),
+    /// A capability: a `Box<dyn Trait>` or `Arc<dyn Trait>` of a `#[capnp]` trait, by the interface's name.
+    Interface(String),
 }
 
 impl std::fmt::Display for CapnpType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Text => write!(f, "Text"),
+            Self::Int8 => write!(f, "Int8"),
+            Self::Int16 => write!(f, "Int16"),
+            Self::Int32 => write!(f, "Int32"),
+            Self::Int64 => write!(f, "Int64"),
+            Self::UInt8 => write!(f, "UInt8"),
+            Self::UInt16 => write!(f, "UInt16"),
             Self::UInt32 => write!(f, "UInt32"),
             Self::UInt64 => write!(f, "UInt64"),
             Self::Float32 => write!(f, "Float32"),
             Self::Float64 => write!(f, "Float64"),
             Self::Bool => write!(f, "Bool"),
-            Self::List(inner) => write!(f, "List({})", inner),
-            Self::Optional(inner) => write!(f, "union {{\n  value @0 :{};\n  none @1 :Void;\n}}", inner),
-            Self::Struct(name) => write!(f, "{}", name),
-            Self::Bytes => write!(f, "List(UInt8)"),
+            Self::List(inner, _) => write!(f, "List({})", inner),
+            Self::Optional(_) => write!(f, "{}", self.ident()),
+            Self::Struct(name) | Self::Interface(name) => write!(f, "{}", name),
+            Self::Bytes(_) => write!(f, "List(UInt8)"),
+            Self::Data => write!(f, "Data"),
+            Self::WellKnown(known) => write!(f, "{}", known.schema_type()),
         }
     }
 }
 
+impl CapnpType {
+    /// Identifier fragment used to name synthesized wrapper structs, e.g. `OptionalListMatrixEntry`.
+    fn ident(&self) -> String {
+        match self {
+            Self::Text | Self::Int8 | Self::Int16 | Self::Int32 | Self::Int64 | Self::UInt8 | Self::UInt16
+            | Self::UInt32 | Self::UInt64 | Self::Float32 | Self::Float64 | Self::Bool => self.to_string(),
+            Self::Bytes(_) => "Bytes".to_string(),
+            Self::Data => "Data".to_string(),
+            Self::WellKnown(known) => known.schema_type().to_string(),
+            Self::List(inner, _) => format!("List{}", inner.ident()),
+            Self::Optional(inner) => format!("Optional{}", inner.ident()),
+            Self::Struct(name) | Self::Interface(name) => name.clone(),
+        }
+    }
+
+    /// The Rust type of a numeric or boolean type, which capnp stores inline rather than behind a pointer.
+    fn scalar(&self) -> Option<&'static str> {
+        Some(match self {
+            Self::Int8 => "i8",
+            Self::Int16 => "i16",
+            Self::Int32 => "i32",
+            Self::Int64 => "i64",
+            Self::UInt8 => "u8",
+            Self::UInt16 => "u16",
+            Self::UInt32 => "u32",
+            Self::UInt64 => "u64",
+            Self::Float32 => "f32",
+            Self::Float64 => "f64",
+            Self::Bool => "bool",
+            _ => return None,
+        })
+    }
+
+    /// The non-container types at the bottom of any `List`/`Optional` nesting.
+    fn leaf(&self) -> &CapnpType {
+        match self {
+            Self::List(inner, _) | Self::Optional(inner) => inner.leaf(),
+            _ => self,
+        }
+    }
+
+    /// Names of the structs this type refers to directly (wrappers count as structs).
+    fn referenced_structs(&self, out: &mut BTreeSet<String>) {
+        match self {
+            Self::Struct(name) => { out.insert(name.clone()); }
+            Self::Optional(_) => { out.insert(self.ident()); }
+            Self::List(inner, _) => inner.referenced_structs(out),
+            _ => {}
+        }
+    }
+
+    /// Synthesizes one wrapper struct per `Optional` layer, innermost first.
+    fn optional_wrappers(&self, out: &mut Vec<CapnpStruct>) {
+        match self {
+            Self::List(inner, _) => inner.optional_wrappers(out),
+            Self::Optional(inner) => {
+                inner.optional_wrappers(out);
+                let name = self.ident();
+                if !out.iter().any(|s| s.name == name) {
+                    out.push(CapnpStruct {
+                        name,
+                        fields: vec![("value".to_string(), 0, (**inner).clone(), None)],
+                        has_serde: false,
+                        is_optional: true,
+                        rust: None,
+                        serde_with: BTreeMap::new(),
+                        validate: BTreeMap::new(),
+                        flatten: BTreeMap::new(),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Name, ordinal, type and rendered `#[capnp(default = ...)]` literal of a struct field.
+type StructField = (String, usize, CapnpType, Option<String>);
+
 #[derive(Clone)]
 struct CapnpStruct {
     name: String,
-    fields: Vec<(String, usize, CapnpType)>,
+    fields: Vec<StructField>,
     has_serde: bool,
-    is_bytes: bool,
+    is_optional: bool,
+    rust: Option<RustItem>,
+    /// Codec named by `#[capnp(serde_with = "...")]`, per serde-bytes field.
+    serde_with: BTreeMap<String, String>,
+    /// Constraints from `#[capnp(validate(...))]`, per field that has any.
+    validate: BTreeMap<String, Vec<validate::Validation>>,
+    /// Fields marked `#[capnp(flatten)]`, with their `prefix`. Emptied once `flatten::splice` has
+    /// replaced them with the fields of the struct they hold.
+    flatten: BTreeMap<String, Option<String>>,
 }
 
 impl CapnpStruct {
-    fn dependencies(&self) -> HashSet<String> {
-        self.fields.iter()
-            .filter_map(|(_, _, ty)| match ty {
-                CapnpType::Struct(name) => Some(name.clone()),
-                CapnpType::List(inner) | CapnpType::Optional(inner) => match &**inner {
-                    CapnpType::Struct(name) => Some(name.clone()),
-                    _ => None
-                },
-                _ => None
-            })
-            .collect()
+    fn dependencies(&self) -> BTreeSet<String> {
+        let mut deps = BTreeSet::new();
+        for (_, _, ty, _) in &self.fields {
+            ty.referenced_structs(&mut deps);
+        }
+        deps
     }
 }
 
+/// Name, parameters, and result field name and type of an interface method.
+type InterfaceMethod = (String, Vec<(String, CapnpType)>, Option<(String, CapnpType)>);
+
 #[derive(Clone)]
 struct CapnpInterface {
     name: String,
-    methods: Vec<(String, Vec<(String, CapnpType)>, Option<CapnpType>)>,
+    methods: Vec<InterfaceMethod>,
+    /// Capnp names of the supertraits, emitted as `extends(...)`.
+    extends: Vec<String>,
+    /// Name, item type and chunk size of each streaming method. Its receiver is already among the
+    /// method's parameters.
+    streams: Vec<(String, CapnpType, usize)>,
+}
+
+/// Items per push of a streaming method without `#[capnp(chunk = ...)]`.
+const DEFAULT_STREAM_CHUNK: usize = 256;
+
+/// Name of the interface a stream of `item` is pushed to, e.g. `MatrixEntryReceiver`.
+fn receiver_name(item: &CapnpType) -> String {
+    format!("{}Receiver", item.ident())
+}
+
+#[derive(Clone)]
+struct CapnpEnum {
+    name: String,
+    variants: Vec<String>,
+    rust_path: Option<String>,
+    rust_variants: Vec<String>,
 }
 
+/// Codecs a serde-bytes field can name in `#[capnp(serde_with = "...")]`, each matching a `capnez` feature.
+const SERDE_CODECS: &[&str] = &["json", "bincode", "postcard"];
+
 #[derive(Default)]
-struct StructRegistry(HashMap<String, (bool, bool)>);
+struct StructRegistry {
+    flags: BTreeMap<String, (bool, bool)>,
+    renames: BTreeMap<String, String>,
+    /// Capnp names of `#[capnp]` traits, so capability fields resolve whichever file declares the trait.
+    interfaces: BTreeSet<String>,
+    /// Rust paths of serde-only structs, for the generated serde-bytes accessors.
+    serde_paths: BTreeMap<String, String>,
+    /// Types defined in hand-written schemas: local capnp name -> (schema file, name in that file).
+    imports: BTreeMap<String, (String, String)>,
+}
 
 impl StructRegistry {
     fn register_serde_struct(&mut self, name: &str) { 
-        let entry = self.0.entry(name.to_string()).or_insert((false, false));
+        let entry = self.flags.entry(name.to_string()).or_insert((false, false));
         entry.1 = true;
     }
     fn register_capnp_struct(&mut self, name: &str) {
-        let entry = self.0.entry(name.to_string()).or_insert((false, false));
+        let entry = self.flags.entry(name.to_string()).or_insert((false, false));
         entry.0 = true;
     }
     fn is_serde_struct(&self, name: &str) -> bool { 
-        self.0.get(name).map_or(false, |(_, serde)| *serde) 
+        self.flags.get(name).is_some_and(|(_, serde)| *serde) 
     }
     fn is_capnp_struct(&self, name: &str) -> bool {
-        self.0.get(name).map_or(false, |(capnp, _)| *capnp)
+        self.flags.get(name).is_some_and(|(capnp, _)| *capnp)
+    }
+    fn register_interface(&mut self, name: &str) {
+        self.interfaces.insert(name.to_string());
+    }
+    /// Records the capnp name of a Rust type so references to it pick up `#[capnp(rename)]`.
+    fn register_name(&mut self, ident: &syn::Ident, name: &str) {
+        self.renames.insert(ident.to_string(), name.to_string());
+    }
+    fn capnp_name(&self, ident: &str) -> String {
+        self.renames.get(ident).cloned().unwrap_or_else(|| naming::pascal_case(ident))
+    }
+    /// Records that `alias` refers to `name` in the hand-written schema `file`.
+    fn register_import(&mut self, alias: &str, file: String, name: String) -> Result<(), CapnezError> {
+        let target = (file, name);
+        match self.imports.get(alias) {
+            Some(existing) if *existing != target => Err(CapnezError::attribute(format!(
+                "`{}` is imported as both `{}` from {} and `{}` from {}",
+                alias, existing.1, existing.0, target.1, target.0
+            ))),
+            _ => {
+                self.imports.insert(alias.to_string(), target);
+                Ok(())
+            }
+        }
+    }
+    /// A library type with a feature-gated mapping, unless an annotated type of the same name shadows it.
+    fn well_known(&self, p: &syn::TypePath) -> Result<Option<wellknown::WellKnown>, CapnezError> {
+        match p.path.segments.last() {
+            Some(last) if !self.renames.contains_key(&last.ident.to_string()) => wellknown::WellKnown::recognize(p),
+            _ => Ok(None),
+        }
     }
 }
 
@@ -88,7 +275,7 @@ fn has_attrs(attrs: &[Attribute]) -> (bool, bool) {
                     let has_serde = list.parse_args_with(syn::punctuated::Punctuated::<syn::Meta, syn::Token![,]>::parse_terminated)
                         .unwrap_or_default()
                         .iter()
-                        .any(|meta| matches!(meta, syn::Meta::Path(p) if p.segments.last().map_or(false, |s| s.ident == "Serialize" || s.ident == "Deserialize")));
+                        .any(|meta| matches!(meta, syn::Meta::Path(p) if p.segments.last().is_some_and(|s| s.ident == "Serialize" || s.ident == "Deserialize")));
                     (capnp, serde || has_serde)
                 } else { (capnp, serde) }
             }
@@ -97,158 +284,516 @@ fn has_attrs(attrs: &[Attribute]) -> (bool, bool) {
     })
 }
 
-fn map_ty(ty: &Type, registry: &StructRegistry) -> CapnpType {
-    match ty {
+fn map_ty(ty: &Type, registry: &StructRegistry) -> Result<CapnpType, CapnezError> {
+    Ok(match ty {
         Type::Path(p) if p.qself.is_none() => {
             let id = p.path.segments.last().unwrap().ident.to_string();
             match id.as_str() {
-                "String" => CapnpType::Text,
+                "String" | "str" => CapnpType::Text,
+                "i8" => CapnpType::Int8,
+                "i16" => CapnpType::Int16,
+                "i32" => CapnpType::Int32,
+                "i64" => CapnpType::Int64,
+                "u8" => CapnpType::UInt8,
+                "u16" => CapnpType::UInt16,
                 "u32" => CapnpType::UInt32,
                 "u64" => CapnpType::UInt64,
                 "f32" => CapnpType::Float32,
                 "f64" => CapnpType::Float64,
                 "bool" => CapnpType::Bool,
-                "Option" => CapnpType::Optional(Box::new(extract_generic_ty(p, registry))),
-                "Vec" => CapnpType::List(Box::new(extract_generic_ty(p, registry))),
+                "Option" => match extract_generic_ty(p, registry)? {
+                    CapnpType::Optional(_) => return Err(CapnezError::unsupported(
+                        quote::ToTokens::to_token_stream(ty).to_string(),
+                        "nested Option<Option<T>> is not supported; use a single Option or a wrapper struct",
+                    )),
+                    inner => CapnpType::Optional(Box::new(inner)),
+                },
+                "Vec" => match extract_generic_ty(p, registry)? {
+                    CapnpType::UInt8 => CapnpType::Data,
+                    inner => CapnpType::List(Box::new(inner), None),
+                },
+                "Box" | "Arc" if holds_trait_object(p) => extract_generic_ty(p, registry)?,
                 name => {
-                    let pascal_name = name.split('_').map(|w| {
-                        let mut c = w.chars();
-                        c.next().map_or(String::new(), |f| f.to_uppercase().chain(c).collect())
-                    }).collect::<String>();
+                    let pascal_name = registry.capnp_name(name);
                     if registry.is_serde_struct(&pascal_name) && !registry.is_capnp_struct(&pascal_name) {
-                        CapnpType::Bytes
+                        CapnpType::Bytes(pascal_name)
+                    } else if let Some(known) = registry.well_known(p)? {
+                        CapnpType::WellKnown(known)
                     } else {
                         CapnpType::Struct(pascal_name)
                     }
                 }
             }
         }
-        Type::Array(a) => CapnpType::List(Box::new(map_ty(&a.elem, registry))),
-        _ => panic!("Unsupported type"),
+        // capnp has no fixed-size lists; the length is only enforced when decoding
+        Type::Array(a) => CapnpType::List(Box::new(map_ty(&a.elem, registry)?), array_len(&a.len)),
+        // Borrowed fields map like their referent; lifetimes never reach the schema
+        Type::Reference(r) => match &*r.elem {
+            Type::Slice(s) if matches!(&*s.elem, Type::Path(p) if p.path.is_ident("u8")) => CapnpType::Data,
+            elem => map_ty(elem, registry)?,
+        },
+        Type::Slice(s) => CapnpType::List(Box::new(map_ty(&s.elem, registry)?), None),
+        Type::TraitObject(obj) => {
+            let name = obj.bounds.iter().find_map(|bound| match bound {
+                syn::TypeParamBound::Trait(t) => t.path.segments.last().map(|s| s.ident.to_string()).filter(|ident| !is_marker_trait(ident)),
+                _ => None,
+            }).map(|ident| registry.capnp_name(&ident));
+            match name {
+                Some(name) if registry.interfaces.contains(&name) => CapnpType::Interface(name),
+                _ => return Err(CapnezError::unsupported(
+                    quote::ToTokens::to_token_stream(ty).to_string(),
+                    "only trait objects of #[capnp] traits map to capabilities",
+                )),
+            }
+        }
+        _ => return Err(CapnezError::unsupported(
+            quote::ToTokens::to_token_stream(ty).to_string(),
+            "only paths, references, slices and arrays map to capnp types",
+        )),
+    })
+}
+
+/// Whether the single type argument of `p` is a `dyn Trait`, as in `Box<dyn Notifier>`.
+fn holds_trait_object(p: &syn::TypePath) -> bool {
+    match &p.path.segments.last().unwrap().arguments {
+        PathArguments::AngleBracketed(args) => matches!(args.args.first(), Some(GenericArgument::Type(Type::TraitObject(_)))),
+        _ => false,
     }
 }
 
-fn extract_generic_ty(p: &syn::TypePath, registry: &StructRegistry) -> CapnpType {
-    match &p.path.segments[0].arguments {
-        PathArguments::AngleBracketed(args) => args.args.first()
-            .and_then(|arg| match arg {
-                GenericArgument::Type(inner_ty) => Some(map_ty(inner_ty, registry)),
-                _ => None
-            })
-            .unwrap_or_else(|| panic!("Generic type must have a type parameter")),
-        _ => panic!("Generic type must have angle bracketed arguments"),
+/// Auto traits and `Sized` say nothing about the wire protocol, in supertraits or trait objects alike.
+fn is_marker_trait(ident: &str) -> bool {
+    matches!(ident, "Send" | "Sync" | "Sized" | "Unpin")
+}
+
+/// The length of an array type, if written as a literal. Other lengths (const generics, named constants)
+/// cannot be evaluated here, so those arrays are stored and read as plain lists.
+fn array_len(len: &syn::Expr) -> Option<usize> {
+    match len {
+        syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Int(n), .. }) => n.base10_parse().ok(),
+        other => {
+            warn(&format!(
+                "array length `{}` is not an integer literal, so its length is not checked when decoding",
+                quote::ToTokens::to_token_stream(other)
+            ));
+            None
+        }
+    }
+}
+
+/// Maps the `Option`/`Vec` layers of `ty` and substitutes `leaf` for whatever they contain.
+fn map_containers(ty: &Type, leaf: &CapnpType) -> CapnpType {
+    let Type::Path(p) = ty else { return leaf.clone() };
+    let segment = p.path.segments.last().unwrap();
+    let inner = match &segment.arguments {
+        PathArguments::AngleBracketed(args) => args.args.iter().find_map(|arg| match arg {
+            GenericArgument::Type(inner) => Some(inner),
+            _ => None,
+        }),
+        _ => None,
+    };
+    match (segment.ident.to_string().as_str(), inner) {
+        ("Option", Some(inner)) => CapnpType::Optional(Box::new(map_containers(inner, leaf))),
+        ("Vec", Some(inner)) => CapnpType::List(Box::new(map_containers(inner, leaf)), None),
+        _ => leaf.clone(),
+    }
+}
+
+fn extract_generic_ty(p: &syn::TypePath, registry: &StructRegistry) -> Result<CapnpType, CapnezError> {
+    let inner = match &p.path.segments.last().unwrap().arguments {
+        PathArguments::AngleBracketed(args) => args.args.first().and_then(|arg| match arg {
+            GenericArgument::Type(inner_ty) => Some(inner_ty),
+            _ => None,
+        }),
+        _ => None,
+    };
+    match inner {
+        Some(inner) => map_ty(inner, registry),
+        None => Err(CapnezError::unsupported(
+            quote::ToTokens::to_token_stream(p).to_string(),
+            "generic type must have a type parameter",
+        )),
     }
 }
 
-fn mk_struct(input: &DeriveInput, has_serde: bool, registry: &mut StructRegistry) -> CapnpStruct {
-    let name = input.ident.to_string().split('_').map(|w| {
-        let mut c = w.chars();
-        c.next().map_or(String::new(), |f| f.to_uppercase().chain(c).collect())
-    }).collect::<String>();
+fn mk_struct(input: &DeriveInput, has_serde: bool, registry: &mut StructRegistry, file: &Path) -> Result<CapnpStruct, CapnezError> {
+    let owner = input.ident.to_string();
+    let name = naming::type_name(&input.ident, &input.attrs).map_err(|e| e.at(file, &owner, ""))?;
     
     if has_serde {
         registry.register_serde_struct(&name);
     }
     registry.register_capnp_struct(&name);
 
-    let fields = match &input.data {
-        Data::Struct(s) => match &s.fields {
-            Fields::Named(n) => n.named.iter().enumerate().map(|(i, f)| {
-                let field_name = f.ident.as_ref().unwrap().to_string();
-                let camel_name = field_name.split('_').enumerate().map(|(i, w)| {
-                    let mut c = w.chars();
-                    if i == 0 { c.next().map_or(String::new(), |f| f.to_lowercase().chain(c).collect()) }
-                    else { c.next().map_or(String::new(), |f| f.to_uppercase().chain(c).collect()) }
-                }).collect::<String>();
-                (camel_name, i, map_ty(&f.ty, registry))
-            }).collect(),
-            _ => panic!("Only named structs are supported"),
+    // A unit struct is an empty capnp struct, e.g. a request or response with no payload
+    let empty = syn::punctuated::Punctuated::new();
+    let named = match &input.data {
+        Data::Struct(syn::DataStruct { fields: Fields::Named(n), .. }) => &n.named,
+        Data::Struct(syn::DataStruct { fields: Fields::Unit, .. }) => &empty,
+        _ => return Err(CapnezError::UnnamedFields { file: file.to_path_buf(), struct_name: owner }),
+    };
+
+    // Every field is checked, so one run reports all of a struct's problems
+    let mut fields = Vec::new();
+    let mut serde_with = BTreeMap::new();
+    let mut validate = BTreeMap::new();
+    let mut flatten = BTreeMap::new();
+    let mut errors = Vec::new();
+    for (i, f) in named.iter().enumerate() {
+        let field = mk_field(f, i, registry)
+            .and_then(|(field, codec)| Ok((validate::validations(&f.attrs, &field.2)?, flattened(f, &field.2)?, field, codec)));
+        match field {
+            Ok((validations, prefix, field, codec)) => {
+                if let Some(codec) = codec {
+                    serde_with.insert(field.0.clone(), codec);
+                }
+                if !validations.is_empty() {
+                    validate.insert(field.0.clone(), validations);
+                }
+                if let Some(prefix) = prefix {
+                    flatten.insert(field.0.clone(), prefix);
+                }
+                fields.push(field);
+            }
+            Err(e) => errors.push(e.at(file, &owner, &f.ident.as_ref().unwrap().to_string())),
+        }
+    }
+    CapnezError::all(errors)?;
+    naming::check_unique(&owner, named.iter().map(|f| f.ident.as_ref().unwrap()).zip(fields.iter().map(|(name, _, _, _)| name.as_str())))?;
+
+    Ok(CapnpStruct { name, fields, has_serde, is_optional: false, rust: None, serde_with, validate, flatten })
+}
+
+/// `Some(prefix)` if the field is `#[capnp(flatten)]`, which only a field holding a struct directly can be.
+fn flattened(f: &syn::Field, ty: &CapnpType) -> Result<Option<Option<String>>, CapnezError> {
+    let prefix = naming::flatten_prefix(&f.attrs)?;
+    if !naming::attr_flag(&f.attrs, "flatten")? {
+        return match prefix {
+            Some(_) => Err(CapnezError::attribute("`prefix` only applies to `flatten` fields")),
+            None => Ok(None),
+        };
+    }
+    match ty {
+        CapnpType::Struct(_) => Ok(Some(prefix)),
+        _ => Err(CapnezError::attribute(format!("`flatten` needs a field holding a #[capnp] struct, not {}", ty))),
+    }
+}
+
+/// One named field, along with the codec its `#[capnp(serde_with = "...")]` picks.
+fn mk_field(f: &syn::Field, index: usize, registry: &mut StructRegistry) -> Result<(StructField, Option<String>), CapnezError> {
+    let name = naming::member_name(f.ident.as_ref().unwrap(), &f.attrs)?;
+    let ty = match naming::attr_value(&f.attrs, "external")? {
+        Some(file) => {
+            let target = naming::attr_value(&f.attrs, "name")?
+                .ok_or_else(|| CapnezError::attribute("`external` needs the imported type's `name`"))?;
+            registry.register_import(&target, file, target.clone())?;
+            map_containers(&f.ty, &CapnpType::Struct(target))
+        }
+        None => wellknown::apply_repr(map_ty(&f.ty, registry)?, naming::attr_value(&f.attrs, "as")?)?,
+    };
+    let default = naming::attr_expr(&f.attrs, "default")?.map(|expr| default_literal(&expr, &ty)).transpose()?;
+    let codec = naming::attr_value(&f.attrs, "serde_with")?;
+    if let Some(codec) = codec.as_deref().filter(|codec| !SERDE_CODECS.contains(codec)) {
+        return Err(CapnezError::attribute(format!("unknown codec serde_with = \"{}\"; expected one of {}", codec, SERDE_CODECS.join(", "))));
+    }
+    Ok(((name, index, ty, default), codec))
+}
+
+/// Renders a `#[capnp(default = ...)]` literal in schema syntax, failing if it does not fit `ty`.
+fn default_literal(expr: &syn::Expr, ty: &CapnpType) -> Result<String, CapnezError> {
+    let (negative, lit) = match expr {
+        syn::Expr::Lit(l) => (false, Some(&l.lit)),
+        syn::Expr::Unary(u) if matches!(u.op, syn::UnOp::Neg(_)) => match &*u.expr {
+            syn::Expr::Lit(l) => (true, Some(&l.lit)),
+            _ => (true, None),
         },
-        _ => panic!("Only structs are supported"),
+        _ => (false, None),
     };
-    CapnpStruct { name, fields, has_serde, is_bytes: false }
-}
-
-fn mk_interface(input: &ItemTrait) -> CapnpInterface {
-    let name = input.ident.to_string().split('_').map(|w| {
-        let mut c = w.chars();
-        c.next().map_or(String::new(), |f| f.to_uppercase().chain(c).collect())
-    }).collect::<String>();
-
-    let methods = input.items.iter().filter_map(|item| {
-        if let syn::TraitItem::Fn(method) = item {
-            let name = method.sig.ident.to_string().split('_').enumerate().map(|(i, w)| {
-                let mut c = w.chars();
-                if i == 0 { c.next().map_or(String::new(), |f| f.to_lowercase().chain(c).collect()) }
-                else { c.next().map_or(String::new(), |f| f.to_uppercase().chain(c).collect()) }
-            }).collect::<String>();
-
-            let params = method.sig.inputs.iter().filter_map(|arg| {
-                if let syn::FnArg::Typed(pat_type) = arg {
-                    if let syn::Pat::Ident(pat_ident) = &*pat_type.pat {
-                        let param_name = pat_ident.ident.to_string().split('_').enumerate().map(|(i, w)| {
-                            let mut c = w.chars();
-                            if i == 0 { c.next().map_or(String::new(), |f| f.to_lowercase().chain(c).collect()) }
-                            else { c.next().map_or(String::new(), |f| f.to_uppercase().chain(c).collect()) }
-                        }).collect::<String>();
-                        Some((param_name, map_ty(&pat_type.ty, &StructRegistry::default())))
-                    } else { None }
-                } else { None }
-            }).collect();
-
-            let ret = match &method.sig.output {
-                syn::ReturnType::Type(_, ty) => Some(map_ty(&ty, &StructRegistry::default())),
-                syn::ReturnType::Default => None,
-            };
-            Some((name, params, ret))
-        } else { None }
+    let sign = if negative { "-" } else { "" };
+    let rendered = match (ty, lit) {
+        (CapnpType::Bool, Some(syn::Lit::Bool(b))) if !negative => Some(b.value.to_string()),
+        (CapnpType::Text, Some(syn::Lit::Str(s))) if !negative => Some(text_literal(&s.value())),
+        (CapnpType::Float32 | CapnpType::Float64, Some(syn::Lit::Float(f))) => Some(format!("{}{}", sign, f.base10_digits())),
+        (CapnpType::Float32 | CapnpType::Float64, Some(syn::Lit::Int(i))) => Some(format!("{}{}", sign, i.base10_digits())),
+        (_, Some(syn::Lit::Int(i))) => int_range(ty).and_then(|(min, max)| {
+            let value = i.base10_parse::<i128>().ok()?;
+            let value = if negative { -value } else { value };
+            (min..=max).contains(&value).then(|| value.to_string())
+        }),
+        _ => None,
+    };
+    rendered.ok_or_else(|| CapnezError::attribute(format!(
+        "default = {} does not fit the capnp type {}",
+        quote::ToTokens::to_token_stream(expr), ty
+    )))
+}
+
+fn int_range(ty: &CapnpType) -> Option<(i128, i128)> {
+    Some(match ty {
+        CapnpType::Int8 => (i8::MIN.into(), i8::MAX.into()),
+        CapnpType::Int16 => (i16::MIN.into(), i16::MAX.into()),
+        CapnpType::Int32 => (i32::MIN.into(), i32::MAX.into()),
+        CapnpType::Int64 => (i64::MIN.into(), i64::MAX.into()),
+        CapnpType::UInt8 => (0, u8::MAX.into()),
+        CapnpType::UInt16 => (0, u16::MAX.into()),
+        CapnpType::UInt32 => (0, u32::MAX.into()),
+        CapnpType::UInt64 => (0, u64::MAX.into()),
+        _ => return None,
+    })
+}
+
+/// A capnp string literal; capnp understands C-style escapes.
+fn text_literal(value: &str) -> String {
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\x{:02x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn mk_enum(input: &syn::ItemEnum, file: &Path) -> Result<CapnpEnum, CapnezError> {
+    let owner = input.ident.to_string();
+    let name = naming::type_name(&input.ident, &input.attrs).map_err(|e| e.at(file, &owner, ""))?;
+
+    let mut variants = Vec::new();
+    let mut errors = Vec::new();
+    for v in &input.variants {
+        if !matches!(v.fields, Fields::Unit) {
+            errors.push(CapnezError::UnsupportedType {
+                file: file.to_path_buf(),
+                struct_name: owner.clone(),
+                field: v.ident.to_string(),
+                ty: quote::ToTokens::to_token_stream(&v.fields).to_string(),
+                reason: "only unit variants are supported in #[capnp] enums".to_string(),
+            });
+            continue;
+        }
+        match naming::member_name(&v.ident, &v.attrs) {
+            Ok(variant) => variants.push(variant),
+            Err(e) => errors.push(e.at(file, &owner, &v.ident.to_string())),
+        }
+    }
+    CapnezError::all(errors)?;
+    naming::check_unique(&owner, input.variants.iter().map(|v| &v.ident).zip(variants.iter().map(String::as_str)))?;
+
+    let rust_variants = input.variants.iter().map(|v| v.ident.to_string()).collect();
+    Ok(CapnpEnum { name, variants, rust_path: None, rust_variants })
+}
+
+/// Item type of a streaming method, one returning `impl Stream<Item = T>` or `Vec<T>` under `#[capnp(stream)]`.
+fn stream_item(method: &syn::TraitItemFn) -> Result<Option<&Type>, CapnezError> {
+    let marked = naming::attr_flag(&method.attrs, "stream")?;
+    let item = match &method.sig.output {
+        syn::ReturnType::Type(_, ty) => match &**ty {
+            Type::ImplTrait(t) => t.bounds.iter().find_map(|bound| {
+                let syn::TypeParamBound::Trait(bound) = bound else { return None };
+                let last = bound.path.segments.last().filter(|s| s.ident == "Stream")?;
+                let PathArguments::AngleBracketed(args) = &last.arguments else { return None };
+                args.args.iter().find_map(|arg| match arg {
+                    GenericArgument::AssocType(assoc) if assoc.ident == "Item" => Some(&assoc.ty),
+                    _ => None,
+                })
+            }),
+            Type::Path(p) if marked => p.path.segments.last().filter(|s| s.ident == "Vec").and_then(|s| match &s.arguments {
+                PathArguments::AngleBracketed(args) => args.args.iter().find_map(|arg| match arg {
+                    GenericArgument::Type(ty) => Some(ty),
+                    _ => None,
+                }),
+                _ => None,
+            }),
+            _ => None,
+        },
+        syn::ReturnType::Default => None,
+    };
+    if marked && item.is_none() {
+        return Err(CapnezError::attribute("#[capnp(stream)] needs a `Vec<T>` or `impl Stream<Item = T>` return type"));
+    }
+    Ok(item)
+}
+
+/// The `#[capnp(chunk = ...)]` of a streaming method.
+fn chunk_size(attrs: &[Attribute]) -> Result<usize, CapnezError> {
+    match naming::attr_expr(attrs, "chunk")? {
+        Some(syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Int(n), .. })) => match n.base10_parse::<usize>() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(CapnezError::attribute(format!("chunk = {} must be a positive number of items", n))),
+        },
+        Some(_) => Err(CapnezError::attribute("`chunk` expects an integer literal")),
+        None => Ok(DEFAULT_STREAM_CHUNK),
+    }
+}
+
+fn mk_interface(input: &ItemTrait, registry: &StructRegistry, file: &Path) -> Result<CapnpInterface, CapnezError> {
+    let owner = input.ident.to_string();
+    let name = naming::type_name(&input.ident, &input.attrs).map_err(|e| e.at(file, &owner, ""))?;
+
+    let mut method_idents = Vec::new();
+    let mut methods = Vec::new();
+    let mut streams = Vec::new();
+    let mut errors = Vec::new();
+    for item in &input.items {
+        let syn::TraitItem::Fn(method) = item else { continue };
+        let method_name = match naming::member_name(&method.sig.ident, &method.attrs) {
+            Ok(name) => name,
+            Err(e) => {
+                errors.push(e.at(file, &owner, &method.sig.ident.to_string()));
+                continue;
+            }
+        };
+        method_idents.push(&method.sig.ident);
+
+        let mut param_idents = Vec::new();
+        let mut params = Vec::new();
+        for arg in &method.sig.inputs {
+            let syn::FnArg::Typed(pat_type) = arg else { continue };
+            let syn::Pat::Ident(pat_ident) = &*pat_type.pat else { continue };
+            let param = naming::member_name(&pat_ident.ident, &pat_type.attrs).and_then(|name| {
+                let ty = wellknown::apply_repr(map_ty(&pat_type.ty, registry)?, naming::attr_value(&pat_type.attrs, "as")?)?;
+                Ok((name, ty))
+            });
+            match param {
+                Ok(param) => {
+                    param_idents.push(&pat_ident.ident);
+                    params.push(param);
+                }
+                Err(e) => errors.push(e.at(file, &owner, &format!("{}({})", method.sig.ident, pat_ident.ident))),
+            }
+        }
+        if let Err(e) = naming::check_unique(
+            &format!("{}::{}", input.ident, method.sig.ident),
+            param_idents.into_iter().zip(params.iter().map(|(name, _)| name.as_str())),
+        ) {
+            errors.push(e);
+        }
+
+        // A stream comes back through a receiver the caller passes in, so the method itself returns nothing
+        let stream = stream_item(method).and_then(|item| match item {
+            Some(item) => match map_ty(item, registry)? {
+                item @ CapnpType::Struct(_) => Ok(Some((item, chunk_size(&method.attrs)?))),
+                other => Err(CapnezError::unsupported(other.to_string(), "stream items must be #[capnp] structs or enums")),
+            },
+            None if naming::attr_expr(&method.attrs, "chunk")?.is_some() => {
+                Err(CapnezError::attribute("`chunk` only applies to streaming methods"))
+            }
+            None => Ok(None),
+        });
+        match stream {
+            Ok(Some((item, chunk))) => {
+                if params.iter().any(|(name, _)| name == "receiver") {
+                    errors.push(CapnezError::attribute("`receiver` is taken by the stream's receiver; rename the parameter")
+                        .at(file, &owner, &method.sig.ident.to_string()));
+                }
+                params.push(("receiver".to_string(), CapnpType::Interface(receiver_name(&item))));
+                streams.push((method_name.clone(), item, chunk));
+                methods.push((method_name, params, None));
+                continue;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                errors.push(e.at(file, &owner, &format!("{}() stream", method.sig.ident)));
+                continue;
+            }
+        }
+
+        let ret = match &method.sig.output {
+            syn::ReturnType::Type(_, ty) => match naming::result_name(&method.attrs).and_then(|result| Ok((result, map_ty(ty, registry)?))) {
+                Ok(ret) => Some(ret),
+                Err(e) => {
+                    errors.push(e.at(file, &owner, &format!("{}() return value", method.sig.ident)));
+                    None
+                }
+            },
+            syn::ReturnType::Default => None,
+        };
+        methods.push((method_name, params, ret));
+    }
+    CapnezError::all(errors)?;
+    naming::check_unique(&owner, method_idents.into_iter().zip(methods.iter().map(|(name, _, _)| name.as_str())))?;
+
+    // Auto traits and lifetimes say nothing about the wire protocol
+    let extends = input.supertraits.iter().filter_map(|bound| match bound {
+        syn::TypeParamBound::Trait(t) => {
+            let ident = t.path.segments.last()?.ident.to_string();
+            (!is_marker_trait(&ident)).then(|| registry.capnp_name(&ident))
+        }
+        _ => None,
     }).collect();
 
-    CapnpInterface { name, methods }
+    Ok(CapnpInterface { name, methods, extends, streams })
 }
 
-fn topo_sort(structs: &[CapnpStruct]) -> Vec<usize> {
+fn topo_sort(structs: &[CapnpStruct]) -> Result<Vec<usize>, CapnezError> {
     let mut visited = HashSet::new();
-    let mut temp = HashSet::new();
+    let mut stack = Vec::new();
     let mut order = Vec::new();
     
-    fn visit(i: usize, structs: &[CapnpStruct], visited: &mut HashSet<usize>, 
-             temp: &mut HashSet<usize>, order: &mut Vec<usize>) -> bool {
-        if temp.contains(&i) { return false; }
-        if visited.contains(&i) { return true; }
+    // `stack` is the path from the current root, which is the cycle once a struct on it comes up again
+    fn visit(i: usize, structs: &[CapnpStruct], visited: &mut HashSet<usize>,
+             stack: &mut Vec<usize>, order: &mut Vec<usize>) -> Result<(), CapnezError> {
+        if let Some(start) = stack.iter().position(|&j| j == i) {
+            let cycle = stack[start..].iter().chain(Some(&i)).map(|&j| structs[j].name.clone()).collect();
+            return Err(CapnezError::CircularDependency { cycle });
+        }
+        if visited.contains(&i) { return Ok(()); }
         
-        temp.insert(i);
+        stack.push(i);
         for dep in structs[i].dependencies() {
             if let Some(j) = structs.iter().position(|s| s.name == dep) {
-                if !visit(j, structs, visited, temp, order) { return false; }
+                visit(j, structs, visited, stack, order)?;
             }
         }
-        temp.remove(&i);
+        stack.pop();
         visited.insert(i);
         order.push(i);
-        true
+        Ok(())
     }
     
-    for i in 0..structs.len() {
-        if !visited.contains(&i) && !visit(i, structs, &mut visited, &mut temp, &mut order) {
-            panic!("Circular dependency detected in struct definitions");
-        }
+    // Roots are taken by name and dependencies come from a BTreeSet, so the order only depends on the names
+    let mut roots = (0..structs.len()).collect::<Vec<_>>();
+    roots.sort_by(|&a, &b| structs[a].name.cmp(&structs[b].name));
+    for i in roots {
+        visit(i, structs, &mut visited, &mut stack, &mut order)?;
     }
     order.reverse();
-    order
+    Ok(order)
+}
+
+/// Module path of a source file relative to the input directory, e.g. `models/user.rs` -> `crate::models::user`.
+///
+/// Returns `None` for binaries under `bin/`, which are separate crates the generated code cannot name.
+fn module_path(input: &Path, file: &Path) -> Option<String> {
+    let rel = file.strip_prefix(input).ok()?.with_extension("");
+    let mut segments = rel.iter().map(|s| s.to_str()).collect::<Option<Vec<_>>>()?;
+    if segments.first() == Some(&"bin") {
+        return None;
+    }
+    if matches!(segments.as_slice(), ["main"] | ["lib"]) || segments.last() == Some(&"mod") {
+        segments.pop();
+    }
+    Some(std::iter::once("crate").chain(segments).collect::<Vec<_>>().join("::"))
 }
 
-fn collect_structs(file: &syn::File, registry: &mut StructRegistry) -> Vec<CapnpStruct> {
+fn collect_structs(file: &syn::File, registry: &mut StructRegistry, module: Option<&str>, path: &Path) -> Result<Vec<CapnpStruct>, CapnezError> {
+    let mut errors = Vec::new();
+
     // First pass: register all serde structs
     for item in &file.items {
         if let Item::Struct(s) = item {
             let (_, has_serde) = has_attrs(&s.attrs);
             if has_serde {
-                let name = s.ident.to_string().split('_').map(|w| {
-                    let mut c = w.chars();
-                    c.next().map_or(String::new(), |f| f.to_uppercase().chain(c).collect())
-                }).collect::<String>();
-                registry.register_serde_struct(&name);
+                match naming::type_name(&s.ident, &s.attrs) {
+                    Ok(name) => registry.register_serde_struct(&name),
+                    Err(e) => errors.push(e.at(path, &s.ident.to_string(), "")),
+                }
             }
         }
     }
@@ -258,17 +803,28 @@ fn collect_structs(file: &syn::File, registry: &mut StructRegistry) -> Vec<Capnp
     for item in &file.items {
         if let Item::Struct(s) = item {
             let (has_capnp, has_serde) = has_attrs(&s.attrs);
-            let name = s.ident.to_string().split('_').map(|w| {
-                let mut c = w.chars();
-                c.next().map_or(String::new(), |f| f.to_uppercase().chain(c).collect())
-            }).collect::<String>();
+            let name = match naming::type_name(&s.ident, &s.attrs) {
+                Ok(name) => name,
+                // Serde structs were already reported by the first pass
+                Err(e) => {
+                    if !has_serde {
+                        errors.push(e.at(path, &s.ident.to_string(), ""));
+                    }
+                    continue;
+                }
+            };
             if has_serde {
                 registry.register_serde_struct(&name);
             }
             if has_capnp {
                 registry.register_capnp_struct(&name);
             }
-            if has_capnp {
+            if has_serde && !has_capnp {
+                if let Some(rust) = module.and_then(|m| convert::type_path(m, &s.ident, &s.vis, &s.generics)) {
+                    registry.serde_paths.insert(name.clone(), rust);
+                }
+            }
+            if has_capnp && !matches!(naming::attr_value(&s.attrs, "external"), Ok(Some(_))) {
                 let input = DeriveInput {
                     attrs: s.attrs.clone(),
                     vis: s.vis.clone(),
@@ -280,146 +836,776 @@ fn collect_structs(file: &syn::File, registry: &mut StructRegistry) -> Vec<Capnp
                         semi_token: s.semi_token,
                     }),
                 };
-                structs.push(mk_struct(&input, has_serde, registry));
+                if s.generics.const_params().next().is_some() {
+                    warn(&format!(
+                        "`{}` has const generic parameters; arrays sized by them become unchecked lists and no conversions are generated",
+                        s.ident
+                    ));
+                }
+                match mk_struct(&input, has_serde, registry, path) {
+                    Ok(mut collected) => {
+                        collected.rust = module.and_then(|m| RustItem::new(m, s));
+                        structs.push(collected);
+                    }
+                    Err(e) => errors.push(e),
+                }
             }
         }
     }
-    structs
+    CapnezError::all(errors)?;
+    Ok(structs)
+}
+
+/// Surfaces a non-fatal diagnostic: as a cargo warning inside a build script, on stderr otherwise.
+fn warn(message: &str) {
+    if env::var_os("OUT_DIR").is_some() {
+        println!("cargo:warning={}", message);
+    } else {
+        eprintln!("warning: {}", message);
+    }
 }
 
+/// Generates `schema.capnp` and `schema_capnp.rs` for the current crate.
+///
+/// Reads `CARGO_MANIFEST_DIR/src` and writes to `OUT_DIR/generated`, so it is meant to be called from `build.rs`.
 pub fn generate_schema() -> Result<()> {
-    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR")?);
-    let out_dir = PathBuf::from(env::var("OUT_DIR")?);
-    let output = out_dir.join("generated");
-    fs::create_dir_all(&output)?;
-    
-    let mut structs = Vec::new();
-    let mut interfaces = Vec::new();
-    let mut registry = StructRegistry::default();
-    
-    // First pass: collect all files to register serde structs
-    let files: Vec<_> = WalkDir::new(manifest_dir.join("src"))
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.path().extension().map_or(false, |ext| ext == "rs"))
-        .collect();
+    SchemaGenerator::new().run()
+}
 
-    // First pass: register all serde structs
-    for entry in &files {
-        let content = fs::read_to_string(entry.path())
-            .with_context(|| format!("Failed to read {}", entry.path().display()))?;
-            
-        let file = parse_file(&content)
-            .with_context(|| format!("Failed to parse {}", entry.path().display()))?;
-            
-        // Register serde structs first
-        for item in &file.items {
-            if let Item::Struct(s) = item {
-                let (has_capnp, has_serde) = has_attrs(&s.attrs);
-                let name = s.ident.to_string().split('_').map(|w| {
-                    let mut c = w.chars();
-                    c.next().map_or(String::new(), |f| f.to_uppercase().chain(c).collect())
-                }).collect::<String>();
-                if has_serde {
-                    registry.register_serde_struct(&name);
-                }
-                if has_capnp {
-                    registry.register_capnp_struct(&name);
-                }
-            }
+/// Collects the schema of the `.rs` files under `input_dir` as a [`SchemaModel`], stopping before
+/// anything is written. The lockfile is not consulted, and the file ID is the derived default; use
+/// [`SchemaGenerator::model`] to configure either.
+pub fn collect_model(input_dir: &Path) -> Result<SchemaModel> {
+    SchemaGenerator::new().input_dir(input_dir).without_lockfile().model()
+}
+
+/// File ID used when none is configured: a hash of the package name in `Cargo.toml` next to `input`
+/// (or that directory's name) and the name of `input` itself, with the high bit capnp requires.
+fn derived_file_id(input: &Path) -> u64 {
+    let root = input.parent();
+    let package = root
+        .and_then(|dir| fs::read_to_string(dir.join("Cargo.toml")).ok())
+        .and_then(|manifest| manifest.parse::<toml::Table>().ok())
+        .and_then(|manifest| Some(manifest.get("package")?.get("name")?.as_str()?.to_string()))
+        .or_else(|| Some(root?.file_name()?.to_string_lossy().into_owned()))
+        .unwrap_or_default();
+    let dir = input.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    // FNV-1a, which unlike `DefaultHasher` is the same across Rust versions and platforms
+    let hash = format!("capnez:{}/{}", package, dir)
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3));
+    hash | 1 << 63
+}
+
+/// Configurable schema generation with explicit paths, usable outside of a build script.
+///
+/// ```no_run
+/// capnez_codegen::SchemaGenerator::new()
+///     .input_dir("src")
+///     .output_dir("generated")
+///     .file_id(0xd0b6_8c8e_2f4a_9b31)
+///     .exclude_glob("tests/**")
+///     .emit_serde_derives(true)
+///     .run()?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct SchemaGenerator {
+    input_dir: Option<PathBuf>,
+    output_dir: Option<PathBuf>,
+    file_id: Option<u64>,
+    exclude: Vec<String>,
+    emit_serde_derives: bool,
+    emit_conversions: bool,
+    no_std: bool,
+    limits: Option<Limits>,
+    lockfile: Option<PathBuf>,
+    use_lockfile: bool,
+    import_paths: Vec<PathBuf>,
+    export: Option<Export>,
+}
+
+impl Default for SchemaGenerator {
+    fn default() -> Self {
+        Self {
+            input_dir: None,
+            output_dir: None,
+            file_id: None,
+            exclude: Vec::new(),
+            emit_serde_derives: true,
+            emit_conversions: true,
+            no_std: false,
+            limits: None,
+            lockfile: None,
+            use_lockfile: true,
+            import_paths: Vec::new(),
+            export: None,
         }
     }
+}
 
-    // Second pass: collect capnp structs and interfaces
-    for entry in files {
-        let content = fs::read_to_string(entry.path())
-            .with_context(|| format!("Failed to read {}", entry.path().display()))?;
-            
-        let file = parse_file(&content)
-            .with_context(|| format!("Failed to parse {}", entry.path().display()))?;
-            
-        structs.extend(collect_structs(&file, &mut registry));
-        
-        for item in file.items {
-            if let Item::Trait(t) = item {
-                let (has_capnp, _) = has_attrs(&t.attrs);
-                if has_capnp { interfaces.push(mk_interface(&t)); }
+/// Output of one generation pass, before anything is written.
+struct Generated {
+    schema: String,
+    model: SchemaModel,
+    structs: Vec<CapnpStruct>,
+    enums: Vec<CapnpEnum>,
+    interfaces: Vec<CapnpInterface>,
+    serde_paths: BTreeMap<String, String>,
+    /// Hand-written schema files imported with `#[capnp(external)]`, as written in the attribute.
+    imports: BTreeSet<String>,
+    lock: Option<(PathBuf, SchemaLock)>,
+}
+
+/// Upper bounds on what a single schema may contain, so runaway inputs (e.g. generated code
+/// annotated by accident) fail with a diagnostic instead of exhausting memory inside capnpc.
+///
+/// The defaults are far above legitimate use. They can be overridden under `[limits]` in a
+/// `capnez.toml` next to the crate's `Cargo.toml`, or with [`SchemaGenerator::limits`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Limits {
+    pub max_structs: usize,
+    pub max_enums: usize,
+    pub max_interfaces: usize,
+    pub max_fields_per_struct: usize,
+    pub max_schema_bytes: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_structs: 10_000,
+            max_enums: 10_000,
+            max_interfaces: 1_000,
+            max_fields_per_struct: 1_000,
+            max_schema_bytes: 16 * 1024 * 1024,
+        }
+    }
+}
+
+impl Limits {
+    /// Reads the `[limits]` table of a `capnez.toml`; missing keys keep their defaults.
+    pub fn from_config(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let config: toml::Table = content.parse()
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+        let mut limits = Self::default();
+        let Some(table) = config.get("limits") else { return Ok(limits) };
+        let table = table.as_table()
+            .with_context(|| format!("`limits` in {} must be a table", path.display()))?;
+        for (key, value) in table {
+            let value = value.as_integer().and_then(|v| usize::try_from(v).ok())
+                .with_context(|| format!("`limits.{}` in {} must be a non-negative integer", key, path.display()))?;
+            match key.as_str() {
+                "max_structs" => limits.max_structs = value,
+                "max_enums" => limits.max_enums = value,
+                "max_interfaces" => limits.max_interfaces = value,
+                "max_fields_per_struct" => limits.max_fields_per_struct = value,
+                "max_schema_bytes" => limits.max_schema_bytes = value,
+                other => bail!("Unknown limit `{}` in {}", other, path.display()),
             }
         }
+        Ok(limits)
     }
 
-    // Generate schema ID using capnpc -i
-    let schema_id = String::from_utf8(std::process::Command::new("capnpc").arg("-i").output()?.stdout)?
-        .trim()
-        .trim_start_matches('@')
-        .to_string();
-    let mut schema = format!("@{};\n", schema_id);
-    
-    // Sort structs topologically
-    let order = topo_sort(&structs);
-    for &i in &order {
-        let s = &structs[i];
-        schema.push_str(&format!("struct {} {{\n", s.name));
-        for (name, id, ty) in &s.fields {
-            schema.push_str(&format!("  {} @{} :{};\n", name, id, ty));
+    fn exceeded(what: &str, count: usize, limit: usize, file_counts: &[(PathBuf, usize)]) -> anyhow::Error {
+        let mut top = file_counts.to_vec();
+        top.sort_by_key(|&(_, n)| std::cmp::Reverse(n));
+        let files = top.iter().take(5)
+            .map(|(path, n)| format!("  {} ({} items)", path.display(), n))
+            .collect::<Vec<_>>()
+            .join("\n");
+        anyhow!(
+            "Collected {} {}, exceeding the limit of {}.\nFiles contributing the most items:\n{}\n\
+             If these files are not meant to be part of the schema, skip them with `exclude_glob` (`--exclude` on the CLI); \
+             otherwise raise the limit under [limits] in capnez.toml.",
+            count, what, limit, files
+        )
+    }
+}
+
+/// Item counts found by [`SchemaGenerator::inspect`], alongside the limits they are checked against.
+#[derive(Clone, Debug)]
+pub struct Inspection {
+    pub structs: usize,
+    pub enums: usize,
+    pub interfaces: usize,
+    pub max_fields: usize,
+    pub files: Vec<(PathBuf, usize)>,
+    pub limits: Limits,
+}
+
+impl std::fmt::Display for Inspection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "structs:           {} / {}", self.structs, self.limits.max_structs)?;
+        writeln!(f, "enums:             {} / {}", self.enums, self.limits.max_enums)?;
+        writeln!(f, "interfaces:        {} / {}", self.interfaces, self.limits.max_interfaces)?;
+        writeln!(f, "fields per struct: {} / {}", self.max_fields, self.limits.max_fields_per_struct)?;
+        writeln!(f, "schema bytes:      - / {}", self.limits.max_schema_bytes)?;
+        let mut files = self.files.iter().filter(|(_, n)| *n > 0).collect::<Vec<_>>();
+        files.sort_by_key(|&&(_, n)| std::cmp::Reverse(n));
+        for (path, n) in files {
+            writeln!(f, "  {} ({} items)", path.display(), n)?;
         }
-        schema.push_str("}\n\n");
+        Ok(())
     }
-    
-    for i in &interfaces {
-        schema.push_str(&format!("interface {} {{\n", i.name));
-        for (name, params, ret) in &i.methods {
-            schema.push_str(&format!("  {} @0 (", name));
-            for (i, (pname, pty)) in params.iter().enumerate() {
-                if i > 0 { schema.push_str(", "); }
-                schema.push_str(&format!("{} :{}", pname, pty));
+}
+
+impl SchemaGenerator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Directory scanned recursively for `.rs` files. Defaults to `CARGO_MANIFEST_DIR/src`.
+    pub fn input_dir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.input_dir = Some(path.into());
+        self
+    }
+
+    /// Directory receiving `schema.capnp` and `schema_capnp.rs`. Defaults to `OUT_DIR/generated`.
+    ///
+    /// The default is private to the crate being built, so workspace members generating in parallel never
+    /// share files. Nothing guards a directory shared between crates; give each its own.
+    pub fn output_dir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.output_dir = Some(path.into());
+        self
+    }
+
+    /// Fixed file ID for the schema. Defaults to one derived from the package name in the crate's
+    /// `Cargo.toml` and the input directory's name, so it is the same on every build and machine.
+    pub fn file_id(mut self, id: u64) -> Self {
+        self.file_id = Some(id);
+        self
+    }
+
+    /// Skips files whose path relative to the input directory matches `pattern`. May be given multiple times.
+    pub fn exclude_glob(mut self, pattern: impl Into<String>) -> Self {
+        self.exclude.push(pattern.into());
+        self
+    }
+
+    /// Whether to inject `serde` derives into the generated Rust code for serde-annotated structs.
+    pub fn emit_serde_derives(mut self, emit: bool) -> Self {
+        self.emit_serde_derives = emit;
+        self
+    }
+
+    /// Whether to generate `to_capnp`/`from_capnp` conversions on the annotated Rust types.
+    ///
+    /// Disable this when the schema is compiled into a crate other than the one declaring the types.
+    pub fn emit_conversions(mut self, emit: bool) -> Self {
+        self.emit_conversions = emit;
+        self
+    }
+
+    /// Whether the schema is compiled into a `#![no_std]` crate. The conversions only need `alloc`
+    /// either way; this leaves out `write_streamed`/`read_streamed`, which are built on `std::io`.
+    pub fn no_std(mut self, no_std: bool) -> Self {
+        self.no_std = no_std;
+        self
+    }
+
+    /// Overrides the resource limits instead of reading them from `capnez.toml`.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = Some(limits);
+        self
+    }
+
+    /// Location of the numbering lockfile. Defaults to `capnez.lock` in the input directory's parent,
+    /// i.e. next to `Cargo.toml`.
+    pub fn lockfile(mut self, path: impl Into<PathBuf>) -> Self {
+        self.lockfile = Some(path.into());
+        self.use_lockfile = true;
+        self
+    }
+
+    /// Disables the numbering lockfile: nothing is checked or written.
+    pub fn without_lockfile(mut self) -> Self {
+        self.use_lockfile = false;
+        self
+    }
+
+    /// Directory searched for the schemas named in `#[capnp(external = "...")]`. Added directories are tried
+    /// first, then the crate root (the input directory's parent) and the input directory itself.
+    pub fn import_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.import_paths.push(path.into());
+        self
+    }
+
+    /// Also writes the schema to `export.path`, with the annotations it configures, instead of following
+    /// `[export]` in `capnez.toml`.
+    pub fn export(mut self, export: Export) -> Self {
+        self.export = Some(export);
+        self
+    }
+
+    fn search_paths(&self) -> Result<Vec<PathBuf>> {
+        let input = self.input()?;
+        Ok(self.import_paths.iter().cloned().chain(input.parent().map(Path::to_path_buf)).chain(Some(input)).collect())
+    }
+
+    fn input(&self) -> Result<PathBuf> {
+        Ok(match &self.input_dir {
+            Some(dir) => dir.clone(),
+            None => PathBuf::from(env::var("CARGO_MANIFEST_DIR").context("CARGO_MANIFEST_DIR is not set; use input_dir()")?).join("src"),
+        })
+    }
+
+    /// Explicit limits win, then `capnez.toml` in the input directory's parent, then the defaults.
+    fn resolved_limits(&self, input: &Path) -> Result<Limits> {
+        if let Some(limits) = &self.limits {
+            return Ok(limits.clone());
+        }
+        match input.parent().map(|dir| dir.join("capnez.toml")) {
+            Some(config) if config.exists() => Limits::from_config(&config),
+            _ => Ok(Limits::default()),
+        }
+    }
+
+    /// An explicit export wins, then `[export]` in `capnez.toml` in the input directory's parent.
+    fn resolved_export(&self, input: &Path) -> Result<Option<Export>> {
+        if let Some(export) = &self.export {
+            return Ok(Some(export.clone()));
+        }
+        match input.parent().map(|dir| dir.join("capnez.toml")) {
+            Some(config) if config.exists() => Export::from_config(&config),
+            _ => Ok(None),
+        }
+    }
+
+    fn source_files(&self, input: &Path) -> Result<Vec<walkdir::DirEntry>> {
+        let exclude = self.exclude.iter()
+            .map(|p| glob::Pattern::new(p).with_context(|| format!("Invalid exclude glob `{}`", p)))
+            .collect::<Result<Vec<_>>>()?;
+        // Sorted, so the schema does not depend on the order the filesystem lists files in
+        Ok(WalkDir::new(input)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().extension().is_some_and(|ext| ext == "rs"))
+            .filter(|e| {
+                let rel = e.path().strip_prefix(input).unwrap_or(e.path());
+                !exclude.iter().any(|p| p.matches_path(rel))
+            })
+            .collect())
+    }
+
+    /// Counts the annotated items that `run` would collect, without generating anything.
+    pub fn inspect(&self) -> Result<Inspection> {
+        let input = self.input()?;
+        let limits = self.resolved_limits(&input)?;
+        let mut inspection = Inspection { structs: 0, enums: 0, interfaces: 0, max_fields: 0, files: Vec::new(), limits };
+
+        for entry in self.source_files(&input)? {
+            let content = fs::read_to_string(entry.path())
+                .with_context(|| format!("Failed to read {}", entry.path().display()))?;
+            let file = parse_file(&content)
+                .with_context(|| format!("Failed to parse {}", entry.path().display()))?;
+
+            let mut count = 0;
+            for item in &file.items {
+                match item {
+                    Item::Struct(s) if has_attrs(&s.attrs).0 => {
+                        inspection.structs += 1;
+                        inspection.max_fields = inspection.max_fields.max(s.fields.len());
+                    }
+                    Item::Enum(e) if has_attrs(&e.attrs).0 => inspection.enums += 1,
+                    Item::Trait(t) if has_attrs(&t.attrs).0 => inspection.interfaces += 1,
+                    _ => continue,
+                }
+                count += 1;
             }
-            schema.push_str(")");
-            if let Some(ret) = ret { schema.push_str(&format!(" -> {}", ret)); }
-            schema.push_str(";\n");
+            inspection.files.push((entry.path().to_path_buf(), count));
         }
-        schema.push_str("}\n\n");
+        Ok(inspection)
     }
-    
-    let schema_path = output.join("schema.capnp");
-    fs::write(&schema_path, &schema)?;
-    
-    // Print final schema for debugging
-    let final_schema = fs::read_to_string(&schema_path)?;
-    println!("Final schema file contents: {:?}", final_schema);
-    
-    capnpc::CompilerCommand::new()
-        .file(&schema_path)
-        .output_path(&output)
-        .src_prefix(&output)
-        .run()
-        .context("Failed to compile Cap'n Proto schema")?;
 
-    let capnp_path = output.join("schema_capnp.rs");
-    let mut capnp_code = fs::read_to_string(&capnp_path)
-        .context("Failed to read generated Cap'n Proto code")?;
+    /// Generates the schema and compiles it into `schema_capnp.rs` in the output directory.
+    pub fn run(&self) -> Result<()> {
+        let output = match &self.output_dir {
+            Some(dir) => dir.clone(),
+            None => PathBuf::from(env::var("OUT_DIR").context("OUT_DIR is not set; use output_dir()")?).join("generated"),
+        };
+        fs::create_dir_all(&output)?;
+        let schema_path = output.join("schema.capnp");
+        println!("cargo:rerun-if-env-changed={}", lock::ACCEPT_ENV);
+        self.write_to(&schema_path, true)
+    }
+
+    /// Returns the schema text without writing or compiling anything.
+    pub fn schema_text(&self) -> Result<String> {
+        Ok(self.generate()?.schema)
+    }
 
-    // Only add serde imports if any struct has serde
-    if structs.iter().any(|s| s.has_serde) {
-        capnp_code = "#[cfg(feature = \"serde\")]\nuse serde::{Serialize, Deserialize};\n\n".to_string() + &capnp_code;
+    /// Returns the collected schema as a [`SchemaModel`] without writing or compiling anything. Its
+    /// [`to_capnp_text`](SchemaModel::to_capnp_text) is what [`schema_text`](Self::schema_text) returns.
+    pub fn model(&self) -> Result<SchemaModel> {
+        Ok(self.generate()?.model)
     }
 
-    for s in &structs {
-        if s.has_serde {
-            let derive = format!("#[cfg_attr(feature = \"serde\", derive(Serialize, Deserialize))]\n");
-            capnp_code = capnp_code.replace(&format!("pub struct {}", s.name), &format!("{}\npub struct {}", derive, s.name));
+    /// Writes the schema to `schema_path` and, if `compile` is set, compiles it with capnpc into
+    /// `<stem>_capnp.rs` in the same directory.
+    pub fn write_to(&self, schema_path: &Path, compile: bool) -> Result<()> {
+        let generated = self.generate()?;
+        fs::write(schema_path, &generated.schema)
+            .with_context(|| format!("Failed to write {}", schema_path.display()))?;
+        if compile {
+            self.compile(schema_path, &generated)?;
         }
+        if let Some((path, lock)) = &generated.lock {
+            lock.save(path)?;
+        }
+        let input = self.input()?;
+        if let Some(export) = self.resolved_export(&input)? {
+            export.write(&generated.schema, input.parent().unwrap_or(Path::new(".")))?;
+        }
+        Ok(())
+    }
+
+    fn generate(&self) -> Result<Generated> {
+        let input = self.input()?;
+        let limits = self.resolved_limits(&input)?;
+        let sources = self.source_files(&input)?.into_iter().map(|entry| {
+            let content = fs::read_to_string(entry.path())
+                .with_context(|| format!("Failed to read {}", entry.path().display()))?;
+            let file = parse_file(&content)
+                .with_context(|| format!("Failed to parse {}", entry.path().display()))?;
+            Ok((entry.into_path(), file))
+        }).collect::<Result<Vec<_>>>()?;
+        self.generate_from(&input, &limits, sources)
+    }
+
+    /// Builds the schema from already parsed source files; `input` is only used to derive module paths
+    /// and the default lockfile location.
+    fn generate_from(&self, input: &Path, limits: &Limits, mut files: Vec<(PathBuf, syn::File)>) -> Result<Generated> {
+        // Whatever order the files were found in, they are collected in path order
+        files.sort_by(|a, b| a.0.cmp(&b.0));
+        if let Some(id) = self.file_id {
+            if id & (1 << 63) == 0 {
+                bail!("File ID {:#x} must have its high bit set", id);
+            }
+        }
+
+        let mut structs = Vec::new();
+        let mut enums = Vec::new();
+        let mut interfaces = Vec::new();
+        let mut registry = StructRegistry::default();
+        let mut type_names = HashMap::new();
+        let mut origins = HashMap::new();
+        // Problems with individual items are collected across all files and reported together
+        let mut errors = Vec::new();
+
+        let mut file_counts = Vec::new();
+
+        // First pass: register all serde structs
+        for (path, file) in &files {
+            // Register serde structs first, along with the capnp name of every annotated type
+            for item in &file.items {
+                let (ident, attrs) = match item {
+                    Item::Struct(s) => (&s.ident, &s.attrs),
+                    Item::Enum(e) => (&e.ident, &e.attrs),
+                    Item::Trait(t) => (&t.ident, &t.attrs),
+                    _ => continue,
+                };
+                let (has_capnp, has_serde) = has_attrs(attrs);
+                if !(has_capnp || (has_serde && matches!(item, Item::Struct(_)))) {
+                    continue;
+                }
+                // A bad name is reported when the item itself is collected
+                let Ok(name) = naming::type_name(ident, attrs) else { continue };
+                registry.register_name(ident, &name);
+                if has_serde && matches!(item, Item::Struct(_)) {
+                    registry.register_serde_struct(&name);
+                }
+                if has_capnp && !matches!(item, Item::Trait(_)) {
+                    registry.register_capnp_struct(&name);
+                }
+                if has_capnp && matches!(item, Item::Trait(_)) {
+                    registry.register_interface(&name);
+                }
+                if let Item::Struct(_) = item {
+                    let import = naming::attr_value(attrs, "external").and_then(|file| match file {
+                        Some(file) => {
+                            let target = naming::attr_value(attrs, "name")?.unwrap_or_else(|| name.clone());
+                            registry.register_import(&name, file, target)
+                        }
+                        None => Ok(()),
+                    });
+                    if let Err(e) = import {
+                        errors.push(e.at(path, &ident.to_string(), ""));
+                    }
+                }
+                if has_capnp {
+                    let origin = format!("`{}` in {}", ident, path.display());
+                    if let Some(other) = type_names.insert(name.clone(), origin.clone()) {
+                        errors.push(CapnezError::DuplicateName { name, first: other, second: origin });
+                    }
+                }
+            }
+        }
+
+        // Second pass: collect capnp structs and interfaces
+        for (path, file) in files {
+            let before = structs.len() + enums.len() + interfaces.len();
+            let (first_struct, first_interface) = (structs.len(), interfaces.len());
+            let module = module_path(input, &path);
+            match collect_structs(&file, &mut registry, module.as_deref(), &path) {
+                Ok(collected) => {
+                    if let Some(s) = collected.iter().find(|s| s.fields.len() > limits.max_fields_per_struct) {
+                        bail!(
+                            "Struct `{}` in {} has {} fields, exceeding the limit of {} (max_fields_per_struct in capnez.toml)",
+                            s.name, path.display(), s.fields.len(), limits.max_fields_per_struct
+                        );
+                    }
+                    structs.extend(collected);
+                }
+                Err(e) => errors.push(e),
+            }
+
+            for item in file.items {
+                match item {
+                    Item::Trait(t) if has_attrs(&t.attrs).0 => match mk_interface(&t, &registry, &path) {
+                        Ok(collected) => interfaces.push(collected),
+                        Err(e) => errors.push(e),
+                    },
+                    Item::Enum(e) if has_attrs(&e.attrs).0 => match mk_enum(&e, &path) {
+                        Ok(mut collected) => {
+                            collected.rust_path = module.as_deref().and_then(|m| convert::type_path(m, &e.ident, &e.vis, &e.generics));
+                            enums.push(collected);
+                        }
+                        Err(e) => errors.push(e),
+                    },
+                    _ => {}
+                }
+            }
+            for name in structs[first_struct..].iter().map(|s| &s.name).chain(interfaces[first_interface..].iter().map(|i| &i.name)) {
+                origins.insert(name.clone(), path.clone());
+            }
+
+            // Fail fast, before parsing any more files, once a count limit is exceeded
+            file_counts.push((path.clone(), structs.len() + enums.len() + interfaces.len() - before));
+            if structs.len() > limits.max_structs {
+                return Err(Limits::exceeded("structs", structs.len(), limits.max_structs, &file_counts));
+            }
+            if enums.len() > limits.max_enums {
+                return Err(Limits::exceeded("enums", enums.len(), limits.max_enums, &file_counts));
+            }
+            if interfaces.len() > limits.max_interfaces {
+                return Err(Limits::exceeded("interfaces", interfaces.len(), limits.max_interfaces, &file_counts));
+            }
+        }
+
+        CapnezError::all(std::mem::take(&mut errors))?;
+        enums.sort_by(|a, b| a.name.cmp(&b.name));
+        interfaces.sort_by(|a, b| a.name.cmp(&b.name));
+
+        // Every referenced type must be defined; serde-only types fall back to bytes, which is worth flagging
+        let defined = structs.iter().map(|s| s.name.as_str())
+            .chain(enums.iter().map(|e| e.name.as_str()))
+            .chain(registry.imports.keys().map(String::as_str))
+            .collect::<HashSet<_>>();
+        let members = structs.iter()
+            .flat_map(|s| s.fields.iter().map(move |(field, _, ty, _)| (field.clone(), format!("field `{}` of `{}`", field, s.name), &s.name, ty)))
+            .chain(interfaces.iter().flat_map(|i| i.methods.iter().flat_map(move |(method, params, ret)| {
+                params.iter()
+                    .map(move |(param, ty)| (format!("{}({})", method, param), format!("parameter `{}` of `{}::{}`", param, i.name, method), &i.name, ty))
+                    .chain(ret.iter().map(move |(_, ty)| (format!("{}() return value", method), format!("return type of `{}::{}`", i.name, method), &i.name, ty)))
+            })))
+            .chain(interfaces.iter().flat_map(|i| i.streams.iter().map(move |(method, item, _)| {
+                (format!("{}() stream", method), format!("stream item of `{}::{}`", i.name, method), &i.name, item)
+            })));
+        for (field, member, owner, ty) in members {
+            let origin = origins.get(owner).map_or(String::new(), |path| format!(" (in {})", path.display()));
+            match ty.leaf() {
+                CapnpType::Struct(name) if !defined.contains(name.as_str()) => errors.push(CapnezError::UnsupportedType {
+                    file: origins.get(owner).cloned().unwrap_or_default(),
+                    struct_name: owner.clone(),
+                    field,
+                    ty: name.clone(),
+                    reason: "it has no #[capnp] or #[derive(Serialize)] annotation".to_string(),
+                }),
+                CapnpType::Bytes(name) => warn(&format!(
+                    "{} stores `{}` as opaque serde bytes because it derives Serialize but is not #[capnp]{}; \
+                     read and write it with the generated `_serde` accessors",
+                    member, name, origin
+                )),
+                _ => {}
+            }
+        }
+
+        // Supertraits become `extends(...)`, so they must be interfaces too
+        let interface_names = interfaces.iter().map(|i| i.name.as_str()).collect::<HashSet<_>>();
+        for i in &interfaces {
+            for parent in i.extends.iter().filter(|parent| !interface_names.contains(parent.as_str())) {
+                errors.push(CapnezError::UnsupportedType {
+                    file: origins.get(&i.name).cloned().unwrap_or_default(),
+                    struct_name: i.name.clone(),
+                    field: "extends".to_string(),
+                    ty: parent.clone(),
+                    reason: "supertraits must be #[capnp] traits too".to_string(),
+                });
+            }
+        }
+        CapnezError::all(std::mem::take(&mut errors))?;
+
+        // Splice flattened fields in now, so everything below sees each struct's final fields
+        flatten::splice(&mut structs, &origins)?;
+
+        // Synthesize a receiver interface per streamed item type, shared by every method streaming it
+        let mut receivers: Vec<CapnpInterface> = Vec::new();
+        for (item, method) in interfaces.iter().flat_map(|i| i.streams.iter().map(move |(method, item, _)| (item, format!("{}::{}", i.name, method)))) {
+            let name = receiver_name(item);
+            if receivers.iter().any(|r| r.name == name) {
+                continue;
+            }
+            if let Some(other) = type_names.get(&name) {
+                errors.push(CapnezError::DuplicateName { name, first: other.clone(), second: format!("the receiver for the stream of `{}`", method) });
+                continue;
+            }
+            let methods = vec![
+                ("push".to_string(), vec![("items".to_string(), CapnpType::List(Box::new(item.clone()), None))], None),
+                ("done".to_string(), Vec::new(), None),
+            ];
+            receivers.push(CapnpInterface { name, methods, extends: Vec::new(), streams: Vec::new() });
+        }
+        CapnezError::all(errors)?;
+        interfaces.extend(receivers);
+        interfaces.sort_by(|a, b| a.name.cmp(&b.name));
+
+        // Synthesize wrapper structs for every Optional layer, deduplicated by name
+        let mut wrappers = Vec::new();
+        for s in &structs {
+            for (_, _, ty, _) in &s.fields { ty.optional_wrappers(&mut wrappers); }
+        }
+        for i in &interfaces {
+            for (_, params, ret) in &i.methods {
+                for (_, ty) in params { ty.optional_wrappers(&mut wrappers); }
+                if let Some((_, ret)) = ret { ret.optional_wrappers(&mut wrappers); }
+            }
+        }
+        let mut errors = Vec::new();
+        for w in &wrappers {
+            if let Some(other) = type_names.get(&w.name) {
+                let inner = &w.fields[0].2;
+                errors.push(CapnezError::DuplicateName { name: w.name.clone(), first: other.clone(), second: format!("the wrapper synthesized for optional `{}`", inner) });
+            }
+        }
+        CapnezError::all(errors)?;
+        structs.extend(wrappers);
+
+        let file_id = self.file_id.unwrap_or_else(|| derived_file_id(input));
+
+        // Structs are emitted in topological order
+        let order = topo_sort(&structs)?;
+        let model = SchemaModel::new(file_id, &registry.imports, &enums, order.iter().map(|&i| &structs[i]), &interfaces);
+        let schema = model.to_capnp_text();
+
+        if schema.len() > limits.max_schema_bytes {
+            bail!(
+                "Generated schema is {} bytes, exceeding the limit of {} (max_schema_bytes in capnez.toml)",
+                schema.len(), limits.max_schema_bytes
+            );
+        }
+
+        // Compare field, enumerant, and method numbering against the lockfile
+        let lock = match (self.use_lockfile, &self.lockfile) {
+            (false, _) => None,
+            (true, Some(path)) => Some(path.clone()),
+            (true, None) => input.parent().map(|dir| dir.join("capnez.lock")),
+        };
+        let lock = match lock {
+            Some(path) => {
+                let current = SchemaLock::from_model(&structs, &enums, &interfaces);
+                let merged = match SchemaLock::load(&path)? {
+                    Some(saved) => {
+                        let (violations, merged) = saved.check(&current);
+                        lock::enforce(&path, &violations)?;
+                        merged
+                    }
+                    None => current,
+                };
+                Some((path, merged))
+            }
+            None => None,
+        };
+
+        let imports = registry.imports.values().map(|(file, _)| file.clone()).collect();
+        Ok(Generated { schema, model, structs, enums, interfaces, serde_paths: registry.serde_paths, imports, lock })
     }
 
-    fs::write(&capnp_path, capnp_code)?;
-    Ok(())
+    fn compile(&self, schema_path: &Path, generated: &Generated) -> Result<()> {
+        let structs = &generated.structs;
+        let output = schema_path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        let stem = schema_path.file_stem().and_then(|s| s.to_str()).context("Schema path has no file name")?;
+
+        let mut command = capnpc::CompilerCommand::new();
+        command.file(schema_path).output_path(output).src_prefix(output);
+
+        // Imported schemas are compiled alongside, and their modules exposed at the crate root where the
+        // generated code expects them (`capnp_include!` pulls in `imports.rs`)
+        let search = self.search_paths()?;
+        for dir in search.iter().filter(|dir| dir.is_dir()) {
+            command.import_path(dir);
+        }
+        let mut modules = String::new();
+        for import in &generated.imports {
+            let rel = Path::new(import.trim_start_matches('/'));
+            let dir = search.iter().find(|dir| dir.join(rel).is_file()).ok_or_else(|| anyhow!(
+                "Imported schema `{}` not found; searched:\n{}\nAdd its directory with `import_path`",
+                import,
+                search.iter().map(|dir| format!("  {}", dir.display())).collect::<Vec<_>>().join("\n")
+            ))?;
+            command.file(dir.join(rel)).src_prefix(dir);
+            let module = format!("{}_capnp", rel.file_stem().and_then(|s| s.to_str()).unwrap_or_default().replace(|c: char| !c.is_ascii_alphanumeric(), "_"));
+            let code = output.join(rel.with_file_name(format!("{}.rs", module)));
+            modules.push_str(&format!("#[allow(unexpected_cfgs)]\npub mod {} {{\n    include!({:?});\n}}\n", module, code.display().to_string()));
+        }
+        command.run().map_err(|e| CapnezError::CapnpcFailed { schema: schema_path.to_path_buf(), message: e.to_string() })?;
+        fs::write(output.join("imports.rs"), modules)?;
+
+        let capnp_path = output.join(format!("{}_capnp.rs", stem));
+        let mut capnp_code = fs::read_to_string(&capnp_path)
+            .context("Failed to read generated Cap'n Proto code")?;
+
+        // Only add serde imports if any struct has serde
+        if self.emit_serde_derives && structs.iter().any(|s| s.has_serde) {
+            capnp_code = "#[cfg(feature = \"serde\")]\nuse serde::{Serialize, Deserialize};\n\n".to_string() + &capnp_code;
+        }
+
+        for s in structs {
+            if self.emit_serde_derives && s.has_serde {
+                let derive = "#[cfg_attr(feature = \"serde\", derive(Serialize, Deserialize))]\n";
+                capnp_code = capnp_code.replace(&format!("pub struct {}", s.name), &format!("{}\npub struct {}", derive, s.name));
+            }
+        }
+
+        capnp_code.push_str(&format!(
+            "\n/// The schema this module was generated from.\n#[allow(dead_code)]\npub const SCHEMA: &str = {:?};\n",
+            generated.schema
+        ));
+        capnp_code.push_str(&optional::generate(structs, &generated.enums));
+        if self.emit_conversions {
+            capnp_code.push_str(&convert::generate(structs, &generated.enums, &generated.serde_paths, !self.no_std));
+        }
+        capnp_code.push_str(&server::generate(&generated.interfaces));
+        if self.emit_conversions {
+            capnp_code.push_str(&stream::generate(&generated.interfaces, structs, &generated.enums));
+        }
+
+        fs::write(&capnp_path, capnp_code)?;
+        Ok(())
+    }
 }
 
 #[macro_export]
 macro_rules! capnp_include {
     () => {
+        // Generated impls are gated on the including crate's optional features (`serde`, `dynamic`)
+        #[allow(unexpected_cfgs)]
         pub mod schema_capnp {
             include!(concat!(env!("OUT_DIR"), "/generated/schema_capnp.rs"));
         }
+        include!(concat!(env!("OUT_DIR"), "/generated/imports.rs"));
     };
 }
\ No newline at end of file
@@ -0,0 +1,201 @@
+//! `capnez.lock`: the persisted field, enumerant, and method numbering of a schema.
+//!
+//! Cap'n Proto identifies fields by number, and capnez numbers them in declaration order, so
+//! inserting a field in the middle of a struct silently breaks every message already on the wire.
+//! Each generation compares the collected model against the lockfile and refuses such changes.
+
+use anyhow::{bail, Context, Result};
+use std::{collections::{BTreeMap, BTreeSet}, env, fs, path::Path};
+use super::{CapnpEnum, CapnpInterface, CapnpStruct};
+
+pub(crate) const ACCEPT_ENV: &str = "CAPNEZ_ACCEPT_SCHEMA_CHANGES";
+
+/// Name -> (ordinal, type) for one struct, enum, or interface, plus ordinals that must not be reused.
+#[derive(Clone, Default, PartialEq)]
+struct Numbering {
+    entries: BTreeMap<String, (usize, String)>,
+    retired: BTreeSet<usize>,
+}
+
+#[derive(Clone, Copy)]
+enum Kind { Struct, Enum, Interface }
+
+impl Kind {
+    fn section(self) -> &'static str {
+        match self { Self::Struct => "structs", Self::Enum => "enums", Self::Interface => "interfaces" }
+    }
+    fn entries(self) -> &'static str {
+        match self { Self::Struct => "fields", Self::Enum => "variants", Self::Interface => "methods" }
+    }
+    fn entry(self) -> &'static str {
+        match self { Self::Struct => "field", Self::Enum => "enumerant", Self::Interface => "method" }
+    }
+}
+
+#[derive(Clone, Default, PartialEq)]
+pub(crate) struct SchemaLock {
+    sections: BTreeMap<&'static str, BTreeMap<String, Numbering>>,
+}
+
+impl SchemaLock {
+    pub(crate) fn from_model(structs: &[CapnpStruct], enums: &[CapnpEnum], interfaces: &[CapnpInterface]) -> Self {
+        let mut lock = Self::default();
+        for s in structs.iter().filter(|s| !s.is_optional) {
+            let entries = s.fields.iter().map(|(name, id, ty, _)| (name.clone(), (*id, ty.to_string()))).collect();
+            lock.insert(Kind::Struct, &s.name, Numbering { entries, retired: BTreeSet::new() });
+        }
+        for e in enums {
+            let entries = e.variants.iter().enumerate().map(|(id, name)| (name.clone(), (id, String::new()))).collect();
+            lock.insert(Kind::Enum, &e.name, Numbering { entries, retired: BTreeSet::new() });
+        }
+        for i in interfaces {
+            let entries = i.methods.iter().enumerate().map(|(id, (name, _, _))| (name.clone(), (id, String::new()))).collect();
+            lock.insert(Kind::Interface, &i.name, Numbering { entries, retired: BTreeSet::new() });
+        }
+        lock
+    }
+
+    fn insert(&mut self, kind: Kind, name: &str, numbering: Numbering) {
+        self.sections.entry(kind.section()).or_default().insert(name.to_string(), numbering);
+    }
+
+    pub(crate) fn load(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let table: toml::Table = content.parse().with_context(|| format!("Failed to parse {}", path.display()))?;
+        let malformed = |what: &str| format!("Malformed {} in {}; delete it to regenerate", what, path.display());
+
+        let mut lock = Self::default();
+        for kind in [Kind::Struct, Kind::Enum, Kind::Interface] {
+            let Some(section) = table.get(kind.section()) else { continue };
+            let section = section.as_table().with_context(|| malformed(kind.section()))?;
+            for (name, item) in section {
+                let item = item.as_table().with_context(|| malformed(name))?;
+                let mut numbering = Numbering::default();
+                if let Some(entries) = item.get(kind.entries()) {
+                    for (entry, value) in entries.as_table().with_context(|| malformed(name))? {
+                        let (id, ty) = match value {
+                            toml::Value::Integer(id) => (*id, String::new()),
+                            toml::Value::Table(t) => (
+                                t.get("id").and_then(|v| v.as_integer()).with_context(|| malformed(entry))?,
+                                t.get("type").and_then(|v| v.as_str()).unwrap_or_default().to_string(),
+                            ),
+                            _ => bail!(malformed(entry)),
+                        };
+                        numbering.entries.insert(entry.clone(), (usize::try_from(id).with_context(|| malformed(entry))?, ty));
+                    }
+                }
+                if let Some(retired) = item.get("retired") {
+                    for id in retired.as_array().with_context(|| malformed(name))? {
+                        let id = id.as_integer().and_then(|id| usize::try_from(id).ok()).with_context(|| malformed(name))?;
+                        numbering.retired.insert(id);
+                    }
+                }
+                lock.insert(kind, name, numbering);
+            }
+        }
+        Ok(Some(lock))
+    }
+
+    pub(crate) fn save(&self, path: &Path) -> Result<()> {
+        let mut content = String::from(
+            "# Generated by capnez. Records the wire numbering of every field, enumerant, and method.\n\
+             # Commit this file; see the capnez README before editing it.\n"
+        );
+        for kind in [Kind::Struct, Kind::Enum, Kind::Interface] {
+            let Some(items) = self.sections.get(kind.section()) else { continue };
+            for (name, numbering) in items {
+                if !numbering.retired.is_empty() {
+                    let retired = numbering.retired.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(", ");
+                    content.push_str(&format!("\n[{}.{}]\nretired = [{}]\n", kind.section(), name, retired));
+                }
+                content.push_str(&format!("\n[{}.{}.{}]\n", kind.section(), name, kind.entries()));
+                let mut entries = numbering.entries.iter().collect::<Vec<_>>();
+                entries.sort_by_key(|(_, (id, _))| *id);
+                for (entry, (id, ty)) in entries {
+                    match kind {
+                        Kind::Struct => content.push_str(&format!("{} = {{ id = {}, type = {:?} }}\n", entry, id, ty)),
+                        _ => content.push_str(&format!("{} = {}\n", entry, id)),
+                    }
+                }
+            }
+        }
+
+        if fs::read_to_string(path).ok().as_deref() != Some(content.as_str()) {
+            fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Checks `current` against this lock, returning the incompatibilities found and the lock to persist.
+    ///
+    /// Items missing from `current` keep their entries so that re-adding them is still checked.
+    pub(crate) fn check(&self, current: &Self) -> (Vec<String>, Self) {
+        let mut violations = Vec::new();
+        let mut merged = self.clone();
+        for kind in [Kind::Struct, Kind::Enum, Kind::Interface] {
+            let Some(items) = current.sections.get(kind.section()) else { continue };
+            for (name, new) in items {
+                let old = self.sections.get(kind.section()).and_then(|s| s.get(name));
+                let Some(old) = old else {
+                    merged.insert(kind, name, new.clone());
+                    continue;
+                };
+
+                let mut retired = old.retired.clone();
+                for (entry, (old_id, old_ty)) in &old.entries {
+                    match new.entries.get(entry) {
+                        Some((id, _)) if id != old_id => violations.push(format!(
+                            "{} `{}` of `{}` moved from @{} to @{}", kind.entry(), entry, name, old_id, id
+                        )),
+                        Some((_, ty)) if ty != old_ty => violations.push(format!(
+                            "{} `{}` of `{}` changed type from {} to {}", kind.entry(), entry, name, old_ty, ty
+                        )),
+                        Some(_) => {}
+                        None => { retired.insert(*old_id); }
+                    }
+                }
+                for (entry, (id, _)) in &new.entries {
+                    if old.entries.contains_key(entry) {
+                        continue;
+                    }
+                    let previous = old.entries.iter().find(|(_, (old_id, _))| old_id == id).map(|(n, _)| n.as_str());
+                    if previous.is_some() || old.retired.contains(id) {
+                        violations.push(format!(
+                            "{} `{}` of `{}` reuses @{}, which previously belonged to {}",
+                            kind.entry(), entry, name, id,
+                            previous.map_or("a removed entry".to_string(), |p| format!("`{}`", p))
+                        ));
+                    }
+                    retired.remove(id);
+                }
+                merged.insert(kind, name, Numbering { entries: new.entries.clone(), retired });
+            }
+        }
+        (violations, merged)
+    }
+}
+
+/// Fails with an explanation unless the changes were accepted via [`ACCEPT_ENV`].
+pub(crate) fn enforce(path: &Path, violations: &[String]) -> Result<()> {
+    if violations.is_empty() {
+        return Ok(());
+    }
+    if env::var(ACCEPT_ENV).is_ok_and(|v| v == "1") {
+        for v in violations {
+            crate::warn(&format!("Accepted incompatible schema change: {}", v));
+        }
+        return Ok(());
+    }
+    bail!(
+        "Schema changes break wire compatibility with {}:\n{}\n\n\
+         Cap'n Proto identifies fields, enumerants, and methods by number, so existing messages would be misread. \
+         Add new entries at the end instead of inserting or reordering them. To accept the change intentionally, \
+         rebuild with {}=1 or delete the affected entries from the lockfile.",
+        path.display(),
+        violations.iter().map(|v| format!("  - {}", v)).collect::<Vec<_>>().join("\n"),
+        ACCEPT_ENV
+    )
+}
@@ -0,0 +1,144 @@
+use anyhow::{bail, Context, Result};
+use capnez_codegen::{compare_schemas, SchemaGenerator};
+use std::{fs, path::{Path, PathBuf}, process};
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+#[structopt(name = "capnez-codegen", about = "Generate a Cap'n Proto schema from annotated Rust sources")]
+struct Opt {
+    /// Directory scanned recursively for `.rs` files
+    #[structopt(long, parse(from_os_str))]
+    input: PathBuf,
+
+    /// Schema file to write (e.g. schema.capnp); `<stem>_capnp.rs` is compiled next to it
+    #[structopt(long, parse(from_os_str), required_unless_one = &["stdout", "inspect", "compat"])]
+    output: Option<PathBuf>,
+
+    /// Print the schema text to stdout
+    #[structopt(long)]
+    stdout: bool,
+
+    /// Write the schema without compiling it with capnpc
+    #[structopt(long)]
+    no_compile: bool,
+
+    /// Exit non-zero, printing a diff, if the schema at --output differs from what would be generated
+    #[structopt(long, requires = "output")]
+    check: bool,
+
+    /// Fixed schema file ID in hex (e.g. 0xd0b68c8e2f4a9b31); defaults to the ID already in --output
+    #[structopt(long)]
+    file_id: Option<String>,
+
+    /// Directory searched for schemas imported with #[capnp(external = "...")]
+    #[structopt(long = "import-path", parse(from_os_str))]
+    import_path: Vec<PathBuf>,
+
+    /// Glob of files to skip, relative to the input directory
+    #[structopt(long = "exclude")]
+    exclude: Vec<String>,
+
+    /// Don't inject serde derives into the generated Rust code
+    #[structopt(long)]
+    no_serde: bool,
+
+    /// Numbering lockfile to check and update (defaults to capnez.lock next to the input directory)
+    #[structopt(long, parse(from_os_str), conflicts_with = "no-lockfile")]
+    lockfile: Option<PathBuf>,
+
+    /// Neither check nor write a numbering lockfile
+    #[structopt(long)]
+    no_lockfile: bool,
+
+    /// Print annotated item counts against the configured limits instead of generating
+    #[structopt(long)]
+    inspect: bool,
+
+    /// Schema snapshot to check the generated schema against for wire compatibility, instead of
+    /// generating; exits non-zero on breaking changes (repeatable)
+    #[structopt(long, parse(from_os_str))]
+    compat: Vec<PathBuf>,
+}
+
+/// Reads the `@0x...;` file ID from an existing schema so regeneration is stable.
+fn existing_file_id(path: &Path) -> Option<u64> {
+    let content = fs::read_to_string(path).ok()?;
+    let id = content.lines().next()?.trim().strip_prefix("@0x")?.strip_suffix(';')?;
+    u64::from_str_radix(id, 16).ok()
+}
+
+fn main() -> Result<()> {
+    let opt = Opt::from_args();
+
+    let mut generator = SchemaGenerator::new()
+        .input_dir(&opt.input)
+        .emit_serde_derives(!opt.no_serde);
+    let file_id = match &opt.file_id {
+        Some(id) => Some(
+            u64::from_str_radix(id.trim_start_matches('@').trim_start_matches("0x"), 16)
+                .with_context(|| format!("Invalid file ID `{}`", id))?,
+        ),
+        None => opt.output.as_deref().and_then(existing_file_id),
+    };
+    if let Some(id) = file_id {
+        generator = generator.file_id(id);
+    }
+    for dir in &opt.import_path {
+        generator = generator.import_path(dir);
+    }
+    for pattern in &opt.exclude {
+        generator = generator.exclude_glob(pattern);
+    }
+    if let Some(lockfile) = &opt.lockfile {
+        generator = generator.lockfile(lockfile);
+    }
+    if opt.no_lockfile {
+        generator = generator.without_lockfile();
+    }
+
+    if opt.inspect {
+        print!("{}", generator.inspect()?);
+        return Ok(());
+    }
+
+    if !opt.compat.is_empty() {
+        let generated = generator.schema_text()?;
+        let mut breaking = false;
+        for snapshot in &opt.compat {
+            let old = fs::read_to_string(snapshot)
+                .with_context(|| format!("Failed to read {}", snapshot.display()))?;
+            let report = compare_schemas(&old, &generated)
+                .with_context(|| format!("Failed to compare against {}", snapshot.display()))?;
+            print!("{}: {}", snapshot.display(), report);
+            breaking |= report.is_breaking();
+        }
+        if breaking {
+            eprintln!("The generated schema breaks wire compatibility with a snapshot");
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if opt.check {
+        let output = opt.output.as_deref().expect("--check requires --output");
+        let on_disk = fs::read_to_string(output)
+            .with_context(|| format!("Failed to read {}", output.display()))?;
+        let generated = generator.schema_text()?;
+        if on_disk == generated {
+            return Ok(());
+        }
+        let diff = similar::TextDiff::from_lines(&on_disk, &generated);
+        print!("{}", diff.unified_diff().header(&output.display().to_string(), "generated"));
+        eprintln!("{} is out of date; rerun capnez-codegen to update it", output.display());
+        process::exit(1);
+    }
+
+    if opt.stdout {
+        print!("{}", generator.schema_text()?);
+    }
+    match &opt.output {
+        Some(output) => generator.write_to(output, !opt.no_compile),
+        None if opt.stdout => Ok(()),
+        None => bail!("--output is required unless --stdout is given"),
+    }
+}
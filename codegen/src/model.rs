@@ -0,0 +1,314 @@
+//! The schema capnez collected from the annotated sources, before anything is written, for tools
+//! that want its structure rather than the `.capnp` text: docs generators, registry uploaders.
+//!
+//! ```no_run
+//! let model = capnez_codegen::collect_model("src".as_ref())?;
+//! for s in &model.structs {
+//!     println!("{}: {} fields", s.name, s.fields.len());
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! Everything is as the schema declares it: names after `#[capnp(rename)]`, flattened fields
+//! spliced in, and the `Optional...` wrapper of every `Option` layer and the receiver of every
+//! stream included alongside the annotated items. [`SchemaModel::to_capnp_text`] is the text
+//! `generate_schema` writes. With the `serde` feature every type here is `Serialize` and
+//! `Deserialize`, and [`SchemaModel::to_json`] renders the model as JSON.
+
+use super::{CapnpEnum, CapnpInterface, CapnpStruct, CapnpType};
+use crate::wellknown::WellKnown;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// Everything one schema file declares.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SchemaModel {
+    /// The `@0x...` ID of the schema file. In JSON it is that hex string, since the number does not
+    /// fit a double.
+    #[cfg_attr(feature = "serde", serde(with = "hex_id"))]
+    pub file_id: u64,
+    /// Types taken from hand-written schemas with `#[capnp(external = "...")]`.
+    pub imports: Vec<Import>,
+    pub enums: Vec<Enum>,
+    /// In the order they are emitted, each after the structs it holds.
+    pub structs: Vec<Struct>,
+    pub interfaces: Vec<Interface>,
+}
+
+/// `using <alias> = import "/<file>".<name>;`
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Import {
+    pub alias: String,
+    pub file: String,
+    pub name: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Enum {
+    pub name: String,
+    /// Enumerants, numbered by position.
+    pub variants: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Struct {
+    pub name: String,
+    pub fields: Vec<Field>,
+    /// Whether this is the wrapper of an `Option`, whose one field forms a union with a `none` arm.
+    pub optional: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Field {
+    pub name: String,
+    pub ordinal: usize,
+    pub ty: Type,
+    /// The `#[capnp(default = ...)]` value, as a schema literal.
+    pub default: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Interface {
+    pub name: String,
+    /// Names of the interfaces it extends, from the trait's supertraits.
+    pub extends: Vec<String>,
+    /// Numbered by position.
+    pub methods: Vec<Method>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Method {
+    pub name: String,
+    pub params: Vec<Param>,
+    /// The return value. A struct or `Option` stands for the results itself; anything else becomes a
+    /// one-field result list under this name.
+    pub result: Option<Param>,
+    /// Set for a streaming method, whose `receiver` is the last of `params`.
+    pub stream: Option<Stream>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Param {
+    pub name: String,
+    pub ty: Type,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Stream {
+    pub item: Type,
+    /// Items per call to the receiver's `push`.
+    pub chunk: usize,
+}
+
+/// The type of a field, parameter or result. Library types mapped behind a cargo feature, such as
+/// `chrono::DateTime<Utc>`, appear as the type they are stored as.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Type {
+    Bool, Int8, Int16, Int32, Int64, UInt8, UInt16, UInt32, UInt64, Float32, Float64, Text, Data,
+    /// `len` is set for a fixed-size array, whose length the conversions check.
+    List { element: Box<Type>, len: Option<usize> },
+    /// Stored as the wrapper struct named e.g. `OptionalText`, which is among the model's structs.
+    Optional(Box<Type>),
+    Struct(String),
+    Enum(String),
+    Interface(String),
+    /// A serde-only type stored as opaque bytes in a `List(UInt8)`; holds its capnp name.
+    SerdeBytes(String),
+}
+
+impl fmt::Display for Type {
+    /// The type as written in the schema.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::List { element, .. } => write!(f, "List({})", element),
+            Self::SerdeBytes(_) => write!(f, "List(UInt8)"),
+            _ => f.write_str(&self.ident()),
+        }
+    }
+}
+
+impl Type {
+    /// Identifier fragment of the `Optional...` wrapper names, matching `CapnpType::ident`.
+    fn ident(&self) -> String {
+        match self {
+            Self::Bool => "Bool".to_string(),
+            Self::Int8 => "Int8".to_string(),
+            Self::Int16 => "Int16".to_string(),
+            Self::Int32 => "Int32".to_string(),
+            Self::Int64 => "Int64".to_string(),
+            Self::UInt8 => "UInt8".to_string(),
+            Self::UInt16 => "UInt16".to_string(),
+            Self::UInt32 => "UInt32".to_string(),
+            Self::UInt64 => "UInt64".to_string(),
+            Self::Float32 => "Float32".to_string(),
+            Self::Float64 => "Float64".to_string(),
+            Self::Text => "Text".to_string(),
+            Self::Data => "Data".to_string(),
+            Self::List { element, .. } => format!("List{}", element.ident()),
+            Self::Optional(inner) => format!("Optional{}", inner.ident()),
+            Self::Struct(name) | Self::Enum(name) | Self::Interface(name) => name.clone(),
+            Self::SerdeBytes(_) => "Bytes".to_string(),
+        }
+    }
+
+    fn new(ty: &CapnpType, enums: &BTreeSet<&str>) -> Self {
+        match ty {
+            CapnpType::Text => Self::Text,
+            CapnpType::Int8 => Self::Int8,
+            CapnpType::Int16 => Self::Int16,
+            CapnpType::Int32 => Self::Int32,
+            CapnpType::Int64 => Self::Int64,
+            CapnpType::UInt8 => Self::UInt8,
+            CapnpType::UInt16 => Self::UInt16,
+            CapnpType::UInt32 => Self::UInt32,
+            CapnpType::UInt64 => Self::UInt64,
+            CapnpType::Float32 => Self::Float32,
+            CapnpType::Float64 => Self::Float64,
+            CapnpType::Bool => Self::Bool,
+            CapnpType::Data => Self::Data,
+            CapnpType::Bytes(name) => Self::SerdeBytes(name.clone()),
+            CapnpType::WellKnown(WellKnown::ChronoUtc | WellKnown::SystemTime) => Self::Int64,
+            CapnpType::WellKnown(WellKnown::Duration) => Self::UInt64,
+            CapnpType::WellKnown(WellKnown::Uuid) => Self::Data,
+            CapnpType::WellKnown(WellKnown::UuidText) => Self::Text,
+            CapnpType::List(inner, len) => Self::List { element: Box::new(Self::new(inner, enums)), len: *len },
+            CapnpType::Optional(inner) => Self::Optional(Box::new(Self::new(inner, enums))),
+            CapnpType::Struct(name) if enums.contains(name.as_str()) => Self::Enum(name.clone()),
+            CapnpType::Struct(name) => Self::Struct(name.clone()),
+            CapnpType::Interface(name) => Self::Interface(name.clone()),
+        }
+    }
+}
+
+impl SchemaModel {
+    /// The public view of what `generate_from` collected; `structs` is already in emission order.
+    pub(crate) fn new<'a>(
+        file_id: u64,
+        imports: &BTreeMap<String, (String, String)>,
+        enums: &[CapnpEnum],
+        structs: impl Iterator<Item = &'a CapnpStruct>,
+        interfaces: &[CapnpInterface],
+    ) -> Self {
+        let names = enums.iter().map(|e| e.name.as_str()).collect::<BTreeSet<_>>();
+        let param = |(name, ty): &(String, CapnpType)| Param { name: name.clone(), ty: Type::new(ty, &names) };
+        Self {
+            file_id,
+            imports: imports.iter()
+                .map(|(alias, (file, name))| Import { alias: alias.clone(), file: file.clone(), name: name.clone() })
+                .collect(),
+            enums: enums.iter().map(|e| Enum { name: e.name.clone(), variants: e.variants.clone() }).collect(),
+            structs: structs.map(|s| Struct {
+                name: s.name.clone(),
+                fields: s.fields.iter().map(|(name, ordinal, ty, default)| Field {
+                    name: name.clone(),
+                    ordinal: *ordinal,
+                    ty: Type::new(ty, &names),
+                    default: default.clone(),
+                }).collect(),
+                optional: s.is_optional,
+            }).collect(),
+            interfaces: interfaces.iter().map(|i| Interface {
+                name: i.name.clone(),
+                extends: i.extends.clone(),
+                methods: i.methods.iter().map(|(name, params, result)| Method {
+                    name: name.clone(),
+                    params: params.iter().map(param).collect(),
+                    result: result.as_ref().map(param),
+                    stream: i.streams.iter().find(|(method, ..)| method == name).map(|(_, item, chunk)| Stream {
+                        item: Type::new(item, &names),
+                        chunk: *chunk,
+                    }),
+                }).collect(),
+            }).collect(),
+        }
+    }
+
+    /// The schema file for this model, exactly as `generate_schema` writes it.
+    pub fn to_capnp_text(&self) -> String {
+        let mut schema = format!("@{:#x};\n", self.file_id);
+        for import in &self.imports {
+            schema.push_str(&format!("using {} = import \"/{}\".{};\n", import.alias, import.file.trim_start_matches('/'), import.name));
+        }
+        if !self.imports.is_empty() {
+            schema.push('\n');
+        }
+
+        for e in &self.enums {
+            schema.push_str(&format!("enum {} {{\n", e.name));
+            for (id, variant) in e.variants.iter().enumerate() {
+                schema.push_str(&format!("  {} @{};\n", variant, id));
+            }
+            schema.push_str("}\n\n");
+        }
+
+        for s in &self.structs {
+            schema.push_str(&format!("struct {} {{\n", s.name));
+            match s.fields.as_slice() {
+                [value] if s.optional => schema.push_str(&format!(
+                    "  union {{\n    {} @{} :{};\n    none @{} :Void;\n  }}\n",
+                    value.name, value.ordinal, value.ty, value.ordinal + 1
+                )),
+                fields => {
+                    for field in fields {
+                        match &field.default {
+                            Some(value) => schema.push_str(&format!("  {} @{} :{} = {};\n", field.name, field.ordinal, field.ty, value)),
+                            None => schema.push_str(&format!("  {} @{} :{};\n", field.name, field.ordinal, field.ty)),
+                        }
+                    }
+                }
+            }
+            schema.push_str("}\n\n");
+        }
+
+        for i in &self.interfaces {
+            match i.extends.as_slice() {
+                [] => schema.push_str(&format!("interface {} {{\n", i.name)),
+                parents => schema.push_str(&format!("interface {} extends({}) {{\n", i.name, parents.join(", "))),
+            }
+            for (id, method) in i.methods.iter().enumerate() {
+                let params = method.params.iter().map(|p| format!("{} :{}", p.name, p.ty)).collect::<Vec<_>>();
+                schema.push_str(&format!("  {} @{} ({})", method.name, id, params.join(", ")));
+                // Only a struct can stand for the results; anything else becomes a one-field result list
+                match &method.result {
+                    Some(Param { ty: ty @ (Type::Struct(_) | Type::Optional(_)), .. }) => schema.push_str(&format!(" -> {}", ty)),
+                    Some(result) => schema.push_str(&format!(" -> ({} :{})", result.name, result.ty)),
+                    None => {}
+                }
+                schema.push_str(";\n");
+            }
+            schema.push_str("}\n\n");
+        }
+        schema
+    }
+
+    /// The model as pretty-printed JSON, one object per item in the shape of the types above.
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("the schema model always serializes")
+    }
+}
+
+#[cfg(feature = "serde")]
+mod hex_id {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(id: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{:#x}", id))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        let id = String::deserialize(deserializer)?;
+        let hex = id.strip_prefix("0x").ok_or_else(|| D::Error::custom(format!("file ID `{}` is not 0x-prefixed hex", id)))?;
+        u64::from_str_radix(hex, 16).map_err(D::Error::custom)
+    }
+}
@@ -0,0 +1,318 @@
+//! Identifier conversions, in both directions:
+//!
+//! - Rust items to capnp names: types become PascalCase, fields, methods, parameters and enumerants
+//!   camelCase, unless overridden with `#[capnp(rename = "...")]`.
+//! - capnp names to the identifiers capnpc-rust generates for them, which the conversion impls refer to.
+//!
+//! Casing only ever touches word boundaries, so already-cased input passes through unchanged and
+//! acronyms stay intact: `HTTPRequest` stays `HTTPRequest`, `http_request` becomes `HttpRequest`,
+//! and `HTTPStatus` as a field becomes `httpStatus`.
+
+use crate::error::CapnezError;
+use proc_macro2::TokenStream;
+use syn::{Attribute, Expr, ExprLit, Ident, Lit};
+
+/// Capnp name of a struct, enum or interface.
+pub(crate) fn type_name(ident: &Ident, attrs: &[Attribute]) -> Result<String, CapnezError> {
+    match attr_value(attrs, "rename")? {
+        Some(name) => checked("rename", name, true),
+        None => Ok(pascal_case(&ident.to_string())),
+    }
+}
+
+/// Capnp name of a field, method, parameter or enumerant.
+pub(crate) fn member_name(ident: &Ident, attrs: &[Attribute]) -> Result<String, CapnezError> {
+    match attr_value(attrs, "rename")? {
+        Some(name) => checked("rename", name, false),
+        None => Ok(camel_case(&ident.to_string())),
+    }
+}
+
+/// Name of the field holding a method's result when it is not a struct: `result`, or the method's
+/// `#[capnp(result = "...")]`.
+pub(crate) fn result_name(attrs: &[Attribute]) -> Result<String, CapnezError> {
+    match attr_value(attrs, "result")? {
+        Some(name) => checked("result", name, false),
+        None => Ok("result".to_string()),
+    }
+}
+
+/// Prefix a `#[capnp(flatten)]` field puts before the names of the fields it splices in, from its
+/// `#[capnp(prefix = "...")]`. `None` keeps the child's names as they are.
+pub(crate) fn flatten_prefix(attrs: &[Attribute]) -> Result<Option<String>, CapnezError> {
+    attr_value(attrs, "prefix")?.map(|prefix| checked("prefix", prefix, false)).transpose()
+}
+
+/// A flattened field's name under `prefix`: `meta` and `createdAt` give `metaCreatedAt`.
+pub(crate) fn prefixed(prefix: &str, name: &str) -> String {
+    format!("{}{}", prefix, upper_first(name))
+}
+
+/// `foo_bar` -> `FooBar`; the first letter of every underscore-separated word is capitalized.
+pub(crate) fn pascal_case(ident: &str) -> String {
+    words(ident).map(upper_first).collect()
+}
+
+/// `foo_bar` -> `fooBar`, `HTTPStatus` -> `httpStatus`, `IO` -> `io`.
+pub(crate) fn camel_case(ident: &str) -> String {
+    let mut words = words(ident);
+    let first = words.next().map(lower_leading).unwrap_or_default();
+    std::iter::once(first).chain(words.map(upper_first)).collect()
+}
+
+fn words(ident: &str) -> impl Iterator<Item = &str> {
+    ident.trim_start_matches("r#").split('_').filter(|w| !w.is_empty())
+}
+
+fn upper_first(word: &str) -> String {
+    let mut c = word.chars();
+    c.next().map_or(String::new(), |f| f.to_uppercase().chain(c).collect())
+}
+
+/// Lowercases a leading run of capitals. A run followed by a lowercase letter keeps its last capital,
+/// which starts the next word (`HTTPStatus` -> `httpStatus`).
+fn lower_leading(word: &str) -> String {
+    let chars = word.chars().collect::<Vec<_>>();
+    let run = chars.iter().take_while(|c| c.is_uppercase()).count();
+    let lowered = match chars.get(run) {
+        Some(next) if run > 1 && next.is_lowercase() => run - 1,
+        _ => run,
+    };
+    chars[..lowered].iter().flat_map(|c| c.to_lowercase()).chain(chars[lowered..].iter().copied()).collect()
+}
+
+/// Keys accepted inside `#[capnp(...)]`.
+const ATTR_KEYS: &[&str] = &["rename", "serde_with", "as", "default", "external", "name", "result", "chunk", "prefix"];
+
+/// Keys accepted inside `#[capnp(...)]` on their own, without a value.
+const FLAG_KEYS: &[&str] = &["stream", "flatten"];
+
+/// Keys accepted inside `#[capnp(...)]` with a parenthesized list, as in `#[capnp(validate(non_empty))]`.
+const LIST_KEYS: &[&str] = &["validate"];
+
+/// One `key`, `key = value` or `key(...)` inside `#[capnp(...)]`.
+enum Entry {
+    Flag,
+    Value(Expr),
+    List(TokenStream),
+}
+
+/// Every entry of every `#[capnp(...)]` in `attrs`, in order, failing on unknown keys.
+fn entries(attrs: &[Attribute]) -> Result<Vec<(&'static str, Entry)>, CapnezError> {
+    let mut entries = Vec::new();
+    for attr in attrs.iter().filter(|a| a.path().segments.last().is_some_and(|s| s.ident == "capnp")) {
+        if matches!(attr.meta, syn::Meta::Path(_)) {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            let find = |keys: &[&'static str]| keys.iter().copied().find(|k| meta.path.is_ident(k));
+            if let Some(key) = find(FLAG_KEYS) {
+                entries.push((key, Entry::Flag));
+            } else if let Some(key) = find(LIST_KEYS) {
+                let content;
+                syn::parenthesized!(content in meta.input);
+                entries.push((key, Entry::List(content.parse()?)));
+            } else if let Some(key) = find(ATTR_KEYS) {
+                entries.push((key, Entry::Value(meta.value()?.parse::<Expr>()?)));
+            } else {
+                let keys = ATTR_KEYS.iter().chain(FLAG_KEYS).chain(LIST_KEYS).copied().collect::<Vec<_>>();
+                return Err(meta.error(format!("unsupported capnp attribute; expected one of {}", keys.join(", "))));
+            }
+            Ok(())
+        }).map_err(|e| CapnezError::attribute(e.to_string()))?;
+    }
+    Ok(entries)
+}
+
+/// The value of `key` in `#[capnp(key = "...")]`, if present.
+pub(crate) fn attr_value(attrs: &[Attribute], key: &str) -> Result<Option<String>, CapnezError> {
+    match attr_expr(attrs, key)? {
+        Some(Expr::Lit(ExprLit { lit: Lit::Str(lit), .. })) => Ok(Some(lit.value())),
+        Some(_) => Err(CapnezError::attribute(format!("`{}` expects a string literal", key))),
+        None => Ok(None),
+    }
+}
+
+/// Whether the flag `key` is set, as in `#[capnp(key)]`.
+pub(crate) fn attr_flag(attrs: &[Attribute], key: &str) -> Result<bool, CapnezError> {
+    Ok(attr_expr(attrs, key)?.is_some())
+}
+
+/// The value of `key` in `#[capnp(key = ...)]` as written, for keys taking any literal. Flags come
+/// back as `true`.
+pub(crate) fn attr_expr(attrs: &[Attribute], key: &str) -> Result<Option<Expr>, CapnezError> {
+    let mut value = None;
+    for (found, entry) in entries(attrs)? {
+        match entry {
+            Entry::Flag if found == key => value = Some(syn::parse_quote!(true)),
+            Entry::Value(expr) if found == key => value = Some(expr),
+            _ => {}
+        }
+    }
+    Ok(value)
+}
+
+/// The contents of every `#[capnp(key(...))]`, for the caller to parse.
+pub(crate) fn attr_lists(attrs: &[Attribute], key: &str) -> Result<Vec<TokenStream>, CapnezError> {
+    Ok(entries(attrs)?
+        .into_iter()
+        .filter_map(|(found, entry)| match entry {
+            Entry::List(tokens) if found == key => Some(tokens),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Rejects renames capnp itself would refuse: type names start uppercase, everything else lowercase,
+/// and only letters and digits are allowed.
+fn checked(key: &str, name: String, is_type: bool) -> Result<String, CapnezError> {
+    let first = name.chars().next();
+    let cased = if is_type { first.is_some_and(char::is_uppercase) } else { first.is_some_and(char::is_lowercase) };
+    if !cased || !name.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(CapnezError::attribute(format!(
+            "{} = \"{}\" is not a valid capnp {} name: it must start with a {} letter and contain only letters and digits",
+            key, name, if is_type { "type" } else { "member" }, if is_type { "capital" } else { "lowercase" }
+        )));
+    }
+    Ok(name)
+}
+
+/// Fails if two members of `owner` convert to the same capnp name.
+pub(crate) fn check_unique<'a>(owner: &str, members: impl IntoIterator<Item = (&'a Ident, &'a str)>) -> Result<(), CapnezError> {
+    let mut seen: Vec<(&Ident, &str)> = Vec::new();
+    for (ident, name) in members {
+        if let Some((other, _)) = seen.iter().find(|(_, n)| *n == name) {
+            return Err(CapnezError::DuplicateName {
+                name: name.to_string(),
+                first: format!("`{}::{}`", owner, other),
+                second: format!("`{}::{}`", owner, ident),
+            });
+        }
+        seen.push((ident, name));
+    }
+    Ok(())
+}
+
+/// Module capnpc-rust generates for a capnp type name, e.g. `SparseMatrixData` -> `sparse_matrix_data`.
+pub(crate) fn rust_module(name: &str) -> String {
+    let snake = rust_accessor(name);
+    match snake.as_str() {
+        "as" | "break" | "const" | "continue" | "crate" | "else" | "enum" | "extern" | "false" | "fn" | "for"
+        | "if" | "impl" | "in" | "let" | "loop" | "match" | "mod" | "move" | "mut" | "pub" | "ref" | "return"
+        | "self" | "static" | "struct" | "super" | "trait" | "true" | "type" | "unsafe" | "use" | "where"
+        | "while" | "async" | "await" | "dyn" | "abstract" | "become" | "box" | "do" | "final" | "macro"
+        | "override" | "priv" | "typeof" | "unsized" | "virtual" | "yield" | "try" => format!("{}_", snake),
+        _ => snake,
+    }
+}
+
+/// Accessor stem capnpc-rust generates for a camelCase capnp name, e.g. `sayHello` -> `say_hello`.
+pub(crate) fn rust_accessor(name: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 { out.push('_'); }
+            out.extend(c.to_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Variant capnpc-rust generates for an enumerant, e.g. `httpError` -> `HttpError`.
+pub(crate) fn rust_variant(name: &str) -> String {
+    upper_first(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use syn::parse_quote;
+
+    /// Rust identifier, its capnp type name, and its capnp member name.
+    const TRICKY: &[(&str, &str, &str)] = &[
+        ("foo", "Foo", "foo"),
+        ("foo_bar", "FooBar", "fooBar"),
+        ("foo_bar_baz", "FooBarBaz", "fooBarBaz"),
+        ("FooBar", "FooBar", "fooBar"),
+        ("fooBar", "FooBar", "fooBar"),
+        ("HTTPRequest", "HTTPRequest", "httpRequest"),
+        ("http_request", "HttpRequest", "httpRequest"),
+        ("HTTPStatus", "HTTPStatus", "httpStatus"),
+        ("IO", "IO", "io"),
+        ("Sha256Hash", "Sha256Hash", "sha256Hash"),
+        ("SHA256Hash", "SHA256Hash", "sha256Hash"),
+        ("sha256_hash", "Sha256Hash", "sha256Hash"),
+        ("x2", "X2", "x2"),
+        ("_private", "Private", "private"),
+        ("trailing_", "Trailing", "trailing"),
+        ("double__underscore", "DoubleUnderscore", "doubleUnderscore"),
+        ("r#type", "Type", "type"),
+        ("r#match_arm", "MatchArm", "matchArm"),
+    ];
+
+    #[test]
+    fn tricky_identifiers() {
+        for &(ident, ty, member) in TRICKY {
+            assert_eq!(pascal_case(ident), ty, "type name of `{}`", ident);
+            assert_eq!(camel_case(ident), member, "member name of `{}`", ident);
+        }
+    }
+
+    #[test]
+    fn conversions_are_idempotent_and_valid_capnp() {
+        for &(ident, _, _) in TRICKY {
+            let ty = pascal_case(ident);
+            let member = camel_case(ident);
+            assert_eq!(pascal_case(&ty), ty, "`{}`", ident);
+            assert_eq!(camel_case(&member), member, "`{}`", ident);
+            assert!(checked("rename", ty.clone(), true).is_ok(), "`{}` -> `{}`", ident, ty);
+            assert!(checked("rename", member.clone(), false).is_ok(), "`{}` -> `{}`", ident, member);
+        }
+    }
+
+    #[test]
+    fn capnpc_rust_names_round_trip() {
+        for (capnp, module) in [("SparseMatrixData", "sparse_matrix_data"), ("Type", "type_"), ("Person", "person")] {
+            assert_eq!(rust_module(capnp), module);
+        }
+        assert_eq!(rust_accessor("sayHello"), "say_hello");
+        assert_eq!(rust_variant("httpError"), "HttpError");
+        assert_eq!(prefixed("meta", "createdAt"), "metaCreatedAt");
+    }
+
+    #[test]
+    fn rename_overrides_casing() {
+        let ident: Ident = parse_quote!(sha256_hash);
+        let attrs: Vec<Attribute> = vec![parse_quote!(#[capnp(rename = "SHA256Digest")])];
+        assert_eq!(type_name(&ident, &attrs).unwrap(), "SHA256Digest");
+        let attrs: Vec<Attribute> = vec![parse_quote!(#[capnp(rename = "digest")])];
+        assert_eq!(member_name(&ident, &attrs).unwrap(), "digest");
+        assert_eq!(member_name(&ident, &[]).unwrap(), "sha256Hash");
+    }
+
+    #[test]
+    fn rename_must_be_a_valid_capnp_name() {
+        let ident: Ident = parse_quote!(value);
+        for (name, is_type) in [("lowercase", true), ("Uppercase", false), ("with_underscore", false), ("", false)] {
+            let attrs: Vec<Attribute> = vec![parse_quote!(#[capnp(rename = #name)])];
+            let result = if is_type { type_name(&ident, &attrs) } else { member_name(&ident, &attrs) };
+            assert!(matches!(result, Err(CapnezError::InvalidAttribute { .. })), "rename = {:?}", name);
+        }
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let attrs: Vec<Attribute> = vec![parse_quote!(#[capnp(renmae = "x")])];
+        assert!(attr_value(&attrs, "rename").is_err());
+    }
+
+    #[test]
+    fn members_colliding_after_conversion_are_reported() {
+        let (a, b): (Ident, Ident) = (parse_quote!(sha256_hash), parse_quote!(sha256Hash));
+        let names = [camel_case(&a.to_string()), camel_case(&b.to_string())];
+        let err = check_unique("Digest", [(&a, names[0].as_str()), (&b, names[1].as_str())]).unwrap_err();
+        assert!(matches!(err, CapnezError::DuplicateName { ref name, .. } if name == "sha256Hash"), "{}", err);
+    }
+}
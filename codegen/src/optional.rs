@@ -0,0 +1,168 @@
+//! Extension traits appended to `schema_capnp.rs` that read and write `Option` fields as `Option`s.
+//!
+//! An `Option<T>` field is stored as a wrapper struct holding a `value`/`none` union, e.g.
+//! `OptionalText`, so the generated reader hands back the wrapper and leaves the caller to match on
+//! its `which()`. For a struct `Person` with optional fields this generates:
+//!
+//! - `PersonReaderExt`, implemented for `person::Reader`, with `<field>_opt() -> capnp::Result<Option<_>>`
+//! - `PersonBuilderExt`, implemented for `person::Builder`, with `set_<field>_opt(Option<_>)`, which
+//!   selects the union arm
+//!
+//! Text and data read back as `&str` and `&[u8]` borrowed from the message, nested structs and lists
+//! as their readers. Bring the traits into scope with `use schema_capnp::{PersonReaderExt, PersonBuilderExt};`.
+
+use super::{CapnpEnum, CapnpStruct, CapnpType};
+use crate::naming::{rust_accessor, rust_module};
+use crate::wellknown::WellKnown;
+
+pub(crate) fn generate(structs: &[CapnpStruct], enums: &[CapnpEnum]) -> String {
+    let types = Types { enums };
+    let mut code = String::new();
+    for s in structs.iter().filter(|s| !s.is_optional) {
+        let module = rust_module(&s.name);
+        let (mut getter_decls, mut getter_impls) = (String::new(), String::new());
+        let (mut setter_decls, mut setter_impls) = (String::new(), String::new());
+        for (name, _, ty, _) in &s.fields {
+            let CapnpType::Optional(inner) = ty else { continue };
+            let inner = base(inner);
+            let (Some(output), Some(input)) = (types.reader(&inner, "'a"), types.reader(&inner, "'_")) else { continue };
+            let accessor = rust_accessor(name);
+            let wrapper = rust_module(&ty.ident());
+            let payload = match &inner {
+                CapnpType::Text => "value?.to_str()?",
+                _ if inner.scalar().is_some() => "value",
+                _ => "value?",
+            };
+            // Struct and list setters copy from a reader, which can fail
+            let fallible = match &inner {
+                CapnpType::Struct(name) => !types.is_enum(name),
+                CapnpType::List(..) | CapnpType::Optional(_) => true,
+                _ => false,
+            };
+
+            getter_decls.push_str(&format!(
+                r#"
+    /// `{name}`, or `None` if it is unset.
+    fn {accessor}_opt(&self) -> ::capnp::Result<Option<{output}>>;
+"#,
+                name = name, accessor = accessor, output = output,
+            ));
+            getter_impls.push_str(&format!(
+                r#"
+    fn {accessor}_opt(&self) -> ::capnp::Result<Option<{output}>> {{
+        Ok(match self.get_{accessor}()?.which()? {{
+            {wrapper}::Which::Value(value) => Some({payload}),
+            {wrapper}::Which::None(()) => None,
+        }})
+    }}
+"#,
+                accessor = accessor, output = output, wrapper = wrapper, payload = payload,
+            ));
+            let (ret, set, done) = if fallible { (" -> ::capnp::Result<()>", "?", "\n        Ok(())") } else { ("", "", "") };
+            setter_decls.push_str(&format!(
+                r#"
+    /// Sets `{name}` to `value`, or unsets it for `None`.
+    fn set_{accessor}_opt(&mut self, value: Option<{input}>){ret};
+"#,
+                name = name, accessor = accessor, input = input, ret = ret,
+            ));
+            setter_impls.push_str(&format!(
+                r#"
+    fn set_{accessor}_opt(&mut self, value: Option<{input}>){ret} {{
+        let mut wrapper = self.reborrow().init_{accessor}();
+        match value {{
+            Some(value) => wrapper.set_value(value){set},
+            None => wrapper.set_none(()),
+        }}{done}
+    }}
+"#,
+                accessor = accessor, input = input, ret = ret, set = set, done = done,
+            ));
+        }
+        if getter_decls.is_empty() {
+            continue;
+        }
+        code.push_str(&format!(
+            r#"
+/// `Option` accessors for the optional fields of `{name}`.
+pub trait {name}ReaderExt<'a> {{{getter_decls}}}
+
+impl<'a> {name}ReaderExt<'a> for {module}::Reader<'a> {{{getter_impls}}}
+
+/// `Option` setters for the optional fields of `{name}`.
+pub trait {name}BuilderExt {{{setter_decls}}}
+
+impl {name}BuilderExt for {module}::Builder<'_> {{{setter_impls}}}
+"#,
+            name = s.name, module = module,
+            getter_decls = getter_decls, getter_impls = getter_impls,
+            setter_decls = setter_decls, setter_impls = setter_impls,
+        ));
+    }
+    code
+}
+
+/// The schema type a well-known or serde-bytes type is stored as.
+fn base(ty: &CapnpType) -> CapnpType {
+    match ty {
+        CapnpType::WellKnown(WellKnown::ChronoUtc | WellKnown::SystemTime) => CapnpType::Int64,
+        CapnpType::WellKnown(WellKnown::Duration) => CapnpType::UInt64,
+        CapnpType::WellKnown(WellKnown::Uuid) => CapnpType::Data,
+        CapnpType::WellKnown(WellKnown::UuidText) => CapnpType::Text,
+        CapnpType::Bytes(_) => CapnpType::List(Box::new(CapnpType::UInt8), None),
+        CapnpType::List(inner, len) => CapnpType::List(Box::new(base(inner)), *len),
+        _ => ty.clone(),
+    }
+}
+
+struct Types<'a> {
+    enums: &'a [CapnpEnum],
+}
+
+impl Types<'_> {
+    fn is_enum(&self, name: &str) -> bool {
+        self.enums.iter().any(|e| e.name == name)
+    }
+
+    /// Rust type a reader of `ty` hands out, borrowing from the message for `lifetime`. `None` for
+    /// capabilities, which have no reader.
+    fn reader(&self, ty: &CapnpType, lifetime: &str) -> Option<String> {
+        let reference = if lifetime == "'_" { "&".to_string() } else { format!("&{} ", lifetime) };
+        Some(match ty {
+            CapnpType::Text => format!("{}str", reference),
+            CapnpType::Data => format!("{}[u8]", reference),
+            _ if ty.scalar().is_some() => ty.scalar()?.to_string(),
+            CapnpType::Struct(name) if self.is_enum(name) => name.clone(),
+            CapnpType::Struct(_) | CapnpType::Optional(_) => format!("{}::Reader<{}>", rust_module(&ty.ident()), lifetime),
+            CapnpType::List(inner, _) => {
+                let (kind, element) = self.list(inner)?;
+                match element {
+                    Some(element) => format!("{}::Reader<{}, {}>", kind, lifetime, element),
+                    None => format!("{}::Reader<{}>", kind, lifetime),
+                }
+            }
+            _ => return None,
+        })
+    }
+
+    /// The capnp list module holding elements of `ty`, and the element type it is generic over.
+    fn list(&self, ty: &CapnpType) -> Option<(&'static str, Option<String>)> {
+        Some(match ty {
+            CapnpType::Text => ("::capnp::text_list", None),
+            CapnpType::Data => ("::capnp::data_list", None),
+            _ if ty.scalar().is_some() => ("::capnp::primitive_list", Some(ty.scalar()?.to_string())),
+            CapnpType::Struct(name) if self.is_enum(name) => ("::capnp::enum_list", Some(name.clone())),
+            CapnpType::Struct(_) | CapnpType::Optional(_) => ("::capnp::struct_list", Some(format!("{}::Owned", rust_module(&ty.ident())))),
+            CapnpType::List(inner, _) => {
+                let (kind, element) = self.list(inner)?;
+                let owned = match element {
+                    Some(element) => format!("{}::Owned<{}>", kind, element),
+                    None => format!("{}::Owned", kind),
+                };
+                ("::capnp::list_list", Some(owned))
+            }
+            CapnpType::WellKnown(_) | CapnpType::Bytes(_) => return self.list(&base(ty)),
+            _ => return None,
+        })
+    }
+}
@@ -0,0 +1,38 @@
+//! Server glue appended to `schema_capnp.rs` for every collected interface.
+//!
+//! For an interface `HelloWorld` this generates `hello_world::Server` for
+//! `capnez::observe::Instrumented<S>` wherever `S` implements it, so wrapping a server implementation
+//! in `Instrumented::new` traces and observes each call without touching its methods. The impls are
+//! compiled only when the consuming crate has a `tracing` feature that turns on `capnez/tracing`.
+
+use super::CapnpInterface;
+use crate::naming::{rust_module, rust_variant};
+
+pub(crate) fn generate(interfaces: &[CapnpInterface]) -> String {
+    let mut code = String::new();
+    for i in interfaces {
+        let module = rust_module(&i.name);
+        let mut methods = String::new();
+        for (name, _, _) in &i.methods {
+            methods.push_str(&format!(
+                r#"
+    fn {method}(&mut self, params: {module}::{Method}Params, results: {module}::{Method}Results) -> ::capnp::capability::Promise<(), ::capnp::Error> {{
+        let params_bytes = params.get().and_then(|p| p.total_size()).ok().map(|size| size.word_count * 8);
+        self.observe("{interface}", "{name}", params_bytes, move |inner| inner.{method}(params, results))
+    }}
+"#,
+                method = rust_module(name),
+                Method = rust_variant(name),
+                module = module,
+                interface = i.name,
+                name = name,
+            ));
+        }
+        code.push_str(&format!(
+            "\n#[cfg(feature = \"tracing\")]\nimpl<S: {module}::Server> {module}::Server for ::capnez::observe::Instrumented<S> {{{methods}}}\n",
+            module = module,
+            methods = methods,
+        ));
+    }
+    code
+}
@@ -0,0 +1,110 @@
+//! RPC glue appended to `schema_capnp.rs` for streaming methods.
+//!
+//! A method returning `impl Stream<Item = MatrixEntry>` (or `Vec<MatrixEntry>` under `#[capnp(stream)]`)
+//! takes a `receiver :MatrixEntryReceiver` in the schema instead, and the server pushes the entries to it
+//! in chunks before returning. For a method `scan` of `Matrix` this generates:
+//!
+//! - `matrix::Client::scan_stream(params)`, which calls `scan` with a local receiver and returns a
+//!   `capnez::rpc::stream::ItemStream` of the entries as they arrive
+//! - `matrix::scan_params::Reader::send_stream(items)`, whose promise the server's `scan` returns; it
+//!   pushes a `Stream` of entries in chunks of the method's `#[capnp(chunk = ...)]`
+//! - `matrix_entry_receiver::Server` for `capnez::rpc::stream::ItemSender<MatrixEntry>`
+//!
+//! The glue needs the item's conversion impls, and is compiled only when the consuming crate has an
+//! `rpc` feature that turns on `capnez/rpc`.
+
+use super::{receiver_name, CapnpEnum, CapnpInterface, CapnpStruct};
+use crate::convert;
+use crate::naming::{rust_accessor, rust_module};
+use std::collections::BTreeSet;
+
+pub(crate) fn generate(interfaces: &[CapnpInterface], structs: &[CapnpStruct], enums: &[CapnpEnum]) -> String {
+    let mut code = String::new();
+    let mut receivers = BTreeSet::new();
+    for i in interfaces {
+        let module = rust_module(&i.name);
+        for (method, item, chunk) in &i.streams {
+            let Some((element, write, read)) = convert::items_codec(structs, enums, item) else { continue };
+            let receiver = rust_module(&receiver_name(item));
+            if receivers.insert(receiver.clone()) {
+                code.push_str(&format!(
+                    r#"
+#[cfg(feature = "rpc")]
+#[allow(unused_parens, clippy::all)]
+impl {receiver}::Server for ::capnez::rpc::stream::ItemSender<{element}> {{
+    fn push(&mut self, params: {receiver}::PushParams, _: {receiver}::PushResults) -> ::capnp::capability::Promise<(), ::capnp::Error> {{
+        let items = (|| -> ::capnp::Result<Vec<{element}>> {{ Ok({read}) }})();
+        ::capnp::capability::Promise::from_future(::capnez::rpc::stream::ItemSender::send(self, ::capnp::pry!(items)))
+    }}
+
+    fn done(&mut self, _: {receiver}::DoneParams, _: {receiver}::DoneResults) -> ::capnp::capability::Promise<(), ::capnp::Error> {{
+        ::capnp::capability::Promise::from_future(::capnez::rpc::stream::ItemSender::finish(self))
+    }}
+}}
+"#,
+                    receiver = receiver,
+                    element = element,
+                    read = read,
+                ));
+            }
+
+            let accessor = rust_accessor(method);
+            code.push_str(&format!(
+                r#"
+#[cfg(feature = "rpc")]
+#[allow(dead_code)]
+impl {module}::Client {{
+    /// Calls `{method}` with a local receiver, yielding the items it streams back as they arrive.
+    /// `params` sets the other parameters.
+    pub fn {accessor}_stream(&self, params: impl FnOnce({module}::{accessor}_params::Builder<'_>)) -> ::capnez::rpc::stream::ItemStream<{element}> {{
+        let (receiver, items) = ::capnez::rpc::stream::channel::<{receiver}::Client, {element}>();
+        let mut request = self.{accessor}_request();
+        params(request.get());
+        request.get().set_receiver(receiver);
+        items.with_call(request.send().promise)
+    }}
+}}
+
+#[cfg(feature = "rpc")]
+#[allow(dead_code, unused_mut, unused_parens, clippy::all)]
+impl {module}::{accessor}_params::Reader<'_> {{
+    /// Pushes `items` to the caller's receiver in chunks of at most {chunk}, then ends the stream.
+    /// The server's `{method}` returns this promise.
+    pub fn send_stream<S>(&self, items: S) -> ::capnp::capability::Promise<(), ::capnp::Error>
+    where
+        S: ::capnez::rpc::stream::Stream<Item = {element}> + 'static,
+    {{
+        let receiver = ::capnp::pry!(self.get_receiver());
+        let done = receiver.clone();
+        ::capnp::capability::Promise::from_future(::capnez::rpc::stream::send_chunks(
+            items,
+            {chunk},
+            move |chunk: Vec<{element}>| {{
+                let mut request = receiver.push_request();
+                {{
+                    let mut params = request.get();
+                    {write}
+                }}
+                let promise = request.send().promise;
+                async move {{ promise.await.map(drop) }}
+            }},
+            move || {{
+                let promise = done.done_request().send().promise;
+                async move {{ promise.await.map(drop) }}
+            }},
+        ))
+    }}
+}}
+"#,
+                module = module,
+                method = method,
+                accessor = accessor,
+                receiver = receiver,
+                element = element,
+                chunk = chunk,
+                write = write,
+            ));
+        }
+    }
+    code
+}
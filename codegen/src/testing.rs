@@ -0,0 +1,62 @@
+//! Helpers for asserting, in your own tests, that annotated types produce a valid schema.
+//!
+//! ```ignore
+//! let schema = capnez_codegen::testing::schema_for_source(r#"
+//!     #[capnp]
+//!     struct Point { x: f64, y: f64 }
+//! "#)?;
+//! capnez_codegen::testing::compile_schema(&schema)?;
+//! ```
+
+use crate::{CapnezError, Limits, SchemaGenerator, SchemaModel};
+use anyhow::{Context, Result};
+use std::{fs, path::Path};
+
+/// File ID of schemas built by [`schema_for_source`], fixed so the output is reproducible.
+pub const SOURCE_FILE_ID: u64 = 0xbf51_47bb_3b06_fa3d;
+
+/// Generates the schema for a single in-memory source file, as if it were `src/lib.rs`.
+///
+/// Nothing is read from or written to disk, and neither `CARGO_MANIFEST_DIR` nor `OUT_DIR` is needed:
+/// no lockfile is consulted, the default [`Limits`] apply, and capnpc is not run.
+pub fn schema_for_source(src: &str) -> Result<String> {
+    Ok(model_for_source(src)?.to_capnp_text())
+}
+
+/// The [`SchemaModel`] [`schema_for_source`] renders, e.g. to assert on its JSON.
+pub fn model_for_source(src: &str) -> Result<SchemaModel> {
+    model_for_sources(&[("lib.rs", src)])
+}
+
+/// Like [`schema_for_source`], for several files given as paths relative to `src/` and their contents.
+/// The order of `files` does not matter, as with files found on disk.
+pub fn schema_for_sources(files: &[(&str, &str)]) -> Result<String> {
+    Ok(model_for_sources(files)?.to_capnp_text())
+}
+
+fn model_for_sources(files: &[(&str, &str)]) -> Result<SchemaModel> {
+    let files = files.iter()
+        .map(|(path, src)| {
+            let file = syn::parse_file(src).with_context(|| format!("Failed to parse {}", path))?;
+            Ok((Path::new("src").join(path), file))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let generator = SchemaGenerator::new().file_id(SOURCE_FILE_ID).without_lockfile();
+    let generated = generator.generate_from(Path::new("src"), &Limits::default(), files)?;
+    Ok(generated.model)
+}
+
+/// Compiles `schema` with capnpc in a temporary directory, failing with [`CapnezError::CapnpcFailed`]
+/// if capnpc rejects it.
+pub fn compile_schema(schema: &str) -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("schema.capnp");
+    fs::write(&path, schema)?;
+    capnpc::CompilerCommand::new()
+        .file(&path)
+        .src_prefix(dir.path())
+        .output_path(dir.path())
+        .run()
+        .map_err(|e| CapnezError::CapnpcFailed { schema: path.clone(), message: e.to_string() })?;
+    Ok(())
+}
@@ -0,0 +1,98 @@
+//! Field constraints from `#[capnp(validate(...))]`, checked by the generated `from_capnp`.
+//!
+//! | Constraint                     | Fields                | Fails when                                 |
+//! |--------------------------------|-----------------------|--------------------------------------------|
+//! | `range = "1..=150"`            | integers and floats   | the value is outside the range             |
+//! | `non_empty`                    | text, data and lists  | there are no bytes or elements             |
+//! | `max_len = 256`                | text, data and lists  | there are more bytes or elements           |
+//! | `with = "path::to::check"`     | any                   | `check(&value)` returns `Err(String)`      |
+//!
+//! Several constraints can share one `validate(...)`. The schema is unaffected; constraints only
+//! exist on the Rust side.
+
+use crate::error::CapnezError;
+use crate::CapnpType;
+use syn::parse::Parser;
+use syn::{Attribute, ExprRange, LitInt, LitStr};
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Validation {
+    /// The range as written, e.g. `1..=150`.
+    Range(String),
+    NonEmpty,
+    MaxLen(usize),
+    /// Path of a `fn(&T) -> Result<(), String>`.
+    With(String),
+}
+
+/// The constraints of a field of type `ty`, failing on ones that do not apply to it.
+pub(crate) fn validations(attrs: &[Attribute], ty: &CapnpType) -> Result<Vec<Validation>, CapnezError> {
+    let mut found = Vec::new();
+    for tokens in crate::naming::attr_lists(attrs, "validate")? {
+        let parser = syn::meta::parser(|meta| {
+            if meta.path.is_ident("range") {
+                let lit = meta.value()?.parse::<LitStr>()?;
+                lit.parse::<ExprRange>().map_err(|_| meta.error(format!("`{}` is not a range", lit.value())))?;
+                found.push(Validation::Range(lit.value()));
+            } else if meta.path.is_ident("non_empty") {
+                found.push(Validation::NonEmpty);
+            } else if meta.path.is_ident("max_len") {
+                found.push(Validation::MaxLen(meta.value()?.parse::<LitInt>()?.base10_parse()?));
+            } else if meta.path.is_ident("with") {
+                let lit = meta.value()?.parse::<LitStr>()?;
+                let path = lit.parse::<syn::Path>().map_err(|_| meta.error(format!("`{}` is not a function path", lit.value())))?;
+                found.push(Validation::With(quote::ToTokens::to_token_stream(&path).to_string().replace(' ', "")));
+            } else {
+                return Err(meta.error("unsupported validation; expected one of range, non_empty, max_len, with"));
+            }
+            Ok(())
+        });
+        parser.parse2(tokens).map_err(|e| CapnezError::attribute(e.to_string()))?;
+    }
+
+    for validation in &found {
+        let applies = match validation {
+            Validation::Range(_) => ty.scalar().is_some() && !matches!(ty, CapnpType::Bool),
+            Validation::NonEmpty | Validation::MaxLen(_) => matches!(ty, CapnpType::Text | CapnpType::Data | CapnpType::List(..)),
+            Validation::With(_) => true,
+        };
+        if !applies {
+            return Err(CapnezError::attribute(format!("`{}` does not apply to a field of type {}", validation.name(), ty)));
+        }
+    }
+    Ok(found)
+}
+
+impl Validation {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Range(_) => "range",
+            Self::NonEmpty => "non_empty",
+            Self::MaxLen(_) => "max_len",
+            Self::With(_) => "with",
+        }
+    }
+
+    /// Statement returning an error naming `label` (e.g. `Person.age`) and the constraint when the
+    /// value behind the reference expression `value` breaks it.
+    pub(crate) fn check(&self, value: &str, label: &str) -> String {
+        match self {
+            Self::Range(range) => format!(
+                "if !({range}).contains({value}) {{ return Err(::capnp::Error::failed(format!(\"{label} is {{}}, outside range {range}\", {value}))); }}",
+                range = range, value = value, label = label
+            ),
+            Self::NonEmpty => format!(
+                "if {value}.is_empty() {{ return Err(::capnp::Error::failed(\"{label} is empty, breaking non_empty\".to_string())); }}",
+                value = value, label = label
+            ),
+            Self::MaxLen(max) => format!(
+                "if {value}.len() > {max} {{ return Err(::capnp::Error::failed(format!(\"{label} has length {{}}, over max_len = {max}\", {value}.len()))); }}",
+                value = value, max = max, label = label
+            ),
+            Self::With(path) => format!(
+                "if let Err(e) = {path}({value}) {{ return Err(::capnp::Error::failed(format!(\"{label} fails {path}: {{}}\", e))); }}",
+                path = path, value = value, label = label
+            ),
+        }
+    }
+}
@@ -0,0 +1,146 @@
+//! Opt-in mappings for common library types that have no capnp counterpart of their own.
+//!
+//! Each mapping is enabled by a cargo feature of this crate; the user crate depends on the library
+//! itself, since the generated conversion impls name it directly.
+//!
+//! | Rust type                  | Feature  | Schema type | Encoding                                   |
+//! |----------------------------|----------|-------------|--------------------------------------------|
+//! | `chrono::DateTime<Utc>`    | `chrono` | `Int64`     | nanoseconds since the Unix epoch           |
+//! | `std::time::SystemTime`    | `time`   | `Int64`     | nanoseconds since the Unix epoch           |
+//! | `std::time::Duration`      | `time`   | `UInt64`    | nanoseconds                                |
+//! | `uuid::Uuid`               | `uuid`   | `Data`      | the 16 raw bytes                           |
+//! | `uuid::Uuid` + `as = "text"` | `uuid` | `Text`      | the hyphenated lowercase form              |
+//!
+//! Nanosecond timestamps cover the years 1677 to 2262; instants outside that range saturate when
+//! encoded. Durations longer than `u64::MAX` nanoseconds (about 584 years) saturate likewise.
+
+use crate::error::CapnezError;
+use syn::{GenericArgument, PathArguments, TypePath};
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum WellKnown {
+    ChronoUtc,
+    SystemTime,
+    Duration,
+    Uuid,
+    UuidText,
+}
+
+impl WellKnown {
+    /// Recognizes `p` by its last path segment, failing if the type needs a feature that is off.
+    pub(crate) fn recognize(p: &TypePath) -> Result<Option<WellKnown>, CapnezError> {
+        let Some(last) = p.path.segments.last() else { return Ok(None) };
+        let (known, feature) = match last.ident.to_string().as_str() {
+            "DateTime" => (WellKnown::ChronoUtc, "chrono"),
+            "SystemTime" => (WellKnown::SystemTime, "time"),
+            "Duration" => (WellKnown::Duration, "time"),
+            "Uuid" => (WellKnown::Uuid, "uuid"),
+            _ => return Ok(None),
+        };
+        let enabled = match feature {
+            "chrono" => cfg!(feature = "chrono"),
+            "time" => cfg!(feature = "time"),
+            _ => cfg!(feature = "uuid"),
+        };
+        if !enabled {
+            return Err(CapnezError::unsupported(
+                last.ident.to_string(),
+                format!("enable the `{}` feature of capnez-codegen to map it, or wrap it in a #[capnp] struct", feature),
+            ));
+        }
+        if known == WellKnown::ChronoUtc {
+            let utc = match &last.arguments {
+                PathArguments::AngleBracketed(args) => matches!(
+                    args.args.first(),
+                    Some(GenericArgument::Type(syn::Type::Path(tz))) if tz.path.segments.last().is_some_and(|s| s.ident == "Utc")
+                ),
+                _ => false,
+            };
+            if !utc {
+                return Err(CapnezError::unsupported("DateTime", "only `DateTime<Utc>` is supported; convert other time zones to UTC first"));
+            }
+        }
+        Ok(Some(known))
+    }
+
+    /// The type this is stored as in the schema.
+    pub(crate) fn schema_type(self) -> &'static str {
+        match self {
+            WellKnown::ChronoUtc | WellKnown::SystemTime => "Int64",
+            WellKnown::Duration => "UInt64",
+            WellKnown::Uuid => "Data",
+            WellKnown::UuidText => "Text",
+        }
+    }
+
+    /// Path of the Rust type, as written in generated code.
+    pub(crate) fn rust_type(self) -> &'static str {
+        match self {
+            WellKnown::ChronoUtc => "::chrono::DateTime<::chrono::Utc>",
+            WellKnown::SystemTime => "::std::time::SystemTime",
+            WellKnown::Duration => "::core::time::Duration",
+            WellKnown::Uuid | WellKnown::UuidText => "::uuid::Uuid",
+        }
+    }
+
+    /// Whether the capnp getter returns a `Result`, i.e. the value is stored behind a pointer.
+    pub(crate) fn is_pointer(self) -> bool {
+        matches!(self, WellKnown::Uuid | WellKnown::UuidText)
+    }
+
+    /// Expression converting the reference expression `value` into the setter argument.
+    pub(crate) fn encode(self, value: &str) -> String {
+        match self {
+            WellKnown::ChronoUtc => format!(
+                "{{ let t: &::chrono::DateTime<::chrono::Utc> = {v}; t.timestamp_nanos_opt().unwrap_or(if t.timestamp() < 0 {{ i64::MIN }} else {{ i64::MAX }}) }}",
+                v = value
+            ),
+            WellKnown::SystemTime => format!(
+                "match {v}.duration_since(::std::time::UNIX_EPOCH) {{ \
+                 Ok(d) => i64::try_from(d.as_nanos()).unwrap_or(i64::MAX), \
+                 Err(e) => i64::try_from(e.duration().as_nanos()).map_or(i64::MIN, |n| -n), }}",
+                v = value
+            ),
+            WellKnown::Duration => format!("u64::try_from({}.as_nanos()).unwrap_or(u64::MAX)", value),
+            WellKnown::Uuid => format!("&{}.as_bytes()[..]", value),
+            WellKnown::UuidText => format!("{}.to_string().as_str()", value),
+        }
+    }
+
+    /// Expression decoding the already unwrapped getter result `reader`.
+    pub(crate) fn decode(self, reader: &str) -> String {
+        match self {
+            WellKnown::ChronoUtc => format!("::chrono::TimeZone::timestamp_nanos(&::chrono::Utc, {})", reader),
+            WellKnown::SystemTime => format!(
+                "{{ let n: i64 = {r}; if n >= 0 {{ ::std::time::UNIX_EPOCH + ::std::time::Duration::from_nanos(n as u64) }} \
+                 else {{ ::std::time::UNIX_EPOCH - ::std::time::Duration::from_nanos(n.unsigned_abs()) }} }}",
+                r = reader
+            ),
+            WellKnown::Duration => format!("::core::time::Duration::from_nanos({})", reader),
+            WellKnown::Uuid => format!(
+                "{{ let bytes: &[u8] = {r}; ::uuid::Uuid::from_slice(bytes).map_err(|_| ::capnp::Error::failed(format!(\"expected a 16-byte uuid, found {{}} bytes\", bytes.len())))? }}",
+                r = reader
+            ),
+            WellKnown::UuidText => format!(
+                "::uuid::Uuid::parse_str({}.to_str()?).map_err(|e| ::capnp::Error::failed(format!(\"invalid uuid: {{}}\", e)))?",
+                reader
+            ),
+        }
+    }
+}
+
+/// Applies a field's or parameter's `#[capnp(as = "...")]` to the well-known leaves of `ty`.
+pub(crate) fn apply_repr(ty: crate::CapnpType, repr: Option<String>) -> Result<crate::CapnpType, CapnezError> {
+    use crate::CapnpType;
+    let Some(repr) = repr else { return Ok(ty) };
+    Ok(match ty {
+        CapnpType::List(inner, len) => CapnpType::List(Box::new(apply_repr(*inner, Some(repr))?), len),
+        CapnpType::Optional(inner) => CapnpType::Optional(Box::new(apply_repr(*inner, Some(repr))?)),
+        CapnpType::WellKnown(WellKnown::Uuid) if repr == "text" => CapnpType::WellKnown(WellKnown::UuidText),
+        CapnpType::WellKnown(WellKnown::Uuid) if repr == "data" => ty,
+        CapnpType::WellKnown(WellKnown::Uuid) => {
+            return Err(CapnezError::attribute(format!("unknown representation as = \"{}\"; expected `data` or `text`", repr)))
+        }
+        _ => return Err(CapnezError::attribute(format!("as = \"{}\" only applies to `Uuid` fields", repr))),
+    })
+}
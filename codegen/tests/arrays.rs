@@ -0,0 +1,42 @@
+//! Fixed-size arrays: lists in the schema, with a diagnostic when a length cannot be checked.
+
+use capnez_codegen::testing::{compile_schema, schema_for_source};
+use std::process::Command;
+
+#[test]
+fn arrays_are_lists_of_their_element() {
+    let schema = schema_for_source("#[capnp]\nstruct Point { coords: [f64; 3] }\n#[capnp]\nstruct Tile { pixels: [[u8; 4]; 4], ids: [u64; 2] }").unwrap();
+    assert!(schema.contains("coords @0 :List(Float64);"), "{}", schema);
+    assert!(schema.contains("pixels @0 :List(List(UInt8));"), "{}", schema);
+    assert!(schema.contains("ids @1 :List(UInt64);"), "{}", schema);
+    compile_schema(&schema).unwrap();
+}
+
+/// stderr of `capnez-codegen --stdout` run outside a build script on a crate made of `src`.
+fn warnings(src: &str) -> String {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("lib.rs"), src).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_capnez-codegen"))
+        .arg("--input").arg(dir.path())
+        .args(["--no-lockfile", "--stdout"])
+        .env_remove("OUT_DIR")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8(output.stderr).unwrap()
+}
+
+#[test]
+fn a_const_generic_struct_is_diagnosed() {
+    let stderr = warnings("#[capnp]\nstruct Vector<const N: usize> { values: [f32; N] }");
+    assert!(
+        stderr.contains("`Vector` has const generic parameters; arrays sized by them become unchecked lists and no conversions are generated"),
+        "{}", stderr
+    );
+    assert!(stderr.contains("array length `N` is not an integer literal"), "{}", stderr);
+}
+
+#[test]
+fn literal_lengths_are_not_diagnosed() {
+    assert_eq!(warnings("#[capnp]\nstruct Point { coords: [f64; 3] }"), "");
+}
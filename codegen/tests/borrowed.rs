@@ -0,0 +1,32 @@
+//! Structs with fields borrowed from the message (`&'a str`, `&'a [u8]`): the lifetime is a Rust-side
+//! detail and never reaches the schema.
+
+use capnez_codegen::testing::{compile_schema, schema_for_source};
+
+#[test]
+fn lifetimes_do_not_reach_the_schema() {
+    let schema = schema_for_source(r#"
+        #[capnp]
+        struct Event<'a> {
+            topic: &'a str,
+            payload: &'a [u8],
+            tags: Vec<&'a str>,
+        }
+
+        #[capnp]
+        struct Envelope<'a> {
+            event: Event<'a>,
+        }
+    "#).unwrap();
+    assert!(schema.contains("struct Event {\n  topic @0 :Text;\n  payload @1 :Data;\n  tags @2 :List(Text);\n}"), "{}", schema);
+    assert!(schema.contains("struct Envelope {\n  event @0 :Event;\n}"), "{}", schema);
+    assert!(!schema.contains('\''), "{}", schema);
+    compile_schema(&schema).unwrap();
+}
+
+#[test]
+fn borrowed_and_owned_fields_map_to_the_same_types() {
+    let borrowed = schema_for_source("#[capnp]\nstruct Event<'a> { topic: &'a str, payload: &'a [u8] }").unwrap();
+    let owned = schema_for_source("#[capnp]\nstruct Event { topic: String, payload: Vec<u8> }").unwrap();
+    assert_eq!(borrowed, owned);
+}
@@ -0,0 +1,115 @@
+//! Runs the `capnez-codegen` binary against the `basic` fixture.
+
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+fn fixture() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/basic/src")
+}
+
+fn codegen(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_capnez-codegen"))
+        .arg("--input").arg(fixture())
+        .args(["--no-lockfile", "--file-id", "0xd0b68c8e2f4a9b31"])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+#[test]
+fn stdout_prints_only_the_schema() {
+    let output = codegen(&["--stdout"]);
+    assert!(output.status.success(), "{:?}", output);
+    let schema = stdout(&output);
+    assert!(schema.starts_with("@0xd0b68c8e2f4a9b31;"), "{}", schema);
+    assert!(schema.contains("struct User"), "{}", schema);
+}
+
+#[test]
+fn no_compile_writes_the_schema_and_check_accepts_it() {
+    let dir = tempfile::tempdir().unwrap();
+    let schema = dir.path().join("schema.capnp");
+    let schema_arg = schema.to_str().unwrap();
+
+    let output = codegen(&["--output", schema_arg, "--no-compile"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(stdout(&output).is_empty(), "{}", stdout(&output));
+    assert_eq!(std::fs::read_to_string(&schema).unwrap(), stdout(&codegen(&["--stdout"])));
+    assert!(!dir.path().join("schema_capnp.rs").exists());
+
+    assert!(codegen(&["--output", schema_arg, "--check"]).status.success());
+}
+
+#[test]
+fn check_fails_with_a_diff_when_out_of_date() {
+    let dir = tempfile::tempdir().unwrap();
+    let schema = dir.path().join("schema.capnp");
+    std::fs::write(&schema, "@0xd0b68c8e2f4a9b31;\n").unwrap();
+
+    let output = codegen(&["--output", schema.to_str().unwrap(), "--check"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).contains("+struct User"), "{}", stdout(&output));
+}
+
+#[test]
+fn inspect_prints_counts() {
+    let output = codegen(&["--inspect"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(stdout(&output).contains("structs:           3 / 10000"), "{}", stdout(&output));
+    assert!(stdout(&output).contains("enums:             1 / 10000"), "{}", stdout(&output));
+}
+
+#[test]
+fn compat_exits_non_zero_on_a_breaking_change() {
+    let dir = tempfile::tempdir().unwrap();
+    let snapshot = dir.path().join("snapshot.capnp");
+    let current = stdout(&codegen(&["--stdout"]));
+
+    std::fs::write(&snapshot, &current).unwrap();
+    let output = codegen(&["--compat", snapshot.to_str().unwrap()]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(stdout(&output).ends_with("snapshot.capnp: no changes\n"), "{}", stdout(&output));
+
+    // A snapshot with a field the fixture no longer has
+    std::fs::write(&snapshot, current.replace("struct Address {", "struct Address {\n  removed @9 :Text;")).unwrap();
+    let output = codegen(&["--compat", snapshot.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1), "{}", stdout(&output));
+    assert!(stdout(&output).contains("breaking: field `Address.removed` (@9) was removed"), "{}", stdout(&output));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("breaks wire compatibility"), "{:?}", output);
+}
+
+#[test]
+fn compat_exits_zero_on_compatible_changes_and_checks_every_snapshot() {
+    let dir = tempfile::tempdir().unwrap();
+    let current = stdout(&codegen(&["--stdout"]));
+
+    // An older snapshot without `User.tags`: the fixture only added a field since
+    let older = dir.path().join("v1.capnp");
+    std::fs::write(&older, current.replace("  tags @3 :List(Text);\n", "")).unwrap();
+    let output = codegen(&["--compat", older.to_str().unwrap()]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(stdout(&output).contains("ok: field `User.tags` (@3) was added"), "{}", stdout(&output));
+
+    // Any breaking snapshot fails the run, and each one is still reported
+    let changed = dir.path().join("v2.capnp");
+    std::fs::write(&changed, current.replace("street @0 :Text;", "street @0 :Data;")).unwrap();
+    let output = codegen(&["--compat", older.to_str().unwrap(), "--compat", changed.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1), "{}", stdout(&output));
+    assert!(stdout(&output).contains("v1.capnp: 0 breaking"), "{}", stdout(&output));
+    assert!(stdout(&output).contains("breaking: field `Address.street` (@0) changed type from Data to Text"), "{}", stdout(&output));
+}
+
+#[test]
+fn invalid_file_id_is_an_error() {
+    let output = Command::new(env!("CARGO_BIN_EXE_capnez-codegen"))
+        .arg("--input").arg(fixture())
+        .args(["--stdout", "--no-lockfile", "--file-id", "nothex"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid file ID `nothex`"));
+}
@@ -0,0 +1,102 @@
+//! `compare_schemas` on small before/after schema pairs, one per class of change.
+
+use capnez_codegen::testing::schema_for_source;
+use capnez_codegen::{compare_schemas, ChangeKind, CompatReport, Severity};
+
+fn schema(body: &str) -> String {
+    format!("@0xbf5147bb3b06fa3d;\n\n{}\n", body)
+}
+
+fn compare(old: &str, new: &str) -> CompatReport {
+    compare_schemas(&schema(old), &schema(new)).unwrap()
+}
+
+/// The kind, severity and path of each change a report should hold.
+type Expected = &'static [(ChangeKind, Severity, &'static str)];
+
+const PERSON: &str = "struct Person {\n  name @0 :Text;\n  age @1 :UInt32;\n}";
+const STATUS: &str = "enum Status {\n  active @0;\n  banned @1;\n}";
+const GREETER: &str = "interface Greeter {\n  greet @0 (name :Text) -> (result :Text);\n  wave @1 () -> (result :Bool);\n}";
+
+#[test]
+fn each_change_class() {
+    use ChangeKind::*;
+    use Severity::*;
+    let cases: &[(&str, &str, &str, Expected)] = &[
+        // Fields
+        ("field added", "struct Person {\n  name @0 :Text;\n}", PERSON, &[(Added, Compatible, "Person.age")]),
+        ("field removed", PERSON, "struct Person {\n  name @0 :Text;\n}", &[(Removed, Breaking, "Person.age")]),
+        ("field type changed", PERSON, &PERSON.replace("age @1 :UInt32", "age @1 :UInt64"), &[(TypeChanged, Breaking, "Person.age")]),
+        ("field renamed, same number", PERSON, &PERSON.replace("age @1", "years @1"), &[(Renamed, Warning, "Person.years")]),
+        ("field default changed", &PERSON.replace(":UInt32", ":UInt32 = 18"), &PERSON.replace(":UInt32", ":UInt32 = 21"), &[(DefaultChanged, Warning, "Person.age")]),
+        // Structs
+        ("struct added", "", PERSON, &[(Added, Compatible, "Person")]),
+        ("struct removed", PERSON, "", &[(Removed, Warning, "Person")]),
+        // Enums
+        ("enumerant added", "enum Status {\n  active @0;\n}", STATUS, &[(Added, Compatible, "Status.banned")]),
+        ("enumerant removed", STATUS, "enum Status {\n  active @0;\n}", &[(Removed, Breaking, "Status.banned")]),
+        ("enumerant renamed", STATUS, &STATUS.replace("banned", "suspended"), &[(Renamed, Warning, "Status.suspended")]),
+        ("enum removed", STATUS, "", &[(Removed, Warning, "Status")]),
+        // Interfaces
+        ("method added", "interface Greeter {\n  greet @0 (name :Text) -> (result :Text);\n}", GREETER, &[(Added, Compatible, "Greeter.wave")]),
+        ("method removed", GREETER, "interface Greeter {\n  greet @0 (name :Text) -> (result :Text);\n}", &[(Removed, Breaking, "Greeter.wave")]),
+        (
+            "methods renumbered",
+            GREETER,
+            "interface Greeter {\n  wave @0 () -> (result :Bool);\n  greet @1 (name :Text) -> (result :Text);\n}",
+            &[(Renumbered, Breaking, "Greeter.greet"), (Renumbered, Breaking, "Greeter.wave")],
+        ),
+        ("method renamed, same number", GREETER, &GREETER.replace("wave @1", "salute @1"), &[(Renamed, Warning, "Greeter.salute")]),
+        ("parameter type changed", GREETER, &GREETER.replace("(name :Text)", "(name :UInt64)"), &[(TypeChanged, Breaking, "Greeter.greet(name)")]),
+        ("parameter added", GREETER, &GREETER.replace("(name :Text)", "(name :Text, loud :Bool)"), &[(Added, Compatible, "Greeter.greet(loud)")]),
+        ("result type changed", GREETER, &GREETER.replace("-> (result :Bool)", "-> (result :Text)"), &[(TypeChanged, Breaking, "Greeter.wave")]),
+        ("result renamed", GREETER, &GREETER.replace("-> (result :Bool)", "-> (waved :Bool)"), &[(Renamed, Warning, "Greeter.wave")]),
+        ("interface removed", GREETER, "", &[(Removed, Breaking, "Greeter")]),
+    ];
+
+    for (name, old, new, expected) in cases {
+        let report = compare(old, new);
+        let found = report.changes.iter().map(|c| (c.kind, c.severity, c.path.as_str())).collect::<Vec<_>>();
+        assert_eq!(found, *expected, "{}:\n{}", name, report);
+        assert_eq!(report.is_breaking(), expected.iter().any(|(_, severity, _)| *severity == Breaking), "{}", name);
+    }
+}
+
+#[test]
+fn an_unchanged_schema_has_no_changes() {
+    let both = [PERSON, STATUS, GREETER].join("\n\n");
+    let report = compare(&both, &both);
+    assert!(report.changes.is_empty(), "{}", report);
+    assert_eq!(report.to_string(), "no changes\n");
+}
+
+#[test]
+fn generated_schemas_compare_through_their_option_wrappers() {
+    // The `Optional*` wrapper structs are not reported themselves; the change shows on the field
+    let old = schema_for_source("#[capnp]\nstruct Person { name: String, nickname: Option<String> }").unwrap();
+    let new = schema_for_source("#[capnp]\nstruct Person { name: String, nickname: Option<u32> }").unwrap();
+    let report = compare_schemas(&old, &new).unwrap();
+    let found = report.changes.iter().map(|c| (c.kind, c.severity, c.path.as_str())).collect::<Vec<_>>();
+    assert_eq!(found, [(ChangeKind::TypeChanged, Severity::Breaking, "Person.nickname")], "{}", report);
+
+    assert!(compare_schemas(&old, &old).unwrap().changes.is_empty());
+}
+
+#[test]
+fn the_report_counts_and_lists_every_change() {
+    let new = PERSON.replace("age @1 :UInt32", "age @1 :Int64").replace("name @0", "fullName @0") + "\n\nstruct Team {\n  name @0 :Text;\n}";
+    let report = compare(PERSON, &new);
+    assert_eq!(
+        report.to_string(),
+        "1 breaking, 1 warnings, 1 compatible\n\
+         \x20 warning: field `Person.name` (@0) was renamed to `fullName`\n\
+         \x20 breaking: field `Person.age` (@1) changed type from UInt32 to Int64\n\
+         \x20 ok: struct `Team` was added\n",
+    );
+}
+
+#[test]
+fn a_schema_capnez_could_not_have_generated_is_an_error() {
+    let err = compare_schemas(&schema(PERSON), &schema("struct Person {\n  name @0 :Text\n")).unwrap_err();
+    assert!(format!("{:#}", err).contains("new schema"), "{:#}", err);
+}
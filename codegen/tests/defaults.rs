@@ -0,0 +1,57 @@
+//! `#[capnp(default = ...)]` literals as they appear in the schema text.
+
+use capnez_codegen::testing::schema_for_source;
+
+fn field_line(ty: &str, default: &str) -> String {
+    let schema = schema_for_source(&format!("#[capnp]\nstruct Config {{\n    #[capnp(default = {})]\n    value: {},\n}}", default, ty)).unwrap();
+    schema.lines().find(|line| line.trim_start().starts_with("value @0")).unwrap().trim().to_string()
+}
+
+fn error(ty: &str, default: &str) -> String {
+    schema_for_source(&format!("#[capnp]\nstruct Config {{\n    #[capnp(default = {})]\n    value: {},\n}}", default, ty))
+        .unwrap_err()
+        .to_string()
+}
+
+#[test]
+fn integers() {
+    assert_eq!(field_line("u32", "42"), "value @0 :UInt32 = 42;");
+    assert_eq!(field_line("i64", "-7"), "value @0 :Int64 = -7;");
+    assert_eq!(field_line("u8", "255"), "value @0 :UInt8 = 255;");
+    assert_eq!(field_line("i8", "-128"), "value @0 :Int8 = -128;");
+    assert_eq!(field_line("u64", "0x10"), "value @0 :UInt64 = 16;");
+}
+
+#[test]
+fn floats() {
+    assert_eq!(field_line("f64", "1.5"), "value @0 :Float64 = 1.5;");
+    assert_eq!(field_line("f32", "-0.25"), "value @0 :Float32 = -0.25;");
+    assert_eq!(field_line("f64", "3"), "value @0 :Float64 = 3;");
+}
+
+#[test]
+fn bools() {
+    assert_eq!(field_line("bool", "true"), "value @0 :Bool = true;");
+    assert_eq!(field_line("bool", "false"), "value @0 :Bool = false;");
+}
+
+#[test]
+fn text() {
+    assert_eq!(field_line("String", "\"en\""), "value @0 :Text = \"en\";");
+    assert_eq!(field_line("String", r#""say \"hi\"\n""#), r#"value @0 :Text = "say \"hi\"\n";"#);
+}
+
+#[test]
+fn a_string_on_an_integer_field_names_the_field() {
+    let err = error("u32", "\"hello\"");
+    assert!(err.contains("default = \"hello\" does not fit the capnp type UInt32"), "{}", err);
+    assert!(err.contains("value") && err.contains("Config"), "{}", err);
+}
+
+#[test]
+fn mismatched_literals_are_rejected() {
+    for (ty, default) in [("u8", "256"), ("u32", "-1"), ("bool", "1"), ("String", "true"), ("i32", "1.5"), ("Vec<u32>", "1")] {
+        let err = error(ty, default);
+        assert!(err.contains("does not fit the capnp type"), "{} = {}: {}", ty, default, err);
+    }
+}
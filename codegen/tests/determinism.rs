@@ -0,0 +1,79 @@
+//! The same sources must give byte-identical schemas, whatever order their files are found in and
+//! however often generation runs.
+
+use capnez_codegen::testing::schema_for_sources;
+use capnez_codegen::SchemaGenerator;
+use std::fs;
+
+/// Interdependent structs spread over several files, declared in an order that is neither
+/// alphabetical nor topological.
+const FILES: &[(&str, &str)] = &[
+    ("order.rs", "#[capnp]\nstruct Order { id: u64, customer: Customer, lines: Vec<Line>, status: Status }"),
+    ("line.rs", "#[capnp]\nstruct Line { product: Product, quantity: u32, discount: Option<f64> }"),
+    ("customer.rs", "#[capnp]\nstruct Customer { name: String, address: Address }\n#[capnp]\nstruct Address { street: String }"),
+    ("product.rs", "#[capnp]\nstruct Product { sku: String, tags: Vec<String> }\n#[capnp]\nenum Status { Open, Shipped }"),
+    ("lib.rs", "#[capnp]\ntrait Orders { fn place(order: Order) -> u64; fn get(id: u64) -> Option<Order>; }"),
+];
+
+/// Every permutation of `items`, by Heap's algorithm.
+fn permutations<T: Clone>(items: &[T]) -> Vec<Vec<T>> {
+    fn heap<T: Clone>(k: usize, items: &mut Vec<T>, out: &mut Vec<Vec<T>>) {
+        if k <= 1 {
+            out.push(items.clone());
+            return;
+        }
+        for i in 0..k {
+            heap(k - 1, items, out);
+            items.swap(if k.is_multiple_of(2) { i } else { 0 }, k - 1);
+        }
+    }
+    let mut out = Vec::new();
+    heap(items.len(), &mut items.to_vec(), &mut out);
+    out
+}
+
+#[test]
+fn discovery_order_does_not_change_the_schema() {
+    let expected = schema_for_sources(FILES).unwrap();
+    let orders = permutations(FILES);
+    assert_eq!(orders.len(), 120);
+    for files in orders {
+        assert_eq!(schema_for_sources(&files).unwrap(), expected, "order: {:?}", files.iter().map(|f| f.0).collect::<Vec<_>>());
+    }
+}
+
+fn write_crate(name: &str) -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("Cargo.toml"), format!("[package]\nname = \"{}\"\nversion = \"0.1.0\"\n", name)).unwrap();
+    fs::create_dir(dir.path().join("src")).unwrap();
+    for (path, src) in FILES {
+        fs::write(dir.path().join("src").join(path), src).unwrap();
+    }
+    dir
+}
+
+#[test]
+fn repeated_runs_are_byte_identical() {
+    let dir = write_crate("orders");
+    let generator = SchemaGenerator::new().input_dir(dir.path().join("src")).without_lockfile();
+    let first = generator.schema_text().unwrap();
+    assert_eq!(generator.schema_text().unwrap(), first);
+
+    // Another checkout of the same crate, e.g. on another machine
+    let other = write_crate("orders");
+    let again = SchemaGenerator::new().input_dir(other.path().join("src")).without_lockfile().schema_text().unwrap();
+    assert_eq!(again, first);
+}
+
+#[test]
+fn default_file_id_is_derived_from_the_package() {
+    let id = |name: &str| {
+        let dir = write_crate(name);
+        let schema = SchemaGenerator::new().input_dir(dir.path().join("src")).without_lockfile().schema_text().unwrap();
+        let id = schema.lines().next().unwrap().strip_prefix("@0x").unwrap().strip_suffix(';').unwrap().to_string();
+        u64::from_str_radix(&id, 16).unwrap()
+    };
+    assert_eq!(id("orders"), id("orders"));
+    assert_ne!(id("orders"), id("invoices"));
+    assert_ne!(id("orders") & 1 << 63, 0, "capnp file IDs need the high bit set");
+}
@@ -0,0 +1,62 @@
+//! Every way of generating a schema runs the same collection and rendering in this crate (the
+//! `#[capnp]` macros only tag items), so the same source must give byte-identical text through each.
+
+use capnez_codegen::testing::{schema_for_source, SOURCE_FILE_ID};
+use capnez_codegen::SchemaGenerator;
+use std::fs;
+use std::process::Command;
+
+const SOURCE: &str = r#"
+use capnez_macros::capnp;
+
+#[capnp]
+pub enum Status { Active, Suspended }
+
+#[capnp]
+pub struct Address {
+    street: String,
+    postcode: Option<u32>,
+}
+
+#[capnp]
+pub struct Account {
+    id: u64,
+    #[capnp(rename = "displayName")]
+    name: String,
+    status: Status,
+    addresses: Vec<Address>,
+    scores: Vec<Option<f64>>,
+    avatar: Vec<u8>,
+}
+
+#[capnp]
+pub trait Accounts {
+    fn get(id: u64) -> Option<Account>;
+    fn rename(id: u64, name: String);
+}
+"#;
+
+#[test]
+fn all_entry_points_emit_identical_schema_text() {
+    let dir = tempfile::tempdir().unwrap();
+    let src = dir.path().join("src");
+    fs::create_dir(&src).unwrap();
+    fs::write(src.join("lib.rs"), SOURCE).unwrap();
+
+    let generator = SchemaGenerator::new().input_dir(&src).file_id(SOURCE_FILE_ID).without_lockfile();
+    let builder = generator.schema_text().unwrap();
+    assert_eq!(generator.model().unwrap().to_capnp_text(), builder);
+    assert_eq!(schema_for_source(SOURCE).unwrap(), builder);
+
+    let written = dir.path().join("schema.capnp");
+    generator.write_to(&written, false).unwrap();
+    assert_eq!(fs::read_to_string(&written).unwrap(), builder);
+
+    let cli = Command::new(env!("CARGO_BIN_EXE_capnez-codegen"))
+        .arg("--input").arg(&src)
+        .args(["--stdout", "--no-lockfile", "--file-id", &format!("{:#x}", SOURCE_FILE_ID)])
+        .output()
+        .unwrap();
+    assert!(cli.status.success(), "{:?}", cli);
+    assert_eq!(String::from_utf8(cli.stdout).unwrap(), builder);
+}
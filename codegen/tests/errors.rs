@@ -0,0 +1,99 @@
+//! Each `CapnezError` variant, from an input that produces exactly that problem.
+
+use capnez_codegen::testing::{compile_schema, schema_for_source, schema_for_sources};
+use capnez_codegen::CapnezError;
+use std::path::Path;
+
+fn error(src: &str) -> CapnezError {
+    schema_for_source(src).unwrap_err().downcast::<CapnezError>().unwrap()
+}
+
+#[test]
+fn unsupported_type() {
+    match error("#[capnp]\nstruct Order { id: u64, placed: std::time::Instant }") {
+        CapnezError::UnsupportedType { file, struct_name, field, ty, .. } => {
+            assert_eq!(file, Path::new("src/lib.rs"));
+            assert_eq!((struct_name.as_str(), field.as_str()), ("Order", "placed"));
+            assert!(ty.contains("Instant"), "{}", ty);
+        }
+        err => panic!("expected UnsupportedType, got: {}", err),
+    }
+}
+
+#[test]
+fn unnamed_fields() {
+    match error("#[capnp]\nstruct Pair(u32, u32);") {
+        CapnezError::UnnamedFields { file, struct_name } => {
+            assert_eq!(file, Path::new("src/lib.rs"));
+            assert_eq!(struct_name, "Pair");
+        }
+        err => panic!("expected UnnamedFields, got: {}", err),
+    }
+}
+
+#[test]
+fn circular_dependency() {
+    match error("#[capnp]\nstruct Parent { child: Child }\n#[capnp]\nstruct Child { parent: Parent }") {
+        CapnezError::CircularDependency { cycle } => {
+            assert_eq!(cycle.len(), 3, "{:?}", cycle);
+            assert_eq!(cycle.first(), cycle.last());
+            assert!(cycle.contains(&"Parent".to_string()) && cycle.contains(&"Child".to_string()), "{:?}", cycle);
+        }
+        err => panic!("expected CircularDependency, got: {}", err),
+    }
+}
+
+#[test]
+fn duplicate_name() {
+    let err = schema_for_sources(&[
+        ("a.rs", "#[capnp]\nstruct Item { id: u64 }"),
+        ("b.rs", "#[capnp]\nstruct Item { name: String }"),
+    ]).unwrap_err();
+    match err.downcast::<CapnezError>().unwrap() {
+        CapnezError::DuplicateName { name, first, second } => {
+            assert_eq!(name, "Item");
+            assert!(first.contains("src/a.rs") && second.contains("src/b.rs"), "{} / {}", first, second);
+        }
+        err => panic!("expected DuplicateName, got: {}", err),
+    }
+}
+
+#[test]
+fn invalid_attribute() {
+    match error("#[capnp]\nstruct Order { #[capnp(serde_with = \"yaml\")] notes: Vec<String> }") {
+        CapnezError::InvalidAttribute { file, item, message } => {
+            assert_eq!(file, Path::new("src/lib.rs"));
+            assert_eq!(item, "Order::notes");
+            assert!(message.contains("yaml"), "{}", message);
+        }
+        err => panic!("expected InvalidAttribute, got: {}", err),
+    }
+}
+
+#[test]
+fn capnpc_failed() {
+    // A field type capnpc has never heard of
+    let err = compile_schema("@0xbf5147bb3b06fa3d;\nstruct Order {\n  total @0 :Money;\n}\n").unwrap_err();
+    match err.downcast::<CapnezError>().unwrap() {
+        CapnezError::CapnpcFailed { schema, message } => {
+            assert_eq!(schema.file_name().unwrap(), "schema.capnp");
+            assert!(!message.is_empty());
+        }
+        err => panic!("expected CapnpcFailed, got: {}", err),
+    }
+}
+
+#[test]
+fn multiple() {
+    let src = "#[capnp]\nstruct Pair(u32, u32);\n#[capnp]\nstruct Invoice { #[capnp(serde_with = \"yaml\")] notes: Vec<String> }";
+    match error(src) {
+        CapnezError::Multiple(errors) => {
+            assert_eq!(errors.len(), 2, "{:?}", errors.iter().map(ToString::to_string).collect::<Vec<_>>());
+            assert!(errors.iter().any(|e| matches!(e, CapnezError::InvalidAttribute { .. })));
+            assert!(errors.iter().any(|e| matches!(e, CapnezError::UnnamedFields { .. })));
+            // Nested errors are flattened into one list
+            assert!(!errors.iter().any(|e| matches!(e, CapnezError::Multiple(_))));
+        }
+        err => panic!("expected Multiple, got: {}", err),
+    }
+}
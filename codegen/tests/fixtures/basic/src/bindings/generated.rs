@@ -0,0 +1,7 @@
+// Stands in for generated code that must be kept out of the schema with an exclude glob
+use capnez_macros::capnp;
+
+#[capnp]
+pub struct Generated {
+    raw: u64,
+}
@@ -0,0 +1,21 @@
+use capnez_macros::capnp;
+
+#[capnp]
+pub enum Role {
+    Admin,
+    Member,
+}
+
+#[capnp]
+pub struct Address {
+    street: String,
+    postcode: Option<u32>,
+}
+
+#[capnp]
+pub struct User {
+    name: String,
+    role: Role,
+    address: Address,
+    tags: Vec<String>,
+}
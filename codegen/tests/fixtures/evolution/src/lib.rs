@@ -0,0 +1,15 @@
+// `Person` as of the saved capnez.lock, with `email` since inserted in the middle
+use capnez_macros::capnp;
+
+#[capnp]
+pub enum Color {
+    Red,
+    Green,
+}
+
+#[capnp]
+pub struct Person {
+    name: String,
+    email: String,
+    age: u32,
+}
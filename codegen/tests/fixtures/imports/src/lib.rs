@@ -0,0 +1,12 @@
+use capnez_macros::capnp;
+
+#[capnp(external = "common.capnp", name = "Address")]
+pub struct Address;
+
+#[capnp]
+pub struct Customer {
+    name: String,
+    home: Address,
+    #[capnp(external = "common.capnp", name = "GeoPoint")]
+    location: Option<GeoPoint>,
+}
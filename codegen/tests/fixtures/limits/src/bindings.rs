@@ -0,0 +1,17 @@
+// Stands in for generated code annotated by accident: it alone pushes the crate over `max_structs`
+use capnez_macros::capnp;
+
+#[capnp]
+pub struct Binding0 {
+    raw: u64,
+}
+
+#[capnp]
+pub struct Binding1 {
+    raw: u64,
+}
+
+#[capnp]
+pub struct Binding2 {
+    raw: u64,
+}
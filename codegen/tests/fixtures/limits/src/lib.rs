@@ -0,0 +1,6 @@
+use capnez_macros::capnp;
+
+#[capnp]
+pub struct Config {
+    name: String,
+}
@@ -0,0 +1,9 @@
+use capnez_macros::capnp;
+
+#[capnp(external = "shared/money.capnp", name = "Money")]
+pub struct Money;
+
+#[capnp]
+pub struct Invoice {
+    total: Money,
+}
@@ -0,0 +1,23 @@
+use capnez_macros::capnp;
+
+#[capnp]
+pub enum Priority {
+    Low,
+    High,
+}
+
+#[capnp]
+pub struct Task {
+    title: String,
+    priority: Priority,
+    #[capnp(default = 3)]
+    retries: u32,
+    due: Option<u64>,
+    window: [u16; 2],
+}
+
+#[capnp]
+pub trait Scheduler {
+    fn submit(&self, task: Task) -> u64;
+    fn cancel(&self, id: u64);
+}
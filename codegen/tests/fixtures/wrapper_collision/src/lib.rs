@@ -0,0 +1,12 @@
+use capnez_macros::capnp;
+
+// Collides with the wrapper synthesized for `Option<String>`
+#[capnp]
+pub struct OptionalText {
+    value: String,
+}
+
+#[capnp]
+pub struct Profile {
+    nickname: Option<String>,
+}
@@ -0,0 +1,64 @@
+//! `#[capnp(flatten)]`: the spliced schema fields, nesting, and clashing names.
+
+use capnez_codegen::testing::schema_for_source;
+use capnez_codegen::CapnezError;
+
+const METADATA: &str = "#[capnp]\nstruct Metadata { created_at: i64, created_by: String }\n";
+
+fn struct_body<'a>(schema: &'a str, name: &str) -> &'a str {
+    let start = schema.find(&format!("struct {} {{", name)).unwrap_or_else(|| panic!("no struct {} in:\n{}", name, schema));
+    let body = &schema[start..];
+    &body[..body.find("\n}").unwrap() + 2]
+}
+
+#[test]
+fn fields_are_spliced_in_place_with_a_prefix() {
+    let src = format!("{}#[capnp]\nstruct Document {{ title: String, #[capnp(flatten, prefix = \"meta\")] meta: Metadata, body: String }}", METADATA);
+    let schema = schema_for_source(&src).unwrap();
+    assert_eq!(
+        struct_body(&schema, "Document"),
+        "struct Document {\n  title @0 :Text;\n  metaCreatedAt @1 :Int64;\n  metaCreatedBy @2 :Text;\n  body @3 :Text;\n}",
+    );
+    // The child stays a struct of its own
+    assert_eq!(struct_body(&schema, "Metadata"), "struct Metadata {\n  createdAt @0 :Int64;\n  createdBy @1 :Text;\n}");
+}
+
+#[test]
+fn without_a_prefix_the_names_are_kept() {
+    let src = format!("{}#[capnp]\nstruct Note {{ #[capnp(flatten)] meta: Metadata, text: String }}", METADATA);
+    let schema = schema_for_source(&src).unwrap();
+    assert_eq!(struct_body(&schema, "Note"), "struct Note {\n  createdAt @0 :Int64;\n  createdBy @1 :Text;\n  text @2 :Text;\n}");
+}
+
+#[test]
+fn a_flattened_struct_may_flatten_its_own_fields() {
+    let src = "#[capnp]\nstruct Audit { at: i64, by: String }\n\
+               #[capnp]\nstruct Metadata { version: u32, #[capnp(flatten, prefix = \"audit\")] audit: Audit }\n\
+               #[capnp]\nstruct Document { title: String, #[capnp(flatten, prefix = \"meta\")] meta: Metadata, body: String }";
+    let schema = schema_for_source(src).unwrap();
+    assert_eq!(
+        struct_body(&schema, "Metadata"),
+        "struct Metadata {\n  version @0 :UInt32;\n  auditAt @1 :Int64;\n  auditBy @2 :Text;\n}",
+    );
+    assert_eq!(
+        struct_body(&schema, "Document"),
+        "struct Document {\n  title @0 :Text;\n  metaVersion @1 :UInt32;\n  metaAuditAt @2 :Int64;\n  metaAuditBy @3 :Text;\n  body @4 :Text;\n}",
+    );
+}
+
+#[test]
+fn a_clashing_name_is_a_build_error() {
+    let src = format!("{}#[capnp]\nstruct Document {{ created_by: String, #[capnp(flatten)] meta: Metadata }}", METADATA);
+    match schema_for_source(&src).unwrap_err().downcast::<CapnezError>().unwrap() {
+        CapnezError::InvalidAttribute { item, message, .. } => {
+            assert_eq!(item, "Document::meta");
+            assert!(message.contains("gives `Document` two fields named `createdBy`"), "{}", message);
+        }
+        err => panic!("expected InvalidAttribute, got: {}", err),
+    }
+
+    // A prefix resolves it
+    let src = src.replace("#[capnp(flatten)]", "#[capnp(flatten, prefix = \"meta\")]");
+    let schema = schema_for_source(&src).unwrap();
+    assert!(schema.contains("createdBy @0 :Text;") && schema.contains("metaCreatedBy @2 :Text;"), "{}", schema);
+}
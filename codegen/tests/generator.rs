@@ -0,0 +1,91 @@
+//! Drives `SchemaGenerator` against the fixture crates under `tests/fixtures`, with every path given
+//! explicitly so no Cargo environment variable is read.
+
+use capnez_codegen::{CapnezError, SchemaGenerator};
+use std::path::PathBuf;
+
+const FILE_ID: u64 = 0xd0b6_8c8e_2f4a_9b31;
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name).join("src")
+}
+
+fn generator(name: &str) -> SchemaGenerator {
+    SchemaGenerator::new().input_dir(fixture(name)).file_id(FILE_ID).without_lockfile()
+}
+
+#[test]
+fn collects_every_annotated_item() {
+    let schema = generator("basic").schema_text().unwrap();
+    assert!(schema.starts_with("@0xd0b68c8e2f4a9b31;"), "{}", schema);
+    for name in ["enum Role", "struct Address", "struct User", "struct Generated", "struct OptionalUInt32"] {
+        assert!(schema.contains(name), "missing `{}` in:\n{}", name, schema);
+    }
+}
+
+#[test]
+fn exclude_glob_skips_matching_files() {
+    let schema = generator("basic").exclude_glob("bindings/**").schema_text().unwrap();
+    assert!(!schema.contains("Generated"), "{}", schema);
+    assert!(schema.contains("struct User"), "{}", schema);
+}
+
+#[test]
+fn write_to_without_compiling() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("schema.capnp");
+    let generator = generator("basic");
+    generator.write_to(&path, false).unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), generator.schema_text().unwrap());
+}
+
+#[test]
+fn run_writes_schema_and_rust_to_output_dir() {
+    let dir = tempfile::tempdir().unwrap();
+    generator("basic").output_dir(dir.path()).run().unwrap();
+    assert!(dir.path().join("schema.capnp").exists());
+    assert!(dir.path().join("schema_capnp.rs").exists());
+}
+
+#[test]
+fn wrapper_colliding_with_a_struct_is_an_error() {
+    let err = generator("wrapper_collision").schema_text().unwrap_err();
+    match err.downcast_ref::<CapnezError>() {
+        Some(CapnezError::DuplicateName { name, first, second }) => {
+            assert_eq!(name, "OptionalText");
+            assert!(first.contains("OptionalText"), "{}", first);
+            assert!(second.contains("wrapper"), "{}", second);
+        }
+        _ => panic!("expected a DuplicateName error, got: {}", err),
+    }
+}
+
+#[test]
+fn imported_schemas_are_referenced_and_compiled_alongside() {
+    let schema = generator("imports").schema_text().unwrap();
+    assert!(schema.contains("using Address = import \"/common.capnp\".Address;"), "{}", schema);
+    assert!(schema.contains("using GeoPoint = import \"/common.capnp\".GeoPoint;"), "{}", schema);
+    assert!(schema.contains("home @1 :Address;"), "{}", schema);
+    assert!(!schema.contains("struct Address"), "{}", schema);
+
+    let dir = tempfile::tempdir().unwrap();
+    generator("imports").output_dir(dir.path()).run().unwrap();
+    let imports = std::fs::read_to_string(dir.path().join("imports.rs")).unwrap();
+    assert!(imports.contains("pub mod common_capnp"), "{}", imports);
+    assert!(dir.path().join("common_capnp.rs").exists());
+    assert!(dir.path().join("schema_capnp.rs").exists());
+}
+
+#[test]
+fn missing_import_lists_every_directory_searched() {
+    let extra = tempfile::tempdir().unwrap();
+    let out = tempfile::tempdir().unwrap();
+    let err = generator("missing_import").import_path(extra.path()).output_dir(out.path()).run().unwrap_err().to_string();
+    assert!(err.contains("`shared/money.capnp` not found"), "{}", err);
+    let crate_root = fixture("missing_import").parent().unwrap().to_path_buf();
+    // Added directories first, then the crate root, then `src`
+    let searched = [extra.path().to_path_buf(), crate_root, fixture("missing_import")]
+        .map(|dir| err.find(&format!("  {}\n", dir.display())).unwrap_or_else(|| panic!("{} not listed in:\n{}", dir.display(), err)));
+    assert!(searched.is_sorted(), "{}", err);
+    assert!(err.contains("import_path"), "{}", err);
+}
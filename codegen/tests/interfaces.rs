@@ -0,0 +1,114 @@
+//! Interfaces: supertraits as inheritance, capability fields, and method results.
+
+use capnez_codegen::testing::{compile_schema, schema_for_source};
+use std::process::Command;
+
+#[test]
+fn a_chain_of_supertraits_extends_level_by_level() {
+    let schema = schema_for_source(
+        "#[capnp]\nstruct Ping;\n#[capnp]\nstruct Count { value: u64 }\n\
+         #[capnp]\ntrait Health { fn ping(request: Ping) -> Ping; }\n\
+         #[capnp]\ntrait Reader: Health { fn count(request: Ping) -> Count; }\n\
+         #[capnp]\ntrait Writer: Reader + Send + Sync { fn reset(request: Ping) -> Count; }",
+    ).unwrap();
+    assert!(schema.contains("interface Health {"), "{}", schema);
+    assert!(schema.contains("interface Reader extends(Health) {"), "{}", schema);
+    // Auto traits are dropped, and only the direct parent is named
+    assert!(schema.contains("interface Writer extends(Reader) {"), "{}", schema);
+    // Each interface numbers only its own methods
+    assert!(schema.contains("ping @0 ("), "{}", schema);
+    assert!(schema.contains("count @0 ("), "{}", schema);
+    assert!(schema.contains("reset @0 ("), "{}", schema);
+    compile_schema(&schema).unwrap();
+}
+
+#[test]
+fn several_supertraits_are_all_extended() {
+    let schema = schema_for_source(
+        "#[capnp]\nstruct Ping;\n\
+         #[capnp]\ntrait User { fn whoami(request: Ping) -> Ping; }\n\
+         #[capnp]\ntrait Auditor { fn audit(request: Ping) -> Ping; }\n\
+         #[capnp]\ntrait Admin: User + Auditor { fn purge(request: Ping) -> Ping; }",
+    ).unwrap();
+    assert!(schema.contains("interface Admin extends(User, Auditor) {"), "{}", schema);
+    compile_schema(&schema).unwrap();
+}
+
+#[test]
+fn a_supertrait_that_is_not_annotated_is_an_error() {
+    let err = schema_for_source("#[capnp]\nstruct Ping;\ntrait Plain {}\n#[capnp]\ntrait Child: Plain { fn ping(request: Ping) -> Ping; }").unwrap_err();
+    assert!(err.to_string().contains("`extends` of `Child` has unsupported type `Plain`: supertraits must be #[capnp] traits too"), "{}", err);
+}
+
+const CALLBACK: &str = r#"
+#[capnp]
+struct Event { topic: String }
+
+#[capnp]
+struct Ack;
+
+#[capnp]
+trait Listener {
+    fn notify(event: Event) -> Ack;
+}
+
+#[capnp]
+struct Subscription {
+    topic: String,
+    listener: Box<dyn Listener>,
+    backup: Option<std::sync::Arc<dyn Listener + Send + Sync>>,
+}
+
+#[capnp]
+trait Broker {
+    fn subscribe(subscription: Subscription) -> Ack;
+}
+"#;
+
+#[test]
+fn a_callback_field_is_a_capability() {
+    let schema = schema_for_source(CALLBACK).unwrap();
+    assert!(schema.contains("listener @1 :Listener;"), "{}", schema);
+    assert!(schema.contains("value @0 :Listener;"), "{}", schema);
+    assert!(schema.contains("subscribe @0 (subscription :Subscription) -> Ack;"), "{}", schema);
+    compile_schema(&schema).unwrap();
+}
+
+#[test]
+fn a_callback_field_takes_no_serde_bytes_fallback() {
+    let dir = tempfile::tempdir().unwrap();
+    let out_dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("lib.rs"), CALLBACK).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_capnez-codegen"))
+        .arg("--input").arg(dir.path())
+        .args(["--no-lockfile", "--stdout"])
+        .env("OUT_DIR", out_dir.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(!stdout.contains("cargo:warning="), "{}", stdout);
+    assert!(!stdout.contains("List(UInt8)"), "{}", stdout);
+}
+
+#[test]
+fn non_struct_results_are_wrapped_in_a_result_list() {
+    let schema = schema_for_source(
+        "#[capnp]\nstruct Query { prefix: String }\n#[capnp]\nstruct Summary { total: u64 }\n\
+         #[capnp]\ntrait Index {\n\
+             fn count(query: Query) -> u64;\n\
+             fn keys(query: Query) -> Vec<String>;\n\
+             fn contains(query: Query) -> bool;\n\
+             #[capnp(result = \"sum\")]\n\
+             fn total(query: Query) -> u64;\n\
+             fn summary(query: Query) -> Summary;\n\
+         }",
+    ).unwrap();
+    assert!(schema.contains("count @0 (query :Query) -> (result :UInt64);"), "{}", schema);
+    assert!(schema.contains("keys @1 (query :Query) -> (result :List(Text));"), "{}", schema);
+    assert!(schema.contains("contains @2 (query :Query) -> (result :Bool);"), "{}", schema);
+    assert!(schema.contains("total @3 (query :Query) -> (sum :UInt64);"), "{}", schema);
+    // A struct stands for the results itself
+    assert!(schema.contains("summary @4 (query :Query) -> Summary;"), "{}", schema);
+    compile_schema(&schema).unwrap();
+}
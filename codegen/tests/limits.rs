@@ -0,0 +1,60 @@
+//! The limits fixture sets `max_structs = 3` in its `capnez.toml` and declares four structs, three of
+//! them in `bindings.rs`.
+
+use capnez_codegen::{Limits, SchemaGenerator};
+use std::path::PathBuf;
+
+fn fixture() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/limits/src")
+}
+
+fn generator() -> SchemaGenerator {
+    SchemaGenerator::new().input_dir(fixture()).file_id(0xd0b6_8c8e_2f4a_9b31).without_lockfile()
+}
+
+#[test]
+fn configured_limit_names_the_offending_file() {
+    let err = generator().schema_text().unwrap_err().to_string();
+    assert!(err.contains("Collected 4 structs, exceeding the limit of 3"), "{}", err);
+    let files = err.lines().skip_while(|line| !line.starts_with("Files contributing")).skip(1).collect::<Vec<_>>();
+    assert!(files[0].contains("bindings.rs (3 items)"), "{}", err);
+    assert!(err.contains("exclude_glob"), "{}", err);
+}
+
+#[test]
+fn excluding_the_offending_file_stays_under_the_limit() {
+    let schema = generator().exclude_glob("bindings.rs").schema_text().unwrap();
+    assert!(schema.contains("struct Config"), "{}", schema);
+}
+
+#[test]
+fn explicit_limits_override_capnez_toml() {
+    let limits = Limits { max_structs: 4, ..Limits::default() };
+    generator().limits(limits).schema_text().unwrap();
+}
+
+#[test]
+fn field_limit_names_the_struct_and_file() {
+    let limits = Limits { max_fields_per_struct: 0, ..Limits::default() };
+    let err = generator().limits(limits).schema_text().unwrap_err().to_string();
+    assert!(err.contains("has 1 fields, exceeding the limit of 0"), "{}", err);
+    assert!(err.contains(".rs"), "{}", err);
+}
+
+#[test]
+fn inspect_reports_counts_against_the_configured_limits() {
+    let inspection = generator().inspect().unwrap();
+    assert_eq!(inspection.structs, 4);
+    assert_eq!(inspection.limits.max_structs, 3);
+    assert_eq!(inspection.limits.max_fields_per_struct, 4);
+    assert!(inspection.to_string().contains("structs:           4 / 3"), "{}", inspection);
+}
+
+#[test]
+fn unknown_limit_is_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("capnez.toml");
+    std::fs::write(&config, "[limits]\nmax_everything = 1\n").unwrap();
+    let err = Limits::from_config(&config).unwrap_err().to_string();
+    assert!(err.contains("Unknown limit `max_everything`"), "{}", err);
+}
@@ -0,0 +1,94 @@
+//! Numbering checks against `capnez.lock`. None of these set `CAPNEZ_ACCEPT_SCHEMA_CHANGES`, which
+//! would leak into the other tests of this binary.
+
+use capnez_codegen::SchemaGenerator;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+const V1: &str = r#"
+#[capnp]
+pub enum Color { Red, Green }
+
+#[capnp]
+pub struct Person { name: String, age: u32 }
+"#;
+
+fn generator(src: &Path) -> SchemaGenerator {
+    SchemaGenerator::new().input_dir(src).file_id(0xd0b6_8c8e_2f4a_9b31)
+}
+
+/// A crate whose `capnez.lock` was written by generating `V1`.
+fn locked_crate() -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    let src = dir.path().join("src");
+    fs::create_dir(&src).unwrap();
+    edit(&dir, V1);
+    generator(&src).write_to(&dir.path().join("schema.capnp"), false).unwrap();
+    assert!(dir.path().join("capnez.lock").exists());
+    dir
+}
+
+fn edit(dir: &TempDir, src: &str) {
+    fs::write(dir.path().join("src/lib.rs"), src).unwrap();
+}
+
+fn check(dir: &TempDir) -> anyhow::Result<String> {
+    generator(&dir.path().join("src")).schema_text()
+}
+
+#[test]
+fn insert_in_the_middle_against_the_saved_lockfile_fails() {
+    let src = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/evolution/src");
+    let err = generator(&src).schema_text().unwrap_err().to_string();
+    assert!(err.contains("field `age` of `Person` moved from @1 to @2"), "{}", err);
+    assert!(err.contains("reuses @1, which previously belonged to `age`"), "{}", err);
+    assert!(err.contains("CAPNEZ_ACCEPT_SCHEMA_CHANGES=1"), "{}", err);
+    assert!(!err.contains("Color"), "{}", err);
+}
+
+#[test]
+fn insert_in_the_middle_fails() {
+    let dir = locked_crate();
+    edit(&dir, &V1.replace("name: String, age: u32", "name: String, email: String, age: u32"));
+    let err = check(&dir).unwrap_err().to_string();
+    assert!(err.contains("field `age` of `Person` moved from @1 to @2"), "{}", err);
+}
+
+#[test]
+fn trailing_additions_are_accepted_and_recorded() {
+    let dir = locked_crate();
+    edit(&dir, &V1.replace("age: u32", "age: u32, email: String").replace("Red, Green", "Red, Green, Blue"));
+    generator(&dir.path().join("src")).write_to(&dir.path().join("schema.capnp"), false).unwrap();
+    let lock = fs::read_to_string(dir.path().join("capnez.lock")).unwrap();
+    assert!(lock.contains("email = { id = 2, type = \"Text\" }"), "{}", lock);
+    assert!(lock.contains("blue = 2"), "{}", lock);
+}
+
+#[test]
+fn type_change_fails() {
+    let dir = locked_crate();
+    edit(&dir, &V1.replace("age: u32", "age: u64"));
+    let err = check(&dir).unwrap_err().to_string();
+    assert!(err.contains("field `age` of `Person` changed type from UInt32 to UInt64"), "{}", err);
+}
+
+#[test]
+fn reusing_a_removed_ordinal_fails() {
+    let dir = locked_crate();
+    edit(&dir, &V1.replace(", age: u32", ""));
+    generator(&dir.path().join("src")).write_to(&dir.path().join("schema.capnp"), false).unwrap();
+    assert!(fs::read_to_string(dir.path().join("capnez.lock")).unwrap().contains("retired = [1]"));
+
+    edit(&dir, &V1.replace("age: u32", "nickname: String"));
+    let err = check(&dir).unwrap_err().to_string();
+    assert!(err.contains("field `nickname` of `Person` reuses @1, which previously belonged to a removed entry"), "{}", err);
+}
+
+#[test]
+fn reordering_enumerants_fails() {
+    let dir = locked_crate();
+    edit(&dir, &V1.replace("Red, Green", "Green, Red"));
+    let err = check(&dir).unwrap_err().to_string();
+    assert!(err.contains("enumerant `red` of `Color` moved from @0 to @1"), "{}", err);
+}
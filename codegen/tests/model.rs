@@ -0,0 +1,30 @@
+//! `collect_model` on a fixture tree: its JSON, and its text against what `generate_schema` writes.
+
+use capnez_codegen::{collect_model, SchemaGenerator, SchemaModel};
+use std::path::PathBuf;
+
+fn fixture() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/model/src")
+}
+
+#[test]
+fn json_of_a_fixture_tree() {
+    let model = collect_model(&fixture()).unwrap();
+    let expected = include_str!("fixtures/model/model.json");
+    assert_eq!(model.to_json(), expected.trim_end());
+
+    // And it reads back as the same model
+    assert_eq!(serde_json::from_str::<SchemaModel>(expected).unwrap(), model);
+}
+
+#[test]
+fn generate_schema_writes_the_model_text() {
+    // What `generate_schema()` runs, pointed at the fixture instead of this crate's `src`
+    let out = tempfile::tempdir().unwrap();
+    SchemaGenerator::new().input_dir(fixture()).without_lockfile().output_dir(out.path()).run().unwrap();
+    let written = std::fs::read_to_string(out.path().join("schema.capnp")).unwrap();
+
+    let model = collect_model(&fixture()).unwrap();
+    assert_eq!(written, model.to_capnp_text());
+    assert_eq!(SchemaGenerator::new().input_dir(fixture()).without_lockfile().schema_text().unwrap(), written);
+}
@@ -0,0 +1,52 @@
+use capnez_codegen::testing::schema_for_source;
+use capnez_codegen::CapnezError;
+
+fn duplicate(src: &str) -> (String, String, String) {
+    let err = schema_for_source(src).unwrap_err();
+    let err = err.downcast::<CapnezError>().unwrap();
+    let err = match err {
+        CapnezError::Multiple(mut errors) if errors.len() == 1 => errors.remove(0),
+        err => err,
+    };
+    match err {
+        CapnezError::DuplicateName { name, first, second } => (name, first, second),
+        err => panic!("expected a DuplicateName error, got: {}", err),
+    }
+}
+
+#[test]
+fn types_colliding_after_conversion_are_an_error() {
+    let (name, first, second) = duplicate(r#"
+        #[capnp]
+        struct Sha256Hash { bytes: Vec<u8> }
+
+        #[capnp]
+        #[allow(non_camel_case_types)]
+        struct sha256_hash { bytes: Vec<u8> }
+    "#);
+    assert_eq!(name, "Sha256Hash");
+    assert!(first.contains("`Sha256Hash`") && second.contains("`sha256_hash`"), "{} / {}", first, second);
+}
+
+#[test]
+fn fields_colliding_after_conversion_are_an_error() {
+    let (name, _, _) = duplicate(r#"
+        #[capnp]
+        struct Digest { sha256_hash: Vec<u8>, sha256Hash: Vec<u8> }
+    "#);
+    assert_eq!(name, "sha256Hash");
+}
+
+#[test]
+fn rename_resolves_a_collision() {
+    let schema = schema_for_source(r#"
+        #[capnp]
+        struct Sha256Hash { bytes: Vec<u8> }
+
+        #[capnp]
+        #[capnp(rename = "LegacySha256Hash")]
+        struct sha256_hash { bytes: Vec<u8> }
+    "#).unwrap();
+    assert!(schema.contains("struct Sha256Hash "), "{}", schema);
+    assert!(schema.contains("struct LegacySha256Hash "), "{}", schema);
+}
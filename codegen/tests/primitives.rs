@@ -0,0 +1,44 @@
+//! How Rust scalar, float and byte types appear in the schema text.
+
+use capnez_codegen::testing::{compile_schema, schema_for_source};
+
+/// The schema line of the single field `value: <ty>`.
+fn field_line(ty: &str) -> String {
+    let schema = schema_for_source(&format!("#[capnp]\nstruct Sample<'a> {{\n    value: {},\n}}", ty)).unwrap();
+    schema.lines().find(|line| line.trim_start().starts_with("value @0")).unwrap().trim().to_string()
+}
+
+#[test]
+fn floats() {
+    assert_eq!(field_line("f32"), "value @0 :Float32;");
+    assert_eq!(field_line("f64"), "value @0 :Float64;");
+    assert_eq!(field_line("Vec<f32>"), "value @0 :List(Float32);");
+    assert_eq!(field_line("Vec<f64>"), "value @0 :List(Float64);");
+}
+
+#[test]
+fn bytes_are_data() {
+    assert_eq!(field_line("Vec<u8>"), "value @0 :Data;");
+    assert_eq!(field_line("&'a [u8]"), "value @0 :Data;");
+    assert_eq!(field_line("Vec<Vec<u8>>"), "value @0 :List(Data);");
+    // Other byte containers stay lists of bytes
+    assert_eq!(field_line("[u8; 4]"), "value @0 :List(UInt8);");
+    assert_eq!(field_line("Vec<u16>"), "value @0 :List(UInt16);");
+}
+
+#[test]
+fn optional_floats_and_data_get_wrappers() {
+    let schema = schema_for_source("#[capnp]\nstruct Sample { gain: Option<f32>, offset: Option<f64>, blob: Option<Vec<u8>> }").unwrap();
+    for wrapper in ["OptionalFloat32", "OptionalFloat64", "OptionalData"] {
+        assert!(schema.contains(&format!("struct {} {{", wrapper)), "{}", schema);
+    }
+    assert!(schema.contains("value @0 :Float32;") && schema.contains("value @0 :Float64;") && schema.contains("value @0 :Data;"), "{}", schema);
+    compile_schema(&schema).unwrap();
+}
+
+#[test]
+fn integers() {
+    for (rust, capnp) in [("i8", "Int8"), ("i16", "Int16"), ("i32", "Int32"), ("i64", "Int64"), ("u8", "UInt8"), ("u16", "UInt16"), ("u32", "UInt32"), ("u64", "UInt64"), ("bool", "Bool")] {
+        assert_eq!(field_line(rust), format!("value @0 :{};", capnp));
+    }
+}
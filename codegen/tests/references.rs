@@ -0,0 +1,104 @@
+//! Fields referring to types that are not `#[capnp]`: an undefined type fails generation, and a
+//! serde-only one falls back to opaque bytes with a warning.
+
+use capnez_codegen::testing::schema_for_sources;
+use capnez_codegen::CapnezError;
+use std::path::Path;
+use std::process::{Command, Output};
+
+#[test]
+fn an_undefined_type_is_an_error_naming_it_and_its_file() {
+    let err = schema_for_sources(&[
+        ("lib.rs", "#[capnp]\nstruct Customer { name: String }"),
+        ("orders.rs", "#[capnp]\nstruct Order { customer: Customer, total: Money }"),
+    ]).unwrap_err();
+    let message = err.to_string();
+    match err.downcast::<CapnezError>().unwrap() {
+        CapnezError::UnsupportedType { file, struct_name, field, ty, .. } => {
+            assert_eq!(file, Path::new("src/orders.rs"));
+            assert_eq!((struct_name.as_str(), field.as_str(), ty.as_str()), ("Order", "total", "Money"));
+        }
+        err => panic!("expected an UnsupportedType error, got: {}", err),
+    }
+    assert!(message.contains("`Money`") && message.contains("src/orders.rs"), "{}", message);
+}
+
+#[test]
+fn an_undefined_type_in_a_list_or_option_is_an_error_too() {
+    for ty in ["Vec<Money>", "Option<Money>", "Vec<Option<Money>>"] {
+        let src = format!("#[capnp]\nstruct Order {{ total: {} }}", ty);
+        let err = schema_for_sources(&[("lib.rs", &src)]).unwrap_err();
+        match err.downcast::<CapnezError>().unwrap() {
+            CapnezError::UnsupportedType { ty: name, .. } => assert_eq!(name, "Money", "{}", ty),
+            err => panic!("expected an UnsupportedType error for {}, got: {}", ty, err),
+        }
+    }
+}
+
+const SERDE_ONLY: &str = r#"
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+struct Information {
+    major: String,
+}
+
+#[capnp]
+struct Student {
+    name: String,
+    information: Information,
+}
+"#;
+
+/// Runs `capnez-codegen --stdout` on [`SERDE_ONLY`], as a build script when `out_dir` is set.
+fn generate(out_dir: Option<&Path>) -> Output {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("lib.rs"), SERDE_ONLY).unwrap();
+    let mut command = Command::new(env!("CARGO_BIN_EXE_capnez-codegen"));
+    command.arg("--input").arg(dir.path())
+        .args(["--no-lockfile", "--file-id", "0xd0b68c8e2f4a9b31", "--stdout"])
+        .env_remove("OUT_DIR");
+    if let Some(out_dir) = out_dir {
+        command.env("OUT_DIR", out_dir);
+    }
+    command.output().unwrap()
+}
+
+#[test]
+fn the_serde_bytes_fallback_is_a_cargo_warning_in_build_scripts() {
+    let out_dir = tempfile::tempdir().unwrap();
+    let output = generate(Some(out_dir.path()));
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let warning = stdout.lines().find(|line| line.starts_with("cargo:warning=")).expect(&stdout);
+    assert!(
+        warning.contains("field `information` of `Student` stores `Information` as opaque serde bytes"),
+        "{}", warning
+    );
+    assert!(stdout.contains("information @1 :List(UInt8);"), "{}", stdout);
+}
+
+#[test]
+fn the_serde_bytes_fallback_goes_to_stderr_elsewhere() {
+    let output = generate(None);
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(!stdout.contains("cargo:warning"), "{}", stdout);
+    assert!(stdout.starts_with("@0xd0b68c8e2f4a9b31;"), "{}", stdout);
+    assert!(stderr.contains("warning: field `information` of `Student` stores `Information`"), "{}", stderr);
+}
+
+#[test]
+fn annotated_types_take_no_fallback() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("lib.rs"), "#[capnp]\nstruct Information { major: String }\n#[capnp]\nstruct Student { information: Information }").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_capnez-codegen"))
+        .arg("--input").arg(dir.path())
+        .args(["--no-lockfile", "--stdout"])
+        .env("OUT_DIR", dir.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert!(!String::from_utf8(output.stdout).unwrap().contains("cargo:warning"));
+}
@@ -0,0 +1,96 @@
+//! Property tests over randomly shaped structs: nested structs, lists, optionals and enums in any
+//! combination must produce a schema with every field numbered in order, which capnpc accepts.
+
+use capnez_codegen::testing::{compile_schema, schema_for_source};
+use proptest::prelude::*;
+
+#[derive(Clone, Debug)]
+enum Ty {
+    Scalar(&'static str),
+    Enum,
+    /// Refers to an earlier struct, so shapes never contain cycles.
+    Struct(usize),
+    List(Box<Ty>),
+    Optional(Box<Ty>),
+}
+
+impl Ty {
+    /// Rust spelling inside struct `owner`; a reference to a struct that does not precede `owner`
+    /// falls back to a scalar.
+    fn rust(&self, owner: usize) -> String {
+        match self {
+            Ty::Scalar(name) => name.to_string(),
+            Ty::Enum => "Color".to_string(),
+            Ty::Struct(_) if owner == 0 => "u32".to_string(),
+            Ty::Struct(i) => format!("Shape{}", i % owner),
+            Ty::List(inner) => format!("Vec<{}>", inner.rust(owner)),
+            Ty::Optional(inner) => format!("Option<{}>", inner.rust(owner)),
+        }
+    }
+}
+
+fn ty() -> impl Strategy<Value = Ty> {
+    let leaf = prop_oneof![
+        prop::sample::select(vec!["bool", "i8", "i16", "i32", "i64", "u8", "u16", "u32", "u64", "f32", "f64", "String"])
+            .prop_map(Ty::Scalar),
+        Just(Ty::Enum),
+        (0..8usize).prop_map(Ty::Struct),
+    ];
+    leaf.prop_recursive(3, 12, 1, |inner| prop_oneof![
+        inner.clone().prop_map(|t| Ty::List(Box::new(t))),
+        // `Option<Option<T>>` is rejected by design
+        inner.prop_map(|t| match t {
+            Ty::Optional(_) => t,
+            t => Ty::Optional(Box::new(t)),
+        }),
+    ])
+}
+
+/// Field types of each struct, in declaration order.
+fn shapes() -> impl Strategy<Value = Vec<Vec<Ty>>> {
+    prop::collection::vec(prop::collection::vec(ty(), 0..6), 1..6)
+}
+
+fn source(shapes: &[Vec<Ty>]) -> String {
+    let mut src = String::from("#[capnp]\nenum Color { Red, Green, Blue }\n");
+    for (i, fields) in shapes.iter().enumerate() {
+        src.push_str(&format!("\n#[capnp]\nstruct Shape{} {{\n", i));
+        for (j, ty) in fields.iter().enumerate() {
+            src.push_str(&format!("    field_{}: {},\n", j, ty.rust(i)));
+        }
+        src.push_str("}\n");
+    }
+    src
+}
+
+proptest! {
+    #[test]
+    fn every_field_is_numbered_in_order(shapes in shapes()) {
+        let src = source(&shapes);
+        let schema = schema_for_source(&src).map_err(|e| TestCaseError::fail(format!("{:#}\n{}", e, src)))?;
+        for (i, fields) in shapes.iter().enumerate() {
+            let body = schema.split(&format!("struct Shape{} {{", i)).nth(1)
+                .and_then(|rest| rest.split('}').next())
+                .ok_or_else(|| TestCaseError::fail(format!("Shape{} missing from:\n{}", i, schema)))?;
+            let ordinals = body.lines().filter_map(|line| line.split('@').nth(1)?.split(' ').next()?.parse::<usize>().ok());
+            prop_assert_eq!(ordinals.collect::<Vec<_>>(), (0..fields.len()).collect::<Vec<_>>(), "{}", schema);
+        }
+    }
+
+    #[test]
+    fn generation_is_deterministic(shapes in shapes()) {
+        let src = source(&shapes);
+        prop_assert_eq!(schema_for_source(&src).unwrap(), schema_for_source(&src).unwrap());
+    }
+}
+
+proptest! {
+    // Each case runs capnpc once
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn schema_compiles(shapes in shapes()) {
+        let schema = schema_for_source(&source(&shapes)).map_err(|e| TestCaseError::fail(format!("{:#}", e)))?;
+        compile_schema(&schema).map_err(|e| TestCaseError::fail(format!("{:#}\n{}", e, schema)))?;
+    }
+}
@@ -0,0 +1,68 @@
+//! Library types behind the `chrono`, `time` and `uuid` features. Run with and without them:
+//! `cargo test -p capnez-codegen --features chrono,time,uuid`.
+
+use capnez_codegen::testing::schema_for_source;
+
+fn field(ty: &str) -> anyhow::Result<String> {
+    schema_for_source(&format!("#[capnp]\nstruct Event {{ at: {} }}", ty))
+}
+
+#[cfg(not(feature = "chrono"))]
+#[test]
+fn chrono_without_its_feature_names_the_feature() {
+    let err = field("chrono::DateTime<chrono::Utc>").unwrap_err().to_string();
+    assert!(err.contains("enable the `chrono` feature of capnez-codegen"), "{}", err);
+}
+
+#[cfg(not(feature = "time"))]
+#[test]
+fn time_types_without_their_feature_name_the_feature() {
+    for ty in ["std::time::SystemTime", "std::time::Duration"] {
+        let err = field(ty).unwrap_err().to_string();
+        assert!(err.contains("enable the `time` feature of capnez-codegen"), "{}: {}", ty, err);
+    }
+}
+
+#[cfg(not(feature = "uuid"))]
+#[test]
+fn uuid_without_its_feature_names_the_feature() {
+    let err = field("uuid::Uuid").unwrap_err().to_string();
+    assert!(err.contains("enable the `uuid` feature of capnez-codegen"), "{}", err);
+}
+
+#[cfg(feature = "chrono")]
+#[test]
+fn chrono_maps_to_nanoseconds() {
+    assert!(field("chrono::DateTime<chrono::Utc>").unwrap().contains("at @0 :Int64;"));
+    let err = field("chrono::DateTime<chrono::Local>").unwrap_err().to_string();
+    assert!(err.contains("only `DateTime<Utc>` is supported"), "{}", err);
+}
+
+#[cfg(feature = "time")]
+#[test]
+fn time_types_map_to_nanoseconds() {
+    assert!(field("std::time::SystemTime").unwrap().contains("at @0 :Int64;"));
+    assert!(field("std::time::Duration").unwrap().contains("at @0 :UInt64;"));
+}
+
+#[cfg(feature = "uuid")]
+#[test]
+fn uuid_maps_to_data_or_text() {
+    assert!(field("uuid::Uuid").unwrap().contains("at @0 :Data;"));
+    let schema = schema_for_source("#[capnp]\nstruct Event { #[capnp(as = \"text\")] id: Option<uuid::Uuid> }").unwrap();
+    assert!(schema.contains("value @0 :Text;"), "{}", schema);
+    let err = schema_for_source("#[capnp]\nstruct Event { #[capnp(as = \"hex\")] id: uuid::Uuid }").unwrap_err().to_string();
+    assert!(err.contains("expected `data` or `text`"), "{}", err);
+}
+
+#[test]
+fn as_only_applies_to_uuid() {
+    let err = schema_for_source("#[capnp]\nstruct Event { #[capnp(as = \"text\")] id: u64 }").unwrap_err().to_string();
+    assert!(err.contains("only applies to `Uuid` fields"), "{}", err);
+}
+
+#[test]
+fn a_capnp_struct_shadows_the_library_type() {
+    let schema = schema_for_source("#[capnp]\nstruct Duration { secs: u64 }\n#[capnp]\nstruct Event { took: Duration }").unwrap();
+    assert!(schema.contains("took @0 :Duration;"), "{}", schema);
+}
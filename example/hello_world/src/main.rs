@@ -4,7 +4,7 @@ use capnez_codegen::capnp_include;
 
 capnp_include!();
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 struct Information {
     major: String,
     age: u32,
@@ -21,6 +21,9 @@ pub struct HelloRequest {
 #[derive(Serialize, Deserialize)]
 pub struct HelloReply {
     message: String,
+    /// The request's information echoed back, encoded with bincode rather than the default JSON
+    #[capnp(serde_with = "bincode")]
+    information: Information,
 }
 
 #[capnp]
@@ -44,4 +47,58 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     println!("usage: {} [client | server] ADDRESS", args[0]);
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use capnez::codec::{Bincode, Json, SerdeCodec};
+
+    fn information() -> Information {
+        Information { major: "Mathematics".to_string(), age: 36 }
+    }
+
+    /// `message` written out and read back, as it would cross the wire.
+    fn reread(message: &capnp::message::Builder<capnp::message::HeapAllocator>) -> capnp::message::Reader<capnp::serialize::OwnedSegments> {
+        let bytes = capnp::serialize::write_message_to_words(message);
+        capnp::serialize::read_message(&mut &bytes[..], Default::default()).unwrap()
+    }
+
+    #[test]
+    fn json_field_round_trips() {
+        let mut message = capnp::message::Builder::new_default();
+        let mut request = message.init_root::<schema_capnp::hello_request::Builder>();
+        request.set_name("Ada");
+        request.set_information_serde(&information()).unwrap();
+
+        let message = reread(&message);
+        let request = message.get_root::<schema_capnp::hello_request::Reader>().unwrap();
+        assert_eq!(request.get_information_serde().unwrap(), information());
+        assert_eq!(request.get_information().unwrap().as_slice().unwrap(), Json::encode(&information()).unwrap());
+    }
+
+    #[test]
+    fn bincode_field_round_trips() {
+        let mut message = capnp::message::Builder::new_default();
+        let mut reply = message.init_root::<schema_capnp::hello_reply::Builder>();
+        reply.set_message("Hello");
+        reply.set_information_serde(&information()).unwrap();
+
+        let message = reread(&message);
+        let reply = message.get_root::<schema_capnp::hello_reply::Reader>().unwrap();
+        assert_eq!(reply.get_information_serde().unwrap(), information());
+        assert_eq!(reply.get_information().unwrap().as_slice().unwrap(), Bincode::encode(&information()).unwrap());
+    }
+
+    #[test]
+    fn bytes_from_the_wrong_codec_fail_to_decode() {
+        let mut message = capnp::message::Builder::new_default();
+        let bytes = Bincode::encode(&information()).unwrap();
+        message.init_root::<schema_capnp::hello_request::Builder>().set_information(&bytes[..]).unwrap();
+
+        let message = reread(&message);
+        let request = message.get_root::<schema_capnp::hello_request::Reader>().unwrap();
+        let error = request.get_information_serde().unwrap_err();
+        assert!(error.to_string().contains("json decoding failed"), "{}", error);
+    }
+}
@@ -0,0 +1,6 @@
+fn main() {
+    capnez_codegen::SchemaGenerator::new()
+        .no_std(true)
+        .run()
+        .expect("Failed to generate schema");
+}
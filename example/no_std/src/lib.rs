@@ -0,0 +1,39 @@
+//! A `#![no_std]` crate sharing message definitions with a std backend, built with only `alloc`.
+//!
+//! `capnp_include!` lives in `capnez-codegen`, which only runs on the host, so the generated module
+//! is included directly. Check that it builds for a bare-metal target with
+//! `cargo build -p capnez-no-std --target thumbv7em-none-eabihf`.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use capnez_macros::capnp;
+
+#[allow(unexpected_cfgs)]
+pub mod schema_capnp {
+    include!(concat!(env!("OUT_DIR"), "/generated/schema_capnp.rs"));
+}
+
+/// A sensor sample, as sent from the device to the backend.
+#[capnp]
+#[derive(Debug, PartialEq)]
+pub struct Reading {
+    pub sensor: String,
+    pub sequence: u32,
+    pub millivolts: Vec<i16>,
+    pub battery: Option<u8>,
+}
+
+/// Bytes to transmit for `reading`.
+pub fn encode(reading: &Reading) -> Vec<u8> {
+    reading.to_capnp_bytes()
+}
+
+/// A reading received from the backend, refusing messages over 4 KiB.
+pub fn decode(bytes: &[u8]) -> Result<Reading, capnez::limits::DecodeError> {
+    let limits = capnez::limits::DecodeLimits { max_message_bytes: Some(4096), ..Default::default() };
+    Reading::from_capnp_bytes_limited(bytes, &limits)
+}
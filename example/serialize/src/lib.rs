@@ -0,0 +1,151 @@
+//! Message types of the serialization example. `main.rs` walks through writing and reading them, and
+//! the tests under `tests/` check each generated helper on its own.
+
+use capnez_macros::capnp;
+use capnez_codegen::capnp_include;
+use serde::{Serialize, Deserialize};
+
+capnp_include!();
+
+// Define a simple struct that we want to serialize
+#[capnp]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Person {
+    pub name: String,
+    pub age: u32,
+    pub email: String,
+}
+
+// A zero-copy view: `from_capnp` borrows `topic` and `payload` from the message
+#[capnp]
+#[derive(Debug, PartialEq)]
+pub struct Event<'a> {
+    pub topic: &'a str,
+    pub payload: &'a [u8],
+}
+
+// Fixed-size arrays are stored as lists, and their length is checked when decoding
+#[capnp]
+#[derive(Debug, PartialEq)]
+pub struct Point {
+    pub coords: [f64; 3],
+}
+
+#[capnp]
+#[derive(Debug, PartialEq)]
+pub struct Tile {
+    pub pixels: [[u8; 4]; 4],
+}
+
+// Large enough that reading it takes more than the default traversal limit; see `tests/mmap.rs`
+#[capnp]
+#[derive(Debug, PartialEq)]
+pub struct Samples {
+    pub label: String,
+    pub values: Vec<u64>,
+}
+
+// Nested structs and lists of them, whose size hints `tests/limits.rs` checks
+#[capnp]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Address {
+    pub street: String,
+    pub city: String,
+    pub zip: Option<u32>,
+}
+
+#[capnp]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Customer {
+    pub id: u64,
+    pub name: String,
+    pub addresses: Vec<Address>,
+    pub tags: Vec<String>,
+    pub scores: Vec<u32>,
+    pub avatar: Vec<u8>,
+}
+
+// Optional fields of each kind, which the generated `ProfileReaderExt`/`ProfileBuilderExt` read and
+// write as `Option`s; see `tests/optional.rs`
+#[capnp]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub id: u64,
+    pub nickname: Option<String>,
+    pub age: Option<u32>,
+    pub home: Option<Address>,
+    pub scores: Option<Vec<u32>>,
+}
+
+// Fields with schema defaults, which `capnp_builder()` lets callers leave out; see `tests/builder.rs`
+#[capnp]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Listener {
+    pub host: String,
+    #[capnp(default = 8080)]
+    pub port: u16,
+    #[capnp(default = "info")]
+    pub log_level: String,
+    pub tls: Option<bool>,
+}
+
+// `Document` carries the fields of `Metadata`, and through it those of `Audit`, inline instead of
+// behind a pointer; see `tests/flatten.rs`
+#[capnp]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Audit {
+    pub at: i64,
+    pub by: String,
+}
+
+#[capnp]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metadata {
+    pub version: u32,
+    #[capnp(flatten, prefix = "audit")]
+    pub audit: Audit,
+}
+
+#[capnp]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Document {
+    pub title: String,
+    #[capnp(flatten, prefix = "meta")]
+    pub meta: Metadata,
+    pub body: String,
+}
+
+// Constraints that `from_capnp` enforces, one field per kind; see `tests/validate.rs`
+#[capnp]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signup {
+    #[capnp(validate(non_empty, max_len = 16))]
+    pub username: String,
+    #[capnp(validate(range = "13..=120"))]
+    pub age: u32,
+    #[capnp(validate(range = "0.0..=1.0"))]
+    pub discount: f64,
+    #[capnp(validate(with = "crate::checks::email"))]
+    pub email: String,
+    #[capnp(validate(max_len = 3))]
+    pub roles: Vec<String>,
+    #[capnp(validate(non_empty))]
+    pub key: Vec<u8>,
+}
+
+#[capnp]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Team {
+    pub name: String,
+    pub members: Vec<Signup>,
+}
+
+pub mod checks {
+    /// The check behind `Signup.email`.
+    pub fn email(value: &str) -> Result<(), String> {
+        match value.split_once('@') {
+            Some((user, domain)) if !user.is_empty() && domain.contains('.') => Ok(()),
+            _ => Err(format!("`{}` is not an email address", value)),
+        }
+    }
+}
@@ -1,17 +1,4 @@
-use capnez_macros::capnp;
-use capnez_codegen::capnp_include;
-use serde::{Serialize, Deserialize};
-
-capnp_include!();
-
-// Define a simple struct that we want to serialize
-#[capnp]
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
-struct Person {
-    name: String,
-    age: u32,
-    email: String,
-}
+use serialize::{schema_capnp, Event, Person};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -48,8 +35,18 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     };
     
     assert_eq!(person, deserialized_person);
+
+    // Borrowed fields point into the message buffer instead of being copied out
+    let payload = [0xca, 0xfe];
+    let event = Event { topic: "people/updated", payload: &payload };
+    let bytes = event.to_capnp_bytes();
+    let message = capnp::serialize::read_message_from_flat_slice(&mut &bytes[..], Default::default())?;
+    let borrowed = Event::from_capnp(message.get_root()?)?;
+    assert_eq!(borrowed, event);
+    let range = bytes.as_ptr_range();
+    assert!(range.contains(&borrowed.topic.as_ptr()) && range.contains(&borrowed.payload.as_ptr()));
     
     println!("All assertions passed!");
 
     Ok(())
-}
\ No newline at end of file
+}
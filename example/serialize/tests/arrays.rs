@@ -0,0 +1,51 @@
+//! Fixed-size arrays, stored as lists whose length is checked when decoding.
+
+use serialize::{schema_capnp, Point, Tile};
+
+fn decode<T>(message: &capnp::message::Builder<capnp::message::HeapAllocator>, from_bytes: fn(&[u8]) -> capnp::Result<T>) -> capnp::Result<T> {
+    from_bytes(&capnp::serialize::write_message_to_words(message))
+}
+
+#[test]
+fn arrays_round_trip() {
+    let point = Point { coords: [1.5, -2.0, 3.25] };
+    assert_eq!(Point::from_capnp_bytes(&point.to_capnp_bytes()).unwrap(), point);
+}
+
+#[test]
+fn nested_arrays_round_trip() {
+    let mut pixels = [[0u8; 4]; 4];
+    for (i, row) in pixels.iter_mut().enumerate() {
+        for (j, pixel) in row.iter_mut().enumerate() {
+            *pixel = (i * 4 + j) as u8;
+        }
+    }
+    let tile = Tile { pixels };
+    assert_eq!(Tile::from_capnp_bytes(&tile.to_capnp_bytes()).unwrap(), tile);
+}
+
+#[test]
+fn a_list_of_the_wrong_length_fails_naming_the_field() {
+    let mut message = capnp::message::Builder::new_default();
+    let mut coords = message.init_root::<schema_capnp::point::Builder>().init_coords(2);
+    coords.set(0, 1.0);
+    coords.set(1, 2.0);
+    let error = decode(&message, Point::from_capnp_bytes).unwrap_err();
+    assert!(error.to_string().contains("expected 3 elements for Point.coords, got 2"), "{}", error);
+}
+
+#[test]
+fn an_inner_list_of_the_wrong_length_fails_too() {
+    let mut message = capnp::message::Builder::new_default();
+    let mut rows = message.init_root::<schema_capnp::tile::Builder>().init_pixels(4);
+    for i in 0..4 {
+        rows.reborrow().init(i, if i == 2 { 3 } else { 4 });
+    }
+    let error = decode(&message, Tile::from_capnp_bytes).unwrap_err();
+    assert!(error.to_string().contains("expected 4 elements for Tile.pixels[], got 3"), "{}", error);
+
+    let mut message = capnp::message::Builder::new_default();
+    message.init_root::<schema_capnp::tile::Builder>().init_pixels(5);
+    let error = decode(&message, Tile::from_capnp_bytes).unwrap_err();
+    assert!(error.to_string().contains("expected 4 elements for Tile.pixels, got 5"), "{}", error);
+}
@@ -0,0 +1,19 @@
+//! Structs whose `&str`/`&[u8]` fields `from_capnp` borrows from the message instead of copying.
+
+use serialize::Event;
+
+#[test]
+fn borrowed_fields_point_into_the_message() {
+    let payload = [1, 2, 3];
+    let event = Event { topic: "people/created", payload: &payload };
+    let bytes = event.to_capnp_bytes();
+    let message = capnp::serialize::read_message_from_flat_slice(&mut &bytes[..], Default::default()).unwrap();
+    let borrowed: Event<'_> = Event::from_capnp(message.get_root().unwrap()).unwrap();
+    assert_eq!(borrowed, event);
+
+    // Neither field was copied out: both point into `bytes`, not at the original values
+    let range = bytes.as_ptr_range();
+    assert!(range.contains(&borrowed.topic.as_ptr()));
+    assert!(range.contains(&borrowed.payload.as_ptr()));
+    assert_ne!(borrowed.payload.as_ptr(), payload.as_ptr());
+}
@@ -0,0 +1,69 @@
+//! `capnp_builder()`: full construction, the error for a forgotten field, and the bytes it builds.
+
+use serialize::schema_capnp::{self, MissingFields};
+use serialize::{Address, Listener, Person, Profile};
+
+fn person() -> Person {
+    Person { name: "John Doe".to_string(), age: 30, email: "john@example.com".to_string() }
+}
+
+#[test]
+fn every_field_set_builds_the_value() {
+    let built = Person::capnp_builder().name("John Doe").age(30u32).email("john@example.com").build().unwrap();
+    assert_eq!(built, person());
+
+    // Setters take anything that converts into the field type, in any order
+    let built = Person::capnp_builder().email(String::from("john@example.com")).age(30u8).name("John Doe").build().unwrap();
+    assert_eq!(built, person());
+}
+
+#[test]
+fn a_missing_field_is_named() {
+    let err = Person::capnp_builder().name("John Doe").email("john@example.com").build().unwrap_err();
+    assert_eq!(err, MissingFields { type_name: "Person", fields: vec!["age"] });
+    assert_eq!(err.to_string(), "Person is missing required fields: age");
+
+    // Every missing field, in declaration order, from each build method
+    let err = Person::capnp_builder().age(30u32).build_bytes().unwrap_err();
+    assert_eq!(err.to_string(), "Person is missing required fields: name, email");
+    let err = Person::capnp_builder().build_message().err().unwrap();
+    assert_eq!(err.fields, ["name", "age", "email"]);
+
+    // It converts into a capnp error for `?` in functions returning `capnp::Result`
+    let err: capnp::Error = Person::capnp_builder().build().unwrap_err().into();
+    assert!(err.to_string().contains("Person is missing required fields: name, age, email"), "{}", err);
+}
+
+#[test]
+fn options_and_defaults_may_be_left_out() {
+    let listener = Listener::capnp_builder().host("0.0.0.0").build().unwrap();
+    assert_eq!(listener, Listener { host: "0.0.0.0".to_string(), port: 8080, log_level: "info".to_string(), tls: None });
+
+    let listener = Listener::capnp_builder().host("0.0.0.0").port(443u16).tls(true).build().unwrap();
+    assert_eq!((listener.port, listener.log_level.as_str(), listener.tls), (443, "info", Some(true)));
+
+    let err = Listener::capnp_builder().port(443u16).build().unwrap_err();
+    assert_eq!(err.fields, ["host"]);
+
+    let profile = Profile::capnp_builder().id(7u64).home(Address { street: "12 Harbour Road".to_string(), city: "Wellington".to_string(), zip: None }).build().unwrap();
+    assert_eq!((profile.nickname, profile.age, profile.scores), (None, None, None));
+    assert_eq!(profile.home.map(|home| home.city), Some("Wellington".to_string()));
+}
+
+#[test]
+fn build_bytes_matches_the_manual_builder() {
+    // The path `main.rs` takes, setting each field on the capnp builder by hand
+    let person = person();
+    let mut message = capnp::message::Builder::new_default();
+    let mut builder = message.init_root::<schema_capnp::person::Builder>();
+    builder.set_name(&person.name);
+    builder.set_age(person.age);
+    builder.set_email(&person.email);
+    let manual = capnp::serialize::write_message_to_words(&message);
+
+    let built = || Person::capnp_builder().name("John Doe").age(30u32).email("john@example.com");
+    assert_eq!(built().build_bytes().unwrap(), manual);
+    assert_eq!(capnp::serialize::write_message_to_words(&built().build_message().unwrap()), manual);
+    assert_eq!(person.to_capnp_bytes(), manual);
+    assert_eq!(Person::from_capnp_bytes(&manual).unwrap(), person);
+}
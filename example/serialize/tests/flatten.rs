@@ -0,0 +1,56 @@
+//! `#[capnp(flatten)]` round trips, one level (`Audit` in `Metadata`) and two (`Document`).
+
+use capnp::message::ReaderOptions;
+use serialize::schema_capnp::{document, metadata};
+use serialize::{Audit, Document, Metadata};
+
+fn metadata() -> Metadata {
+    Metadata { version: 3, audit: Audit { at: 1_700_000_000, by: "ada".to_string() } }
+}
+
+fn document() -> Document {
+    Document { title: "Notes".to_string(), meta: metadata(), body: "On the analytical engine".to_string() }
+}
+
+#[test]
+fn one_level_round_trips() {
+    let metadata = metadata();
+    let bytes = metadata.to_capnp_bytes();
+    assert_eq!(Metadata::from_capnp_bytes(&bytes).unwrap(), metadata);
+
+    // On the wire the audit fields sit in `Metadata` itself
+    let message = capnp::serialize::read_message_from_flat_slice(&mut &bytes[..], ReaderOptions::new()).unwrap();
+    let reader = message.get_root::<metadata::Reader>().unwrap();
+    assert_eq!(reader.get_audit_at(), 1_700_000_000);
+    assert_eq!(reader.get_audit_by().unwrap().to_str().unwrap(), "ada");
+}
+
+#[test]
+fn nested_flattening_round_trips() {
+    let document = document();
+    let bytes = document.to_capnp_bytes();
+    assert_eq!(Document::from_capnp_bytes(&bytes).unwrap(), document);
+
+    let message = capnp::serialize::read_message_from_flat_slice(&mut &bytes[..], ReaderOptions::new()).unwrap();
+    let reader = message.get_root::<document::Reader>().unwrap();
+    assert_eq!(reader.get_meta_version(), 3);
+    assert_eq!(reader.get_meta_audit_at(), 1_700_000_000);
+    assert_eq!(reader.get_meta_audit_by().unwrap().to_str().unwrap(), "ada");
+    assert_eq!(reader.get_body().unwrap().to_str().unwrap(), "On the analytical engine");
+}
+
+#[test]
+fn spliced_fields_are_written_from_the_nested_value() {
+    // Set on the capnp builder field by field, the spliced fields read back as the nested structs
+    let mut message = capnp::message::Builder::new_default();
+    let mut builder = message.init_root::<document::Builder>();
+    builder.set_title("Notes");
+    builder.set_meta_version(3);
+    builder.set_meta_audit_at(1_700_000_000);
+    builder.set_meta_audit_by("ada");
+    builder.set_body("On the analytical engine");
+    let manual = capnp::serialize::write_message_to_words(&message);
+
+    assert_eq!(Document::from_capnp_bytes(&manual).unwrap(), document());
+    assert_eq!(document().to_capnp_bytes(), manual);
+}
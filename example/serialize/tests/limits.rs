@@ -0,0 +1,76 @@
+//! `capnp_size_hint` against real sizes, and decoding within `DecodeLimits`.
+#![cfg(feature = "limits")]
+
+use capnez::limits::{DecodeError, DecodeLimits, Limit};
+use serialize::{Address, Customer};
+
+fn address(i: usize) -> Address {
+    Address { street: format!("{} Harbour Road", i), city: "Wellington".to_string(), zip: (i % 2 == 0).then_some(6011) }
+}
+
+fn customer(addresses: usize, tags: usize, scores: usize, avatar: usize) -> Customer {
+    Customer {
+        id: 42,
+        name: "Ada Lovelace".to_string(),
+        addresses: (0..addresses).map(address).collect(),
+        tags: (0..tags).map(|i| format!("tag-{}", i)).collect(),
+        scores: (0..scores as u32).collect(),
+        avatar: vec![0xab; avatar],
+    }
+}
+
+#[test]
+fn size_hints_are_within_twice_the_real_size() {
+    let cases = [
+        ("empty", customer(0, 0, 0, 0)),
+        ("one address", customer(1, 0, 0, 0)),
+        ("many addresses", customer(500, 0, 0, 0)),
+        ("many tags", customer(0, 2_000, 0, 0)),
+        ("long lists", customer(50, 50, 100_000, 0)),
+        ("large blob", customer(1, 1, 1, 1 << 20)),
+        ("everything", customer(200, 300, 10_000, 4096)),
+    ];
+    for (name, customer) in cases {
+        let actual = customer.to_capnp_bytes().len();
+        let hint = customer.capnp_size_hint();
+        assert!(hint <= actual * 2 && actual <= hint * 2, "{}: hint {} for {} bytes", name, hint, actual);
+    }
+}
+
+#[test]
+fn a_message_within_the_limits_decodes() {
+    let customer = customer(10, 10, 10, 10);
+    let bytes = customer.to_capnp_bytes();
+    assert_eq!(Customer::from_capnp_bytes_limited(&bytes, &DecodeLimits::default()).unwrap(), customer);
+}
+
+#[test]
+fn an_input_over_the_byte_limit_is_rejected_before_reading() {
+    let bytes = customer(10, 10, 10, 1000).to_capnp_bytes();
+    let limits = DecodeLimits { max_message_bytes: Some(512), ..DecodeLimits::default() };
+    match Customer::from_capnp_bytes_limited(&bytes, &limits) {
+        Err(DecodeError::Limit(Limit::MessageBytes { limit, actual })) => assert_eq!((limit, actual), (512, bytes.len())),
+        other => panic!("expected the byte limit, got {:?}", other.map(|c| c.id)),
+    }
+}
+
+#[test]
+fn a_message_over_the_traversal_limit_is_rejected() {
+    let bytes = customer(0, 0, 10_000, 0).to_capnp_bytes();
+    let limits = DecodeLimits { traversal_limit_words: Some(1_000), ..DecodeLimits::default() };
+    match Customer::from_capnp_bytes_limited(&bytes, &limits) {
+        Err(DecodeError::Limit(Limit::TraversalWords { limit })) => assert_eq!(limit, 1_000),
+        other => panic!("expected the traversal limit, got {:?}", other.map(|c| c.id)),
+    }
+}
+
+#[test]
+fn a_message_over_the_nesting_limit_is_rejected() {
+    // Customer -> list of addresses -> address
+    let bytes = customer(1, 0, 0, 0).to_capnp_bytes();
+    let limits = DecodeLimits { nesting_limit: 1, ..DecodeLimits::default() };
+    match Customer::from_capnp_bytes_limited(&bytes, &limits) {
+        Err(DecodeError::Limit(Limit::Nesting { limit })) => assert_eq!(limit, 1),
+        other => panic!("expected the nesting limit, got {:?}", other.map(|c| c.id)),
+    }
+}
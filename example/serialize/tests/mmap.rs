@@ -0,0 +1,54 @@
+//! `open_mmap` on a message too large for the default traversal limit.
+#![cfg(feature = "mmap")]
+
+use capnp::message::{ReaderOptions, ReaderSegments};
+use serialize::{schema_capnp, Samples};
+
+/// More words than the default 64 MiB traversal limit allows reading.
+const COUNT: u64 = 9_000_000;
+
+#[test]
+fn a_large_message_is_read_in_place() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("samples.bin");
+    let samples = Samples { label: "ramp".to_string(), values: (0..COUNT).map(|i| i * 3).collect() };
+    std::fs::write(&path, samples.to_capnp_bytes()).unwrap();
+    drop(samples);
+
+    // SAFETY: the file is private to this test and not written again while mapped
+    let reader = unsafe { Samples::open_mmap(&path) }.unwrap();
+    let root = reader.get().unwrap();
+    assert_eq!(root.get_label().unwrap().to_str().unwrap(), "ramp");
+    let values = root.get_values().unwrap();
+    assert_eq!(values.len() as u64, COUNT);
+    for i in [0, 1, COUNT / 2, COUNT - 1] {
+        assert_eq!(values.get(i as u32), i * 3);
+    }
+
+    // capnp requires word-aligned segments, which the mapping gives without copying
+    let segments = reader.into_inner().into_segments();
+    assert!(segments.len() > 1, "expected the message to span several segments");
+    for i in 0..segments.len() {
+        let segment = segments.get_segment(i as u32).unwrap();
+        assert_eq!(segment.as_ptr() as usize % 8, 0, "segment {} is misaligned", i);
+    }
+}
+
+#[test]
+fn the_default_traversal_limit_rejects_it() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("samples.bin");
+    let samples = Samples { label: "ramp".to_string(), values: vec![7; COUNT as usize] };
+    std::fs::write(&path, samples.to_capnp_bytes()).unwrap();
+    drop(samples);
+
+    // SAFETY: as above
+    let message = unsafe { capnez::io::read_message_mmap(&path, ReaderOptions::new()) }.unwrap();
+    let err = message.get_root::<schema_capnp::samples::Reader>().and_then(|root| root.get_values()).err().expect("read past the default limit");
+    assert!(err.to_string().contains("limit"), "{}", err);
+
+    // `sized_options` covers the whole message
+    let options = capnez::io::sized_options(std::fs::metadata(&path).unwrap().len());
+    let message = unsafe { capnez::io::read_message_mmap(&path, options) }.unwrap();
+    assert_eq!(message.get_root::<schema_capnp::samples::Reader>().unwrap().get_values().unwrap().len() as u64, COUNT);
+}
@@ -0,0 +1,71 @@
+//! The generated `Option` accessors, with `Some` and `None` for text, an integer, a nested struct and a list.
+
+use capnp::message::ReaderOptions;
+use serialize::schema_capnp::{profile, ProfileBuilderExt, ProfileReaderExt};
+use serialize::{Address, Profile};
+
+fn profiles() -> [Profile; 3] {
+    let home = Address { street: "12 Harbour Road".to_string(), city: "Wellington".to_string(), zip: Some(6011) };
+    [
+        Profile { id: 1, nickname: Some("ada".to_string()), age: Some(36), home: Some(home.clone()), scores: Some(vec![3, 1, 4]) },
+        Profile { id: 2, nickname: None, age: None, home: None, scores: None },
+        // Present but empty or zero is still `Some`
+        Profile { id: 3, nickname: Some(String::new()), age: Some(0), home: None, scores: Some(Vec::new()) },
+    ]
+}
+
+fn read(bytes: &[u8]) -> capnp::message::Reader<capnp::serialize::BufferSegments<&[u8]>> {
+    capnp::serialize::read_message_from_flat_slice(&mut &bytes[..], ReaderOptions::new()).unwrap()
+}
+
+#[test]
+fn getters_read_some_and_none() -> capnp::Result<()> {
+    for profile in profiles() {
+        let bytes = profile.to_capnp_bytes();
+        let message = read(&bytes);
+        let reader = message.get_root::<profile::Reader>()?;
+
+        assert_eq!(reader.nickname_opt()?, profile.nickname.as_deref(), "{:?}", profile);
+        assert_eq!(reader.age_opt()?, profile.age, "{:?}", profile);
+        assert_eq!(reader.home_opt()?.map(Address::from_capnp).transpose()?, profile.home, "{:?}", profile);
+        assert_eq!(reader.scores_opt()?.map(|scores| scores.iter().collect::<Vec<_>>()), profile.scores, "{:?}", profile);
+    }
+    Ok(())
+}
+
+#[test]
+fn setters_write_some_and_none() -> capnp::Result<()> {
+    for profile in profiles() {
+        // Copy every field across with the accessors, then read the copy back through `from_capnp`
+        let bytes = profile.to_capnp_bytes();
+        let source = read(&bytes);
+        let source = source.get_root::<profile::Reader>()?;
+
+        let mut message = capnp::message::Builder::new_default();
+        let mut builder = message.init_root::<profile::Builder>();
+        builder.set_id(source.get_id());
+        builder.set_nickname_opt(source.nickname_opt()?);
+        builder.set_age_opt(source.age_opt()?);
+        builder.set_home_opt(source.home_opt()?)?;
+        builder.set_scores_opt(source.scores_opt()?)?;
+
+        let copy = Profile::from_capnp(message.get_root_as_reader()?)?;
+        assert_eq!(copy, profile);
+    }
+    Ok(())
+}
+
+#[test]
+fn a_setter_replaces_an_earlier_value() -> capnp::Result<()> {
+    let mut message = capnp::message::Builder::new_default();
+    let mut builder = message.init_root::<profile::Builder>();
+    builder.set_nickname_opt(Some("ada"));
+    builder.set_age_opt(Some(36));
+    builder.set_nickname_opt(None);
+    builder.set_age_opt(Some(37));
+
+    let reader = message.get_root_as_reader::<profile::Reader>()?;
+    assert_eq!(reader.nickname_opt()?, None);
+    assert_eq!(reader.age_opt()?, Some(37));
+    Ok(())
+}
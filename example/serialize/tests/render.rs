@@ -0,0 +1,21 @@
+//! `to_capnp_text` and `to_capnp_json`, compiled with the `dynamic` feature.
+
+use serialize::Person;
+
+fn person() -> Person {
+    Person { name: "Ada".to_string(), age: 36, email: "ada@example.com".to_string() }
+}
+
+#[test]
+fn text_names_every_field_with_its_value() {
+    let text = person().to_capnp_text().unwrap();
+    for field in [r#"name = "Ada""#, "age = 36", r#"email = "ada@example.com""#] {
+        assert!(text.contains(field), "{:?} missing from {}", field, text);
+    }
+}
+
+#[test]
+fn json_names_every_field_with_its_value() {
+    let json: serde_json::Value = serde_json::from_str(&person().to_capnp_json().unwrap()).unwrap();
+    assert_eq!(json, serde_json::json!({ "name": "Ada", "age": 36, "email": "ada@example.com" }));
+}
@@ -0,0 +1,98 @@
+//! `#[capnp(validate(...))]`: one test per constraint kind, nested structs, and the unchecked reads.
+
+use serialize::{schema_capnp, Signup, Team};
+
+fn signup() -> Signup {
+    Signup {
+        username: "ada".to_string(),
+        age: 36,
+        discount: 0.25,
+        email: "ada@example.com".to_string(),
+        roles: vec!["admin".to_string(), "author".to_string()],
+        key: vec![0xde, 0xad, 0xbe, 0xef],
+    }
+}
+
+/// The error `from_capnp_bytes` reports for `value`, after checking `capnp_validate` agrees.
+fn rejected(value: &Signup) -> String {
+    let error = Signup::from_capnp_bytes(&value.to_capnp_bytes()).unwrap_err();
+    assert_eq!(value.capnp_validate().unwrap_err().to_string(), error.to_string());
+    error.to_string()
+}
+
+#[test]
+fn a_valid_message_passes_unchanged() {
+    let signup = signup();
+    signup.capnp_validate().unwrap();
+    assert_eq!(Signup::from_capnp_bytes(&signup.to_capnp_bytes()).unwrap(), signup);
+    assert_eq!(Signup::from_capnp_bytes_unchecked(&signup.to_capnp_bytes()).unwrap(), signup);
+}
+
+#[test]
+fn range_on_an_integer() {
+    let young = Signup { age: 12, ..signup() };
+    let error = rejected(&young);
+    assert!(error.contains("Signup.age is 12, outside range 13..=120"), "{}", error);
+
+    // Both ends are inclusive
+    for age in [13, 120] {
+        Signup { age, ..signup() }.capnp_validate().unwrap();
+    }
+    let error = rejected(&Signup { age: 121, ..signup() });
+    assert!(error.contains("Signup.age is 121, outside range 13..=120"), "{}", error);
+}
+
+#[test]
+fn range_on_a_float() {
+    let error = rejected(&Signup { discount: 1.5, ..signup() });
+    assert!(error.contains("Signup.discount is 1.5, outside range 0.0..=1.0"), "{}", error);
+}
+
+#[test]
+fn non_empty_on_text_and_data() {
+    let error = rejected(&Signup { username: String::new(), ..signup() });
+    assert!(error.contains("Signup.username is empty, breaking non_empty"), "{}", error);
+
+    let error = rejected(&Signup { key: Vec::new(), ..signup() });
+    assert!(error.contains("Signup.key is empty, breaking non_empty"), "{}", error);
+}
+
+#[test]
+fn max_len_on_text_and_a_list() {
+    // Text is measured in bytes
+    Signup { username: "a".repeat(16), ..signup() }.capnp_validate().unwrap();
+    let error = rejected(&Signup { username: "é".repeat(9), ..signup() });
+    assert!(error.contains("Signup.username has length 18, over max_len = 16"), "{}", error);
+
+    let roles = ["admin", "author", "editor", "viewer"].map(String::from).to_vec();
+    let error = rejected(&Signup { roles, ..signup() });
+    assert!(error.contains("Signup.roles has length 4, over max_len = 3"), "{}", error);
+}
+
+#[test]
+fn with_runs_the_named_check() {
+    let error = rejected(&Signup { email: "ada.example.com".to_string(), ..signup() });
+    assert!(error.contains("Signup.email fails crate::checks::email: `ada.example.com` is not an email address"), "{}", error);
+}
+
+#[test]
+fn a_nested_struct_is_checked_too() {
+    let team = Team { name: "engines".to_string(), members: vec![signup(), Signup { age: 7, ..signup() }] };
+    let error = Team::from_capnp_bytes(&team.to_capnp_bytes()).unwrap_err();
+    assert!(error.to_string().contains("Signup.age is 7, outside range 13..=120"), "{}", error);
+    assert!(team.capnp_validate().is_err());
+    assert_eq!(Team::from_capnp_bytes_unchecked(&team.to_capnp_bytes()).unwrap(), team);
+}
+
+#[test]
+fn unchecked_reads_skip_the_constraints() {
+    let invalid = Signup { username: String::new(), age: 200, email: "nobody".to_string(), ..signup() };
+    assert!(Signup::from_capnp_bytes(&invalid.to_capnp_bytes()).is_err());
+    assert_eq!(Signup::from_capnp_bytes_unchecked(&invalid.to_capnp_bytes()).unwrap(), invalid);
+
+    let mut message = capnp::message::Builder::new_default();
+    invalid.to_capnp(message.init_root());
+    let reader = message.get_root_as_reader::<schema_capnp::signup::Reader>().unwrap();
+    assert_eq!(Signup::from_capnp_unchecked(reader).unwrap(), invalid);
+    assert!(Signup::from_capnp(reader).is_err());
+}
@@ -6,11 +6,11 @@ use entry::MatrixEntry;
 use matrix::SparseMatrix;
 use multiply::multiply;
 use std::fs::File;
+use std::io::{BufReader, BufWriter};
 use capnp::serialize;
 use capnez_codegen::capnp_include;
 use capnez_macros::capnp;
 use std::error::Error;
-use serde_json;
 
 #[capnp]
 struct SparseMatrixData {
@@ -19,6 +19,22 @@ struct SparseMatrixData {
     values: Vec<MatrixEntry>,
 }
 
+/// A coordinate-format entry with a native capnp layout, so large matrices can be streamed.
+#[capnp]
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Triplet {
+    row: u32,
+    col: u32,
+    value: f64,
+}
+
+#[capnp]
+struct TripletMatrix {
+    rows: u32,
+    cols: u32,
+    entries: Vec<Triplet>,
+}
+
 capnp_include!();
 
 fn main() -> Result<(), Box<dyn Error>> {
@@ -35,15 +51,7 @@ fn main() -> Result<(), Box<dyn Error>> {
     let mut builder = msg.init_root::<schema_capnp::sparse_matrix::Builder>();
     builder.set_rows(result.rows);
     builder.set_cols(result.cols);
-    
-    let mut values = builder.init_values(result.values.len() as u32);
-    for (i, e) in result.values.iter().enumerate() {
-        let bytes = serde_json::to_vec(e)?;
-        let mut entry = values.reborrow().init(i as u32, bytes.len() as u32);
-        for (j, b) in bytes.iter().enumerate() {
-            entry.set(j as u32, *b);
-        }
-    }
+    builder.set_values_serde(&result.values)?;
 
     let path = format!("{}/target/result.bin", env!("OUT_DIR"));
     std::fs::create_dir_all(format!("{}/target", env!("OUT_DIR")))?;
@@ -51,22 +59,104 @@ fn main() -> Result<(), Box<dyn Error>> {
     serialize::write_message(&mut file, &msg)?;
     println!("\nSerialized to {}", path);
 
-    // Verify serialization
-    let mut file = File::open(&path)?;
-    let message_reader = serialize::read_message(&mut file, capnp::message::ReaderOptions::new())?;
+    // Verify serialization, reading the file in place through a memory map instead of copying it
+    // (nothing else touches the file while it is mapped)
+    let message_reader = unsafe { capnez::io::read_message_mmap(&path, capnp::message::ReaderOptions::new())? };
     let reader = message_reader.get_root::<schema_capnp::sparse_matrix::Reader>()?;
     
     assert_eq!(reader.get_rows(), result.rows);
     assert_eq!(reader.get_cols(), result.cols);
     
-    let values = reader.get_values()?;
-    for (i, e) in values.iter().enumerate() {
-        let bytes: Vec<u8> = e?.iter().map(|b| b).collect();
-        let deserialized: MatrixEntry = serde_json::from_slice(&bytes)?;
+    for (i, deserialized) in reader.get_values_serde()?.iter().enumerate() {
         assert_eq!(deserialized.row, result.values[i].row);
         assert_eq!(deserialized.col, result.values[i].col);
         assert!((deserialized.value - result.values[i].value).abs() < 1e-6);
     }
     println!("Deserialization passed!");
+
+    // Stream a million entries through a file without materializing them on either side
+    let n = 1_000_000u32;
+    let triplet = |i: u32| Triplet { row: i, col: (i * 7) % n, value: i as f64 * 0.5 };
+    let path = format!("{}/target/streamed.bin", env!("OUT_DIR"));
+    let header = TripletMatrix { rows: n, cols: n, entries: Vec::new() };
+    header.write_streamed(BufWriter::new(File::create(&path)?), (0..n).map(triplet))?;
+
+    let (header, entries) = TripletMatrix::read_streamed(BufReader::new(File::open(&path)?))?;
+    assert_eq!((header.rows, header.cols), (n, n));
+    let mut count = 0;
+    for (i, entry) in entries.enumerate() {
+        assert_eq!(entry?, triplet(i as u32));
+        count += 1;
+    }
+    assert_eq!(count, n as usize);
+    assert!(count > schema_capnp::STREAM_CHUNK);
+    println!("Streamed {} entries in chunks of {}", count, schema_capnp::STREAM_CHUNK);
     Ok(())
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triplet(i: u32) -> Triplet {
+        Triplet { row: i / 100, col: i % 100, value: i as f64 }
+    }
+
+    /// Lengths of the `entries` list in each framed message of a stream.
+    fn chunk_lengths(mut bytes: &[u8]) -> Vec<u32> {
+        let mut lengths = Vec::new();
+        while !bytes.is_empty() {
+            let message = serialize::read_message(&mut bytes, Default::default()).unwrap();
+            lengths.push(message.get_root::<schema_capnp::triplet_matrix::Reader>().unwrap().get_entries().unwrap().len());
+        }
+        lengths
+    }
+
+    fn round_trip(count: u32) -> Vec<u8> {
+        // Entries already in the header are not written; the iterator is what gets streamed
+        let header = TripletMatrix { rows: 7, cols: 100, entries: vec![triplet(999_999)] };
+        let mut bytes = Vec::new();
+        header.write_streamed(&mut bytes, (0..count).map(triplet)).unwrap();
+
+        let (read, entries) = TripletMatrix::read_streamed(&bytes[..]).unwrap();
+        assert_eq!((read.rows, read.cols), (7, 100));
+        assert!(read.entries.is_empty());
+        let entries = entries.collect::<capnp::Result<Vec<_>>>().unwrap();
+        assert_eq!(entries, (0..count).map(triplet).collect::<Vec<_>>());
+        bytes
+    }
+
+    #[test]
+    fn entries_span_several_chunks() {
+        let chunk = schema_capnp::STREAM_CHUNK as u32;
+        let bytes = round_trip(3 * chunk + 17);
+        // Header, three full chunks, the remainder, and the empty terminator
+        assert_eq!(chunk_lengths(&bytes), [0, chunk, chunk, chunk, 17, 0]);
+    }
+
+    #[test]
+    fn a_whole_number_of_chunks_ends_with_just_the_terminator() {
+        let chunk = schema_capnp::STREAM_CHUNK as u32;
+        let bytes = round_trip(2 * chunk);
+        assert_eq!(chunk_lengths(&bytes), [0, chunk, chunk, 0]);
+    }
+
+    #[test]
+    fn no_entries() {
+        let bytes = round_trip(0);
+        assert_eq!(chunk_lengths(&bytes), [0, 0]);
+    }
+
+    #[test]
+    fn a_stream_cut_short_fails_instead_of_ending_early() {
+        let chunk = schema_capnp::STREAM_CHUNK as u32;
+        let header = TripletMatrix { rows: 1, cols: 1, entries: Vec::new() };
+        let mut bytes = Vec::new();
+        header.write_streamed(&mut bytes, (0..2 * chunk).map(triplet)).unwrap();
+        bytes.truncate(bytes.len() - 8);
+
+        let (_, entries) = TripletMatrix::read_streamed(&bytes[..]).unwrap();
+        let results = entries.collect::<Vec<_>>();
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 2 * chunk as usize);
+        assert!(results.last().unwrap().is_err());
+    }
+}
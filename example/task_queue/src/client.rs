@@ -0,0 +1,49 @@
+use futures::StreamExt;
+use crate::schema_capnp::{health, optional_task, task_queue};
+use crate::{LogEntry, Ping, Pong, Task, TaskStatus};
+
+pub async fn submit(task_queue: &task_queue::Client, task: &Task) -> capnp::Result<u64> {
+    let mut request = task_queue.submit_request();
+    task.to_capnp(request.get().init_task());
+    let response = request.send().promise.await?;
+    Ok(response.get()?.get_id())
+}
+
+pub async fn query(task_queue: &task_queue::Client, id: u64) -> capnp::Result<Option<Task>> {
+    let mut request = task_queue.query_request();
+    request.get().init_handle().set_id(id);
+    let response = request.send().promise.await?;
+    match response.get()?.which()? {
+        optional_task::Which::Value(task) => Ok(Some(Task::from_capnp(task?)?)),
+        optional_task::Which::None(()) => Ok(None),
+    }
+}
+
+/// Takes any `Health` capability; a `task_queue::Client` converts with `cast_to()`.
+pub async fn ping(health: &health::Client) -> capnp::Result<Pong> {
+    let mut request = health.ping_request();
+    Ping.to_capnp(request.get().init_request());
+    let response = request.send().promise.await?;
+    Pong::from_capnp(response.get()?)
+}
+
+/// Subscribes to a task and takes the updates it streams until it finishes, returning its final
+/// status and every log entry seen.
+pub async fn follow(task_queue: &task_queue::Client, id: u64) -> capnp::Result<(TaskStatus, Vec<LogEntry>)> {
+    let mut updates = task_queue.subscribe_stream(|params| {
+        let mut query = params.init_query();
+        query.set_id(id);
+        query.set_since(0);
+    });
+    let mut status = TaskStatus::Queued;
+    let mut logs = Vec::new();
+    while let Some(update) = updates.next().await {
+        let update = update?;
+        for entry in update.logs {
+            println!("task {} @{}: {}", id, entry.timestamp, entry.message);
+            logs.push(entry);
+        }
+        status = update.status;
+    }
+    Ok((status, logs))
+}
@@ -0,0 +1,86 @@
+//! Message types and RPC interface of the task queue, with its server and client in `server` and
+//! `client`. `tests/task_queue.rs` drives both end to end.
+
+use capnez_macros::capnp;
+use capnez_codegen::capnp_include;
+
+capnp_include!();
+
+pub mod client;
+pub mod server;
+
+#[capnp]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TaskStatus {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+}
+
+#[capnp]
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogEntry {
+    pub timestamp: u64,
+    pub message: String,
+}
+
+#[capnp]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Owner {
+    pub name: String,
+    pub team: Option<String>,
+}
+
+#[capnp]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Task {
+    pub id: u64,
+    pub title: String,
+    pub description: Option<String>,
+    pub priority: Option<u32>,
+    pub status: TaskStatus,
+    pub owner: Owner,
+    pub logs: Vec<LogEntry>,
+}
+
+#[capnp]
+pub struct TaskHandle {
+    pub id: u64,
+}
+
+#[capnp]
+pub struct TaskQuery {
+    pub id: u64,
+    pub since: u32,
+}
+
+#[capnp]
+pub struct TaskUpdate {
+    pub status: TaskStatus,
+    pub logs: Vec<LogEntry>,
+}
+
+/// A liveness check carries nothing either way, so both sides are unit structs.
+#[capnp]
+#[derive(Debug, PartialEq)]
+pub struct Ping;
+
+#[capnp]
+#[derive(Debug, PartialEq)]
+pub struct Pong;
+
+/// Liveness on its own, so a monitor can be handed a capability that cannot touch any task.
+#[capnp]
+pub trait Health {
+    fn ping(request: Ping) -> Pong;
+}
+
+#[capnp]
+pub trait TaskQueue: Health {
+    fn submit(task: Task) -> TaskHandle;
+    fn query(handle: TaskHandle) -> Option<Task>;
+    /// Runs the task to completion, streaming an update for every step.
+    #[capnp(stream)]
+    fn subscribe(query: TaskQuery) -> Vec<TaskUpdate>;
+}
@@ -0,0 +1,35 @@
+use capnez::rpc::local_pair;
+use std::error::Error;
+use std::path::PathBuf;
+use task_queue::schema_capnp::task_queue;
+use task_queue::{client, server, Owner, Task, TaskStatus};
+
+/// Submits one task and follows it to completion. The checks live in `tests/task_queue.rs`.
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let dir = PathBuf::from(env!("OUT_DIR")).join("target");
+    std::fs::create_dir_all(&dir)?;
+    let log_path = dir.join("tasks.log");
+    let _ = std::fs::remove_file(&log_path);
+
+    let task = Task {
+        id: 0,
+        title: "Rebuild search index".to_string(),
+        description: Some("Nightly full rebuild".to_string()),
+        priority: None,
+        status: TaskStatus::Queued,
+        owner: Owner { name: "Ada".to_string(), team: None },
+        logs: Vec::new(),
+    };
+
+    tokio::task::LocalSet::new().run_until(async move {
+        let server: task_queue::Client = capnp_rpc::new_client(server::TaskQueueImpl::open(&log_path)?);
+        let (task_queue, rpc_system) = local_pair(server);
+        tokio::task::spawn_local(rpc_system);
+
+        let id = client::submit(&task_queue, &task).await?;
+        let (status, _) = client::follow(&task_queue, id).await?;
+        println!("task {} finished as {:?}; log at {}", id, status, log_path.display());
+        Ok::<(), Box<dyn Error>>(())
+    }).await
+}
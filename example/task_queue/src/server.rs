@@ -0,0 +1,123 @@
+use capnez::io::{MessageLogReader, MessageLogWriter};
+use capnp::capability::Promise;
+use capnp::message::ReaderOptions;
+use capnp_rpc::pry;
+use std::collections::BTreeMap;
+use std::path::Path;
+use crate::schema_capnp::{health, task_queue};
+use crate::{LogEntry, Ping, Pong, Task, TaskStatus, TaskUpdate};
+
+/// Replays the task log; later snapshots of a task replace earlier ones.
+pub fn load_tasks(path: &Path) -> capnp::Result<BTreeMap<u64, Task>> {
+    let mut tasks = BTreeMap::new();
+    if !path.exists() {
+        return Ok(tasks);
+    }
+    for message in MessageLogReader::open(path)?.iter(ReaderOptions::new()) {
+        let task = Task::from_capnp(message?.get_root()?)?;
+        tasks.insert(task.id, task);
+    }
+    Ok(tasks)
+}
+
+pub struct TaskQueueImpl {
+    tasks: BTreeMap<u64, Task>,
+    log: MessageLogWriter,
+    next_id: u64,
+    clock: u64,
+}
+
+impl TaskQueueImpl {
+    pub fn open(path: &Path) -> capnp::Result<Self> {
+        let tasks = load_tasks(path)?;
+        let next_id = tasks.keys().max().map_or(1, |id| id + 1);
+        let clock = tasks.values().flat_map(|t| t.logs.iter().map(|e| e.timestamp)).max().unwrap_or(0);
+        let log = MessageLogWriter::open(path)?;
+        Ok(Self { tasks, log, next_id, clock })
+    }
+
+    /// Moves a task to `status`, records a log entry, and appends the new snapshot to the log.
+    fn transition(&mut self, id: u64, status: TaskStatus, message: &str) -> capnp::Result<LogEntry> {
+        self.clock += 1;
+        let task = self.tasks.get_mut(&id).ok_or_else(|| capnp::Error::failed(format!("no task with id {}", id)))?;
+        let entry = LogEntry { timestamp: self.clock, message: message.to_string() };
+        task.status = status;
+        task.logs.push(entry.clone());
+
+        let mut message = capnp::message::Builder::new_default();
+        task.to_capnp(message.init_root());
+        self.log.append(&message)?;
+        self.log.sync()?;
+        Ok(entry)
+    }
+}
+
+impl task_queue::Server for TaskQueueImpl {
+    fn submit(
+        &mut self,
+        params: task_queue::SubmitParams,
+        mut results: task_queue::SubmitResults,
+    ) -> Promise<(), ::capnp::Error> {
+        let mut task = pry!(Task::from_capnp(pry!(pry!(params.get()).get_task())));
+        task.id = self.next_id;
+        task.logs.clear();
+        self.next_id += 1;
+        self.tasks.insert(task.id, task.clone());
+        pry!(self.transition(task.id, TaskStatus::Queued, "submitted"));
+        results.get().set_id(task.id);
+        Promise::ok(())
+    }
+
+    fn query(
+        &mut self,
+        params: task_queue::QueryParams,
+        mut results: task_queue::QueryResults,
+    ) -> Promise<(), ::capnp::Error> {
+        let id = pry!(pry!(params.get()).get_handle()).get_id();
+        match self.tasks.get(&id) {
+            Some(task) => task.to_capnp(results.get().init_value()),
+            None => results.get().set_none(()),
+        }
+        Promise::ok(())
+    }
+
+    /// Streams where the task stands, then runs it to completion, streaming each step as it is logged.
+    fn subscribe(
+        &mut self,
+        params: task_queue::SubscribeParams,
+        _: task_queue::SubscribeResults,
+    ) -> Promise<(), ::capnp::Error> {
+        let query = pry!(pry!(params.get()).get_query());
+        let id = query.get_id();
+        let Some(task) = self.tasks.get(&id) else {
+            return Promise::err(capnp::Error::failed(format!("no task with id {}", id)));
+        };
+        let logs = task.logs.get(query.get_since() as usize..).unwrap_or_default();
+        let mut updates = vec![TaskUpdate { status: task.status, logs: logs.to_vec() }];
+
+        let mut status = task.status;
+        loop {
+            let (next, message) = match status {
+                TaskStatus::Queued => (TaskStatus::Running, "started"),
+                TaskStatus::Running => (TaskStatus::Completed, "finished"),
+                TaskStatus::Completed | TaskStatus::Failed => break,
+            };
+            let entry = pry!(self.transition(id, next, message));
+            updates.push(TaskUpdate { status: next, logs: vec![entry] });
+            status = next;
+        }
+        pry!(params.get()).send_stream(futures::stream::iter(updates))
+    }
+}
+
+impl health::Server for TaskQueueImpl {
+    fn ping(
+        &mut self,
+        params: health::PingParams,
+        mut results: health::PingResults,
+    ) -> Promise<(), ::capnp::Error> {
+        pry!(Ping::from_capnp(pry!(pry!(params.get()).get_request())));
+        Pong.to_capnp(results.get());
+        Promise::ok(())
+    }
+}
@@ -0,0 +1,100 @@
+//! `RequestExt` deadlines and cancellation against a server that never answers.
+
+use capnez::rpc::{local_pair, CallError, CallOptions, RequestExt};
+use capnp::capability::Promise;
+use std::cell::Cell;
+use std::error::Error;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use task_queue::schema_capnp::health;
+use task_queue::Ping;
+use tokio_util::sync::CancellationToken;
+
+/// Sets its flag when dropped, i.e. when the server lets go of the call.
+struct Dropped(Rc<Cell<bool>>);
+
+impl Drop for Dropped {
+    fn drop(&mut self) {
+        self.0.set(true);
+    }
+}
+
+/// Holds every `ping` open until it is cancelled.
+struct Stalled {
+    released: Rc<Cell<bool>>,
+}
+
+impl health::Server for Stalled {
+    fn ping(
+        &mut self,
+        _: health::PingParams,
+        _: health::PingResults,
+    ) -> Promise<(), capnp::Error> {
+        let guard = Dropped(self.released.clone());
+        Promise::from_future(async move {
+            let _guard = guard;
+            futures::future::pending::<Result<(), capnp::Error>>().await
+        })
+    }
+}
+
+/// A client of a [`Stalled`] server, and the flag telling whether its call was let go; must be
+/// called inside a `LocalSet`.
+fn stalled() -> (health::Client, Rc<Cell<bool>>) {
+    let released = Rc::new(Cell::new(false));
+    let (health, rpc_system) = local_pair::<health::Client>(capnp_rpc::new_client(Stalled { released: released.clone() }));
+    tokio::task::spawn_local(rpc_system);
+    (health, released)
+}
+
+async fn ping_with(health: &health::Client, options: CallOptions) -> Result<(), CallError> {
+    let mut request = health.ping_request();
+    Ping.to_capnp(request.get().init_request());
+    request.send_with(options).await.map(|_| ())
+}
+
+/// Waits until the server has dropped the call it was holding.
+async fn until_released(released: &Cell<bool>) {
+    for _ in 0..1000 {
+        if released.get() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("the server never let go of the call");
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn a_call_past_its_deadline_times_out() -> Result<(), Box<dyn Error>> {
+    tokio::task::LocalSet::new().run_until(async {
+        let (health, released) = stalled();
+        let started = Instant::now();
+        let options = CallOptions { timeout: Some(Duration::from_millis(100)), ..CallOptions::default() };
+        let err = ping_with(&health, options).await.err().expect("a stalled call completed");
+        assert!(matches!(err, CallError::Timeout(timeout) if timeout == Duration::from_millis(100)), "{}", err);
+        assert!(started.elapsed() < Duration::from_secs(5), "{:?}", started.elapsed());
+
+        // Giving up cancels the call on the server too
+        until_released(&released).await;
+        Ok::<(), Box<dyn Error>>(())
+    }).await
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn a_cancelled_token_cancels_the_call() -> Result<(), Box<dyn Error>> {
+    tokio::task::LocalSet::new().run_until(async {
+        let (health, released) = stalled();
+        let cancel = CancellationToken::new();
+        let trigger = cancel.clone();
+        tokio::task::spawn_local(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            trigger.cancel();
+        });
+
+        let options = CallOptions { timeout: Some(Duration::from_secs(30)), cancel: Some(cancel) };
+        let err = ping_with(&health, options).await.err().expect("a stalled call completed");
+        assert!(matches!(err, CallError::Canceled), "{}", err);
+        until_released(&released).await;
+        Ok::<(), Box<dyn Error>>(())
+    }).await
+}
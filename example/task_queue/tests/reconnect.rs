@@ -0,0 +1,103 @@
+//! `ReconnectingClient` across a server that is killed mid-call and restarted on the same port.
+
+use capnez::rpc::{serve_tcp_listener, CallError, ReconnectingClient, RetryPolicy, ServerOptions};
+use capnp::capability::{FromClientHook, Promise};
+use std::error::Error;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use task_queue::schema_capnp::{health, task_queue};
+use task_queue::{client, server, Owner, Pong, Task, TaskStatus};
+
+/// Serves what `server` builds on `listener` from its own thread, until the returned sender is dropped.
+fn spawn_server(
+    listener: TcpListener,
+    server: impl FnOnce() -> task_queue::Client + Send + 'static,
+) -> (tokio::sync::oneshot::Sender<()>, std::thread::JoinHandle<()>) {
+    listener.set_nonblocking(true).unwrap();
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let thread = std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(async move {
+            let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+            serve_tcp_listener(listener, server(), ServerOptions::default(), async { let _ = stopped.await; }).await.unwrap();
+        });
+    });
+    (stop, thread)
+}
+
+/// Never answers `ping`, counting the calls it holds; every other method is unimplemented.
+struct Stalled {
+    started: Arc<AtomicUsize>,
+}
+
+impl task_queue::Server for Stalled {}
+
+impl health::Server for Stalled {
+    fn ping(
+        &mut self,
+        _: health::PingParams,
+        _: health::PingResults,
+    ) -> Promise<(), capnp::Error> {
+        self.started.fetch_add(1, Ordering::SeqCst);
+        Promise::from_future(futures::future::pending())
+    }
+}
+
+fn task() -> Task {
+    Task {
+        id: 0,
+        title: "Rotate the signing keys".to_string(),
+        description: None,
+        priority: Some(2),
+        status: TaskStatus::Queued,
+        owner: Owner { name: "Ada".to_string(), team: None },
+        logs: Vec::new(),
+    }
+}
+
+async fn ping(task_queue: task_queue::Client) -> Result<Pong, CallError> {
+    Ok(client::ping(&task_queue.cast_to()).await?)
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn calls_fail_fast_across_a_restart_and_then_reconnect() -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr: SocketAddr = listener.local_addr()?;
+    let started = Arc::new(AtomicUsize::new(0));
+    let held = started.clone();
+    let (stop, thread) = spawn_server(listener, move || capnp_rpc::new_client(Stalled { started: held }));
+
+    let dir = tempfile::tempdir()?;
+    let log = dir.path().join("tasks.log");
+    let policy = RetryPolicy { initial_delay: Duration::from_millis(10), max_delay: Duration::from_millis(100), ..RetryPolicy::default() };
+    let task_queue = ReconnectingClient::<task_queue::Client>::tcp(addr, policy);
+
+    tokio::task::LocalSet::new().run_until(async move {
+        let pinging = task_queue.clone();
+        let in_flight = tokio::task::spawn_local(async move { pinging.call(ping).await });
+        while started.load(Ordering::SeqCst) == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // Kill the server with the call still held: it fails as soon as the connection drops
+        drop(stop);
+        let killed = Instant::now();
+        thread.join().unwrap();
+        let failed = tokio::time::timeout(Duration::from_secs(5), in_flight).await??;
+        assert!(matches!(failed, Err(CallError::Retryable(_))), "{:?}", failed);
+        assert!(killed.elapsed() < Duration::from_secs(5));
+
+        // Restart on the same port; the next call reconnects and goes through
+        let (stop, thread) = spawn_server(TcpListener::bind(addr)?, move || capnp_rpc::new_client(server::TaskQueueImpl::open(&log).unwrap()));
+        let id = task_queue.call(|task_queue| async move { client::submit(&task_queue, &task()).await.map_err(CallError::from) }).await?;
+        let submitted = task_queue.call(|task_queue| async move { client::query(&task_queue, id).await.map_err(CallError::from) }).await?;
+        assert_eq!(submitted.map(|t| t.title), Some(task().title));
+        assert_eq!(task_queue.call(ping).await?, Pong);
+
+        drop(stop);
+        thread.join().unwrap();
+        Ok::<(), Box<dyn Error>>(())
+    }).await
+}
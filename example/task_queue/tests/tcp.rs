@@ -0,0 +1,243 @@
+//! `capnez::rpc` over real sockets, with the task queue as the served interface.
+
+use capnez::rpc::{connect_tcp, serve_tcp_listener, ServerOptions, ServerStats, WhenFull};
+use capnp::capability::{FromClientHook, Promise};
+use std::error::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use task_queue::schema_capnp::{health, task_queue};
+use task_queue::{client, server, Owner, Pong, Task, TaskStatus};
+use tokio::sync::Semaphore;
+
+fn task() -> Task {
+    Task {
+        id: 0,
+        title: "Compact the event log".to_string(),
+        description: None,
+        priority: Some(1),
+        status: TaskStatus::Queued,
+        owner: Owner { name: "Ada".to_string(), team: None },
+        logs: Vec::new(),
+    }
+}
+
+/// Serves what `server` builds on an ephemeral port from its own thread, until the returned sender
+/// is dropped. Returns the bound address and every stats update the server reported.
+fn spawn_server(
+    server: impl FnOnce() -> task_queue::Client + Send + 'static,
+    options: ServerOptions,
+) -> (std::net::SocketAddr, tokio::sync::oneshot::Sender<()>, std::thread::JoinHandle<()>, Arc<Mutex<Vec<ServerStats>>>) {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let stats = Arc::new(Mutex::new(Vec::new()));
+    let reported = stats.clone();
+    let options = ServerOptions { on_stats: Some(Arc::new(move |s| reported.lock().unwrap().push(s))), ..options };
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let thread = std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(async move {
+            let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+            serve_tcp_listener(listener, server(), options, async { let _ = stopped.await; }).await.unwrap();
+        });
+    });
+    (addr, stop, thread, stats)
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn one_call_over_an_ephemeral_port() -> Result<(), Box<dyn Error>> {
+    let dir = tempfile::tempdir()?;
+    let log = dir.path().join("tasks.log");
+    let (addr, stop, thread, stats) = spawn_server(move || capnp_rpc::new_client(server::TaskQueueImpl::open(&log).unwrap()), ServerOptions::default());
+
+    tokio::task::LocalSet::new().run_until(async move {
+        let (task_queue, rpc_system) = connect_tcp::<task_queue::Client>(addr).await?;
+        tokio::task::spawn_local(rpc_system);
+        assert_eq!(client::ping(&task_queue.cast_to()).await?, Pong);
+        Ok::<(), Box<dyn Error>>(())
+    }).await?;
+
+    drop(stop);
+    thread.join().unwrap();
+    let stats = stats.lock().unwrap();
+    assert!(stats.iter().any(|s| s.connections == 1), "{:?}", stats);
+    assert!(stats.iter().all(|s| s.accept_errors == 0), "{:?}", stats);
+    Ok(())
+}
+
+/// Answers `ping` only once the test adds a permit; every other method is unimplemented.
+struct Held {
+    started: Arc<AtomicUsize>,
+    release: Arc<Semaphore>,
+}
+
+impl task_queue::Server for Held {}
+
+impl health::Server for Held {
+    fn ping(
+        &mut self,
+        _: health::PingParams,
+        mut results: health::PingResults,
+    ) -> Promise<(), capnp::Error> {
+        self.started.fetch_add(1, Ordering::SeqCst);
+        let release = self.release.clone();
+        Promise::from_future(async move {
+            release.acquire().await.map_err(|e| capnp::Error::failed(e.to_string()))?.forget();
+            Pong.to_capnp(results.get());
+            Ok(())
+        })
+    }
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn calls_beyond_the_limit_wait_and_then_fail() -> Result<(), Box<dyn Error>> {
+    let started = Arc::new(AtomicUsize::new(0));
+    let release = Arc::new(Semaphore::new(0));
+    let held = Held { started: started.clone(), release: release.clone() };
+    let options = ServerOptions { max_inflight_calls_per_connection: 2, max_queued_calls_per_connection: 1, ..ServerOptions::default() };
+    let (addr, stop, thread, stats) = spawn_server(move || capnp_rpc::new_client(held), options);
+
+    tokio::task::LocalSet::new().run_until(async move {
+        let (task_queue, rpc_system) = connect_tcp::<task_queue::Client>(addr).await?;
+        tokio::task::spawn_local(rpc_system);
+        let mut pings = (0..4).map(|_| {
+            let health = task_queue.clone().cast_to();
+            tokio::task::spawn_local(async move { client::ping(&health).await })
+        }).collect::<Vec<_>>();
+
+        // Two run, one waits, and the fourth is turned away while the others are still held, so the
+        // connection was read past the limit
+        let rejected = tokio::time::timeout(Duration::from_secs(10), pings.pop().unwrap()).await??.unwrap_err();
+        assert_eq!(rejected.kind, capnp::ErrorKind::Overloaded, "{}", rejected);
+        while started.load(Ordering::SeqCst) < 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(started.load(Ordering::SeqCst), 2);
+
+        release.add_permits(3);
+        for ping in pings {
+            assert_eq!(tokio::time::timeout(Duration::from_secs(10), ping).await???, Pong);
+        }
+        assert_eq!(started.load(Ordering::SeqCst), 3);
+        Ok::<(), Box<dyn Error>>(())
+    }).await?;
+
+    drop(stop);
+    thread.join().unwrap();
+    let stats = stats.lock().unwrap();
+    assert!(stats.iter().any(|s| s.inflight_calls == 2), "{:?}", stats);
+    assert!(stats.iter().all(|s| s.inflight_calls <= 2), "{:?}", stats);
+    Ok(())
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn streams_back_to_the_client_at_the_call_limit() -> Result<(), Box<dyn Error>> {
+    let dir = tempfile::tempdir()?;
+    let log = dir.path().join("tasks.log");
+    let options = ServerOptions { max_inflight_calls_per_connection: 1, ..ServerOptions::default() };
+    let (addr, stop, thread, _) = spawn_server(move || capnp_rpc::new_client(server::TaskQueueImpl::open(&log).unwrap()), options);
+
+    // `subscribe` takes the one slot while the server pushes its updates to the client's receiver,
+    // whose returns must still be read
+    tokio::task::LocalSet::new().run_until(async move {
+        let (task_queue, rpc_system) = connect_tcp::<task_queue::Client>(addr).await?;
+        tokio::task::spawn_local(rpc_system);
+        let id = client::submit(&task_queue, &task()).await?;
+        let (status, logs) = tokio::time::timeout(Duration::from_secs(10), client::follow(&task_queue, id)).await??;
+        assert_eq!(status, TaskStatus::Completed);
+        assert_eq!(logs.len(), 3);
+        Ok::<(), Box<dyn Error>>(())
+    }).await?;
+
+    drop(stop);
+    thread.join().unwrap();
+    Ok(())
+}
+
+/// A connection whose `RpcSystem` runs as a local task, so aborting the task closes the connection.
+async fn open(addr: std::net::SocketAddr) -> Result<(task_queue::Client, tokio::task::JoinHandle<Result<(), capnp::Error>>), Box<dyn Error>> {
+    let (task_queue, rpc_system) = connect_tcp::<task_queue::Client>(addr).await?;
+    Ok((task_queue, tokio::task::spawn_local(rpc_system)))
+}
+
+/// Waits until the latest stats the server reported satisfy `done`.
+async fn wait_for(stats: &Mutex<Vec<ServerStats>>, done: impl Fn(&ServerStats) -> bool) {
+    for _ in 0..1000 {
+        if stats.lock().unwrap().last().is_some_and(&done) {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("the server never got there: {:?}", stats.lock().unwrap());
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn connections_beyond_the_limit_queue_until_one_closes() -> Result<(), Box<dyn Error>> {
+    let dir = tempfile::tempdir()?;
+    let log = dir.path().join("tasks.log");
+    let options = ServerOptions { max_connections: 1, when_full: WhenFull::Queue, ..ServerOptions::default() };
+    let (addr, stop, thread, stats) = spawn_server(move || capnp_rpc::new_client(server::TaskQueueImpl::open(&log).unwrap()), options);
+
+    let reported = stats.clone();
+    tokio::task::LocalSet::new().run_until(async move {
+        let (first, first_system) = open(addr).await?;
+        assert_eq!(client::ping(&first.cast_to()).await?, Pong);
+
+        // The second connection sits in the listener's backlog, unserved, while the first is open
+        let (second, _second_system) = open(addr).await?;
+        let waiting = tokio::task::spawn_local(async move { client::ping(&second.cast_to()).await });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!waiting.is_finished());
+
+        first_system.abort();
+        assert_eq!(tokio::time::timeout(Duration::from_secs(10), waiting).await???, Pong);
+        Ok::<(), Box<dyn Error>>(())
+    }).await?;
+
+    // Both connections are gone once their clients are, so no connection task outlives its client
+    wait_for(&reported, |s| s.connections == 0).await;
+    drop(stop);
+    thread.join().unwrap();
+    let stats = stats.lock().unwrap();
+    assert!(stats.iter().all(|s| s.connections <= 1), "{:?}", stats);
+    assert!(stats.iter().all(|s| s.rejected == 0), "{:?}", stats);
+    Ok(())
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn connections_beyond_the_limit_are_rejected() -> Result<(), Box<dyn Error>> {
+    let dir = tempfile::tempdir()?;
+    let log = dir.path().join("tasks.log");
+    let options = ServerOptions { max_connections: 1, when_full: WhenFull::Reject, ..ServerOptions::default() };
+    let (addr, stop, thread, stats) = spawn_server(move || capnp_rpc::new_client(server::TaskQueueImpl::open(&log).unwrap()), options);
+
+    let reported = stats.clone();
+    tokio::task::LocalSet::new().run_until(async move {
+        let (first, first_system) = open(addr).await?;
+        assert_eq!(client::ping(&first.cast_to()).await?, Pong);
+
+        // Closed as soon as it is accepted
+        let (second, _second_system) = open(addr).await?;
+        let rejected = tokio::time::timeout(Duration::from_secs(10), client::ping(&second.cast_to())).await?.unwrap_err();
+        assert_eq!(rejected.kind, capnp::ErrorKind::Disconnected, "{}", rejected);
+        wait_for(&reported, |s| s.rejected == 1).await;
+
+        // With the first connection closed there is room again
+        first_system.abort();
+        wait_for(&reported, |s| s.connections == 0).await;
+        let (third, third_system) = open(addr).await?;
+        assert_eq!(client::ping(&third.cast_to()).await?, Pong);
+        third_system.abort();
+        Ok::<(), Box<dyn Error>>(())
+    }).await?;
+
+    wait_for(&stats, |s| s.connections == 0).await;
+    drop(stop);
+    thread.join().unwrap();
+    let stats = stats.lock().unwrap();
+    assert!(stats.iter().all(|s| s.connections <= 1), "{:?}", stats);
+    assert_eq!(stats.last().unwrap().rejected, 1, "{:?}", stats);
+    Ok(())
+}
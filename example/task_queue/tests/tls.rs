@@ -0,0 +1,88 @@
+//! `capnez::rpc` over TLS with a self-signed certificate, trusted by one client and not another.
+
+use capnez::rpc::rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName};
+use capnez::rpc::rustls::{ClientConfig, RootCertStore, ServerConfig};
+use capnez::rpc::{connect_tls, serve_tls, ServerOptions};
+use capnp::capability::FromClientHook;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use task_queue::schema_capnp::task_queue;
+use task_queue::{client, server, Pong};
+
+/// A certificate for `localhost` and its private key.
+fn self_signed() -> (CertificateDer<'static>, PrivateKeyDer<'static>) {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let key = PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der());
+    (certified.cert.der().clone(), key.into())
+}
+
+/// A client config trusting only `cert`.
+fn trusting(cert: CertificateDer<'static>) -> Arc<ClientConfig> {
+    let mut roots = RootCertStore::empty();
+    roots.add(cert).unwrap();
+    Arc::new(ClientConfig::builder().with_root_certificates(roots).with_no_client_auth())
+}
+
+/// Serves a task queue over TLS on a free port from its own thread, until the returned sender is dropped.
+fn spawn_server(dir: &std::path::Path, config: Arc<ServerConfig>) -> (SocketAddr, tokio::sync::oneshot::Sender<()>, std::thread::JoinHandle<()>) {
+    let addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let log = dir.join("tasks.log");
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let thread = std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(async move {
+            let server: task_queue::Client = capnp_rpc::new_client(server::TaskQueueImpl::open(&log).unwrap());
+            serve_tls(addr, config, server, ServerOptions::default(), async { let _ = stopped.await; }).await.unwrap();
+        });
+    });
+    (addr, stop, thread)
+}
+
+/// Connects with `config`, retrying while the server is still binding its port; the handshake's own
+/// failure is returned as is. Must be called inside a `LocalSet`.
+async fn connect(addr: SocketAddr, config: Arc<ClientConfig>) -> std::io::Result<task_queue::Client> {
+    let mut attempts = 0;
+    loop {
+        let name = ServerName::try_from("localhost").unwrap();
+        match connect_tls::<task_queue::Client>(addr, config.clone(), name).await {
+            Ok((task_queue, rpc_system)) => {
+                tokio::task::spawn_local(rpc_system);
+                return Ok(task_queue);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused && attempts < 200 => {
+                attempts += 1;
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn a_call_over_tls_and_an_untrusted_certificate_fails_to_connect() -> Result<(), Box<dyn Error>> {
+    let dir = tempfile::tempdir()?;
+    let (cert, key) = self_signed();
+    let config = ServerConfig::builder().with_no_client_auth().with_single_cert(vec![cert.clone()], key)?;
+    let (addr, stop, thread) = spawn_server(dir.path(), Arc::new(config));
+
+    tokio::task::LocalSet::new().run_until(async move {
+        let task_queue = connect(addr, trusting(cert)).await?;
+        assert_eq!(client::ping(&task_queue.clone().cast_to()).await?, Pong);
+
+        // Trusting some other certificate, the handshake fails promptly instead of hanging
+        let (other, _) = self_signed();
+        let rejected = tokio::time::timeout(Duration::from_secs(10), connect(addr, trusting(other))).await?;
+        let err = rejected.err().expect("a client not trusting the certificate connected");
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData, "{}", err);
+
+        // The failed handshake cost only its own connection
+        assert_eq!(client::ping(&task_queue.cast_to()).await?, Pong);
+        Ok::<(), Box<dyn Error>>(())
+    }).await?;
+
+    drop(stop);
+    thread.join().unwrap();
+    Ok(())
+}
@@ -0,0 +1,94 @@
+//! `capnez::rpc` over a Unix domain socket: a call, and the lifecycle of the socket file.
+#![cfg(unix)]
+
+use capnez::rpc::{connect_unix, serve_unix, ServerOptions};
+use capnp::capability::FromClientHook;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use task_queue::schema_capnp::task_queue;
+use task_queue::{client, server, Pong};
+
+/// Serves a task queue logging to `dir` on the socket at `path` from its own thread, until the returned
+/// sender is dropped. The thread's result is what `serve_unix` returned.
+fn spawn_server(dir: &Path, path: &Path) -> (tokio::sync::oneshot::Sender<()>, std::thread::JoinHandle<std::io::Result<()>>) {
+    let log = dir.join("tasks.log");
+    let path = path.to_path_buf();
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let thread = std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(async move {
+            let server: task_queue::Client = capnp_rpc::new_client(server::TaskQueueImpl::open(&log).unwrap());
+            serve_unix(&path, server, ServerOptions::default(), async { let _ = stopped.await; }).await
+        })
+    });
+    (stop, thread)
+}
+
+/// Pings the server at `path`, retrying until it has bound the socket; must be called inside a `LocalSet`.
+async fn ping(path: &Path) -> Result<Pong, Box<dyn Error>> {
+    for _ in 0..200 {
+        if let Ok((task_queue, rpc_system)) = connect_unix::<task_queue::Client>(path).await {
+            tokio::task::spawn_local(rpc_system);
+            return Ok(client::ping(&task_queue.cast_to()).await?);
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    Err(format!("no server came up on {}", path.display()).into())
+}
+
+fn socket(dir: &Path) -> PathBuf {
+    dir.join("queue.sock")
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn one_call_and_the_socket_file_is_removed_on_shutdown() -> Result<(), Box<dyn Error>> {
+    let dir = tempfile::tempdir()?;
+    let path = socket(dir.path());
+    let (stop, thread) = spawn_server(dir.path(), &path);
+
+    tokio::task::LocalSet::new().run_until(ping(&path)).await?;
+    assert!(path.exists());
+
+    drop(stop);
+    thread.join().unwrap()?;
+    assert!(!path.exists(), "{} was left behind", path.display());
+    Ok(())
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn a_stale_socket_file_is_replaced() -> Result<(), Box<dyn Error>> {
+    let dir = tempfile::tempdir()?;
+    let path = socket(dir.path());
+    // A listener that goes away without removing its file, as after a crash
+    drop(std::os::unix::net::UnixListener::bind(&path)?);
+    assert!(path.exists());
+
+    let (stop, thread) = spawn_server(dir.path(), &path);
+    assert_eq!(tokio::task::LocalSet::new().run_until(ping(&path)).await?, Pong);
+
+    drop(stop);
+    thread.join().unwrap()?;
+    assert!(!path.exists());
+    Ok(())
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn a_live_listener_is_addr_in_use() -> Result<(), Box<dyn Error>> {
+    let dir = tempfile::tempdir()?;
+    let path = socket(dir.path());
+    let (stop, thread) = spawn_server(dir.path(), &path);
+    tokio::task::LocalSet::new().run_until(ping(&path)).await?;
+
+    let second = tempfile::tempdir()?;
+    let (_, rejected) = spawn_server(second.path(), &path);
+    let err = rejected.join().unwrap().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::AddrInUse, "{}", err);
+
+    // The first server keeps its socket
+    assert_eq!(tokio::task::LocalSet::new().run_until(ping(&path)).await?, Pong);
+    drop(stop);
+    thread.join().unwrap()?;
+    assert!(!path.exists());
+    Ok(())
+}
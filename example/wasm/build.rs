@@ -0,0 +1,3 @@
+fn main() {
+    capnez_codegen::generate_schema().expect("Failed to generate schema");
+}
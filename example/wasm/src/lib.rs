@@ -0,0 +1,54 @@
+//! A `#[capnp]` interface served and called over message ports, as a browser page and a worker would,
+//! with no tokio and no sockets. `tests/ports.rs` runs both ends under `wasm-bindgen-test`.
+
+use capnez_macros::capnp;
+use capnp::capability::Promise;
+use capnp_rpc::pry;
+
+// `capnp_include!` comes from the host-only `capnez-codegen`, so the generated module is included directly
+#[allow(unexpected_cfgs)]
+pub mod schema_capnp {
+    include!(concat!(env!("OUT_DIR"), "/generated/schema_capnp.rs"));
+}
+
+use schema_capnp::greeter;
+
+#[capnp]
+#[derive(Debug, PartialEq)]
+pub struct Greeting {
+    pub name: String,
+    pub times: u32,
+}
+
+#[capnp]
+#[derive(Debug, PartialEq)]
+pub struct Reply {
+    pub message: String,
+}
+
+#[capnp]
+pub trait Greeter {
+    fn greet(greeting: Greeting) -> Reply;
+}
+
+pub struct GreeterImpl;
+
+impl greeter::Server for GreeterImpl {
+    fn greet(
+        &mut self,
+        params: greeter::GreetParams,
+        mut results: greeter::GreetResults,
+    ) -> Promise<(), ::capnp::Error> {
+        let greeting = pry!(Greeting::from_capnp(pry!(pry!(params.get()).get_greeting())));
+        let message = vec![format!("Hello, {}!", greeting.name); greeting.times as usize].join(" ");
+        Reply { message }.to_capnp(results.get());
+        Promise::ok(())
+    }
+}
+
+pub async fn greet(greeter: &greeter::Client, greeting: &Greeting) -> capnp::Result<Reply> {
+    let mut request = greeter.greet_request();
+    greeting.to_capnp(request.get().init_greeting());
+    let response = request.send().promise.await?;
+    Reply::from_capnp(response.get()?)
+}
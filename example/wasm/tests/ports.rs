@@ -0,0 +1,61 @@
+//! Round trips between a client and a server joined by a pair of channels standing in for a
+//! `MessagePort`. Run in a JavaScript engine with `wasm-pack test --node example/wasm`.
+
+use capnez_wasm::schema_capnp::greeter;
+use capnez_wasm::{greet, Greeting, GreeterImpl};
+use futures::channel::mpsc;
+use futures::future::{self, Either};
+use std::future::Future;
+use wasm_bindgen_test::wasm_bindgen_test;
+
+/// A client connected to a fresh server, and the future driving both ends of the port.
+fn connect() -> (greeter::Client, impl Future<Output = Result<(), capnp::Error>>) {
+    let (to_server, from_client) = mpsc::unbounded::<Vec<u8>>();
+    let (to_client, from_server) = mpsc::unbounded::<Vec<u8>>();
+    let server = capnez::wasm::serve_port(to_client, from_client, capnp_rpc::new_client::<greeter::Client, _>(GreeterImpl));
+    let (client, client_system) = capnez::wasm::connect_port::<greeter::Client, _, _>(to_server, from_server);
+    (client, async move { future::try_join(client_system, server).await.map(|_| ()) })
+}
+
+/// Runs `call` while polling the connection, failing if the connection ends first.
+async fn run<T>(connection: impl Future<Output = Result<(), capnp::Error>>, call: impl Future<Output = T>) -> T {
+    futures::pin_mut!(connection, call);
+    match future::select(call, connection).await {
+        Either::Left((value, _)) => value,
+        Either::Right((result, _)) => panic!("connection ended during the call: {:?}", result),
+    }
+}
+
+#[wasm_bindgen_test]
+async fn one_call_round_trips() {
+    let (client, connection) = connect();
+    let greeting = Greeting { name: "wasm".to_string(), times: 2 };
+    let reply = run(connection, greet(&client, &greeting)).await.unwrap();
+    assert_eq!(reply.message, "Hello, wasm! Hello, wasm!");
+}
+
+#[wasm_bindgen_test]
+async fn calls_share_one_connection() {
+    let (client, connection) = connect();
+    let calls = async {
+        let mut messages = Vec::new();
+        for times in 0..3 {
+            let greeting = Greeting { name: "again".to_string(), times };
+            messages.push(greet(&client, &greeting).await.unwrap().message);
+        }
+        messages
+    };
+    let messages = run(connection, calls).await;
+    assert_eq!(messages, ["", "Hello, again!", "Hello, again! Hello, again!"]);
+}
+
+#[wasm_bindgen_test]
+async fn calls_fail_once_the_server_is_gone() {
+    let (to_server, from_client) = mpsc::unbounded::<Vec<u8>>();
+    let (to_client, from_server) = mpsc::unbounded::<Vec<u8>>();
+    drop((from_client, to_client));
+    let (client, connection) = capnez::wasm::connect_port::<greeter::Client, _, _>(to_server, from_server);
+    let greeting = Greeting { name: "nobody".to_string(), times: 1 };
+    let (call, _) = future::join(greet(&client, &greeting), connection).await;
+    assert!(call.is_err());
+}
@@ -0,0 +1,19 @@
+#![no_main]
+
+use capnez_fuzz::{Device, Frame};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Anything that decodes has to survive a round trip unchanged
+    if let Ok(device) = Device::from_capnp_bytes(data) {
+        let again = Device::from_capnp_bytes(&device.to_capnp_bytes()).expect("a re-encoded device decodes");
+        assert_eq!(again, device);
+    }
+
+    let options = capnp::message::ReaderOptions::new();
+    if let Ok(message) = capnp::serialize::read_message_from_flat_slice(&mut &data[..], options) {
+        if let Ok(root) = message.get_root() {
+            let _ = Frame::from_capnp(root);
+        }
+    }
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use capnez::limits::DecodeLimits;
+use capnez_fuzz::Device;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Tight limits, so the fuzzer reaches them quickly
+    let limits = DecodeLimits { max_message_bytes: Some(1 << 16), traversal_limit_words: Some(1 << 12), nesting_limit: 8 };
+    let _ = Device::from_capnp_bytes_limited(data, &limits);
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use capnez_fuzz::Device;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(device) = capnez_serde::from_bytes::<Device>(data) {
+        let bytes = capnez_serde::to_bytes(&device).expect("a decoded device encodes");
+        let again: Device = capnez_serde::from_bytes(&bytes).expect("a re-encoded device decodes");
+        assert_eq!(again, device);
+    }
+});
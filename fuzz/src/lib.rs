@@ -0,0 +1,47 @@
+//! Message types the fuzz targets decode: between them they cover nested structs, lists of structs,
+//! text and data, nested lists, fixed-size arrays, enums, `Option` unions inside and outside lists,
+//! `#[capnp(validate(...))]` constraints and fields borrowed from the message.
+
+use capnez_codegen::capnp_include;
+use capnez_macros::capnp;
+use serde::{Deserialize, Serialize};
+
+capnp_include!();
+
+#[capnp]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Kind {
+    Sensor,
+    Actuator,
+    Gateway,
+}
+
+#[capnp]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Tag {
+    pub key: String,
+    pub value: Option<String>,
+}
+
+#[capnp]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Device {
+    pub id: u64,
+    #[capnp(validate(non_empty, max_len = 64))]
+    pub name: String,
+    pub kind: Kind,
+    pub firmware: Vec<u8>,
+    pub tags: Vec<Tag>,
+    pub parent: Option<Tag>,
+    pub samples: Vec<Vec<i32>>,
+    pub position: [i32; 3],
+    pub labels: Vec<Option<String>>,
+}
+
+#[capnp]
+#[derive(Debug, PartialEq)]
+pub struct Frame<'a> {
+    pub topic: &'a str,
+    pub payload: &'a [u8],
+    pub device: Device,
+}
@@ -0,0 +1,33 @@
+//! Replays the inputs in `regressions/` under a plain `cargo test`, so a decoding crash the fuzzer
+//! found stays fixed. Each is malformed, so decoding must fail, and fail without panicking or
+//! allocating for the elements a list only claims to have.
+
+use capnez::limits::DecodeLimits;
+use capnez_fuzz::Device;
+use std::path::{Path, PathBuf};
+
+fn inputs() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("regressions");
+    let mut inputs = std::fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()).collect::<Vec<_>>();
+    inputs.sort();
+    assert!(!inputs.is_empty(), "no regression inputs found");
+    inputs
+}
+
+#[test]
+fn regressions_fail_under_limits() {
+    // The limits of the `from_capnp_bytes_limited` target
+    let limits = DecodeLimits { max_message_bytes: Some(1 << 16), traversal_limit_words: Some(1 << 12), nesting_limit: 8 };
+    for input in inputs() {
+        let bytes = std::fs::read(&input).unwrap();
+        assert!(Device::from_capnp_bytes_limited(&bytes, &limits).is_err(), "{} decoded", input.display());
+    }
+}
+
+#[test]
+fn regressions_fail_under_default_limits() {
+    for input in inputs() {
+        let bytes = std::fs::read(&input).unwrap();
+        assert!(Device::from_capnp_bytes(&bytes).is_err(), "{} decoded", input.display());
+    }
+}
@@ -1,6 +1,15 @@
+//! Marker attributes read by `capnez-codegen`.
+//!
+//! `#[capnp]` and `#[capnp_bytes]` only tag items and add a `capnp_schema()` accessor. All type mapping,
+//! naming and schema layout lives in `capnez-codegen`, so there is a single implementation to keep
+//! consistent and the generated schema does not depend on which crate expanded the attribute.
+//!
+//! The one check made here is for field types no capnp type can represent, such as tuples: capnez-codegen
+//! rejects them too, but from the build script, while a `compile_error!` here points at the field.
+
 use proc_macro::TokenStream;
 use quote::quote;
-use syn::{parse_macro_input, Item, ItemStruct, ItemEnum, Ident, Generics, Attribute, Meta};
+use syn::{parse_macro_input, Item, ItemStruct, ItemEnum, Ident, Generics, Attribute, Meta, TraitItem, FnArg, Fields, GenericArgument, PathArguments, Type};
 
 #[proc_macro_attribute]
 pub fn capnp_bytes(_attr: TokenStream, item: TokenStream) -> TokenStream {
@@ -12,6 +21,7 @@ pub fn capnp_bytes(_attr: TokenStream, item: TokenStream) -> TokenStream {
             attrs.push(syn::parse_quote!(#[capnp_bytes]));
             let mut new_item = item.clone();
             new_item.attrs = attrs;
+            new_item.fields.iter_mut().for_each(|f| strip_capnp_attrs(&mut f.attrs));
             impl_capnp_item(new_item)
         }
         _ => panic!("The #[capnp_bytes] attribute can only be used on structs"),
@@ -23,17 +33,84 @@ pub fn capnp(_attr: TokenStream, item: TokenStream) -> TokenStream {
     let input = parse_macro_input!(item);
     
     match input {
-        Item::Struct(item) => impl_capnp_item(item),
-        Item::Enum(item) => impl_capnp_item(item),
-        Item::Trait(item) => TokenStream::from(quote! { #item }),
+        Item::Struct(mut item) => {
+            let errors = unmappable_fields(&item.fields).map(|e| e.to_compile_error());
+            item.fields.iter_mut().for_each(|f| strip_capnp_attrs(&mut f.attrs));
+            let mut tokens = impl_capnp_item(item);
+            tokens.extend(errors.map(TokenStream::from));
+            tokens
+        }
+        Item::Enum(mut item) => {
+            item.variants.iter_mut().for_each(|v| strip_capnp_attrs(&mut v.attrs));
+            impl_capnp_item(item)
+        }
+        Item::Trait(mut item) => {
+            for trait_item in &mut item.items {
+                if let TraitItem::Fn(method) = trait_item {
+                    strip_capnp_attrs(&mut method.attrs);
+                    for arg in &mut method.sig.inputs {
+                        if let FnArg::Typed(pat) = arg {
+                            strip_capnp_attrs(&mut pat.attrs);
+                        }
+                    }
+                }
+            }
+            TokenStream::from(quote! { #item })
+        }
         _ => panic!("The #[capnp] attribute can only be used on structs, enums, and traits"),
     }
 }
 
+/// Removes `#[capnp(rename = "...")]` from fields, variants, methods and parameters. Only `capnez-codegen`
+/// reads them, and left in place they would be expanded as nested attribute macros.
+fn strip_capnp_attrs(attrs: &mut Vec<Attribute>) {
+    attrs.retain(|attr| attr.path().segments.last().is_none_or(|seg| seg.ident != "capnp"));
+}
+
+/// One error spanning each field whose type, or a type inside it, has no capnp counterpart. Named types
+/// are left to capnez-codegen, the only place that knows which of them are annotated.
+fn unmappable_fields(fields: &Fields) -> Option<syn::Error> {
+    fields.iter()
+        .filter_map(|field| unmappable(&field.ty))
+        .map(|(ty, reason)| syn::Error::new_spanned(ty, format!("`{}` has no capnp type: {}", quote!(#ty), reason)))
+        .reduce(|mut all, error| {
+            all.combine(error);
+            all
+        })
+}
+
+fn unmappable(ty: &Type) -> Option<(&Type, &'static str)> {
+    match ty {
+        Type::Path(p) => {
+            let last = p.path.segments.last()?;
+            let PathArguments::AngleBracketed(args) = &last.arguments else { return None };
+            let inner = args.args.iter().filter_map(|arg| match arg {
+                GenericArgument::Type(inner) => Some(inner),
+                _ => None,
+            }).collect::<Vec<_>>();
+            let nested_option = matches!(inner.first(), Some(Type::Path(p)) if p.path.segments.last().is_some_and(|s| s.ident == "Option"));
+            if last.ident == "Option" && nested_option {
+                return Some((ty, "nested Option<Option<T>> is not supported; use a single Option or a wrapper struct"));
+            }
+            inner.into_iter().find_map(unmappable)
+        }
+        Type::Reference(r) => unmappable(&r.elem),
+        Type::Slice(s) => unmappable(&s.elem),
+        Type::Array(a) => unmappable(&a.elem),
+        Type::Paren(p) => unmappable(&p.elem),
+        Type::Group(g) => unmappable(&g.elem),
+        Type::Tuple(_) => Some((ty, "use a #[capnp] struct with named fields instead of a tuple")),
+        Type::BareFn(_) => Some((ty, "functions cannot be sent; use a #[capnp] trait for a callback")),
+        Type::Ptr(_) => Some((ty, "raw pointers cannot be sent; store the value itself")),
+        Type::ImplTrait(_) | Type::Infer(_) | Type::Never(_) => Some((ty, "fields need a concrete type")),
+        _ => None,
+    }
+}
+
 fn has_capnp_bytes_attr(attrs: &[Attribute]) -> bool {
     attrs.iter().any(|attr| {
         if let Meta::Path(path) = &attr.meta {
-            path.segments.last().map_or(false, |seg| seg.ident == "capnp_bytes")
+            path.segments.last().is_some_and(|seg| seg.ident == "capnp_bytes")
         } else {
             false
         }
@@ -43,14 +120,15 @@ fn has_capnp_bytes_attr(attrs: &[Attribute]) -> bool {
 fn impl_capnp_item<T: quote::ToTokens + HasIdent + HasGenerics + HasAttrs>(item: T) -> TokenStream {
     let name = &item.ident();
     let (impl_generics, ty_generics, where_clause) = item.generics().split_for_impl();
-    let is_bytes = has_capnp_bytes_attr(&item.attrs());
+    let is_bytes = has_capnp_bytes_attr(item.attrs());
+    let schema = schema_source();
     
     TokenStream::from(quote! {
         #item
 
         impl #impl_generics #name #ty_generics #where_clause {
             pub fn capnp_schema() -> &'static str {
-                include_str!(concat!(env!("OUT_DIR"), "/generated/schema.capnp"))
+                #schema
             }
 
             pub fn is_capnp_bytes() -> bool {
@@ -60,6 +138,17 @@ fn impl_capnp_item<T: quote::ToTokens + HasIdent + HasGenerics + HasAttrs>(item:
     })
 }
 
+/// Where `capnp_schema()` reads the schema from: the file the build script wrote to `OUT_DIR`, or, for
+/// crates without one (e.g. ones that check in code generated with a custom `output_dir`), the `SCHEMA`
+/// constant of `schema_capnp` at the crate root.
+fn schema_source() -> proc_macro2::TokenStream {
+    let built = std::env::var_os("OUT_DIR").map(|dir| std::path::Path::new(&dir).join("generated").join("schema.capnp"));
+    match built.filter(|path| path.is_file()).and_then(|path| path.to_str().map(str::to_string)) {
+        Some(path) => quote! { include_str!(#path) },
+        None => quote! { crate::schema_capnp::SCHEMA },
+    }
+}
+
 trait HasIdent {
     fn ident(&self) -> &Ident;
 }
@@ -107,3 +196,50 @@ impl HasAttrs for ItemEnum {
         &self.attrs
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// For each field `#[capnp]` rejects in `src`: the type its error spans, and the message.
+    fn errors(src: &str) -> Vec<(String, String)> {
+        let item: ItemStruct = syn::parse_str(src).unwrap();
+        let spanned = item.fields.iter()
+            .filter_map(|field| unmappable(&field.ty))
+            .map(|(ty, _)| quote!(#ty).to_string());
+        let messages = unmappable_fields(&item.fields).into_iter().flatten().map(|e| e.to_string());
+        spanned.zip(messages).collect()
+    }
+
+    #[test]
+    fn mappable_fields_pass() {
+        let src = "struct Reading { sensor: String, samples: Vec<f32>, raw: &'a [u8], at: Option<Timestamp>, grid: [[u8; 4]; 4], notify: Box<dyn Notifier> }";
+        assert!(unmappable_fields(&syn::parse_str::<ItemStruct>(src).unwrap().fields).is_none());
+    }
+
+    #[test]
+    fn each_unmappable_field_is_an_error_at_its_type() {
+        let errors = errors("struct Reading { pair: (u32, u32), name: String, callback: fn(u32) }");
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert_eq!(errors[0].0, "(u32 , u32)");
+        assert!(errors[0].1.contains("use a #[capnp] struct with named fields"), "{}", errors[0].1);
+        assert_eq!(errors[1].0, "fn (u32)");
+        assert!(errors[1].1.contains("use a #[capnp] trait"), "{}", errors[1].1);
+    }
+
+    #[test]
+    fn unmappable_types_inside_containers_are_found() {
+        let errors = errors("struct Reading { points: Vec<Option<(f64, f64)>> }");
+        assert_eq!(errors, [(
+            "(f64 , f64)".to_string(),
+            "`(f64 , f64)` has no capnp type: use a #[capnp] struct with named fields instead of a tuple".to_string(),
+        )]);
+    }
+
+    #[test]
+    fn nested_options_are_an_error_at_the_outer_option() {
+        let errors = errors("struct Reading { level: Option<Option<u8>> }");
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert_eq!(errors[0].0, "Option < Option < u8 > >");
+    }
+}
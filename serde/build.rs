@@ -0,0 +1,7 @@
+fn main() {
+    capnpc::CompilerCommand::new()
+        .src_prefix("schema")
+        .file("schema/value.capnp")
+        .run()
+        .expect("Failed to compile schema/value.capnp");
+}
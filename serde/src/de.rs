@@ -0,0 +1,170 @@
+//! A [`Value`] tree to `Deserialize` types.
+
+use crate::value::Value;
+use crate::Error;
+use serde::de::{self, DeserializeSeed, IntoDeserializer, Visitor};
+use std::vec;
+
+impl<'de> de::Deserializer<'de> for Value {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Self::Unit => visitor.visit_unit(),
+            Self::Bool(v) => visitor.visit_bool(v),
+            Self::Int(v) => visitor.visit_i64(v),
+            Self::UInt(v) => visitor.visit_u64(v),
+            Self::Float(v) => visitor.visit_f64(v),
+            Self::Text(v) => visitor.visit_string(v),
+            Self::Bytes(v) => visitor.visit_byte_buf(v),
+            Self::None => visitor.visit_none(),
+            Self::Some(v) => visitor.visit_some(*v),
+            Self::Seq(items) => visitor.visit_seq(SeqAccess { items: items.into_iter() }),
+            Self::Map(entries) => visitor.visit_map(MapAccess { entries: entries.into_iter(), value: None }),
+            Self::Struct(fields) => visitor.visit_map(MapAccess {
+                entries: fields.into_iter().map(|(name, value)| (Value::Text(name), value)).collect::<Vec<_>>().into_iter(),
+                value: None,
+            }),
+            // Without an enum hint, a variant reads as a one-entry map, as in JSON
+            Self::Variant(name, value) => {
+                visitor.visit_map(MapAccess { entries: vec![(Value::Text(name), *value)].into_iter(), value: None })
+            }
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Self::None => visitor.visit_none(),
+            Self::Some(v) => visitor.visit_some(*v),
+            other => visitor.visit_some(other),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self {
+            Self::Variant(name, value) => visitor.visit_enum(EnumAccess { name, value: *value }),
+            // A unit variant used as a map key, or written by hand as its name
+            Self::Text(name) => visitor.visit_enum(EnumAccess { name, value: Value::Unit }),
+            other => Err(de::Error::invalid_type(other.unexpected(), &"an enum variant")),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for Value {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl Value {
+    fn unexpected(&self) -> de::Unexpected<'_> {
+        match self {
+            Self::Unit => de::Unexpected::Unit,
+            Self::Bool(v) => de::Unexpected::Bool(*v),
+            Self::Int(v) => de::Unexpected::Signed(*v),
+            Self::UInt(v) => de::Unexpected::Unsigned(*v),
+            Self::Float(v) => de::Unexpected::Float(*v),
+            Self::Text(v) => de::Unexpected::Str(v),
+            Self::Bytes(v) => de::Unexpected::Bytes(v),
+            Self::None | Self::Some(_) => de::Unexpected::Option,
+            Self::Seq(_) => de::Unexpected::Seq,
+            Self::Map(_) | Self::Struct(_) => de::Unexpected::Map,
+            Self::Variant(..) => de::Unexpected::Enum,
+        }
+    }
+}
+
+struct SeqAccess {
+    items: vec::IntoIter<Value>,
+}
+
+impl<'de> de::SeqAccess<'de> for SeqAccess {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, Error> {
+        self.items.next().map(|item| seed.deserialize(item)).transpose()
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.items.len())
+    }
+}
+
+struct MapAccess {
+    entries: vec::IntoIter<(Value, Value)>,
+    value: Option<Value>,
+}
+
+impl<'de> de::MapAccess<'de> for MapAccess {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Error> {
+        let Some((key, value)) = self.entries.next() else { return Ok(None) };
+        self.value = Some(value);
+        seed.deserialize(key).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let value = self.value.take().ok_or_else(|| <Error as de::Error>::custom("map key without a value"))?;
+        seed.deserialize(value)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.entries.len())
+    }
+}
+
+struct EnumAccess {
+    name: String,
+    value: Value,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumAccess {
+    type Error = Error;
+    type Variant = Value;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Value), Error> {
+        let name: de::value::StringDeserializer<Error> = self.name.into_deserializer();
+        let variant = seed.deserialize(name)?;
+        Ok((variant, self.value))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Value {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        match self {
+            Self::Unit => Ok(()),
+            other => Err(de::Error::invalid_type(other.unexpected(), &"a unit variant")),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_any(self, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_any(self, visitor)
+    }
+}
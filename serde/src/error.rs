@@ -0,0 +1,46 @@
+use std::fmt;
+
+/// Why a value could not be encoded or decoded.
+#[derive(Debug)]
+pub enum Error {
+    /// Raised by a `Serialize` or `Deserialize` impl, e.g. a missing field or a type mismatch.
+    Message(String),
+    /// The bytes are not a valid message.
+    Capnp(capnp::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Message(message) => f.write_str(message),
+            Self::Capnp(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Capnp(e) => Some(e),
+            Self::Message(_) => None,
+        }
+    }
+}
+
+impl From<capnp::Error> for Error {
+    fn from(e: capnp::Error) -> Self {
+        Self::Capnp(e)
+    }
+}
+
+impl serde::ser::Error for Error {
+    fn custom<T: fmt::Display>(message: T) -> Self {
+        Self::Message(message.to_string())
+    }
+}
+
+impl serde::de::Error for Error {
+    fn custom<T: fmt::Display>(message: T) -> Self {
+        Self::Message(message.to_string())
+    }
+}
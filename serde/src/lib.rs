@@ -0,0 +1,50 @@
+//! Cap'n Proto as a serde data format: any `Serialize` type to message bytes and back, without a
+//! schema of its own.
+//!
+//! ```ignore
+//! let bytes = capnez_serde::to_bytes(&config)?;
+//! let config: Config = capnez_serde::from_bytes(&bytes)?;
+//! ```
+//!
+//! Values are encoded self-describingly as the `Value` struct of `schema/value.capnp`, so the bytes
+//! are a regular capnp message that any capnp reader with that schema can walk. Struct fields keep
+//! their names and declaration order, enums are externally tagged, and map entries are sorted by
+//! key, so the same value always encodes to the same bytes. For types with a schema of their own,
+//! `#[capnp]` and the generated `to_capnp`/`from_capnp` are smaller and faster; this is for the
+//! types that only derive serde.
+
+mod de;
+mod error;
+mod ser;
+mod value;
+
+#[allow(clippy::all)]
+mod value_capnp {
+    include!(concat!(env!("OUT_DIR"), "/value_capnp.rs"));
+}
+
+pub use error::Error;
+
+use capnp::message::ReaderOptions;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Encodes `value` as a single unsegmented-framed capnp message.
+pub fn to_bytes<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Error> {
+    let value = value.serialize(ser::Serializer)?;
+    let mut message = capnp::message::Builder::new_default();
+    value.write(message.init_root());
+    Ok(capnp::serialize::write_message_to_words(&message))
+}
+
+/// Decodes a message written by [`to_bytes`], with capnp's default reader limits.
+pub fn from_bytes<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+    from_bytes_with_options(bytes, ReaderOptions::new())
+}
+
+/// Decodes a message written by [`to_bytes`] under the given traversal and nesting limits.
+pub fn from_bytes_with_options<T: DeserializeOwned>(bytes: &[u8], options: ReaderOptions) -> Result<T, Error> {
+    let message = capnp::serialize::read_message_from_flat_slice(&mut &bytes[..], options)?;
+    let value = value::Value::read(message.get_root()?)?;
+    T::deserialize(value)
+}
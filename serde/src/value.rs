@@ -0,0 +1,150 @@
+//! The serde data model as an owned tree, and its encoding as the capnp `Value` struct.
+
+use crate::value_capnp::value;
+use std::cmp::Ordering;
+
+/// One serde value, between the serde side and the message.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Value {
+    Unit,
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    Text(String),
+    Bytes(Vec<u8>),
+    None,
+    Some(Box<Value>),
+    Seq(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+    Struct(Vec<(String, Value)>),
+    Variant(String, Box<Value>),
+}
+
+impl Value {
+    pub(crate) fn write(&self, mut builder: value::Builder<'_>) {
+        match self {
+            Self::Unit => builder.set_unit(()),
+            Self::Bool(v) => builder.set_boolean(*v),
+            Self::Int(v) => builder.set_int(*v),
+            Self::UInt(v) => builder.set_uint(*v),
+            Self::Float(v) => builder.set_float(*v),
+            Self::Text(v) => builder.set_text(v.as_str()),
+            Self::Bytes(v) => builder.set_bytes(&v[..]),
+            Self::None => builder.set_option_none(()),
+            Self::Some(v) => v.write(builder.init_option_some()),
+            Self::Seq(items) => {
+                let mut list = builder.init_seq(items.len() as u32);
+                for (i, item) in items.iter().enumerate() {
+                    item.write(list.reborrow().get(i as u32));
+                }
+            }
+            Self::Map(entries) => {
+                let mut list = builder.init_map(entries.len() as u32);
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    let mut entry = list.reborrow().get(i as u32);
+                    key.write(entry.reborrow().init_key());
+                    value.write(entry.init_value());
+                }
+            }
+            Self::Struct(fields) => {
+                let mut list = builder.init_fields(fields.len() as u32);
+                for (i, (name, value)) in fields.iter().enumerate() {
+                    let mut field = list.reborrow().get(i as u32);
+                    field.set_name(name.as_str());
+                    value.write(field.init_value());
+                }
+            }
+            Self::Variant(name, value) => {
+                let mut variant = builder.init_variant();
+                variant.set_name(name.as_str());
+                value.write(variant.init_value());
+            }
+        }
+    }
+
+    pub(crate) fn read(reader: value::Reader<'_>) -> capnp::Result<Self> {
+        Ok(match reader.which()? {
+            value::Unit(()) => Self::Unit,
+            value::Boolean(v) => Self::Bool(v),
+            value::Int(v) => Self::Int(v),
+            value::Uint(v) => Self::UInt(v),
+            value::Float(v) => Self::Float(v),
+            value::Text(v) => Self::Text(v?.to_string()?),
+            value::Bytes(v) => Self::Bytes(v?.to_vec()),
+            value::OptionNone(()) => Self::None,
+            value::OptionSome(v) => Self::Some(Box::new(Self::read(v?)?)),
+            value::Seq(list) => Self::Seq(list?.iter().map(Self::read).collect::<capnp::Result<_>>()?),
+            value::Map(list) => Self::Map(
+                list?.iter()
+                    .map(|entry| Ok((Self::read(entry.get_key()?)?, Self::read(entry.get_value()?)?)))
+                    .collect::<capnp::Result<_>>()?,
+            ),
+            value::Fields(list) => Self::Struct(
+                list?.iter()
+                    .map(|field| Ok((field.get_name()?.to_string()?, Self::read(field.get_value()?)?)))
+                    .collect::<capnp::Result<_>>()?,
+            ),
+            value::Variant(variant) => {
+                let variant = variant?;
+                Self::Variant(variant.get_name()?.to_string()?, Box::new(Self::read(variant.get_value()?)?))
+            }
+        })
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            Self::Unit => 0,
+            Self::Bool(_) => 1,
+            Self::Int(_) => 2,
+            Self::UInt(_) => 3,
+            Self::Float(_) => 4,
+            Self::Text(_) => 5,
+            Self::Bytes(_) => 6,
+            Self::None => 7,
+            Self::Some(_) => 8,
+            Self::Seq(_) => 9,
+            Self::Map(_) => 10,
+            Self::Struct(_) => 11,
+            Self::Variant(..) => 12,
+        }
+    }
+
+    /// A total order, used to sort map entries so encoding does not depend on iteration order.
+    pub(crate) fn order(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Self::Bool(a), Self::Bool(b)) => a.cmp(b),
+            (Self::Int(a), Self::Int(b)) => a.cmp(b),
+            (Self::UInt(a), Self::UInt(b)) => a.cmp(b),
+            (Self::Float(a), Self::Float(b)) => a.total_cmp(b),
+            (Self::Text(a), Self::Text(b)) => a.cmp(b),
+            (Self::Bytes(a), Self::Bytes(b)) => a.cmp(b),
+            (Self::Some(a), Self::Some(b)) => a.order(b),
+            (Self::Seq(a), Self::Seq(b)) => order_all(a.iter(), b.iter(), |a, b| a.order(b)),
+            (Self::Map(a), Self::Map(b)) => {
+                order_all(a.iter(), b.iter(), |a, b| a.0.order(&b.0).then_with(|| a.1.order(&b.1)))
+            }
+            (Self::Struct(a), Self::Struct(b)) => {
+                order_all(a.iter(), b.iter(), |a, b| a.0.cmp(&b.0).then_with(|| a.1.order(&b.1)))
+            }
+            (Self::Variant(a, x), Self::Variant(b, y)) => a.cmp(b).then_with(|| x.order(y)),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+/// Lexicographic order of two sequences under `order`.
+fn order_all<'a, T: 'a>(
+    a: impl Iterator<Item = &'a T>,
+    mut b: impl Iterator<Item = &'a T>,
+    order: impl Fn(&T, &T) -> Ordering,
+) -> Ordering {
+    for x in a {
+        let Some(y) = b.next() else { return Ordering::Greater };
+        match order(x, y) {
+            Ordering::Equal => {}
+            unequal => return unequal,
+        }
+    }
+    if b.next().is_some() { Ordering::Less } else { Ordering::Equal }
+}
@@ -0,0 +1,258 @@
+//! Every kind of the serde data model through capnez-serde, serde_json and bincode: each value must
+//! come back unchanged from all three, and encode to the same capnp bytes every time.
+
+use serde::de::{self, DeserializeOwned, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug};
+
+fn round_trip<T: Serialize + DeserializeOwned + PartialEq + Debug>(value: &T) {
+    let bytes = capnez_serde::to_bytes(value).unwrap();
+    let capnp: T = capnez_serde::from_bytes(&bytes).unwrap();
+    assert_eq!(&capnp, value, "capnez-serde");
+    assert_eq!(capnez_serde::to_bytes(&capnp).unwrap(), bytes, "capnez-serde re-encoding {:?}", value);
+
+    let json: T = serde_json::from_slice(&serde_json::to_vec(value).unwrap()).unwrap();
+    assert_eq!(&json, value, "serde_json");
+    let bincode: T = bincode::deserialize(&bincode::serialize(value).unwrap()).unwrap();
+    assert_eq!(&bincode, value, "bincode");
+}
+
+/// Goes through `serialize_bytes` rather than a sequence of `u8`, as `serde_bytes` does.
+#[derive(Clone, Debug, PartialEq)]
+struct Bytes(Vec<u8>);
+
+impl Serialize for Bytes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Bytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct BytesVisitor;
+
+        impl<'de> Visitor<'de> for BytesVisitor {
+            type Value = Bytes;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("bytes")
+            }
+
+            fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Bytes, E> {
+                Ok(Bytes(v.to_vec()))
+            }
+
+            fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Bytes, E> {
+                Ok(Bytes(v))
+            }
+
+            // serde_json writes bytes as an array of numbers
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Bytes, A::Error> {
+                let mut bytes = Vec::new();
+                while let Some(byte) = seq.next_element()? {
+                    bytes.push(byte);
+                }
+                Ok(Bytes(bytes))
+            }
+        }
+
+        deserializer.deserialize_byte_buf(BytesVisitor)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct UnitStruct;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct Newtype(u32);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct TupleStruct(i16, String);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+enum Shape {
+    Empty,
+    Circle(f64),
+    Rect(u32, u32),
+    Labeled { name: String, sides: u8 },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct Inner {
+    id: u64,
+    tags: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct Everything {
+    flag: bool,
+    small: i8,
+    short: i16,
+    int: i32,
+    long: i64,
+    byte: u8,
+    ushort: u16,
+    uint: u32,
+    ulong: u64,
+    single: f32,
+    double: f64,
+    letter: char,
+    text: String,
+    bytes: Bytes,
+    list: Vec<u16>,
+    array: [u8; 4],
+    tuple: (u8, String, bool),
+    unit: (),
+    unit_struct: UnitStruct,
+    newtype: Newtype,
+    tuple_struct: TupleStruct,
+    some: Option<u32>,
+    none: Option<String>,
+    nested: Inner,
+    nested_list: Vec<Inner>,
+    by_name: BTreeMap<String, i32>,
+    by_number: BTreeMap<u32, String>,
+    shapes: Vec<Shape>,
+    shape: Option<Shape>,
+}
+
+#[test]
+fn scalars() {
+    for flag in [false, true] {
+        round_trip(&flag);
+    }
+    for v in [i8::MIN, -1, 0, i8::MAX] {
+        round_trip(&v);
+    }
+    for v in [i16::MIN, 0, i16::MAX] {
+        round_trip(&v);
+    }
+    for v in [i32::MIN, 0, i32::MAX] {
+        round_trip(&v);
+    }
+    for v in [i64::MIN, -1, 0, i64::MAX] {
+        round_trip(&v);
+    }
+    for v in [0u8, u8::MAX] {
+        round_trip(&v);
+    }
+    for v in [0u16, u16::MAX] {
+        round_trip(&v);
+    }
+    for v in [0u32, u32::MAX] {
+        round_trip(&v);
+    }
+    for v in [0u64, u64::MAX] {
+        round_trip(&v);
+    }
+    for v in [0.0f32, -1.5, f32::MAX, f32::MIN_POSITIVE] {
+        round_trip(&v);
+    }
+    for v in [0.0f64, -2.25, 1e300, f64::MIN_POSITIVE] {
+        round_trip(&v);
+    }
+    for c in ['a', 'é', '\u{1F980}'] {
+        round_trip(&c);
+    }
+}
+
+#[test]
+fn text_and_bytes() {
+    for text in ["", "plain", "with \"quotes\" and a\nnewline", "多字节"] {
+        round_trip(&text.to_string());
+    }
+    round_trip(&Bytes(Vec::new()));
+    round_trip(&Bytes((0..=255).collect()));
+}
+
+#[test]
+fn options() {
+    round_trip(&None::<u32>);
+    round_trip(&Some(7u32));
+    round_trip(&Some("text".to_string()));
+    round_trip(&Some(Inner { id: 1, tags: vec!["a".to_string()] }));
+}
+
+#[test]
+fn units_and_newtypes() {
+    round_trip(&());
+    round_trip(&UnitStruct);
+    round_trip(&Newtype(42));
+    round_trip(&TupleStruct(-3, "three".to_string()));
+}
+
+#[test]
+fn sequences_and_tuples() {
+    round_trip(&Vec::<u32>::new());
+    round_trip(&vec![1u16, 2, 3]);
+    round_trip(&vec![vec!["a".to_string()], Vec::new()]);
+    round_trip(&[9u8, 8, 7, 6]);
+    round_trip(&(1u8, "two".to_string(), false));
+}
+
+#[test]
+fn maps() {
+    round_trip(&BTreeMap::<String, i32>::new());
+    round_trip(&BTreeMap::from([("b".to_string(), 2), ("a".to_string(), -1)]));
+    round_trip(&BTreeMap::from([(3u32, "three".to_string()), (1, "one".to_string())]));
+    round_trip(&BTreeMap::from([("nested".to_string(), BTreeMap::from([(1u64, true)]))]));
+}
+
+#[test]
+fn enum_variants() {
+    for shape in [
+        Shape::Empty,
+        Shape::Circle(1.5),
+        Shape::Rect(3, 4),
+        Shape::Labeled { name: "triangle".to_string(), sides: 3 },
+    ] {
+        round_trip(&shape);
+    }
+}
+
+#[test]
+fn every_kind_in_one_struct() {
+    round_trip(&Everything {
+        flag: true,
+        small: -8,
+        short: -16,
+        int: -32,
+        long: -64,
+        byte: 8,
+        ushort: 16,
+        uint: 32,
+        ulong: u64::MAX,
+        single: 0.5,
+        double: -0.125,
+        letter: 'z',
+        text: "text".to_string(),
+        bytes: Bytes(vec![0, 1, 254, 255]),
+        list: vec![1, 2, 3],
+        array: [1, 2, 3, 4],
+        tuple: (5, "five".to_string(), true),
+        unit: (),
+        unit_struct: UnitStruct,
+        newtype: Newtype(6),
+        tuple_struct: TupleStruct(7, "seven".to_string()),
+        some: Some(8),
+        none: None,
+        nested: Inner { id: 9, tags: vec!["x".to_string(), "y".to_string()] },
+        nested_list: vec![Inner { id: 10, tags: Vec::new() }, Inner { id: 11, tags: vec!["z".to_string()] }],
+        by_name: BTreeMap::from([("one".to_string(), 1), ("two".to_string(), 2)]),
+        by_number: BTreeMap::from([(1, "one".to_string())]),
+        shapes: vec![Shape::Empty, Shape::Circle(2.0), Shape::Rect(1, 2), Shape::Labeled { name: "sq".to_string(), sides: 4 }],
+        shape: Some(Shape::Rect(5, 6)),
+    });
+}
+
+/// JSON writes `Some(None)` and `None` alike, so nested options go through the other two only.
+#[test]
+fn nested_options() {
+    for value in [None, Some(None), Some(Some(3u8))] {
+        let capnp: Option<Option<u8>> = capnez_serde::from_bytes(&capnez_serde::to_bytes(&value).unwrap()).unwrap();
+        assert_eq!(capnp, value);
+        let bincode: Option<Option<u8>> = bincode::deserialize(&bincode::serialize(&value).unwrap()).unwrap();
+        assert_eq!(bincode, value);
+    }
+}